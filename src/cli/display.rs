@@ -1,17 +1,30 @@
 use crate::query::QueryResult;
+use crate::query::value::NULL_TEXT;
 
 pub fn display_result(result: &QueryResult) {
     match result {
-        QueryResult::Select(rows) => {
-            if rows.is_empty() {
+        QueryResult::Select(select_result) => {
+            if select_result.rows.is_empty() {
                 println!("No results found");
                 return;
             }
 
-            // Display all rows without duplicating the first one
-            for row in rows {
+            // Display headers
+            for header in &select_result.headers {
+                print!("{} | ", header);
+            }
+            println!();
+
+            // Display separator
+            for _ in &select_result.headers {
+                print!("--------- | ");
+            }
+            println!();
+
+            // Display rows
+            for row in &select_result.rows {
                 for value in row {
-                    print!("{} | ", value);
+                    print!("{} | ", value.as_deref().unwrap_or(NULL_TEXT));
                 }
                 println!();
             }
@@ -37,7 +50,7 @@ pub fn display_result(result: &QueryResult) {
             // Display rows
             for row in &join_result.rows {
                 for value in row {
-                    print!("{} | ", value);
+                    print!("{} | ", value.as_deref().unwrap_or(NULL_TEXT));
                 }
                 println!();
             }
@@ -63,7 +76,7 @@ pub fn display_result(result: &QueryResult) {
             // Display rows
             for row in &agg_result.rows {
                 for value in row {
-                    print!("{} | ", value);
+                    print!("{} | ", value.as_deref().unwrap_or(NULL_TEXT));
                 }
                 println!();
             }
@@ -73,6 +86,12 @@ pub fn display_result(result: &QueryResult) {
         QueryResult::Delete(count) => println!("Deleted {} rows", count),
         QueryResult::CreateTable => println!("Table created successfully"),
         QueryResult::DropTable => println!("Table dropped successfully"),
+        QueryResult::CreateIndex => println!("Index created successfully"),
+        QueryResult::DropIndex => println!("Index dropped successfully"),
+        QueryResult::Begin => println!("Transaction started"),
+        QueryResult::Commit => println!("Transaction committed"),
+        QueryResult::Rollback => println!("Transaction rolled back"),
+        QueryResult::Set => println!("SET"),
         QueryResult::Error(msg) => println!("Error: {}", msg),
     }
 } 
\ No newline at end of file
@@ -7,6 +7,12 @@ pub struct CLI {
     pub prompt: String,
 }
 
+impl Default for CLI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CLI {
     pub fn new() -> Self {
         CLI {
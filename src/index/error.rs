@@ -3,4 +3,22 @@ pub enum IndexError {
     ColumnNotFound(String),
     IndexAlreadyExists(String),
     IndexNotFound(String),
-}
\ No newline at end of file
+    DuplicateKey(String),
+    InvalidKey(String),
+    StorageError(String),
+}
+
+impl std::fmt::Display for IndexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexError::ColumnNotFound(msg) => write!(f, "Column not found: {}", msg),
+            IndexError::IndexAlreadyExists(msg) => write!(f, "Index already exists: {}", msg),
+            IndexError::IndexNotFound(msg) => write!(f, "Index not found: {}", msg),
+            IndexError::DuplicateKey(msg) => write!(f, "Duplicate key: {}", msg),
+            IndexError::InvalidKey(msg) => write!(f, "Invalid key: {}", msg),
+            IndexError::StorageError(msg) => write!(f, "Storage error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for IndexError {}
@@ -0,0 +1,199 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// BM25 term frequency saturation
+const K1: f64 = 1.2;
+/// BM25 document length normalization
+const B: f64 = 0.75;
+
+/// Split text into lower case words of letters and digits
+/// Example: "Wireless Head-phones (2nd gen)" -> [wireless, head, phones, 2nd, gen]
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Inverted index of the texts of a FULLTEXT index, with the statistics BM25 ranks matches by
+#[derive(Debug, Default)]
+pub struct InvertedIndex {
+    postings: HashMap<String, BTreeMap<u64, u32>>, // term -> record id -> occurrences
+    lengths: HashMap<u64, u32>,                    // record id -> number of terms
+    total_length: u64,
+}
+
+impl InvertedIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index the texts of a record; NULLs hold no terms
+    pub fn add(&mut self, id: u64, texts: &[Option<String>]) {
+        let terms: Vec<String> = texts.iter()
+            .flatten()
+            .flat_map(|text| tokenize(text))
+            .collect();
+        for term in &terms {
+            *self.postings.entry(term.clone()).or_default().entry(id).or_default() += 1;
+        }
+        self.lengths.insert(id, terms.len() as u32);
+        self.total_length += terms.len() as u64;
+    }
+
+    /// Drop a record given the texts it was indexed with
+    pub fn remove(&mut self, id: u64, texts: &[Option<String>]) {
+        let Some(length) = self.lengths.remove(&id) else {
+            return;
+        };
+        self.total_length -= length as u64;
+        for text in texts.iter().flatten() {
+            for term in tokenize(text) {
+                if let Some(ids) = self.postings.get_mut(&term) {
+                    ids.remove(&id);
+                    if ids.is_empty() {
+                        self.postings.remove(&term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records holding any term of the query, best match first, with their BM25 scores
+    pub fn search(&self, query: &str) -> Vec<(u64, f64)> {
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+
+        let documents = self.lengths.len() as f64;
+        let average_length = (self.total_length as f64 / documents).max(1.0);
+        let mut scores: HashMap<u64, f64> = HashMap::new();
+        for term in &terms {
+            let Some(ids) = self.postings.get(term) else {
+                continue;
+            };
+            let matching = ids.len() as f64;
+            let idf = (1.0 + (documents - matching + 0.5) / (matching + 0.5)).ln();
+            for (id, &occurrences) in ids {
+                let frequency = occurrences as f64;
+                let length = self.lengths[id] as f64;
+                let score = idf * frequency * (K1 + 1.0) / (frequency + K1 * (1.0 - B + B * length / average_length));
+                *scores.entry(*id).or_default() += score;
+            }
+        }
+
+        let mut ranked: Vec<(u64, f64)> = scores.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{QueryEngine, QueryError};
+    use tempfile::TempDir;
+    use crate::test_util::rows;
+
+    fn ids(engine: &mut QueryEngine, query: &str) -> Vec<String> {
+        rows(engine, query)
+            .into_iter()
+            .map(|row| row[0].clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_bm25_ranking() {
+        assert_eq!(tokenize("Wireless Head-phones (2nd GEN)"), vec!["wireless", "head", "phones", "2nd", "gen"]);
+
+        let mut inverted = InvertedIndex::new();
+        for (id, text) in [(1, "red apple"), (2, "green apple apple"), (3, "red car"), (4, "blue car with a long name")] {
+            inverted.add(id, &[Some(text.to_string())]);
+        }
+        // Rarer terms and more occurrences weigh more, longer texts less
+        let ranked: Vec<u64> = inverted.search("apple").iter().map(|(id, _)| *id).collect();
+        assert_eq!(ranked, vec![2, 1]);
+        let ranked: Vec<u64> = inverted.search("red CAR").iter().map(|(id, _)| *id).collect();
+        assert_eq!(ranked, vec![3, 1, 4]);
+
+        inverted.remove(3, &[Some("red car".to_string())]);
+        let ranked: Vec<u64> = inverted.search("red car").iter().map(|(id, _)| *id).collect();
+        assert_eq!(ranked, vec![1, 4]);
+        assert!(inverted.search("missing").is_empty());
+    }
+
+    #[test]
+    fn test_fulltext_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        engine.execute("CREATE TABLE products (id INTEGER PRIMARY KEY, name VARCHAR 60, price FLOAT)").unwrap();
+        engine.execute("INSERT INTO products VALUES (1, 'Wired headphones with a wireless charging case', 80.0), \
+                        (2, 'Wireless Mouse', 20.0), (3, 'USB cable', 5.0), (4, 'Wireless headphones', 120.0), \
+                        (5, NULL, 1.0)").unwrap();
+
+        // MATCH searches a FULLTEXT index, so it needs one on its columns
+        let query = "SELECT id FROM products WHERE MATCH(name, 'wireless headphones')";
+        assert!(matches!(
+            engine.execute(query),
+            Err(QueryError::InvalidValue(message)) if message == "MATCH needs a FULLTEXT index on name"
+        ));
+
+        engine.execute("CREATE FULLTEXT INDEX ON products(name)").unwrap();
+        assert_eq!(
+            ids(&mut engine, &format!("EXPLAIN {}", query)),
+            vec!["Full Text Search on products using products_name_fulltext (MATCH(name, 'wireless headphones'))"]
+        );
+        assert_eq!(ids(&mut engine, query), vec!["4", "1", "2"]);
+        assert_eq!(ids(&mut engine, "SELECT id FROM products WHERE MATCH(name, 'WIRELESS') AND price < 100"), vec!["2", "1"]);
+        assert_eq!(ids(&mut engine, "SELECT id FROM products WHERE MATCH(name, 'headphones') ORDER BY price"), vec!["1", "4"]);
+
+        // The index follows every write, and is persisted
+        engine.execute("UPDATE products SET name = 'Wireless USB cable' WHERE id = 3").unwrap();
+        engine.execute("DELETE FROM products WHERE id = 4").unwrap();
+        drop(engine);
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert_eq!(ids(&mut engine, "SELECT id FROM products WHERE MATCH(name, 'usb wireless')"), vec!["3", "2", "1"]);
+        assert_eq!(ids(&mut engine, "SELECT id FROM products WHERE MATCH(name, 'headphones')"), vec!["1"]);
+
+        assert!(matches!(engine.execute("CREATE FULLTEXT INDEX ON products(price)"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(
+            engine.execute("CREATE FULLTEXT INDEX products_x ON products(name) INCLUDE (price)"),
+            Err(QueryError::SyntaxError(_))
+        ));
+        engine.execute("DROP INDEX products_name_fulltext").unwrap();
+        assert!(matches!(engine.execute("SELECT id FROM products WHERE MATCH(name, 'wireless')"), Err(QueryError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_match_without_fulltext_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        engine.execute("CREATE TABLE products (id INTEGER PRIMARY KEY, name VARCHAR 60, note VARCHAR 60)").unwrap();
+        engine.execute("CREATE TABLE reviews (id INTEGER, product_id INTEGER, body VARCHAR 60)").unwrap();
+        engine.execute("INSERT INTO products VALUES (1, 'Wireless Mouse', 'usb'), (2, 'USB cable', NULL)").unwrap();
+        engine.execute("INSERT INTO reviews VALUES (1, 1, 'great mouse'), (2, 2, 'too short')").unwrap();
+        engine.execute("CREATE FULLTEXT INDEX ON products(name)").unwrap();
+
+        // The error names the columns searched, wherever the MATCH sits in the condition
+        for (query, columns) in [
+            ("SELECT id FROM products WHERE MATCH(note, 'usb')", "note"),
+            ("SELECT id FROM products WHERE id > 0 OR NOT MATCH(name, note, 'usb')", "name, note"),
+            ("UPDATE products SET note = 'x' WHERE MATCH(note, 'usb')", "note"),
+            ("DELETE FROM products WHERE MATCH(note, 'usb')", "note"),
+            ("EXPLAIN SELECT id FROM products WHERE MATCH(note, 'usb')", "note"),
+            ("SELECT p.id FROM products p JOIN reviews r ON r.product_id = p.id WHERE MATCH(r.body, 'mouse')", "body"),
+        ] {
+            match engine.execute(query) {
+                Err(QueryError::InvalidValue(message)) => assert_eq!(message, format!("MATCH needs a FULLTEXT index on {}", columns), "{}", query),
+                other => panic!("Expected an error for {}, got {:?}", query, other),
+            }
+        }
+        assert!(matches!(engine.execute("SELECT id FROM products WHERE MATCH(UPPER(name), 'usb')"), Err(QueryError::SyntaxError(_))));
+
+        // Through a join, the index of the table holding the columns is used
+        let query = "SELECT r.id FROM products p JOIN reviews r ON r.product_id = p.id WHERE MATCH(p.name, 'usb')";
+        assert_eq!(ids(&mut engine, query), vec!["2"]);
+        engine.execute("CREATE FULLTEXT INDEX ON reviews(body)").unwrap();
+        let query = "SELECT p.name FROM products p JOIN reviews r ON r.product_id = p.id WHERE MATCH(r.body, 'mouse')";
+        assert_eq!(ids(&mut engine, query), vec!["Wireless Mouse"]);
+    }
+}
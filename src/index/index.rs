@@ -1,36 +1,482 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::ops::Bound;
+use crate::metadata::{ColumnType, IndexDefinition, Table};
+use crate::query::record::Row;
+use crate::storage::LSMEngine;
+use super::error::IndexError;
+use super::fulltext::InvertedIndex;
+use super::key::encode_key;
 
+/// A secondary index over one or more columns of a table
+///
+/// Entries are persisted in the index's own LSM keyspace as record id -> encoded key and
+/// the values of the covered columns (key columns, then INCLUDE columns), and kept in memory
+/// ordered by key for lookups. A FULLTEXT index keeps the terms of its columns' texts instead.
 pub struct Index {
-    name: String,
-    column: String,
-    values: HashMap<String, Vec<usize>>,
+    definition: IndexDefinition,
+    positions: Vec<usize>, // positions of the key columns in the table
+    covered: Vec<usize>,   // positions of the key and INCLUDE columns in the table
+    types: Vec<ColumnType>,
+    storage: LSMEngine,
+    data_dir: String,
+    entries: BTreeMap<Vec<u8>, BTreeSet<u64>>, // key -> record ids
+    keys: HashMap<u64, Vec<u8>>,               // record id -> key
+    values: HashMap<u64, Vec<Option<String>>>, // record id -> covered values
+    inverted: Option<InvertedIndex>,           // terms of a FULLTEXT index, which has no key entries
+    view: Option<u64>,                                          // transaction whose changes are buffered
+    pending: HashMap<u64, BTreeMap<u64, Option<Row>>>, // transaction -> record id -> new row
+}
+
+fn storage_error(e: std::io::Error) -> IndexError {
+    IndexError::StorageError(e.to_string())
+}
+
+/// Length written in place of a value's length for a NULL, which has no text
+const NULL_LENGTH: u32 = u32::MAX;
+
+/// Lay out an entry as the key length (u32), the key, then each value as its length (u32) and text
+fn encode_entry(key: &[u8], values: &[Option<String>]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend((key.len() as u32).to_be_bytes());
+    data.extend(key);
+    for value in values {
+        match value {
+            Some(text) => {
+                data.extend((text.len() as u32).to_be_bytes());
+                data.extend(text.as_bytes());
+            }
+            None => data.extend(NULL_LENGTH.to_be_bytes()),
+        }
+    }
+    data
+}
+
+fn decode_entry(data: &[u8]) -> Option<(Vec<u8>, Vec<Option<String>>)> {
+    let mut parts = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (length, tail) = rest.split_first_chunk::<4>()?;
+        let length = u32::from_be_bytes(*length);
+        if length == NULL_LENGTH {
+            parts.push(None);
+            rest = tail;
+            continue;
+        }
+        let length = length as usize;
+        if tail.len() < length {
+            return None;
+        }
+        parts.push(Some(&tail[..length]));
+        rest = &tail[length..];
+    }
+
+    let (key, values) = parts.split_first()?;
+    let values = values.iter()
+        .map(|value| match value {
+            Some(bytes) => String::from_utf8(bytes.to_vec()).ok().map(Some),
+            None => Some(None),
+        })
+        .collect::<Option<_>>()?;
+    Some(((*key)?.to_vec(), values))
 }
 
 impl Index {
-    pub fn new(name: String, column: String) -> Self {
-        Index {
-            name,
-            column,
+    /// Open the index kept under `data_dir`, loading the entries it already holds
+    pub fn open(definition: IndexDefinition, table: &Table, data_dir: &str) -> Result<Self, IndexError> {
+        let mut covered = Vec::new();
+        for column in definition.columns.iter().chain(&definition.include) {
+            let position = table.get_column_index(column)
+                .ok_or_else(|| IndexError::ColumnNotFound(format!("{}.{}", table.name, column)))?;
+            covered.push(position);
+        }
+        let positions = covered[..definition.columns.len()].to_vec();
+        let types: Vec<ColumnType> = positions.iter().map(|&position| table.columns[position].data_type.clone()).collect();
+        if definition.fulltext && !types.iter().all(|data_type| matches!(data_type, ColumnType::Varchar(_))) {
+            return Err(IndexError::InvalidKey(format!("FULLTEXT index {} can only cover VARCHAR columns", definition.name)));
+        }
+        let inverted = definition.fulltext.then(InvertedIndex::new);
+
+        let storage = LSMEngine::new(data_dir, 100).map_err(storage_error)?;
+        let mut index = Index {
+            definition,
+            positions,
+            covered,
+            types,
+            storage,
+            data_dir: data_dir.to_string(),
+            entries: BTreeMap::new(),
+            keys: HashMap::new(),
             values: HashMap::new(),
+            inverted,
+            view: None,
+            pending: HashMap::new(),
+        };
+        index.load_entries()?;
+        Ok(index)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    pub fn definition(&self) -> &IndexDefinition {
+        &self.definition
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.definition.unique
+    }
+
+    pub fn is_fulltext(&self) -> bool {
+        self.inverted.is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The key of a decoded row, given in table column order
+    pub fn key_of(&self, row: &[Option<String>]) -> Result<Vec<u8>, IndexError> {
+        let values: Vec<Option<&str>> = self.positions.iter().map(|&position| row[position].as_deref()).collect();
+        encode_key(&values, &self.types)
+    }
+
+    /// Whether the index holds the values of all these table columns, so it can stand in for the table
+    pub fn covers(&self, columns: &[usize]) -> bool {
+        columns.iter().all(|column| self.covered.contains(column))
+    }
+
+    /// The row of a record as far as the index knows it, in table column order with `width` columns
+    /// Columns the index doesn't cover are NULL
+    pub fn covered_row(&self, id: u64, width: usize) -> Option<Row> {
+        let values = self.values.get(&id)?;
+        let mut row = vec![None; width];
+        for (&position, value) in self.covered.iter().zip(values) {
+            row[position] = value.clone();
+        }
+        Some(row)
+    }
+
+    /// Record ids of the rows whose key is exactly `key`
+    pub fn find(&self, key: &[u8]) -> Vec<u64> {
+        self.entries.get(key).map_or_else(Vec::new, |ids| ids.iter().copied().collect())
+    }
+
+    /// Record ids of the rows whose key is at least `lower` and below `upper`, or with no upper end for None
+    pub fn range(&self, lower: &[u8], upper: Option<&[u8]>) -> Vec<u64> {
+        let upper = match upper {
+            Some(upper) if upper <= lower => return Vec::new(),
+            Some(upper) => Bound::Excluded(upper),
+            None => Bound::Unbounded,
+        };
+        self.entries.range::<[u8], _>((Bound::Included(lower), upper))
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect()
+    }
+
+    /// Records whose texts hold any term of the query, best BM25 match first, with their scores
+    /// Only a FULLTEXT index finds anything
+    pub fn search(&self, query: &str) -> Vec<(u64, f64)> {
+        self.inverted.as_ref().map_or_else(Vec::new, |inverted| inverted.search(query))
+    }
+
+    /// Fill the index with the rows of its table, replacing any entries it had
+    pub fn build(&mut self, rows: &[(u64, Row)]) -> Result<(), IndexError> {
+        let stale: Vec<u64> = self.keys.keys().copied().collect();
+        for id in stale {
+            self.remove(id)?;
+        }
+
+        let changes: Vec<(u64, Option<&[Option<String>]>)> = rows.iter().map(|(id, row)| (*id, Some(row.as_slice()))).collect();
+        self.check_unique(&changes)?;
+        for (id, row) in rows {
+            self.insert(*id, row)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the entries of records that no longer exist in the table
+    pub fn retain_records(&mut self, live: &HashSet<u64>) -> Result<(), IndexError> {
+        let stale: Vec<u64> = self.keys.keys().filter(|id| !live.contains(id)).copied().collect();
+        for id in stale {
+            self.remove(id)?;
+        }
+        Ok(())
+    }
+
+    /// Check that a UNIQUE index keeps every key on one row once the changes are made
+    /// Each change is a record id with its new row, or None for a deleted record;
+    /// a key with a NULL in it never clashes
+    /// Under a view, the changes its transaction buffered so far count too
+    pub fn check_unique(&self, changes: &[(u64, Option<&[Option<String>]>)]) -> Result<(), IndexError> {
+        if !self.definition.unique {
+            return Ok(());
+        }
+        let Some(buffered) = self.view.and_then(|transaction| self.pending.get(&transaction)) else {
+            return self.check_changes(changes);
+        };
+        let mut all_changes: Vec<(u64, Option<&[Option<String>]>)> = buffered.iter()
+            .filter(|(id, _)| !changes.iter().any(|(changed, _)| changed == *id))
+            .map(|(id, row)| (*id, row.as_deref()))
+            .collect();
+        all_changes.extend_from_slice(changes);
+        self.check_changes(&all_changes)
+    }
+
+    fn check_changes(&self, changes: &[(u64, Option<&[Option<String>]>)]) -> Result<(), IndexError> {
+        let changed: HashSet<u64> = changes.iter().map(|(id, _)| *id).collect();
+        let mut added = HashSet::new();
+        for (_, row) in changes {
+            let Some(row) = row else {
+                continue;
+            };
+            if self.positions.iter().any(|&position| row[position].is_none()) {
+                continue;
+            }
+
+            let key = self.key_of(row)?;
+            let taken = self.entries.get(&key).is_some_and(|ids| ids.iter().any(|id| !changed.contains(id)));
+            if taken || !added.insert(key) {
+                let values: Vec<&str> = self.positions.iter().filter_map(|&position| row[position].as_deref()).collect();
+                return Err(IndexError::DuplicateKey(format!(
+                    "UNIQUE index {} already contains ({})", self.definition.name, values.join(", ")
+                )));
+            }
         }
+        Ok(())
     }
 
-    pub fn insert(&mut self, value: String, row_id: usize) {
-        self.values
-            .entry(value)
-            .or_insert_with(Vec::new)
-            .push(row_id);
+    /// Add or replace the entry of a record
+    pub fn insert(&mut self, id: u64, row: &[Option<String>]) -> Result<(), IndexError> {
+        match self.view {
+            Some(transaction) => {
+                self.key_of(row)?;
+                self.pending.entry(transaction).or_default().insert(id, Some(row.to_vec()));
+                Ok(())
+            }
+            None => self.write_entry(id, row),
+        }
     }
 
-    pub fn remove(&mut self, value: &str, row_id: usize) {
-        if let Some(rows) = self.values.get_mut(value) {
-            if let Some(pos) = rows.iter().position(|&id| id == row_id) {
-                rows.remove(pos);
+    /// Remove the entry of a record, if it has one
+    pub fn remove(&mut self, id: u64) -> Result<(), IndexError> {
+        match self.view {
+            Some(transaction) => {
+                self.pending.entry(transaction).or_default().insert(id, None);
+                Ok(())
             }
+            None => self.delete_entry(id),
+        }
+    }
+
+    fn write_entry(&mut self, id: u64, row: &[Option<String>]) -> Result<(), IndexError> {
+        let key = self.key_of(row)?;
+        let values: Vec<Option<String>> = self.covered.iter().map(|&position| row[position].clone()).collect();
+        if self.keys.get(&id) == Some(&key) && self.values.get(&id) == Some(&values) {
+            return Ok(());
         }
+
+        self.remove_entry(id);
+        self.storage.update(id, encode_entry(&key, &values)).map_err(storage_error)?;
+        self.add_entry(id, key, values);
+        Ok(())
     }
 
-    pub fn find(&self, value: &str) -> Option<&Vec<usize>> {
-        self.values.get(value)
+    fn delete_entry(&mut self, id: u64) -> Result<(), IndexError> {
+        if self.remove_entry(id) {
+            self.storage.delete(id).map_err(storage_error)?;
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    /// Buffer the changes that follow for the given transaction, or make them directly with None
+    /// The entries only ever hold committed rows
+    pub fn set_view(&mut self, transaction: Option<u64>) {
+        self.view = transaction;
+    }
+
+    /// Check a UNIQUE index against the changes of a transaction before they are committed,
+    /// which may clash with rows committed since they were made
+    pub fn check_transaction(&self, transaction: u64) -> Result<(), IndexError> {
+        if !self.definition.unique {
+            return Ok(());
+        }
+        let changes: Vec<(u64, Option<&[Option<String>]>)> = self.pending.get(&transaction)
+            .into_iter()
+            .flatten()
+            .map(|(id, row)| (*id, row.as_deref()))
+            .collect();
+        self.check_changes(&changes)
+    }
+
+    /// Make the changes buffered by a transaction
+    pub fn commit_transaction(&mut self, transaction: u64) -> Result<(), IndexError> {
+        for (id, row) in self.pending.remove(&transaction).into_iter().flatten() {
+            match row {
+                Some(row) => self.write_entry(id, &row)?,
+                None => self.delete_entry(id)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Discard the changes buffered by a transaction
+    pub fn rollback_transaction(&mut self, transaction: u64) {
+        self.pending.remove(&transaction);
+    }
+
+    /// Write the entries still in memory to disk
+    pub fn flush(&mut self) -> Result<(), IndexError> {
+        self.storage.flush().map_err(storage_error)
+    }
+
+    /// Delete the index's keyspace from disk
+    pub fn destroy(self) -> Result<(), IndexError> {
+        let data_dir = self.data_dir.clone();
+        drop(self);
+        fs::remove_dir_all(&data_dir).map_err(storage_error)
+    }
+
+    fn load_entries(&mut self) -> Result<(), IndexError> {
+        for record in self.storage.get_all_records().map_err(storage_error)? {
+            let (key, values) = decode_entry(&record.data)
+                .ok_or_else(|| IndexError::StorageError(format!("Corrupt entry for record {} in index {}", record.id, self.name())))?;
+            self.add_entry(record.id, key, values);
+        }
+        Ok(())
+    }
+
+    fn add_entry(&mut self, id: u64, key: Vec<u8>, values: Vec<Option<String>>) {
+        match &mut self.inverted {
+            Some(inverted) => inverted.add(id, &values),
+            None => {
+                self.entries.entry(key.clone()).or_default().insert(id);
+            }
+        }
+        self.keys.insert(id, key);
+        self.values.insert(id, values);
+    }
+
+    fn remove_entry(&mut self, id: u64) -> bool {
+        let Some(key) = self.keys.remove(&id) else {
+            return false;
+        };
+        let values = self.values.remove(&id);
+        if let (Some(inverted), Some(values)) = (&mut self.inverted, values) {
+            inverted.remove(id, &values);
+        }
+        if let Some(ids) = self.entries.get_mut(&key) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{QueryEngine, QueryError};
+    use tempfile::TempDir;
+    use crate::test_util::setup_engine;
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE users (id INTEGER, email VARCHAR 30, age INTEGER)",
+        "INSERT INTO users VALUES (1, 'ann@example.com', 30), (2, 'bob@example.com', 25), (3, NULL, 30)",
+    ];
+
+    fn lookup(engine: &QueryEngine, index_name: &str, values: &[&str]) -> usize {
+        let index = engine.get_index(index_name).expect("Expected index");
+        let values: Vec<Option<&str>> = values.iter().copied().map(Some).collect();
+        index.find(&encode_key(&values, &[ColumnType::Integer, ColumnType::Varchar(Some(30))][..values.len()]).unwrap()).len()
+    }
+
+    #[test]
+    fn test_key_order() {
+        let ints = [i64::MIN, -5, -1, 0, 1, 300, i64::MAX].map(|n| encode_key(&[Some(&n.to_string())], &[ColumnType::Integer]).unwrap());
+        assert!(ints.windows(2).all(|pair| pair[0] < pair[1]));
+        let floats = ["-1e9", "-2.5", "-0.0", "0.5", "3", "1e9"].map(|n| encode_key(&[Some(n)], &[ColumnType::Float]).unwrap());
+        assert!(floats.windows(2).all(|pair| pair[0] < pair[1]));
+
+        // Composite keys sort column by column, whatever the text lengths, and NULL sorts first
+        let types = [ColumnType::Varchar(Some(10)), ColumnType::Integer];
+        let keys = [[None, Some("9")], [Some("a"), None], [Some("a"), Some("2")], [Some("a\0b"), Some("1")], [Some("ab"), Some("1")], [Some("b"), Some("0")]]
+            .map(|values| encode_key(&values, &types).unwrap());
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(encode_key(&[Some("abc")], &[ColumnType::Integer]).is_err());
+    }
+
+    #[test]
+    fn test_index_maintenance() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // CREATE INDEX builds over existing rows
+        engine.execute("CREATE INDEX users_age ON users (age)").unwrap();
+        assert_eq!(lookup(&engine, "users_age", &["30"]), 2);
+
+        // ... and the index follows every write
+        engine.execute("INSERT INTO users VALUES (4, 'cid@example.com', 25)").unwrap();
+        engine.execute("UPDATE users SET age = 31 WHERE id = 1").unwrap();
+        engine.execute("DELETE FROM users WHERE id = 2").unwrap();
+        assert_eq!(lookup(&engine, "users_age", &["25"]), 1);
+        assert_eq!(lookup(&engine, "users_age", &["30"]), 1);
+        assert_eq!(lookup(&engine, "users_age", &["31"]), 1);
+        assert_eq!(engine.get_index("users_age").unwrap().len(), 3);
+
+        // It is persisted, not rebuilt, on restart
+        drop(engine);
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert_eq!(lookup(&engine, "users_age", &["31"]), 1);
+        assert_eq!(engine.get_index("users_age").unwrap().len(), 3);
+
+        let index_dir = temp_dir.path().join("users").join("index_users_age");
+        assert!(index_dir.exists());
+        engine.execute("DROP INDEX users_age").unwrap();
+        assert!(engine.get_index("users_age").is_none());
+        assert!(!index_dir.exists());
+        assert!(matches!(engine.execute("DROP INDEX users_age"), Err(QueryError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_unique_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // Existing duplicates prevent the index from being created
+        assert!(matches!(engine.execute("CREATE UNIQUE INDEX users_age ON users (age)"), Err(QueryError::UniqueViolation(_))));
+        assert!(engine.get_index("users_age").is_none());
+
+        engine.execute("CREATE UNIQUE INDEX users_id_email ON users (id, email)").unwrap();
+        engine.execute("CREATE UNIQUE INDEX users_email ON users (email)").unwrap();
+        for query in [
+            "INSERT INTO users VALUES (5, 'ann@example.com', 40)",
+            "INSERT INTO users VALUES (5, 'x@example.com', 40), (6, 'x@example.com', 41)",
+            "UPDATE users SET email = 'bob@example.com' WHERE id = 1",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::UniqueViolation(_))), "{}", query);
+        }
+        // NULLs never clash, and a key may move between rows in one statement
+        engine.execute("INSERT INTO users VALUES (4, NULL, 20)").unwrap();
+        engine.execute("DELETE FROM users WHERE id = 1").unwrap();
+        engine.execute("INSERT INTO users VALUES (1, 'ann@example.com', 30)").unwrap();
+        assert_eq!(lookup(&engine, "users_id_email", &["1", "ann@example.com"]), 1);
+
+        for query in [
+            "CREATE INDEX users_email ON users (age)",
+            "CREATE INDEX users_x ON users (missing)",
+            "CREATE INDEX users_x ON missing (age)",
+            "CREATE INDEX users_x ON users (age, age)",
+            "CREATE INDEX users_x ON users",
+        ] {
+            assert!(engine.execute(query).is_err(), "{}", query);
+        }
+    }
+}
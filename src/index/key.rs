@@ -0,0 +1,58 @@
+use crate::metadata::{ColumnType, parse_timestamp};
+use super::error::IndexError;
+
+/// Encode column values into an index key whose byte order is the order of the values
+///
+/// Each value starts with a tag byte, 0 for NULL (so NULLs sort first) and 1 otherwise.
+/// Numbers are stored big-endian with their sign bit flipped, and text is terminated
+/// by 0x00 0x00 with any 0x00 inside it escaped as 0x00 0xFF, so that a key made of
+/// several columns sorts column by column and a prefix of columns is a prefix of the key.
+pub fn encode_key(values: &[Option<&str>], types: &[ColumnType]) -> Result<Vec<u8>, IndexError> {
+    let mut key = Vec::new();
+    for (value, data_type) in values.iter().zip(types) {
+        encode_value(&mut key, *value, data_type)?;
+    }
+    Ok(key)
+}
+
+fn encode_value(key: &mut Vec<u8>, value: Option<&str>, data_type: &ColumnType) -> Result<(), IndexError> {
+    let Some(value) = value else {
+        key.push(0);
+        return Ok(());
+    };
+    key.push(1);
+
+    let invalid = || IndexError::InvalidKey(format!("'{}' is not a valid {} value", value, data_type));
+    match data_type {
+        ColumnType::Integer => {
+            let number = value.parse::<i64>().map_err(|_| invalid())?;
+            key.extend(((number as u64) ^ (1 << 63)).to_be_bytes());
+        }
+        ColumnType::Timestamp => {
+            let seconds = parse_timestamp(value).ok_or_else(invalid)?;
+            key.extend(((seconds as u64) ^ (1 << 63)).to_be_bytes());
+        }
+        ColumnType::Float => {
+            let number = value.parse::<f64>().map_err(|_| invalid())?;
+            // Negative numbers have all bits flipped so that larger magnitudes sort lower
+            let bits = (number + 0.0).to_bits();
+            let ordered = if bits >> 63 == 1 { !bits } else { bits ^ (1 << 63) };
+            key.extend(ordered.to_be_bytes());
+        }
+        ColumnType::Boolean => match value.to_lowercase().as_str() {
+            "false" => key.push(0),
+            "true" => key.push(1),
+            _ => return Err(invalid()),
+        },
+        ColumnType::Varchar(_) => {
+            for &byte in value.as_bytes() {
+                key.push(byte);
+                if byte == 0 {
+                    key.push(0xFF);
+                }
+            }
+            key.extend([0, 0]);
+        }
+    }
+    Ok(())
+}
@@ -1,5 +1,10 @@
 pub mod error;
+pub mod fulltext;
+#[allow(clippy::module_inception)]
 pub mod index;
+pub mod key;
 
 pub use error::IndexError;
-pub use index::Index;
\ No newline at end of file
+pub use fulltext::{InvertedIndex, tokenize};
+pub use index::Index;
+pub use key::encode_key;
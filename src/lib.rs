@@ -6,6 +6,21 @@ pub mod storage;
 pub mod transaction;
 pub mod persistence_test;
 
+#[cfg(test)]
+mod test_util;
+
+pub use storage::block::Block;
+pub use storage::record::Record;
+pub use storage::table::Table as StorageTable;
+pub use metadata::Table as MetadataTable;
+pub use metadata::Schema;
+pub use metadata::Column;
+pub use query::QueryEngine;
+pub use query::QueryResult;
+pub use query::QueryError;
+pub use cli::CLI;
+pub use persistence_test::run_persistence_test;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -88,13 +103,11 @@ mod tests {
         
         // Check for SSTable files
         if let Ok(entries) = fs::read_dir(&users_dir) {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let name = entry.file_name();
-                    if let Some(name) = name.to_str() {
-                        if name.starts_with("sstable_") {
-                            println!("   💾 Found SSTable: {}", name);
-                        }
+            for entry in entries.flatten() {
+                let name = entry.file_name();
+                if let Some(name) = name.to_str() {
+                    if name.starts_with("sstable_") {
+                        println!("   💾 Found SSTable: {}", name);
                     }
                 }
             }
@@ -105,16 +118,4 @@ mod tests {
         println!("✅ Data survives engine restarts");
         println!("✅ LSM files are properly managed");
     }
-}
-
-pub use storage::block::Block;
-pub use storage::record::Record;
-pub use storage::table::Table as StorageTable;
-pub use metadata::Table as MetadataTable;
-pub use metadata::Schema;
-pub use metadata::Column;
-pub use query::QueryEngine;
-pub use query::QueryResult;
-pub use query::QueryError;
-pub use cli::CLI;
-pub use persistence_test::run_persistence_test;
\ No newline at end of file
+}
\ No newline at end of file
@@ -1,12 +1,5 @@
-mod storage;
-mod query;
-mod cli;
-mod metadata;
-mod transaction;
-mod index;
-mod persistence_test;
-
-use cli::CLI;
+use dms_try1::cli::CLI;
+use dms_try1::persistence_test;
 use std::env;
 
 fn main() {
@@ -1,15 +1,85 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use serde::{Serialize, Deserialize};
+use std::fmt;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ColumnType {
     Integer,
     Float,
-    Varchar(usize),
+    /// Maximum length in characters, none for VARCHAR without a length, as CAST(x AS VARCHAR) converts to
+    Varchar(Option<usize>),
     Boolean,
     Timestamp,
 }
 
+impl ColumnType {
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, ColumnType::Integer | ColumnType::Float | ColumnType::Timestamp)
+    }
+
+    /// Check whether a value of type `source` can be stored in a column of this type
+    pub fn accepts(&self, source: &ColumnType) -> bool {
+        matches!(
+            (self, source),
+            (ColumnType::Integer, ColumnType::Integer)
+                | (ColumnType::Float, ColumnType::Integer | ColumnType::Float)
+                | (ColumnType::Varchar(_), ColumnType::Varchar(_))
+                | (ColumnType::Boolean, ColumnType::Boolean)
+                | (ColumnType::Timestamp, ColumnType::Timestamp | ColumnType::Integer)
+        )
+    }
+}
+
+/// The SQL name of the type, as error messages show it
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnType::Integer => write!(f, "INTEGER"),
+            ColumnType::Float => write!(f, "FLOAT"),
+            ColumnType::Varchar(None) => write!(f, "VARCHAR"),
+            ColumnType::Varchar(Some(len)) => write!(f, "VARCHAR({})", len),
+            ColumnType::Boolean => write!(f, "BOOLEAN"),
+            ColumnType::Timestamp => write!(f, "TIMESTAMP"),
+        }
+    }
+}
+
+/// SQL name of an expression type, which is unknown until the query runs for NULL and scalar subqueries
+pub fn type_name(data_type: &Option<ColumnType>) -> String {
+    data_type.as_ref().map_or_else(|| "unknown".to_string(), ColumnType::to_string)
+}
+
+/// How timestamps are written in decoded rows and query output
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Parse a timestamp as seconds since the Unix epoch, in UTC
+/// Accepts a plain number of seconds, RFC 3339 and ISO-8601 dates and date-times
+/// Example: '2024-05-01 12:00:00', '2024-05-01T12:00:00+02:00', '2024-05-01'
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    let text = text.trim();
+    if let Ok(seconds) = text.parse::<i64>() {
+        return Some(seconds);
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return Some(datetime.timestamp());
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(datetime.and_utc().timestamp());
+        }
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| datetime.and_utc().timestamp())
+}
+
+/// Render seconds since the Unix epoch as a UTC date-time
+pub fn format_timestamp(seconds: i64) -> String {
+    DateTime::from_timestamp(seconds, 0)
+        .map(|datetime| datetime.format(TIMESTAMP_FORMAT).to_string())
+        .unwrap_or_else(|| seconds.to_string())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Column {
     pub name: String,
@@ -25,11 +95,36 @@ pub enum ColumnConstraint {
     NotNull,
     Unique,
     PrimaryKey,
-    ForeignKey(String, String), // (table_name, column_name)
-    Check(String), // SQL condition
+    ForeignKey(ForeignKey),
+    Check(CheckConstraint),
     Default(String), // Default value
 }
 
+/// What happens to referencing rows when the row they reference is deleted or its key changes
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ReferentialAction {
+    #[default]
+    Restrict,
+    Cascade,
+    SetNull,
+}
+
+/// A named condition that no row may make false, declared with CHECK
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckConstraint {
+    pub name: String,
+    pub condition: String, // SQL text of the condition
+}
+
+/// A column's reference to a UNIQUE or PRIMARY KEY column, declared with REFERENCES
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ForeignKey {
+    pub table: String,
+    pub column: String,
+    pub on_delete: ReferentialAction,
+    pub on_update: ReferentialAction,
+}
+
 impl Column {
     pub fn new(name: String, data_type: ColumnType) -> Self {
         let now = Utc::now();
@@ -57,20 +152,14 @@ impl Column {
         match self.data_type {
             ColumnType::Integer => value.parse::<i64>().is_ok(),
             ColumnType::Float => value.parse::<f64>().is_ok(),
-            ColumnType::Varchar(max_len) => {
-                let cleaned_value = value.trim_matches(|c| c == '\'' || c == '"');
-                cleaned_value.len() <= max_len
-            }
-            ColumnType::Boolean => {
-                let cleaned_value = value.trim_matches(|c| c == '\'' || c == '"').to_lowercase();
-                matches!(cleaned_value.as_str(), "true" | "false")
-            }
-            ColumnType::Timestamp => value.parse::<i64>().is_ok(),
+            ColumnType::Varchar(max_len) => max_len.is_none_or(|max_len| value.chars().count() <= max_len),
+            ColumnType::Boolean => matches!(value.to_lowercase().as_str(), "true" | "false"),
+            ColumnType::Timestamp => parse_timestamp(value).is_some(),
         }
     }
 
     pub fn is_nullable(&self) -> bool {
-        !self.constraints.iter().any(|c| matches!(c, ColumnConstraint::NotNull))
+        !self.constraints.iter().any(|c| matches!(c, ColumnConstraint::NotNull | ColumnConstraint::PrimaryKey))
     }
 
     pub fn is_unique(&self) -> bool {
@@ -80,4 +169,18 @@ impl Column {
     pub fn is_primary_key(&self) -> bool {
         self.constraints.iter().any(|c| matches!(c, ColumnConstraint::PrimaryKey))
     }
+
+    pub fn checks(&self) -> impl Iterator<Item = &CheckConstraint> {
+        self.constraints.iter().filter_map(|c| match c {
+            ColumnConstraint::Check(check) => Some(check),
+            _ => None,
+        })
+    }
+
+    pub fn foreign_key(&self) -> Option<&ForeignKey> {
+        self.constraints.iter().find_map(|c| match c {
+            ColumnConstraint::ForeignKey(foreign_key) => Some(foreign_key),
+            _ => None,
+        })
+    }
 } 
\ No newline at end of file
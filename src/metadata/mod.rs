@@ -2,6 +2,6 @@ mod table;
 mod schema;
 mod column;
 
-pub use table::Table;
+pub use table::{Table, IndexDefinition};
 pub use schema::Schema;
-pub use column::{Column, ColumnType}; 
\ No newline at end of file
+pub use column::{Column, ColumnType, ColumnConstraint, CheckConstraint, ForeignKey, ReferentialAction, parse_timestamp, format_timestamp, type_name, TIMESTAMP_FORMAT}; 
\ No newline at end of file
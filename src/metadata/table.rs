@@ -1,4 +1,4 @@
-use super::column::Column;
+use super::column::{Column, CheckConstraint};
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 
@@ -7,7 +7,21 @@ pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
     pub primary_key: Option<String>,
-    pub indexes: HashMap<String, Vec<usize>>,
+    pub indexes: HashMap<String, IndexDefinition>, // secondary indexes by name
+    #[serde(default)]
+    pub checks: Vec<CheckConstraint>, // table level CHECK constraints
+}
+
+/// A secondary index declared with CREATE INDEX, or one enforcing a PRIMARY KEY or UNIQUE column
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub columns: Vec<String>, // key columns, in key order
+    pub unique: bool,
+    #[serde(default)]
+    pub include: Vec<String>, // non-key columns stored in the index so it can answer queries alone
+    #[serde(default)]
+    pub fulltext: bool, // an inverted index over the words of its columns, searched with MATCH
 }
 
 impl Table {
@@ -17,6 +31,7 @@ impl Table {
             columns: Vec::new(),
             primary_key: None,
             indexes: HashMap::new(),
+            checks: Vec::new(),
         }
     }
 
@@ -28,17 +43,51 @@ impl Table {
         self.primary_key = Some(column_name);
     }
 
-    pub fn create_index(&mut self, column_name: String) {
-        self.indexes.insert(column_name, Vec::new());
+    pub fn create_index(&mut self, definition: IndexDefinition) {
+        self.indexes.insert(definition.name.clone(), definition);
+    }
+
+    pub fn get_column(&self, column_name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name == column_name)
     }
 
     pub fn get_column_index(&self, column_name: &str) -> Option<usize> {
         self.columns.iter().position(|c| c.name == column_name)
     }
 
+    /// Columns of this table that reference another table (or this one), with their positions
+    pub fn foreign_keys(&self) -> impl Iterator<Item = (usize, &super::ForeignKey)> {
+        self.columns.iter().enumerate()
+            .filter_map(|(index, column)| column.foreign_key().map(|foreign_key| (index, foreign_key)))
+    }
+
+    /// The unique indexes enforcing the PRIMARY KEY and UNIQUE columns, one per column
+    /// Example: users_pkey on the primary key id, users_email_key on a UNIQUE email column
+    pub fn key_indexes(&self) -> Vec<IndexDefinition> {
+        self.columns.iter()
+            .filter(|column| column.is_unique())
+            .map(|column| IndexDefinition {
+                name: if column.is_primary_key() {
+                    format!("{}_pkey", self.name)
+                } else {
+                    format!("{}_{}_key", self.name, column.name)
+                },
+                columns: vec![column.name.clone()],
+                unique: true,
+                include: Vec::new(),
+                fulltext: false,
+            })
+            .collect()
+    }
+
+    /// Every CHECK constraint of the table, those declared on columns first
+    pub fn check_constraints(&self) -> impl Iterator<Item = &CheckConstraint> {
+        self.columns.iter().flat_map(|column| column.checks()).chain(&self.checks)
+    }
+
     pub fn get_column_offset(&self, column_index: usize) -> usize {
         let mut offset = 0;
-        for (i, column) in self.columns.iter().enumerate() {
+        for i in 0..self.columns.len() {
             if i == column_index {
                 break;
             }
@@ -52,7 +101,7 @@ impl Table {
             match column.data_type {
                 super::ColumnType::Integer => 8,
                 super::ColumnType::Float => 8,
-                super::ColumnType::Varchar(len) => 4 + len, // 4 bytes for length + data
+                super::ColumnType::Varchar(len) => 4 + len.unwrap_or(0), // 4 bytes for length + data
                 super::ColumnType::Boolean => 1,
                 super::ColumnType::Timestamp => 8,
             }
@@ -155,15 +155,15 @@ pub fn test_database_persistence() -> Result<(), Box<dyn std::error::Error>> {
         
         // Test complex query persistence
         let user_count = engine.execute("SELECT * FROM users")?;
-        if let QueryResult::Select(rows) = user_count {
-            println!("📊 Total users persisted: {}", rows.len());
-            assert_eq!(rows.len(), 6, "Should have 6 users total");
+        if let QueryResult::Select(result) = user_count {
+            println!("📊 Total users persisted: {}", result.rows.len());
+            assert_eq!(result.rows.len(), 6, "Should have 6 users total");
         }
         
         let order_count = engine.execute("SELECT * FROM orders")?;
-        if let QueryResult::Select(rows) = order_count {
-            println!("📊 Total orders persisted: {}", rows.len());
-            assert_eq!(rows.len(), 6, "Should have 6 orders total");
+        if let QueryResult::Select(result) = order_count {
+            println!("📊 Total orders persisted: {}", result.rows.len());
+            assert_eq!(result.rows.len(), 6, "Should have 6 orders total");
         }
     }
     
@@ -196,12 +196,10 @@ pub fn test_database_persistence() -> Result<(), Box<dyn std::error::Error>> {
     for table_dir in [&users_dir, &orders_dir] {
         if let Ok(entries) = fs::read_dir(table_dir) {
             println!("📂 Contents of {}:", table_dir);
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let file_name = entry.file_name();
-                    let file_size = entry.metadata().map(|m| m.len()).unwrap_or(0);
-                    println!("   📄 {} ({} bytes)", file_name.to_string_lossy(), file_size);
-                }
+            for entry in entries.flatten() {
+                let file_name = entry.file_name();
+                let file_size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                println!("   📄 {} ({} bytes)", file_name.to_string_lossy(), file_size);
             }
         }
     }
@@ -242,9 +240,36 @@ pub fn run_persistence_test() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::query::QueryError;
 
     #[test]
     fn test_persistence_integration() {
         test_database_persistence().expect("Persistence test should pass");
     }
+
+    #[test]
+    fn test_unrestored_table_keeps_its_metadata() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().to_str().unwrap();
+        {
+            let mut engine = QueryEngine::new_with_data_dir(data_dir);
+            engine.execute("CREATE TABLE kept (id INTEGER)").unwrap();
+            engine.execute("CREATE TABLE broken (id INTEGER)").unwrap();
+            engine.execute("INSERT INTO kept VALUES (1)").unwrap();
+        }
+        // The storage of one table can't be opened
+        fs::remove_dir_all(temp_dir.path().join("broken")).unwrap();
+        fs::write(temp_dir.path().join("broken"), "not a directory").unwrap();
+
+        // The other tables work, but nothing may rewrite the metadata and drop the broken one
+        let mut engine = QueryEngine::new_with_data_dir(data_dir);
+        assert!(matches!(engine.execute("SELECT * FROM kept").unwrap(), QueryResult::Select(result) if result.rows.len() == 1));
+        engine.execute("INSERT INTO kept VALUES (2)").unwrap();
+        match engine.execute("CREATE TABLE added (id INTEGER)") {
+            Err(QueryError::InternalError(message)) => assert!(message.contains("'broken'"), "{}", message),
+            other => panic!("Expected the metadata to stay untouched, got {:?}", other.map(|_| ())),
+        }
+        let metadata = fs::read_to_string(temp_dir.path().join("tables.json")).unwrap();
+        assert!(metadata.contains("\"broken\"") && !metadata.contains("\"added\""));
+    }
 } 
\ No newline at end of file
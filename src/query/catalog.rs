@@ -0,0 +1,24 @@
+use std::collections::HashMap;
+use crate::metadata::Table;
+use super::record::Row;
+
+/// The decoded rows of every table a statement reads, loaded up front so that
+/// subqueries can scan tables without going back to the storage engines
+#[derive(Default)]
+pub struct Catalog {
+    tables: HashMap<String, (Table, Vec<Row>)>,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Catalog { tables: HashMap::new() }
+    }
+
+    pub fn add_table(&mut self, table: Table, rows: Vec<Row>) {
+        self.tables.insert(table.name.clone(), (table, rows));
+    }
+
+    pub fn get_table(&self, name: &str) -> Option<(&Table, &[Row])> {
+        self.tables.get(name).map(|(table, rows)| (table, rows.as_slice()))
+    }
+}
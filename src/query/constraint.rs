@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+use crate::metadata::Table;
+use super::error::QueryError;
+use super::record::Row;
+use super::parser::{ExpressionParser, Lexer, RowContext};
+
+/// Checks rows against the constraints declared on their table
+/// Rows are given in decoded form, in table column order
+pub struct ConstraintChecker;
+
+impl Default for ConstraintChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConstraintChecker {
+    pub fn new() -> Self {
+        ConstraintChecker
+    }
+
+    /// Check the constraints that only depend on the row itself: NOT NULL and CHECK
+    /// The row must be normalized, as CHECK conditions read its values the way queries do
+    pub fn check_row(&self, row: &[Option<String>], table: &Table) -> Result<(), QueryError> {
+        for (value, column) in row.iter().zip(&table.columns) {
+            if value.is_none() && !column.is_nullable() {
+                let constraint = if column.is_primary_key() { "PRIMARY KEY" } else { "NOT NULL" };
+                return Err(QueryError::NotNullViolation(format!(
+                    "{} column {}.{} cannot be NULL", constraint, table.name, column.name
+                )));
+            }
+        }
+
+        // A condition that comes out NULL doesn't fail the check
+        for check in table.check_constraints() {
+            let condition = ExpressionParser::new().parse_tokens(&Lexer::new().tokenize(&check.condition)?)?;
+            if !condition.is_allowed(&RowContext::new(table, row))? {
+                return Err(QueryError::CheckViolation(format!(
+                    "Row of {} fails CHECK constraint {} ({})", table.name, check.name, check.condition
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether any column needs the whole table to be checked
+    pub fn has_unique_columns(&self, table: &Table) -> bool {
+        table.columns.iter().any(|column| column.is_unique())
+    }
+
+    /// Check that UNIQUE and PRIMARY KEY columns hold no value twice
+    /// `rows` is the whole content of the table as it would be after the statement; NULLs never clash
+    pub fn check_unique(&self, rows: &[Row], table: &Table) -> Result<(), QueryError> {
+        for (index, column) in table.columns.iter().enumerate() {
+            if !column.is_unique() {
+                continue;
+            }
+
+            let mut seen = HashSet::new();
+            for row in rows {
+                let Some(value) = &row[index] else {
+                    continue;
+                };
+                if !seen.insert(value) {
+                    let constraint = if column.is_primary_key() { "PRIMARY KEY" } else { "UNIQUE" };
+                    return Err(QueryError::UniqueViolation(format!(
+                        "{} column {}.{} already contains '{}'", constraint, table.name, column.name, value
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryEngine, QueryError};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, email VARCHAR(30) NOT NULL UNIQUE, \
+            name VARCHAR 20, active BOOLEAN DEFAULT TRUE, credit FLOAT DEFAULT -1.5)",
+        "INSERT INTO users (id, email) VALUES (1, 'ann@example.com')",
+        "INSERT INTO users VALUES (2, 'bob@example.com', 'Bob', false, 10.0)",
+    ];
+
+    #[test]
+    fn test_defaults_and_not_null() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert_eq!(rows(&mut engine, "SELECT * FROM users ORDER BY id"), vec![
+            vec!["1", "ann@example.com", "NULL", "true", "-1.5"],
+            vec!["2", "bob@example.com", "Bob", "false", "10"],
+        ]);
+
+        for query in [
+            "INSERT INTO users (id, name) VALUES (3, 'Cid')",
+            "INSERT INTO users VALUES (NULL, 'cid@example.com', 'Cid', true, 0.0)",
+            "UPDATE users SET email = NULL WHERE id = 2",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::NotNullViolation(_))), "{}", query);
+        }
+        assert_eq!(rows(&mut engine, "SELECT COUNT(*) FROM users WHERE email IS NOT NULL"), vec![vec!["2"]]);
+    }
+
+    #[test]
+    fn test_unique_and_primary_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        for query in [
+            "INSERT INTO users (id, email) VALUES (1, 'other@example.com')",
+            "INSERT INTO users (id, email) VALUES (3, 'bob@example.com')",
+            // Rows of the same statement clash with each other too, and none of them is written
+            "INSERT INTO users (id, email) VALUES (3, 'cid@example.com'), (4, 'cid@example.com')",
+            "UPDATE users SET id = 1 WHERE id = 2",
+            "UPDATE users SET email = 'same@example.com'",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::UniqueViolation(_))), "{}", query);
+        }
+        assert_eq!(rows(&mut engine, "SELECT id, email FROM users ORDER BY id"), vec![
+            vec!["1", "ann@example.com"],
+            vec!["2", "bob@example.com"],
+        ]);
+
+        // Swapping keys is fine, as uniqueness is checked on the table after the statement
+        engine.execute("UPDATE users SET id = 3 - id").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT id, email FROM users ORDER BY id"), vec![
+            vec!["1", "bob@example.com"],
+            vec!["2", "ann@example.com"],
+        ]);
+
+        // NULLs never clash in a UNIQUE column
+        engine.execute("CREATE TABLE tags (id INTEGER, label VARCHAR 10 UNIQUE, PRIMARY KEY (id))").unwrap();
+        engine.execute("INSERT INTO tags VALUES (1, NULL), (2, NULL), (3, 'x')").unwrap();
+        assert!(matches!(engine.execute("INSERT INTO tags VALUES (1, 'y')"), Err(QueryError::UniqueViolation(_))));
+    }
+
+    #[test]
+    fn test_unique_checked_at_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        let other = engine.open_session();
+
+        // Neither transaction sees the other's row, so the clash only shows when the second one commits
+        engine.execute("BEGIN").unwrap();
+        engine.execute("INSERT INTO users (id, email) VALUES (3, 'cid@example.com')").unwrap();
+        engine.execute_in(other, "BEGIN").unwrap();
+        engine.execute_in(other, "INSERT INTO users (id, email) VALUES (4, 'cid@example.com')").unwrap();
+        engine.execute("COMMIT").unwrap();
+        assert!(matches!(engine.execute_in(other, "COMMIT"), Err(QueryError::UniqueViolation(_))));
+        assert_eq!(rows(&mut engine, "SELECT id FROM users WHERE email = 'cid@example.com'"), vec![vec!["3"]]);
+
+        // The columns are enforced by unique indexes, which go with the table only
+        assert_eq!(
+            rows(&mut engine, "EXPLAIN SELECT * FROM users WHERE email = 'cid@example.com'"),
+            vec![vec!["Index Lookup on users using users_email_key (email = 'cid@example.com')"]]
+        );
+        assert!(matches!(engine.execute("DROP INDEX users_pkey"), Err(QueryError::InvalidValue(_))));
+        engine.execute("CREATE INDEX notes_body_key ON users (name)").unwrap();
+        assert!(matches!(engine.execute("CREATE TABLE notes (id INTEGER, body VARCHAR 10 UNIQUE)"), Err(QueryError::DuplicateKey(_))));
+    }
+
+    #[test]
+    fn test_constraint_syntax_and_persistence() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        for query in [
+            "CREATE TABLE t1 (a INTEGER PRIMARY KEY, b INTEGER PRIMARY KEY)",
+            "CREATE TABLE t2 (a INTEGER NOT)",
+            "CREATE TABLE t3 (a INTEGER DEFAULT)",
+            "CREATE TABLE t4 (a INTEGER, b INTEGER, UNIQUE (a, b))",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+        assert!(matches!(
+            engine.execute("CREATE TABLE t5 (a INTEGER DEFAULT 'abc')"),
+            Err(QueryError::TypeMismatch(_))
+        ));
+
+        // Constraints are saved with the table metadata
+        drop(engine);
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert!(matches!(
+            engine.execute("INSERT INTO users (id, email) VALUES (2, 'new@example.com')"),
+            Err(QueryError::UniqueViolation(_))
+        ));
+        engine.execute("INSERT INTO users (id, email) VALUES (3, 'new@example.com')").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT active, credit FROM users WHERE id = 3"), vec![vec!["true", "-1.5"]]);
+    }
+
+    #[test]
+    fn test_check_constraints() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        engine.execute("CREATE TABLE products (id INTEGER PRIMARY KEY, name VARCHAR 20 CHECK (LENGTH(name) > 1), \
+                        price FLOAT CONSTRAINT positive_price CHECK (price >= 0), quantity INTEGER, \
+                        CHECK (price >= 0 AND quantity > 0), CHECK (quantity < 1000))").unwrap();
+        engine.execute("INSERT INTO products VALUES (1, 'Pen', 1.5, 10), (2, 'Ink', NULL, 5)").unwrap();
+
+        // Each violation names the constraint, given or generated
+        for (query, name) in [
+            ("INSERT INTO products VALUES (3, 'Cap', -1.0, 1)", "positive_price"),
+            ("INSERT INTO products VALUES (3, 'X', 1.0, 1)", "products_name_check"),
+            ("INSERT INTO products VALUES (3, 'Cap', 1.0, 0)", "products_price_check"),
+            ("UPDATE products SET quantity = quantity * 100 WHERE id = 1", "products_quantity_check"),
+        ] {
+            match engine.execute(query) {
+                Err(QueryError::CheckViolation(message)) => assert!(message.contains(name), "{}: {}", query, message),
+                other => panic!("{}: expected a check violation, got {:?}", query, other.map(|_| ())),
+            }
+        }
+
+        // A condition that comes out NULL passes
+        engine.execute("UPDATE products SET price = NULL, quantity = 20 WHERE id = 1").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT id, price, quantity FROM products ORDER BY id"), vec![
+            vec!["1", "NULL", "20"],
+            vec!["2", "NULL", "5"],
+        ]);
+
+        for query in [
+            "CREATE TABLE t1 (a INTEGER CHECK a > 0)",
+            "CREATE TABLE t2 (a INTEGER CHECK (a > 0)",
+            "CREATE TABLE t3 (a INTEGER, CHECK (a > (SELECT MAX(a) FROM products)))",
+            "CREATE TABLE t4 (a INTEGER, CHECK (COUNT(*) > 0))",
+            "CREATE TABLE t5 (a INTEGER CONSTRAINT)",
+            "CREATE TABLE t9 (a INTEGER CHECK ())",
+            "CREATE TABLE t10 (a INTEGER, CHECK)",
+            "CREATE TABLE t11 (a INTEGER CHECK (a >))",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+        assert!(matches!(engine.execute("CREATE TABLE t6 (a INTEGER CHECK (a + 1))"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(engine.execute("CREATE TABLE t7 (a INTEGER CHECK (b > 0))"), Err(QueryError::ColumnNotFound(_))));
+        assert!(matches!(
+            engine.execute("CREATE TABLE t8 (a INTEGER CONSTRAINT c CHECK (a > 0), CONSTRAINT c CHECK (a < 9))"),
+            Err(QueryError::DuplicateKey(_))
+        ));
+
+        // IS NULL still decides on a NULL operand, and an empty table has nothing to check
+        engine.execute("CREATE TABLE ranges (low INTEGER, high INTEGER, CHECK (low < high), CHECK (low IS NOT NULL OR high IS NULL))").unwrap();
+        engine.execute("UPDATE ranges SET low = 10, high = 0").unwrap();
+        engine.execute("INSERT INTO ranges VALUES (1, NULL), (NULL, NULL)").unwrap();
+        assert!(matches!(engine.execute("INSERT INTO ranges VALUES (NULL, 5)"), Err(QueryError::CheckViolation(_))));
+        // One failing row rejects the whole statement
+        assert!(matches!(engine.execute("INSERT INTO ranges VALUES (2, 3), (3, 2)"), Err(QueryError::CheckViolation(_))));
+        assert_eq!(rows(&mut engine, "SELECT COUNT(*) FROM ranges"), vec![vec!["2"]]);
+
+        // Constraints are saved with the table metadata
+        drop(engine);
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert!(matches!(engine.execute("INSERT INTO products VALUES (3, 'Cap', -1.0, 1)"), Err(QueryError::CheckViolation(_))));
+    }
+}
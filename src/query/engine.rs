@@ -1,22 +1,49 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
-use crate::metadata::Table;
-use crate::storage::{LSMEngine, Record};
+use crate::metadata::{Table, ColumnConstraint, IndexDefinition};
+use crate::storage::{LSMEngine, ReadView};
+use crate::index::Index;
+use crate::transaction::{IsolationLevel, Transaction, TransactionError, TransactionLog, TransactionWrite};
+use super::catalog::Catalog;
 use super::error::QueryError;
-use super::result::QueryResult;
-use super::parser::{SelectParser, InsertParser, UpdateParser, DeleteParser, CreateParser};
+use super::foreign_key::ForeignKeyEnforcer;
+use super::planner::{AccessPlanner, AccessPath};
+use super::record::{RecordCodec, Row};
+use super::result::{QueryResult, SelectResult};
+use super::parser::{SelectParser, InsertParser, UpdateParser, DeleteParser, CreateParser, IndexParser, FromParser, JoinParser, Lexer};
 
 // TODO: AST mode for tree and plan execution + DEBUG MODE
 
+/// Identifies a session of an engine; each runs its own transactions
+pub type SessionId = u64;
+
+/// The session `QueryEngine::execute` runs statements in
+const DEFAULT_SESSION: SessionId = 0;
+
 pub struct QueryEngine {
     tables: HashMap<String, Table>,
     storage_engines: HashMap<String, LSMEngine>,
+    indexes: HashMap<String, Vec<Index>>, // secondary indexes by table name
     select_parser: SelectParser,
     insert_parser: InsertParser,
     update_parser: UpdateParser,
     delete_parser: DeleteParser,
     create_parser: CreateParser,
+    index_parser: IndexParser,
+    planner: AccessPlanner,
+    sessions: HashMap<SessionId, Transaction>,
+    next_session: SessionId,
+    next_transaction: u64,
+    commit_sequence: u64, // sequence number of the latest commit
+    transaction_log: TransactionLog,
     data_dir: String,
+    restore_error: Option<String>, // why stored tables are missing, which keeps their metadata from being rewritten
+}
+
+impl Default for QueryEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl QueryEngine {
@@ -28,17 +55,27 @@ impl QueryEngine {
         let mut engine = QueryEngine {
             tables: HashMap::new(),
             storage_engines: HashMap::new(),
+            indexes: HashMap::new(),
             select_parser: SelectParser::new(),
             insert_parser: InsertParser::new(),
             update_parser: UpdateParser::new(),
             delete_parser: DeleteParser::new(),
             create_parser: CreateParser::new(),
+            index_parser: IndexParser::new(),
+            planner: AccessPlanner::new(),
+            sessions: HashMap::from([(DEFAULT_SESSION, Transaction::new())]),
+            next_session: DEFAULT_SESSION,
+            next_transaction: 0,
+            commit_sequence: 0,
+            transaction_log: TransactionLog::new(&format!("{}/transaction.log", data_dir)),
             data_dir: data_dir.to_string(),
+            restore_error: None,
         };
         
         // Load existing tables and their storage engines
         if let Err(e) = engine.load_existing_tables() {
             eprintln!("Warning: Failed to load existing tables: {}", e);
+            engine.restore_error = Some(e.to_string());
         }
         
         engine
@@ -62,6 +99,7 @@ impl QueryEngine {
             Ok(content) => {
                 match serde_json::from_str::<HashMap<String, Table>>(&content) {
                     Ok(loaded_tables) => {
+                        let mut metadata_changed = false;
                         for (table_name, table) in loaded_tables {
                             // Create LSM storage engine for this table
                             let table_data_dir = format!("{}/{}", self.data_dir, table_name);
@@ -69,13 +107,19 @@ impl QueryEngine {
                                 Ok(storage_engine) => {
                                     self.tables.insert(table_name.clone(), table);
                                     self.storage_engines.insert(table_name.clone(), storage_engine);
+                                    self.load_indexes(&table_name)?;
+                                    metadata_changed |= self.create_key_indexes(&table_name)?;
                                     println!("Restored table: {}", table_name);
                                 }
                                 Err(e) => {
                                     eprintln!("Warning: Failed to restore table '{}': {}", table_name, e);
+                                    self.restore_error = Some(format!("table '{}' could not be restored: {}", table_name, e));
                                 }
                             }
                         }
+                        if metadata_changed && self.restore_error.is_none() {
+                            self.save_table_metadata()?;
+                        }
                     }
                     Err(e) => {
                         return Err(QueryError::InternalError(format!("Failed to parse table metadata: {}", e)));
@@ -87,11 +131,138 @@ impl QueryEngine {
             }
         }
 
+        // Commits carry on from the last one stored with the rows
+        self.commit_sequence = self.storage_engines.values().map(LSMEngine::last_commit).max().unwrap_or(0);
+        self.recover_transaction()
+    }
+
+    /// Finish a transaction that committed before all of its writes reached the tables
+    /// Its writes are applied again as the commit they were logged with, deletes included,
+    /// so they reach the write logs and SSTables of the tables like those of any commit
+    fn recover_transaction(&mut self) -> Result<(), QueryError> {
+        let Some((sequence, writes)) = self.transaction_log.recover()? else {
+            return Ok(());
+        };
+        let recovery_error = |e: std::io::Error| QueryError::InternalError(format!("Failed to recover transaction: {}", e));
+
+        let transaction = self.new_transaction_id();
+        let mut written = BTreeSet::new();
+        for write in writes {
+            let Some(storage_engine) = self.storage_engines.get_mut(&write.table) else {
+                continue;
+            };
+            storage_engine.set_view(Some(ReadView { transaction, snapshot: sequence }));
+            let result = match write.data {
+                Some(data) => storage_engine.update(write.id, data),
+                None => storage_engine.delete(write.id),
+            };
+            storage_engine.set_view(None);
+            result.map_err(recovery_error)?;
+            written.insert(write.table);
+        }
+        for storage_engine in self.storage_engines.values_mut() {
+            storage_engine.commit_transaction(transaction, sequence).map_err(recovery_error)?;
+        }
+        self.commit_sequence = self.commit_sequence.max(sequence);
+
+        for table_name in &written {
+            self.sync_indexes(table_name)?;
+        }
+        Ok(self.transaction_log.clear()?)
+    }
+
+    /// Decoded rows of a table, by record ID
+    fn table_rows(&mut self, table_name: &str) -> Result<Vec<(u64, Row)>, QueryError> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| QueryError::TableNotFound(table_name.to_string()))?;
+        let storage_engine = self.storage_engines.get_mut(table_name)
+            .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", table_name)))?;
+        let codec = RecordCodec::new();
+        storage_engine.get_all_records()
+            .map_err(|e| QueryError::InternalError(format!("Failed to get records of table {}: {}", table_name, e)))?
+            .iter()
+            .map(|record| Ok((record.id, codec.decode(record, table)?)))
+            .collect()
+    }
+
+    /// Bring the indexes of a table back in line with its rows
+    fn sync_indexes(&mut self, table_name: &str) -> Result<(), QueryError> {
+        let rows = self.table_rows(table_name)?;
+        let live: HashSet<u64> = rows.iter().map(|(id, _)| *id).collect();
+        for index in self.indexes.get_mut(table_name).into_iter().flatten() {
+            index.retain_records(&live)?;
+            for (id, row) in &rows {
+                index.insert(*id, row)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Directory of an index's keyspace, inside the directory of its table
+    fn index_data_dir(&self, table_name: &str, index_name: &str) -> String {
+        format!("{}/{}/index_{}", self.data_dir, table_name, index_name)
+    }
+
+    /// Open the indexes of a restored table
+    /// Entries of records deleted after the index was last flushed are dropped again
+    fn load_indexes(&mut self, table_name: &str) -> Result<(), QueryError> {
+        let table = &self.tables[table_name];
+        let storage_engine = self.storage_engines.get_mut(table_name)
+            .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", table_name)))?;
+        let live: HashSet<u64> = storage_engine.get_all_records()
+            .map_err(|e| QueryError::InternalError(format!("Failed to get records of table {}: {}", table_name, e)))?
+            .iter()
+            .map(|record| record.id)
+            .collect();
+
+        let mut indexes = Vec::new();
+        for definition in table.indexes.values() {
+            let mut index = Index::open(definition.clone(), table, &self.index_data_dir(table_name, &definition.name))?;
+            index.retain_records(&live)?;
+            indexes.push(index);
+        }
+        indexes.sort_by(|a, b| a.name().cmp(b.name()));
+        self.indexes.insert(table_name.to_string(), indexes);
         Ok(())
     }
 
+    /// Give the PRIMARY KEY and UNIQUE columns of a table the unique indexes enforcing them,
+    /// building those it lacks from its rows. Returns whether any was added
+    fn create_key_indexes(&mut self, table_name: &str) -> Result<bool, QueryError> {
+        let table = &self.tables[table_name];
+        let missing: Vec<IndexDefinition> = table.key_indexes().into_iter()
+            .filter(|definition| !table.indexes.contains_key(&definition.name))
+            .collect();
+        if missing.is_empty() {
+            return Ok(false);
+        }
+
+        let rows = self.table_rows(table_name)?;
+        for definition in missing {
+            let data_dir = self.index_data_dir(table_name, &definition.name);
+            let _ = fs::remove_dir_all(&data_dir);
+            let mut index = Index::open(definition.clone(), &self.tables[table_name], &data_dir)?;
+            if let Err(e) = index.build(&rows) {
+                let _ = index.destroy();
+                return Err(e.into());
+            }
+
+            if let Some(table) = self.tables.get_mut(table_name) {
+                table.create_index(definition);
+            }
+            let indexes = self.indexes.entry(table_name.to_string()).or_default();
+            indexes.push(index);
+            indexes.sort_by(|a, b| a.name().cmp(b.name()));
+        }
+        Ok(true)
+    }
+
     /// Save table metadata to disk
     fn save_table_metadata(&self) -> Result<(), QueryError> {
+        // Tables that failed to restore would be missing from the rewritten metadata, and lost
+        if let Some(reason) = &self.restore_error {
+            return Err(QueryError::InternalError(format!("Table metadata can't be changed, as {}", reason)));
+        }
         let metadata_path = format!("{}/tables.json", self.data_dir);
         
         match serde_json::to_string_pretty(&self.tables) {
@@ -108,294 +279,340 @@ impl QueryEngine {
         Ok(())
     }
 
+    /// Run a statement in the default session
     pub fn execute(&mut self, query: &str) -> Result<QueryResult, QueryError> {
-        let tokens: Vec<&str> = query.split_whitespace().collect();
-        if tokens.is_empty() {
-            return Err(QueryError::SyntaxError("Empty query".to_string()));
-        }
+        self.execute_in(DEFAULT_SESSION, query)
+    }
 
-        match tokens[0].to_uppercase().as_str() {
-            "SELECT" => self.execute_select(&tokens),
-            "INSERT" => self.execute_insert(&tokens),
-            "UPDATE" => self.execute_update(&tokens),
-            "DELETE" => self.execute_delete(&tokens),
-            "CREATE" => self.execute_create(&tokens),
-            "DROP" => self.execute_drop(&tokens),
-            _ => Err(QueryError::SyntaxError(format!("Unknown command: {}", tokens[0]))),
-        }
+    /// Open another session, whose transactions run alongside those of the others
+    pub fn open_session(&mut self) -> SessionId {
+        self.next_session += 1;
+        self.sessions.insert(self.next_session, Transaction::new());
+        self.next_session
     }
 
-    fn execute_select(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
-        if tokens.len() < 4 {
-            return Err(QueryError::SyntaxError("Invalid SELECT syntax".to_string()));
+    /// Roll back the open transaction of a session, and close it unless it is the default one
+    pub fn close_session(&mut self, session: SessionId) -> Result<(), QueryError> {
+        let transaction = self.session(session)?;
+        if transaction.is_active() {
+            let id = transaction.id();
+            transaction.rollback()?;
+            self.discard_writes(id);
+        }
+        if session != DEFAULT_SESSION {
+            self.sessions.remove(&session);
         }
+        Ok(())
+    }
 
-        // Check if this is a JOIN query
-        let has_join = tokens.iter().any(|&t| t.to_uppercase() == "JOIN");
+    fn session(&mut self, session: SessionId) -> Result<&mut Transaction, QueryError> {
+        self.sessions.get_mut(&session)
+            .ok_or_else(|| TransactionError::ExecutionError(format!("Unknown session {}", session)).into())
+    }
 
-        if has_join {
-            // Handle JOIN query with multiple tables
-            self.execute_join_select(tokens)
-        } else {
-            // Handle single table SELECT
-            let from_index = if tokens[1] == "*" { 2 } else {
-                tokens.iter()
-                    .position(|&t| t.to_uppercase() == "FROM")
-                    .ok_or_else(|| QueryError::SyntaxError("Expected FROM clause".to_string()))?
-            };
+    /// Run a statement in a session
+    /// Outside BEGIN ... COMMIT each statement is a transaction of its own
+    pub fn execute_in(&mut self, session: SessionId, query: &str) -> Result<QueryResult, QueryError> {
+        let tokens = Lexer::new().split_statement(query);
+        if tokens.is_empty() {
+            return Err(QueryError::SyntaxError("Empty query".to_string()));
+        }
+        self.session(session)?;
+
+        let command = tokens[0].to_uppercase();
+        match command.as_str() {
+            "BEGIN" | "START" => self.execute_begin(session, &tokens),
+            "COMMIT" => self.execute_commit(session, &tokens),
+            "ROLLBACK" => self.execute_rollback(session, &tokens),
+            "SET" => self.execute_set(session, &tokens),
+            "CREATE" | "DROP" => {
+                if self.sessions.values().any(Transaction::is_active) {
+                    return Err(TransactionError::ExecutionError(format!("{} is not allowed while a transaction is open", command)).into());
+                }
+                match command.as_str() {
+                    "CREATE" => self.execute_create(&tokens),
+                    _ => self.execute_drop(&tokens),
+                }
+            }
+            _ => self.execute_statement(session, &command, &tokens),
+        }
+    }
 
-            let table_name = tokens[from_index + 1];
-            let table = self.tables.get(table_name)
-                .ok_or_else(|| QueryError::TableNotFound(table_name.to_string()))?;
+    /// Run a statement that reads or writes rows, as part of the session's transaction
+    fn execute_statement(&mut self, session: SessionId, command: &str, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        let latest = self.commit_sequence;
+        let transaction = self.session(session)?;
+        let explicit = transaction.is_active();
+        let view = if explicit {
+            ReadView { transaction: transaction.id(), snapshot: transaction.statement_snapshot(latest) }
+        } else {
+            ReadView { transaction: self.new_transaction_id(), snapshot: latest }
+        };
 
-            let storage_engine = self.storage_engines.get_mut(table_name)
-                .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", table_name)))?;
+        self.set_view(Some(view));
+        let result = match command {
+            "SELECT" | "WITH" => self.execute_select(tokens),
+            "INSERT" => self.execute_insert(tokens),
+            "UPDATE" => self.execute_update(tokens),
+            "DELETE" => self.execute_delete(tokens),
+            "EXPLAIN" => self.execute_explain(&tokens[1..]),
+            _ => Err(QueryError::SyntaxError(format!("Unknown command: {}", tokens[0]))),
+        };
+        self.set_view(None);
 
-            self.select_parser.parse_and_execute_lsm(tokens, table, storage_engine)
+        if explicit {
+            return result;
+        }
+        match result {
+            Ok(result) => {
+                self.commit_writes(view.transaction, view.snapshot, false)?;
+                Ok(result)
+            }
+            Err(e) => {
+                self.discard_writes(view.transaction);
+                Err(e)
+            }
         }
     }
 
-    fn execute_join_select(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
-        // Parse table names from JOIN query
-        // Expected format: SELECT ... FROM table1 INNER JOIN table2 ON ...
-        
-        let from_index = tokens.iter()
-            .position(|&t| t.to_uppercase() == "FROM")
-            .ok_or_else(|| QueryError::SyntaxError("Expected FROM clause".to_string()))?;
-
-        let join_index = tokens.iter()
-            .position(|&t| t.to_uppercase() == "JOIN")
-            .ok_or_else(|| QueryError::SyntaxError("Expected JOIN clause".to_string()))?;
+    fn new_transaction_id(&mut self) -> u64 {
+        self.next_transaction += 1;
+        self.next_transaction
+    }
 
-        if from_index + 1 >= tokens.len() || join_index + 1 >= tokens.len() {
-            return Err(QueryError::SyntaxError("Invalid JOIN syntax".to_string()));
+    /// Make tables and indexes read and write as the given transaction, or directly with None
+    fn set_view(&mut self, view: Option<ReadView>) {
+        for storage_engine in self.storage_engines.values_mut() {
+            storage_engine.set_view(view);
+        }
+        for index in self.indexes.values_mut().flatten() {
+            index.set_view(view.map(|view| view.transaction));
         }
+    }
 
-        let left_table_name = tokens[from_index + 1];
-        let right_table_name = tokens[join_index + 1];
+    /// Parse BEGIN [TRANSACTION | WORK] [ISOLATION LEVEL level], START TRANSACTION [ISOLATION LEVEL level],
+    /// COMMIT [TRANSACTION | WORK] or ROLLBACK [TRANSACTION | WORK], returning the isolation level given
+    fn parse_transaction_statement(tokens: &[&str]) -> Result<Option<IsolationLevel>, QueryError> {
+        let command = tokens[0].to_uppercase();
+        let syntax_error = || QueryError::SyntaxError(format!("Invalid {} syntax", command));
+        let mut rest = &tokens[1..];
+        match rest.first().map(|word| word.to_uppercase()) {
+            Some(word) if word == "TRANSACTION" || (word == "WORK" && command != "START") => rest = &rest[1..],
+            _ if command == "START" => return Err(syntax_error()),
+            _ => {}
+        }
 
-        // Clone table metadata to avoid borrowing conflicts
-        let left_table = self.tables.get(left_table_name)
-            .ok_or_else(|| QueryError::TableNotFound(left_table_name.to_string()))?
-            .clone();
-        let right_table = self.tables.get(right_table_name)
-            .ok_or_else(|| QueryError::TableNotFound(right_table_name.to_string()))?
-            .clone();
+        match rest {
+            [] => Ok(None),
+            [isolation, level, words @ ..]
+                if matches!(command.as_str(), "BEGIN" | "START")
+                    && isolation.eq_ignore_ascii_case("ISOLATION")
+                    && level.eq_ignore_ascii_case("LEVEL") => IsolationLevel::parse(words).map(Some).ok_or_else(syntax_error),
+            _ => Err(syntax_error()),
+        }
+    }
 
-        // Since we need mutable references to storage engines, we need to handle them carefully
-        // We'll process them one at a time to avoid borrowing conflicts
-        
-        // First, collect the results from both engines separately
-        let left_records = {
-            let left_engine = self.storage_engines.get_mut(left_table_name)
-                .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", left_table_name)))?;
-            left_engine.get_all_records()
-                .map_err(|e| QueryError::InternalError(format!("Failed to get left table records: {}", e)))?
-        };
+    /// Open a transaction: its writes are buffered, and only visible to its own statements, until COMMIT
+    fn execute_begin(&mut self, session: SessionId, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        let level = Self::parse_transaction_statement(tokens)?;
+        let id = self.new_transaction_id();
+        let transaction = self.session(session)?;
+        transaction.begin(id)?;
+        if let Some(level) = level {
+            transaction.set_isolation_level(level)?;
+        }
+        Ok(QueryResult::Begin)
+    }
 
-        let right_records = {
-            let right_engine = self.storage_engines.get_mut(right_table_name)
-                .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", right_table_name)))?;
-            right_engine.get_all_records()
-                .map_err(|e| QueryError::InternalError(format!("Failed to get right table records: {}", e)))?
+    /// SET TRANSACTION ISOLATION LEVEL level, for the open transaction or else the next one
+    fn execute_set(&mut self, session: SessionId, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        let level = match tokens {
+            [_, transaction, isolation, level, words @ ..]
+                if transaction.eq_ignore_ascii_case("TRANSACTION")
+                    && isolation.eq_ignore_ascii_case("ISOLATION")
+                    && level.eq_ignore_ascii_case("LEVEL") => IsolationLevel::parse(words),
+            _ => None,
         };
+        let level = level.ok_or_else(|| QueryError::SyntaxError(
+            "Expected SET TRANSACTION ISOLATION LEVEL READ COMMITTED, REPEATABLE READ or SERIALIZABLE".to_string()
+        ))?;
+        self.session(session)?.set_isolation_level(level)?;
+        Ok(QueryResult::Set)
+    }
 
-        // Execute the join using the collected records
-        self.execute_join_with_records(tokens, &left_table, &right_table, &left_records, &right_records)
+    fn execute_commit(&mut self, session: SessionId, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        Self::parse_transaction_statement(tokens)?;
+        let latest = self.commit_sequence;
+        let transaction = self.session(session)?;
+        let id = transaction.id();
+        let snapshot = transaction.snapshot().unwrap_or(latest);
+        let serializable = transaction.isolation_level() == IsolationLevel::Serializable;
+        transaction.commit()?;
+        self.commit_writes(id, snapshot, serializable)?;
+        Ok(QueryResult::Commit)
     }
 
-    fn execute_join_with_records(
-        &mut self,
-        tokens: &[&str],
-        left_table: &Table,
-        right_table: &Table,
-        left_records: &[crate::storage::Record],
-        right_records: &[crate::storage::Record],
-    ) -> Result<QueryResult, QueryError> {
-        use super::parser::{JoinParser, JoinClause};
-        use super::result::{QueryResult, JoinResult};
-        use crate::metadata::ColumnType;
-        use std::collections::HashMap;
+    fn execute_rollback(&mut self, session: SessionId, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        Self::parse_transaction_statement(tokens)?;
+        let transaction = self.session(session)?;
+        let id = transaction.id();
+        transaction.rollback()?;
+        self.discard_writes(id);
+        Ok(QueryResult::Rollback)
+    }
 
-        let join_parser = JoinParser::new();
-        
-        // Parse JOIN clause
-        let join_clause = join_parser.parse_join_clause(tokens)?;
-        
-        // Find column indices for join condition
-        let left_join_col_index = left_table.columns.iter()
-            .position(|c| c.name == join_clause.left_column)
-            .ok_or_else(|| QueryError::ColumnNotFound(join_clause.left_column.clone()))?;
-
-        let right_join_col_index = right_table.columns.iter()
-            .position(|c| c.name == join_clause.right_column)
-            .ok_or_else(|| QueryError::ColumnNotFound(join_clause.right_column.clone()))?;
-
-        // Parse record data helper function
-        let parse_record_data = |record: &crate::storage::Record, table: &Table| -> Result<Vec<String>, QueryError> {
-            let mut offset = 0;
-            let row_data: Vec<String> = table.columns.iter().map(|col| {
-                let result = match col.data_type {
-                    ColumnType::Integer => {
-                        if offset + 8 <= record.data.len() {
-                            let bytes = &record.data[offset..offset+8];
-                            let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                            offset += 8;
-                            num.to_string()
-                        } else {
-                            offset += 8;
-                            "0".to_string()
-                        }
-                    },
-                    ColumnType::Float => {
-                        if offset + 8 <= record.data.len() {
-                            let bytes = &record.data[offset..offset+8];
-                            let num = f64::from_be_bytes(bytes.try_into().unwrap());
-                            offset += 8;
-                            num.to_string()
-                        } else {
-                            offset += 8;
-                            "0.0".to_string()
-                        }
-                    },
-                    ColumnType::Varchar(_max_len) => {
-                        if offset + 4 <= record.data.len() {
-                            let length_bytes = &record.data[offset..offset+4];
-                            let length = u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize;
-                            offset += 4;
-                            
-                            if offset + length <= record.data.len() {
-                                let string_bytes = &record.data[offset..offset+length];
-                                offset += length;
-                                String::from_utf8_lossy(string_bytes).to_string()
-                            } else {
-                                offset += length;
-                                String::new()
-                            }
-                        } else {
-                            offset += 4;
-                            String::new()
-                        }
-                    },
-                    ColumnType::Boolean => {
-                        let result = if offset < record.data.len() && record.data[offset] == 1 { 
-                            "true".to_string() 
-                        } else { 
-                            "false".to_string() 
-                        };
-                        offset += 1;
-                        result
-                    },
-                    ColumnType::Timestamp => {
-                        if offset + 8 <= record.data.len() {
-                            let bytes = &record.data[offset..offset+8];
-                            let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                            offset += 8;
-                            num.to_string()
-                        } else {
-                            offset += 8;
-                            "0".to_string()
-                        }
-                    },
-                };
-                result
-            }).collect();
+    /// Check the writes of a transaction against the commits made since its snapshot, then apply them atomically
+    /// They are logged together first, so a crash before they all reach the tables is recovered on restart;
+    /// a transaction that fails the checks is rolled back
+    fn commit_writes(&mut self, transaction: u64, snapshot: u64, serializable: bool) -> Result<(), QueryError> {
+        if let Err(e) = self.check_commit(transaction, snapshot, serializable) {
+            self.discard_writes(transaction);
+            return Err(e);
+        }
 
-            Ok(row_data)
-        };
+        let mut writes = Vec::new();
+        for (table_name, storage_engine) in &self.storage_engines {
+            writes.extend(storage_engine.pending_writes(transaction).into_iter().map(|(id, data)| TransactionWrite {
+                table: table_name.clone(),
+                id,
+                data: data.map(<[u8]>::to_vec),
+            }));
+        }
+        let sequence = self.commit_sequence + 1;
+        if !writes.is_empty() {
+            if let Err(e) = self.transaction_log.log_commit(&writes, sequence) {
+                self.discard_writes(transaction);
+                return Err(e.into());
+            }
+            self.commit_sequence = sequence;
+        }
 
-        // Build hash table from right table (smaller table assumed)
-        let mut hash_table: HashMap<String, Vec<Vec<String>>> = HashMap::new();
-        
-        for record in right_records {
-            let row_data = parse_record_data(record, right_table)?;
-            let join_key = row_data[right_join_col_index].clone();
-            
-            hash_table.entry(join_key)
-                .or_insert_with(Vec::new)
-                .push(row_data);
+        for storage_engine in self.storage_engines.values_mut() {
+            storage_engine.commit_transaction(transaction, sequence)
+                .map_err(|e| QueryError::InternalError(format!("Failed to commit: {}", e)))?;
+        }
+        for index in self.indexes.values_mut().flatten() {
+            index.commit_transaction(transaction)?;
         }
+        self.transaction_log.clear()?;
+        self.release_snapshots();
+        Ok(())
+    }
 
-        // Probe left table and build results
-        let mut result_rows = Vec::new();
-        
-        for record in left_records {
-            let left_row_data = parse_record_data(record, left_table)?;
-            let join_key = &left_row_data[left_join_col_index];
-            
-            if let Some(matching_right_rows) = hash_table.get(join_key) {
-                for right_row_data in matching_right_rows {
-                    // Combine left and right row data
-                    let mut combined_row = left_row_data.clone();
-                    combined_row.extend(right_row_data.iter().cloned());
-                    result_rows.push(combined_row);
-                }
+    /// The first committer wins: a transaction fails if another committed a change since its snapshot
+    /// to a record it wrote, or, when SERIALIZABLE, to a table it read.
+    /// Unique indexes, those of PRIMARY KEY and UNIQUE columns included, are checked again,
+    /// as rows committed in the meantime may clash with its writes
+    fn check_commit(&mut self, transaction: u64, snapshot: u64, serializable: bool) -> Result<(), QueryError> {
+        for (table_name, storage_engine) in &self.storage_engines {
+            if storage_engine.write_conflict(transaction).is_some() {
+                return Err(TransactionError::SerializationFailure(format!(
+                    "a row of {} was changed by a concurrent transaction", table_name
+                )).into());
+            }
+            if serializable && storage_engine.was_read_by(transaction) && storage_engine.changed_since(snapshot) {
+                return Err(TransactionError::SerializationFailure(format!(
+                    "{} was changed by a concurrent transaction after this one read it", table_name
+                )).into());
             }
         }
+        for index in self.indexes.values().flatten() {
+            index.check_transaction(transaction)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the writes buffered by a transaction
+    fn discard_writes(&mut self, transaction: u64) {
+        for storage_engine in self.storage_engines.values_mut() {
+            storage_engine.rollback_transaction(transaction);
+        }
+        for index in self.indexes.values_mut().flatten() {
+            index.rollback_transaction(transaction);
+        }
+        self.release_snapshots();
+    }
 
-        // Build headers for result
-        let mut headers = Vec::new();
-        for col in &left_table.columns {
-            headers.push(format!("{}.{}", join_clause.left_table, col.name));
+    /// Let compaction drop the row versions older than what the oldest open transaction reads
+    fn release_snapshots(&mut self) {
+        let oldest = self.sessions.values()
+            .filter(|transaction| transaction.is_active())
+            .filter_map(Transaction::snapshot)
+            .min()
+            .unwrap_or(self.commit_sequence);
+        for storage_engine in self.storage_engines.values_mut() {
+            storage_engine.set_horizon(oldest);
         }
-        for col in &right_table.columns {
-            headers.push(format!("{}.{}", join_clause.right_table, col.name));
+    }
+
+    fn execute_select(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        if tokens.len() < 4 {
+            return Err(QueryError::SyntaxError("Invalid SELECT syntax".to_string()));
         }
 
-        // Handle column selection
-        let select_end = tokens.iter()
-            .position(|&t| t.to_uppercase() == "FROM")
-            .unwrap_or(tokens.len());
-        
-        let (filtered_headers, filtered_rows) = if tokens[1] == "*" {
-            (headers, result_rows)
-        } else {
-            // Parse selected columns and filter
-            let selected_columns = self.select_parser.column_parser.parse_column_list(&tokens[1..select_end])?;
-            self.filter_join_columns(&headers, &result_rows, &selected_columns)?
-        };
+        // A query reading a single table only needs the rows an index narrows its WHERE clause down to
+        let access = self.single_table_access(tokens)?;
 
-        Ok(QueryResult::Join(JoinResult {
-            headers: filtered_headers,
-            rows: filtered_rows,
-        }))
+        // Load every table the query, its WITH queries and its subqueries read, then run it against them
+        let catalog = self.load_catalog(tokens, access)?;
+        self.select_parser.execute_statement(tokens, catalog)
     }
 
-    fn filter_join_columns(
-        &self,
-        headers: &[String],
-        rows: &[Vec<String>],
-        selected_columns: &[String],
-    ) -> Result<(Vec<String>, Vec<Vec<String>>), QueryError> {
-        let mut selected_indices = Vec::new();
-        let mut filtered_headers = Vec::new();
-
-        for col_name in selected_columns {
-            // Handle table.column format or just column name
-            let column_index = if col_name.contains('.') {
-                headers.iter().position(|h| h == col_name)
-            } else {
-                headers.iter().position(|h| h.ends_with(&format!(".{}", col_name)))
-            };
+    /// Names of the tables the query reads, subqueries included
+    /// Names of WITH queries are left out, unless a table has the same name
+    fn referenced_tables(&self, tokens: &[&str]) -> Result<Vec<String>, QueryError> {
+        let mut table_names: Vec<String> = FromParser::new().table_names(&tokens.join(" "))?
+            .into_iter()
+            .filter(|table_name| self.tables.contains_key(table_name))
+            .collect();
+        table_names.sort();
+        table_names.dedup();
+        Ok(table_names)
+    }
 
-            match column_index {
-                Some(index) => {
-                    selected_indices.push(index);
-                    filtered_headers.push(headers[index].clone());
-                }
-                None => return Err(QueryError::ColumnNotFound(col_name.clone())),
+    /// The table a SELECT reads alone, with the way to read it
+    fn single_table_access<'a>(&self, tokens: &[&'a str]) -> Result<Option<(&'a str, AccessPath)>, QueryError> {
+        let Some(table_name) = self.planner.single_table(tokens) else {
+            return Ok(None);
+        };
+        let Some(table) = self.tables.get(table_name) else {
+            return Ok(None);
+        };
+        let needed = self.planner.referenced_columns(tokens, table);
+        Ok(Some((table_name, self.access_path(table_name, tokens, needed.as_deref())?)))
+    }
+
+    /// Decode the rows of each table the query reads
+    /// `access` gives the way to read one of the tables; the others are read in full
+    fn load_catalog(&mut self, tokens: &[&str], access: Option<(&str, AccessPath)>) -> Result<Catalog, QueryError> {
+        let table_names = self.referenced_tables(tokens)?;
+        // A table read alone had its MATCH terms checked with its access path; a WHERE clause the
+        // planner can't parse here is reported once the query runs
+        if access.is_none() {
+            if let Ok(Some(condition)) = self.planner.where_condition(tokens) {
+                let tables: Vec<(&Table, &[Index])> = table_names.iter()
+                    .map(|table_name| (&self.tables[table_name], self.table_indexes(table_name)))
+                    .collect();
+                self.planner.check_fulltext_terms(&condition, &tables)?;
             }
         }
 
-        let filtered_rows: Vec<Vec<String>> = rows.iter()
-            .map(|row| {
-                selected_indices.iter()
-                    .map(|&index| row[index].clone())
-                    .collect()
-            })
-            .collect();
+        let mut catalog = Catalog::new();
+        for table_name in table_names {
+            let table = &self.tables[&table_name];
+            let storage_engine = self.storage_engines.get_mut(&table_name)
+                .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", table_name)))?;
+            let path = match &access {
+                Some((name, path)) if *name == table_name => path,
+                _ => &AccessPath::FullScan,
+            };
+            let indexes = self.indexes.get(&table_name).map(Vec::as_slice).unwrap_or_default();
+            let rows = path.read_rows(table, storage_engine, indexes)?;
 
-        Ok((filtered_headers, filtered_rows))
+            catalog.add_table(table.clone(), rows);
+        }
+
+        Ok(catalog)
     }
 
     fn execute_insert(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
@@ -407,10 +624,16 @@ impl QueryEngine {
         let table = self.tables.get(table_name)
             .ok_or_else(|| QueryError::TableNotFound(table_name.to_string()))?;
 
+        let rows = self.insert_parser.prepare_rows(tokens, table)?;
+        if table.foreign_keys().next().is_some() {
+            ForeignKeyEnforcer::new(&self.tables, &mut self.storage_engines, &mut self.indexes).check_references(table_name, &rows)?;
+        }
+
         let storage_engine = self.storage_engines.get_mut(table_name)
             .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", table_name)))?;
 
-        self.insert_parser.parse_and_execute_lsm(tokens, table, storage_engine)
+        let indexes = self.indexes.get_mut(table_name).map(Vec::as_mut_slice).unwrap_or_default();
+        self.insert_parser.insert_rows(rows, table, storage_engine, indexes)
     }
 
     fn execute_update(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
@@ -419,13 +642,23 @@ impl QueryEngine {
         }
 
         let table_name = tokens[1];
-        let table = self.tables.get(table_name)
-            .ok_or_else(|| QueryError::TableNotFound(table_name.to_string()))?;
+        let candidates = self.access_path(table_name, tokens, None)?.record_ids(self.table_indexes(table_name));
+        let table = &self.tables[table_name];
 
         let storage_engine = self.storage_engines.get_mut(table_name)
             .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", table_name)))?;
 
-        self.update_parser.parse_and_execute_lsm(tokens, table, storage_engine)
+        let changes = self.update_parser.plan_update(tokens, table, storage_engine, candidates.as_deref())?;
+        if !ForeignKeyEnforcer::is_involved(&self.tables, table_name) {
+            let indexes = self.indexes.get_mut(table_name).map(Vec::as_mut_slice).unwrap_or_default();
+            return self.update_parser.write_updates(changes, table, storage_engine, indexes);
+        }
+
+        // Changes may cascade into other tables, so they are checked together before any is written
+        let mut enforcer = ForeignKeyEnforcer::new(&self.tables, &mut self.storage_engines, &mut self.indexes);
+        enforcer.update_rows(table_name, changes)?;
+        enforcer.check()?;
+        Ok(QueryResult::Update(enforcer.apply(table_name)?))
     }
 
     fn execute_delete(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
@@ -434,16 +667,86 @@ impl QueryEngine {
         }
 
         let table_name = tokens[2];
-        let table = self.tables.get(table_name)
-            .ok_or_else(|| QueryError::TableNotFound(table_name.to_string()))?;
+        let candidates = self.access_path(table_name, tokens, None)?.record_ids(self.table_indexes(table_name));
+        let table = &self.tables[table_name];
 
         let storage_engine = self.storage_engines.get_mut(table_name)
             .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", table_name)))?;
 
-        self.delete_parser.parse_and_execute_lsm(tokens, table, storage_engine)
+        let record_ids = self.delete_parser.matching_records(tokens, table, storage_engine, candidates.as_deref())?;
+        if ForeignKeyEnforcer::referencing(&self.tables, table_name).is_empty() {
+            let indexes = self.indexes.get_mut(table_name).map(Vec::as_mut_slice).unwrap_or_default();
+            return self.delete_parser.delete_records(&record_ids, storage_engine, indexes);
+        }
+
+        let mut enforcer = ForeignKeyEnforcer::new(&self.tables, &mut self.storage_engines, &mut self.indexes);
+        enforcer.delete_rows(table_name, &record_ids)?;
+        enforcer.check()?;
+        Ok(QueryResult::Delete(enforcer.apply(table_name)?))
+    }
+
+    /// Secondary indexes of a table
+    fn table_indexes(&self, table_name: &str) -> &[Index] {
+        self.indexes.get(table_name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Choose how a statement reads `table_name`, from its WHERE clause and the table's indexes
+    /// `needed` are the only columns it reads, if it doesn't need whole rows
+    fn access_path(&self, table_name: &str, tokens: &[&str], needed: Option<&[usize]>) -> Result<AccessPath, QueryError> {
+        let table = self.tables.get(table_name)
+            .ok_or_else(|| QueryError::TableNotFound(table_name.to_string()))?;
+        let condition = self.planner.where_condition(tokens)?;
+        if let Some(condition) = &condition {
+            self.planner.check_fulltext_terms(condition, &[(table, self.table_indexes(table_name))])?;
+        }
+        // Indexes hold the latest committed rows, so a statement that reads any others scans the table
+        if !self.storage_engines.get(table_name).is_some_and(LSMEngine::reads_latest) {
+            return Ok(AccessPath::FullScan);
+        }
+        Ok(self.planner.choose(table, condition.as_ref(), self.table_indexes(table_name), needed))
+    }
+
+    /// Show how a SELECT, UPDATE or DELETE would read each of its tables, without running it
+    fn execute_explain(&self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        let statement = tokens.first().map(|t| t.to_uppercase()).unwrap_or_default();
+        let paths = match statement.as_str() {
+            "UPDATE" | "DELETE" => {
+                let table_name = tokens.get(if statement == "UPDATE" { 1 } else { 2 })
+                    .ok_or_else(|| QueryError::SyntaxError(format!("Invalid {} syntax", statement)))?;
+                vec![(table_name.to_string(), self.access_path(table_name, tokens, None)?)]
+            }
+            "SELECT" | "WITH" => {
+                // The statement must be one that could run, reading at least one table
+                if tokens.len() < 4 {
+                    return Err(QueryError::SyntaxError("Invalid SELECT syntax".to_string()));
+                }
+                let table_ref = if Lexer::new().has_keyword(tokens, &["JOIN"]) {
+                    JoinParser::new().parse_join_clause(tokens)?.base_table
+                } else {
+                    FromParser::new().parse_from_table(tokens)?
+                };
+                let paths: Vec<(String, AccessPath)> = match self.single_table_access(tokens)? {
+                    Some((table_name, path)) => vec![(table_name.to_string(), path)],
+                    None => self.referenced_tables(tokens)?.into_iter().map(|table_name| (table_name, AccessPath::FullScan)).collect(),
+                };
+                if paths.is_empty() {
+                    return Err(QueryError::TableNotFound(table_ref.name));
+                }
+                paths
+            }
+            _ => return Err(QueryError::SyntaxError("EXPLAIN expects a SELECT, UPDATE or DELETE statement".to_string())),
+        };
+
+        Ok(QueryResult::Select(SelectResult {
+            headers: vec!["plan".to_string()],
+            rows: paths.iter().map(|(table_name, path)| vec![Some(path.describe(table_name))]).collect(),
+        }))
     }
 
     fn execute_create(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        if self.index_parser.is_index_statement(tokens) {
+            return self.execute_create_index(tokens);
+        }
         if tokens.len() < 3 {
             return Err(QueryError::SyntaxError("Invalid CREATE TABLE syntax".to_string()));
         }
@@ -453,6 +756,11 @@ impl QueryEngine {
         if self.tables.contains_key(&table_name) {
             return Err(QueryError::DuplicateKey(format!("Table {} already exists", table_name)));
         }
+        ForeignKeyEnforcer::check_definition(&self.tables, &table)?;
+        if let Some(definition) = table.key_indexes().into_iter()
+            .find(|definition| self.tables.values().any(|other| other.indexes.contains_key(&definition.name))) {
+            return Err(QueryError::DuplicateKey(format!("Index {} already exists", definition.name)));
+        }
 
         // Create LSM storage engine for this table
         let table_data_dir = format!("{}/{}", self.data_dir, table_name);
@@ -461,6 +769,7 @@ impl QueryEngine {
 
         self.tables.insert(table_name.clone(), table);
         self.storage_engines.insert(table_name.clone(), storage_engine);
+        self.create_key_indexes(&table_name)?;
 
         // Save table metadata
         self.save_table_metadata()?;
@@ -468,7 +777,60 @@ impl QueryEngine {
         Ok(QueryResult::CreateTable)
     }
 
+    fn execute_create_index(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        let (table_name, definition) = self.index_parser.parse_create(tokens)?;
+        let table = self.tables.get(&table_name)
+            .ok_or_else(|| QueryError::TableNotFound(table_name.clone()))?;
+        if self.tables.values().any(|table| table.indexes.contains_key(&definition.name)) {
+            return Err(QueryError::DuplicateKey(format!("Index {} already exists", definition.name)));
+        }
+
+        // Build the index over the rows the table already holds
+        let data_dir = self.index_data_dir(&table_name, &definition.name);
+        let _ = fs::remove_dir_all(&data_dir);
+        let mut index = Index::open(definition.clone(), table, &data_dir)?;
+        let rows = self.table_rows(&table_name)?;
+        if let Err(e) = index.build(&rows) {
+            let _ = index.destroy();
+            return Err(e.into());
+        }
+
+        if let Some(table) = self.tables.get_mut(&table_name) {
+            table.create_index(definition);
+        }
+        let indexes = self.indexes.entry(table_name).or_default();
+        indexes.push(index);
+        indexes.sort_by(|a, b| a.name().cmp(b.name()));
+        self.save_table_metadata()?;
+
+        Ok(QueryResult::CreateIndex)
+    }
+
+    fn execute_drop_index(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        let index_name = self.index_parser.parse_drop(tokens)?;
+        let table = self.tables.values_mut()
+            .find(|table| table.indexes.contains_key(&index_name))
+            .ok_or_else(|| QueryError::InvalidValue(format!("Index not found: {}", index_name)))?;
+        if table.key_indexes().iter().any(|definition| definition.name == index_name) {
+            return Err(QueryError::InvalidValue(format!(
+                "Index {} enforces a PRIMARY KEY or UNIQUE column of {} and is dropped with the table", index_name, table.name
+            )));
+        }
+        table.indexes.remove(&index_name);
+
+        let indexes = self.indexes.entry(table.name.clone()).or_default();
+        if let Some(position) = indexes.iter().position(|index| index.name() == index_name) {
+            indexes.remove(position).destroy()?;
+        }
+        self.save_table_metadata()?;
+
+        Ok(QueryResult::DropIndex)
+    }
+
     fn execute_drop(&mut self, tokens: &[&str]) -> Result<QueryResult, QueryError> {
+        if self.index_parser.is_index_statement(tokens) {
+            return self.execute_drop_index(tokens);
+        }
         if tokens.len() < 3 {
             return Err(QueryError::SyntaxError("Invalid DROP TABLE syntax".to_string()));
         }
@@ -483,8 +845,34 @@ impl QueryEngine {
             return Err(QueryError::TableNotFound(table_name.to_string()));
         }
 
+        let cascade = match tokens.get(3).map(|t| t.to_uppercase()) {
+            None => false,
+            Some(word) if word == "RESTRICT" => false,
+            Some(word) if word == "CASCADE" => true,
+            Some(word) => return Err(QueryError::SyntaxError(format!("Unexpected {} after DROP TABLE {}", word, table_name))),
+        };
+
+        // Foreign keys of other tables pointing at this one block the drop, or are dropped with CASCADE
+        let referencing: Vec<(String, String)> = ForeignKeyEnforcer::referencing(&self.tables, table_name).into_iter()
+            .filter(|(name, _, _)| *name != table_name)
+            .map(|(name, index, _)| (name.to_string(), self.tables[name].columns[index].name.clone()))
+            .collect();
+        if let Some((name, column)) = referencing.first().filter(|_| !cascade) {
+            return Err(QueryError::ForeignKeyViolation(format!(
+                "Table {} is referenced from {}.{}; use DROP TABLE {} CASCADE", table_name, name, column, table_name
+            )));
+        }
+        for (name, _) in &referencing {
+            if let Some(table) = self.tables.get_mut(name) {
+                for column in &mut table.columns {
+                    column.constraints.retain(|constraint| !matches!(constraint, ColumnConstraint::ForeignKey(foreign_key) if foreign_key.table == table_name));
+                }
+            }
+        }
+
         self.tables.remove(table_name);
         self.storage_engines.remove(table_name);
+        self.indexes.remove(table_name);
 
         // Remove table data directory
         let table_data_dir = format!("{}/{}", self.data_dir, table_name);
@@ -513,17 +901,29 @@ impl QueryEngine {
             engine.flush()
                 .map_err(|e| QueryError::InternalError(format!("Failed to flush: {}", e)))?;
         }
+        for index in self.indexes.values_mut().flatten() {
+            index.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Compact every table, dropping the row versions no open transaction reads
+    pub fn compact_all(&mut self) -> Result<(), QueryError> {
+        for engine in self.storage_engines.values_mut() {
+            engine.compact()
+                .map_err(|e| QueryError::InternalError(format!("Failed to compact: {}", e)))?;
+        }
         Ok(())
     }
 
+    /// Look up a secondary index by name
+    pub fn get_index(&self, index_name: &str) -> Option<&Index> {
+        self.indexes.values().flatten().find(|index| index.name() == index_name)
+    }
+
     /// List all tables
     pub fn list_tables(&self) -> Vec<String> {
         self.tables.keys().cloned().collect()
     }
 }
-
-struct WhereClause {
-    column: String,
-    operator: String,
-    value: String,
-} 
\ No newline at end of file
+ 
\ No newline at end of file
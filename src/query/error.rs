@@ -1,3 +1,6 @@
+use crate::index::IndexError;
+use crate::transaction::TransactionError;
+
 #[derive(Debug)]
 pub enum QueryError {
     SyntaxError(String),
@@ -5,7 +8,12 @@ pub enum QueryError {
     ColumnNotFound(String),
     TypeMismatch(String),
     DuplicateKey(String),
+    NotNullViolation(String),
+    UniqueViolation(String),
+    ForeignKeyViolation(String),
+    CheckViolation(String),
     InvalidValue(String),
+    TransactionError(String),
     InternalError(String),
 }
 
@@ -17,10 +25,37 @@ impl std::fmt::Display for QueryError {
             QueryError::ColumnNotFound(msg) => write!(f, "Column not found: {}", msg),
             QueryError::TypeMismatch(msg) => write!(f, "Type mismatch: {}", msg),
             QueryError::DuplicateKey(msg) => write!(f, "Duplicate key: {}", msg),
+            QueryError::NotNullViolation(msg) => write!(f, "Not-null violation: {}", msg),
+            QueryError::UniqueViolation(msg) => write!(f, "Unique violation: {}", msg),
+            QueryError::ForeignKeyViolation(msg) => write!(f, "Foreign key violation: {}", msg),
+            QueryError::CheckViolation(msg) => write!(f, "Check violation: {}", msg),
             QueryError::InvalidValue(msg) => write!(f, "Invalid value: {}", msg),
+            QueryError::TransactionError(msg) => write!(f, "Transaction error: {}", msg),
             QueryError::InternalError(msg) => write!(f, "Internal error: {}", msg),
         }
     }
 }
 
+impl From<IndexError> for QueryError {
+    fn from(error: IndexError) -> Self {
+        match error {
+            IndexError::ColumnNotFound(msg) => QueryError::ColumnNotFound(msg),
+            IndexError::IndexAlreadyExists(msg) => QueryError::DuplicateKey(msg),
+            IndexError::IndexNotFound(msg) => QueryError::InvalidValue(format!("Index not found: {}", msg)),
+            IndexError::DuplicateKey(msg) => QueryError::UniqueViolation(msg),
+            IndexError::InvalidKey(msg) => QueryError::TypeMismatch(msg),
+            IndexError::StorageError(msg) => QueryError::InternalError(format!("Index storage: {}", msg)),
+        }
+    }
+}
+
+impl From<TransactionError> for QueryError {
+    fn from(error: TransactionError) -> Self {
+        match error {
+            TransactionError::TableNotFound(msg) => QueryError::TableNotFound(msg),
+            error => QueryError::TransactionError(error.to_string()),
+        }
+    }
+}
+
 impl std::error::Error for QueryError {} 
\ No newline at end of file
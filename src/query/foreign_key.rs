@@ -0,0 +1,390 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use crate::metadata::{Table, ForeignKey, ReferentialAction};
+use crate::storage::LSMEngine;
+use crate::index::Index;
+use super::constraint::ConstraintChecker;
+use super::error::QueryError;
+use super::record::{RecordCodec, Row};
+
+/// Enforces FOREIGN KEY constraints across the tables of an engine
+///
+/// Deletes and updates are made to in-memory copies of the tables involved,
+/// following the ON DELETE and ON UPDATE actions into referencing tables.
+/// Nothing reaches storage until `check` has passed and `apply` is called.
+pub struct ForeignKeyEnforcer<'a> {
+    tables: &'a HashMap<String, Table>,
+    storage_engines: &'a mut HashMap<String, LSMEngine>,
+    indexes: &'a mut HashMap<String, Vec<Index>>,
+    rows: HashMap<String, BTreeMap<u64, Row>>, // decoded rows by record id, loaded on first use
+    touched: BTreeMap<String, BTreeSet<u64>>,          // records changed or deleted so far
+}
+
+impl<'a> ForeignKeyEnforcer<'a> {
+    pub fn new(
+        tables: &'a HashMap<String, Table>,
+        storage_engines: &'a mut HashMap<String, LSMEngine>,
+        indexes: &'a mut HashMap<String, Vec<Index>>,
+    ) -> Self {
+        ForeignKeyEnforcer {
+            tables,
+            storage_engines,
+            indexes,
+            rows: HashMap::new(),
+            touched: BTreeMap::new(),
+        }
+    }
+
+    /// Columns of any table that reference the given table, as (table name, column index, foreign key)
+    pub fn referencing<'t>(tables: &'t HashMap<String, Table>, table_name: &str) -> Vec<(&'t str, usize, &'t ForeignKey)> {
+        let mut columns: Vec<_> = tables.iter()
+            .flat_map(|(name, table)| table.foreign_keys().map(move |(index, foreign_key)| (name.as_str(), index, foreign_key)))
+            .filter(|(_, _, foreign_key)| foreign_key.table == table_name)
+            .collect();
+        columns.sort_by_key(|&(name, index, _)| (name, index));
+        columns
+    }
+
+    /// Whether changing rows of the table can involve a foreign key, its own or one referencing it
+    pub fn is_involved(tables: &HashMap<String, Table>, table_name: &str) -> bool {
+        tables.get(table_name).is_some_and(|table| table.foreign_keys().next().is_some())
+            || !Self::referencing(tables, table_name).is_empty()
+    }
+
+    /// Check the foreign keys of a table about to be created
+    /// The referenced column must exist, be UNIQUE or the PRIMARY KEY, and have the same type
+    pub fn check_definition(tables: &HashMap<String, Table>, table: &Table) -> Result<(), QueryError> {
+        for (index, foreign_key) in table.foreign_keys() {
+            let column = &table.columns[index];
+            let parent = if foreign_key.table == table.name {
+                table
+            } else {
+                tables.get(&foreign_key.table)
+                    .ok_or_else(|| QueryError::TableNotFound(foreign_key.table.clone()))?
+            };
+            let referenced = parent.get_column(&foreign_key.column)
+                .ok_or_else(|| QueryError::ColumnNotFound(format!("{}.{}", foreign_key.table, foreign_key.column)))?;
+
+            if !referenced.is_unique() {
+                return Err(QueryError::SyntaxError(format!(
+                    "{}.{} can only reference a UNIQUE or PRIMARY KEY column, not {}.{}",
+                    table.name, column.name, foreign_key.table, foreign_key.column
+                )));
+            }
+            if !column.data_type.accepts(&referenced.data_type) || !referenced.data_type.accepts(&column.data_type) {
+                return Err(QueryError::TypeMismatch(format!(
+                    "{}.{} of type {} cannot reference {}.{} of type {}",
+                    table.name, column.name, column.data_type, foreign_key.table, foreign_key.column, referenced.data_type
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn table(&self, table_name: &str) -> Result<&'a Table, QueryError> {
+        self.tables.get(table_name).ok_or_else(|| QueryError::TableNotFound(table_name.to_string()))
+    }
+
+    fn load(&mut self, table_name: &str) -> Result<&mut BTreeMap<u64, Row>, QueryError> {
+        if !self.rows.contains_key(table_name) {
+            let table = self.table(table_name)?;
+            let storage_engine = self.storage_engines.get_mut(table_name)
+                .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", table_name)))?;
+            let records = storage_engine.get_all_records()
+                .map_err(|e| QueryError::InternalError(format!("Failed to get records of table {}: {}", table_name, e)))?;
+
+            let codec = RecordCodec::new();
+            let rows = records.iter()
+                .map(|record| Ok((record.id, codec.decode(record, table)?)))
+                .collect::<Result<BTreeMap<_, _>, QueryError>>()?;
+            self.rows.insert(table_name.to_string(), rows);
+        }
+        Ok(self.rows.get_mut(table_name).unwrap())
+    }
+
+    /// Check that every non-NULL foreign key value of the new rows has a referenced row
+    /// New rows of a table that references itself may reference each other
+    pub fn check_references(&mut self, table_name: &str, new_rows: &[Row]) -> Result<(), QueryError> {
+        let table = self.table(table_name)?;
+        for (index, foreign_key) in table.foreign_keys() {
+            let parent = self.table(&foreign_key.table)?;
+            let parent_index = parent.get_column_index(&foreign_key.column)
+                .ok_or_else(|| QueryError::ColumnNotFound(format!("{}.{}", foreign_key.table, foreign_key.column)))?;
+
+            let mut keys: HashSet<String> = self.load(&foreign_key.table)?.values()
+                .filter_map(|row| row[parent_index].clone())
+                .collect();
+            if foreign_key.table == table_name {
+                keys.extend(new_rows.iter().filter_map(|row| row[parent_index].clone()));
+            }
+
+            let missing = new_rows.iter()
+                .filter_map(|row| row[index].as_ref())
+                .find(|value| !keys.contains(*value));
+            if let Some(value) = missing {
+                return Err(QueryError::ForeignKeyViolation(format!(
+                    "{}.{} value '{}' has no matching row in {}({})",
+                    table_name, table.columns[index].name, value, foreign_key.table, foreign_key.column
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete records, applying the ON DELETE action of every foreign key referencing them
+    pub fn delete_rows(&mut self, table_name: &str, record_ids: &[u64]) -> Result<(), QueryError> {
+        let rows = self.load(table_name)?;
+        let removed: Vec<Row> = record_ids.iter().filter_map(|id| rows.remove(id)).collect();
+        self.touched.entry(table_name.to_string()).or_default().extend(record_ids);
+        if removed.is_empty() {
+            return Ok(());
+        }
+
+        let table = self.table(table_name)?;
+        for (child_name, child_index, foreign_key) in Self::referencing(self.tables, table_name) {
+            let parent_index = table.get_column_index(&foreign_key.column)
+                .ok_or_else(|| QueryError::ColumnNotFound(format!("{}.{}", table_name, foreign_key.column)))?;
+            let keys: HashSet<&String> = removed.iter()
+                .filter_map(|row| row[parent_index].as_ref())
+                .collect();
+
+            let dependents: Vec<(u64, Row)> = self.load(child_name)?.iter()
+                .filter(|(_, row)| row[child_index].as_ref().is_some_and(|value| keys.contains(value)))
+                .map(|(id, row)| (*id, row.clone()))
+                .collect();
+            if dependents.is_empty() {
+                continue;
+            }
+
+            match foreign_key.on_delete {
+                ReferentialAction::Restrict => {
+                    return Err(self.still_referenced(table_name, child_name, child_index, dependents[0].1[child_index].as_deref().unwrap_or_default()));
+                }
+                ReferentialAction::Cascade => {
+                    let ids: Vec<u64> = dependents.iter().map(|(id, _)| *id).collect();
+                    self.delete_rows(child_name, &ids)?;
+                }
+                ReferentialAction::SetNull => {
+                    let updates = dependents.into_iter()
+                        .map(|(id, mut row)| {
+                            row[child_index] = None;
+                            (id, row)
+                        })
+                        .collect();
+                    self.update_rows(child_name, updates)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace records with new versions, applying the ON UPDATE action of every
+    /// foreign key whose referenced value changes
+    pub fn update_rows(&mut self, table_name: &str, updates: Vec<(u64, Row)>) -> Result<(), QueryError> {
+        self.touched.entry(table_name.to_string()).or_default().extend(updates.iter().map(|(id, _)| *id));
+        let rows = self.load(table_name)?;
+        let mut changes = Vec::new();
+        for (id, new_row) in updates {
+            if let Some(old_row) = rows.insert(id, new_row.clone()) {
+                changes.push((old_row, new_row));
+            }
+        }
+
+        let table = self.table(table_name)?;
+        for (child_name, child_index, foreign_key) in Self::referencing(self.tables, table_name) {
+            let parent_index = table.get_column_index(&foreign_key.column)
+                .ok_or_else(|| QueryError::ColumnNotFound(format!("{}.{}", table_name, foreign_key.column)))?;
+
+            // Old key -> new key, for every key the update changes; a key still held by another row stays valid
+            let remaining: HashSet<String> = self.load(table_name)?.values().filter_map(|row| row[parent_index].clone()).collect();
+            let renamed: HashMap<&String, &Option<String>> = changes.iter()
+                .filter(|(old, new)| old[parent_index] != new[parent_index])
+                .filter_map(|(old, new)| Some((old[parent_index].as_ref()?, &new[parent_index])))
+                .filter(|(old, _)| !remaining.contains(*old))
+                .collect();
+            if renamed.is_empty() {
+                continue;
+            }
+
+            let dependents: Vec<(u64, Row)> = self.load(child_name)?.iter()
+                .filter(|(_, row)| row[child_index].as_ref().is_some_and(|value| renamed.contains_key(value)))
+                .map(|(id, row)| (*id, row.clone()))
+                .collect();
+            if dependents.is_empty() {
+                continue;
+            }
+
+            let set_null = match foreign_key.on_update {
+                ReferentialAction::Restrict => {
+                    return Err(self.still_referenced(table_name, child_name, child_index, dependents[0].1[child_index].as_deref().unwrap_or_default()));
+                }
+                ReferentialAction::Cascade => false,
+                ReferentialAction::SetNull => true,
+            };
+            let child_updates = dependents.into_iter()
+                .map(|(id, mut row)| {
+                    row[child_index] = match &row[child_index] {
+                        Some(old) if !set_null => renamed[old].clone(),
+                        _ => None,
+                    };
+                    (id, row)
+                })
+                .collect();
+            self.update_rows(child_name, child_updates)?;
+        }
+        Ok(())
+    }
+
+    fn still_referenced(&self, table_name: &str, child_name: &str, child_index: usize, value: &str) -> QueryError {
+        let column = self.tables.get(child_name).map_or("?", |child| child.columns[child_index].name.as_str());
+        QueryError::ForeignKeyViolation(format!(
+            "Row of {} with key '{}' is still referenced from {}.{}", table_name, value, child_name, column
+        ))
+    }
+
+    /// Check the changed rows of every table against its constraints, foreign keys included
+    pub fn check(&mut self) -> Result<(), QueryError> {
+        let checker = ConstraintChecker::new();
+        let touched = self.touched.clone();
+        for (table_name, ids) in &touched {
+            let table = self.table(table_name)?;
+            let rows = self.load(table_name)?;
+            let changes: Vec<(u64, Option<Row>)> = ids.iter().map(|id| (*id, rows.get(id).cloned())).collect();
+            let changed: Vec<Row> = changes.iter().filter_map(|(_, row)| row.clone()).collect();
+            for row in &changed {
+                checker.check_row(row, table)?;
+            }
+            let changes: Vec<(u64, Option<&[Option<String>]>)> = changes.iter().map(|(id, row)| (*id, row.as_deref())).collect();
+            for index in self.indexes.get(table_name).into_iter().flatten() {
+                index.check_unique(&changes)?;
+            }
+            // The changed rows are already in the table, so they count as referenced rows too
+            self.check_references(table_name, &changed)?;
+        }
+        Ok(())
+    }
+
+    /// Write every changed row to storage and remove every deleted one, index entries included
+    /// Returns the number of records changed or deleted in the given table
+    pub fn apply(self, table_name: &str) -> Result<usize, QueryError> {
+        let codec = RecordCodec::new();
+        let mut count = 0;
+        for (name, ids) in &self.touched {
+            let table = self.table(name)?;
+            let rows = &self.rows[name];
+            let storage_engine = self.storage_engines.get_mut(name)
+                .ok_or_else(|| QueryError::TableNotFound(format!("Storage engine not found: {}", name)))?;
+            let indexes = self.indexes.get_mut(name).map(Vec::as_mut_slice).unwrap_or_default();
+
+            for id in ids {
+                for index in indexes.iter_mut() {
+                    match rows.get(id) {
+                        Some(row) => index.insert(*id, row)?,
+                        None => index.remove(*id)?,
+                    }
+                }
+                let written = match rows.get(id) {
+                    Some(row) => storage_engine.update(*id, codec.encode(row, table)?),
+                    None => storage_engine.delete(*id),
+                }
+                .map_err(|e| QueryError::InternalError(format!("Failed to write record of table {}: {}", name, e)))?;
+                if written && name == table_name {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::{QueryEngine, QueryResult};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE companies (id INTEGER PRIMARY KEY, name VARCHAR 20)",
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, name VARCHAR 20, company INTEGER REFERENCES companies(id) ON DELETE SET NULL ON UPDATE CASCADE)",
+        "CREATE TABLE orders (id INTEGER PRIMARY KEY, user_id INTEGER, total FLOAT, FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE)",
+        "INSERT INTO companies VALUES (1, 'Acme'), (2, 'Globex')",
+        "INSERT INTO users VALUES (1, 'Ann', 1), (2, 'Bob', 2), (3, 'Cid', NULL)",
+        "INSERT INTO orders VALUES (10, 1, 5.0), (11, 1, 7.5), (12, 2, 3.0)",
+    ];
+
+    #[test]
+    fn test_references_checked_on_insert_and_update() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert!(matches!(engine.execute("INSERT INTO orders VALUES (13, 9, 1.0)"), Err(QueryError::ForeignKeyViolation(_))));
+        assert!(matches!(engine.execute("UPDATE orders SET user_id = 9 WHERE id = 10"), Err(QueryError::ForeignKeyViolation(_))));
+        engine.execute("INSERT INTO orders VALUES (13, NULL, 1.0)").unwrap();
+        engine.execute("UPDATE orders SET user_id = 3 WHERE id = 13").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT user_id FROM orders WHERE id = 13"), vec![vec!["3"]]);
+
+        // Rows of a self-referencing table may reference rows inserted alongside them
+        engine.execute("CREATE TABLE staff (id INTEGER PRIMARY KEY, manager INTEGER REFERENCES staff(id))").unwrap();
+        engine.execute("INSERT INTO staff VALUES (1, NULL), (2, 1), (3, 2)").unwrap();
+        assert!(matches!(engine.execute("INSERT INTO staff VALUES (4, 5)"), Err(QueryError::ForeignKeyViolation(_))));
+        // RESTRICT is the default
+        assert!(matches!(engine.execute("DELETE FROM staff WHERE id = 2"), Err(QueryError::ForeignKeyViolation(_))));
+        engine.execute("DELETE FROM staff WHERE id >= 2").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT id FROM staff"), vec![vec!["1"]]);
+    }
+
+    #[test]
+    fn test_referential_actions() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // ON UPDATE CASCADE follows a changed key into the referencing rows
+        assert!(matches!(engine.execute("UPDATE companies SET id = 3 WHERE id = 2"), Ok(QueryResult::Update(1))));
+        assert_eq!(rows(&mut engine, "SELECT company FROM users WHERE id = 2"), vec![vec!["3"]]);
+
+        // users.id is referenced with the default ON UPDATE RESTRICT
+        assert!(matches!(engine.execute("UPDATE users SET id = 5 WHERE id = 1"), Err(QueryError::ForeignKeyViolation(_))));
+        engine.execute("UPDATE users SET id = 5 WHERE id = 3").unwrap();
+
+        // ON DELETE SET NULL clears the key, ON DELETE CASCADE follows on down
+        engine.execute("DELETE FROM companies WHERE id = 1").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT id, company FROM users ORDER BY id"), vec![
+            vec!["1", "NULL"], vec!["2", "3"], vec!["5", "NULL"],
+        ]);
+        assert!(matches!(engine.execute("DELETE FROM users WHERE id = 1"), Ok(QueryResult::Delete(1))));
+        assert_eq!(rows(&mut engine, "SELECT id FROM orders"), vec![vec!["12"]]);
+
+        // A failed statement leaves every table untouched
+        engine.execute("CREATE TABLE audits (id INTEGER, order_id INTEGER REFERENCES orders(id))").unwrap();
+        engine.execute("INSERT INTO audits VALUES (1, 12)").unwrap();
+        assert!(matches!(engine.execute("DELETE FROM users"), Err(QueryError::ForeignKeyViolation(_))));
+        assert_eq!(rows(&mut engine, "SELECT COUNT(*) FROM users"), vec![vec!["2"]]);
+        assert_eq!(rows(&mut engine, "SELECT COUNT(*) FROM orders"), vec![vec!["1"]]);
+    }
+
+    #[test]
+    fn test_foreign_key_definitions_and_drop() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert!(matches!(engine.execute("CREATE TABLE a (x INTEGER REFERENCES missing(id))"), Err(QueryError::TableNotFound(_))));
+        assert!(matches!(engine.execute("CREATE TABLE a (x INTEGER REFERENCES users(age))"), Err(QueryError::ColumnNotFound(_))));
+        assert!(matches!(engine.execute("CREATE TABLE a (x INTEGER REFERENCES users(name))"), Err(QueryError::SyntaxError(_))));
+        assert!(matches!(engine.execute("CREATE TABLE a (x VARCHAR 5 REFERENCES users(id))"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(engine.execute("CREATE TABLE a (x INTEGER REFERENCES users(id) ON DELETE NOTHING)"), Err(QueryError::SyntaxError(_))));
+
+        // The constraints survive a restart
+        drop(engine);
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert!(matches!(engine.execute("INSERT INTO orders VALUES (13, 9, 1.0)"), Err(QueryError::ForeignKeyViolation(_))));
+
+        assert!(matches!(engine.execute("DROP TABLE users"), Err(QueryError::ForeignKeyViolation(_))));
+        assert!(matches!(engine.execute("DROP TABLE users RESTRICT"), Err(QueryError::ForeignKeyViolation(_))));
+        engine.execute("DROP TABLE users CASCADE").unwrap();
+
+        // Only the referencing constraint goes, the orders themselves stay
+        engine.execute("INSERT INTO orders VALUES (13, 9, 1.0)").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT COUNT(*) FROM orders"), vec![vec!["4"]]);
+        engine.execute("DROP TABLE companies").unwrap();
+    }
+}
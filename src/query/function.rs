@@ -0,0 +1,754 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::OnceLock;
+use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc};
+use crate::index::tokenize;
+use crate::metadata::{ColumnType, parse_timestamp};
+use super::error::QueryError;
+use super::parser::expression::Expression;
+use super::value::Value;
+
+/// Works out a function's result type from the types of its arguments
+/// An unknown type (None), such as that of NULL, is only checked once the function runs
+pub type ReturnType = fn(&[Option<ColumnType>]) -> Result<Option<ColumnType>, QueryError>;
+
+/// Computes a function's result from its argument values
+pub type Implementation = fn(&[Value]) -> Result<Value, QueryError>;
+
+/// A built-in function applied to the values of a single row
+pub struct ScalarFunction {
+    pub name: &'static str,
+    pub min_args: usize,
+    pub max_args: Option<usize>, // None for any number of arguments
+    pub null_on_null: bool,      // a NULL argument makes the result NULL without calling the function
+    pub return_type: ReturnType,
+    pub evaluate: Implementation,
+}
+
+/// The scalar functions known to the engine, by upper case name
+pub struct FunctionRegistry {
+    functions: HashMap<&'static str, ScalarFunction>,
+}
+
+impl Default for FunctionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FunctionRegistry {
+    /// An empty registry
+    pub fn new() -> Self {
+        FunctionRegistry { functions: HashMap::new() }
+    }
+
+    /// The registry of built-in functions used when parsing expressions
+    pub fn builtins() -> &'static FunctionRegistry {
+        static BUILTINS: OnceLock<FunctionRegistry> = OnceLock::new();
+        BUILTINS.get_or_init(|| {
+            let mut registry = FunctionRegistry::new();
+            register_string_functions(&mut registry);
+            register_numeric_functions(&mut registry);
+            register_null_functions(&mut registry);
+            register_date_functions(&mut registry);
+            registry
+        })
+    }
+
+    pub fn register(&mut self, function: ScalarFunction) {
+        self.functions.insert(function.name, function);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ScalarFunction> {
+        self.functions.get(name.to_uppercase().as_str())
+    }
+
+    /// Look up a function and check it accepts the given number of arguments
+    pub fn resolve(&self, name: &str, arg_count: usize) -> Result<&ScalarFunction, QueryError> {
+        let function = self.get(name)
+            .ok_or_else(|| QueryError::SyntaxError(format!("Unknown function: {}", name)))?;
+
+        let expected = match function.max_args {
+            Some(max) if max == function.min_args => format!("{}", max),
+            Some(max) => format!("{} to {}", function.min_args, max),
+            None => format!("at least {}", function.min_args),
+        };
+        if arg_count < function.min_args || function.max_args.is_some_and(|max| arg_count > max) {
+            return Err(QueryError::SyntaxError(format!(
+                "{} expects {} arguments, got {}", function.name, expected, arg_count
+            )));
+        }
+        Ok(function)
+    }
+
+    /// Result type of a call, rejecting arguments of the wrong type
+    pub fn check_call(&self, name: &str, argument_types: &[Option<ColumnType>]) -> Result<Option<ColumnType>, QueryError> {
+        (self.resolve(name, argument_types.len())?.return_type)(argument_types)
+    }
+
+    /// Apply a function to its argument values
+    pub fn call(&self, name: &str, arguments: &[Value]) -> Result<Value, QueryError> {
+        let function = self.resolve(name, arguments.len())?;
+        if function.null_on_null && arguments.iter().any(Value::is_null) {
+            return Ok(Value::Null);
+        }
+        (function.evaluate)(arguments)
+    }
+}
+
+fn is_text(data_type: &ColumnType) -> bool {
+    matches!(data_type, ColumnType::Varchar(_))
+}
+
+fn is_numeric(data_type: &ColumnType) -> bool {
+    matches!(data_type, ColumnType::Integer | ColumnType::Float)
+}
+
+fn is_integer(data_type: &ColumnType) -> bool {
+    matches!(data_type, ColumnType::Integer)
+}
+
+/// Characters a value of the type renders to, none when the type is unknown
+fn text_length(data_type: &Option<ColumnType>) -> Option<usize> {
+    data_type.as_ref().map_or(Some(0), Expression::display_length)
+}
+
+// Dates may also be written as text, which is parsed when the function runs
+fn is_timestamp(data_type: &ColumnType) -> bool {
+    matches!(data_type, ColumnType::Timestamp | ColumnType::Varchar(_))
+}
+
+/// A test an argument type must pass, with a description of what it expects
+type ArgumentCheck<'a> = (fn(&ColumnType) -> bool, &'a str);
+
+/// Check each argument type against what the function expects there; the last check covers any further arguments
+/// Arguments of unknown type pass, and are checked by the function itself when it runs
+fn expect_arguments(name: &str, types: &[Option<ColumnType>], checks: &[ArgumentCheck]) -> Result<(), QueryError> {
+    for (index, data_type) in types.iter().enumerate() {
+        let Some(data_type) = data_type else {
+            continue;
+        };
+        let (check, expected) = checks[index.min(checks.len() - 1)];
+        if !check(data_type) {
+            return Err(QueryError::TypeMismatch(format!(
+                "Argument {} of {} must be {}, got {}", index + 1, name, expected, data_type
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn as_number(name: &str, value: &Value) -> Result<f64, QueryError> {
+    match value {
+        Value::Integer(_) | Value::Float(_) => value.as_f64(),
+        _ => None,
+    }
+    .ok_or_else(|| QueryError::TypeMismatch(format!("{} expects a number, got '{}'", name, value)))
+}
+
+fn as_integer(name: &str, value: &Value) -> Result<i64, QueryError> {
+    match value {
+        Value::Integer(num) => Ok(*num),
+        other => Err(QueryError::TypeMismatch(format!("{} expects an integer, got '{}'", name, other))),
+    }
+}
+
+fn as_datetime(name: &str, value: &Value) -> Result<DateTime<Utc>, QueryError> {
+    let seconds = match value {
+        Value::Timestamp(ts) => Some(*ts),
+        Value::Text(text) => parse_timestamp(text),
+        _ => None,
+    };
+    seconds.and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .ok_or_else(|| QueryError::TypeMismatch(format!("{} expects a timestamp, got '{}'", name, value)))
+}
+
+/// Convert a float result to an integer, failing when it doesn't fit
+fn float_to_integer(name: &str, num: f64) -> Result<Value, QueryError> {
+    if num.is_finite() && num >= i64::MIN as f64 && num <= i64::MAX as f64 {
+        Ok(Value::Integer(num as i64))
+    } else {
+        Err(QueryError::InvalidValue(format!("{} result {} is out of integer range", name, num)))
+    }
+}
+
+fn register_string_functions(registry: &mut FunctionRegistry) {
+    registry.register(ScalarFunction {
+        name: "UPPER",
+        min_args: 1,
+        max_args: Some(1),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("UPPER", types, &[(is_text, "text")])?;
+            Ok(types[0].clone())
+        },
+        evaluate: |args| Ok(Value::Text(args[0].to_string().to_uppercase())),
+    });
+    registry.register(ScalarFunction {
+        name: "LOWER",
+        min_args: 1,
+        max_args: Some(1),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("LOWER", types, &[(is_text, "text")])?;
+            Ok(types[0].clone())
+        },
+        evaluate: |args| Ok(Value::Text(args[0].to_string().to_lowercase())),
+    });
+    registry.register(ScalarFunction {
+        name: "TRIM",
+        min_args: 1,
+        max_args: Some(1),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("TRIM", types, &[(is_text, "text")])?;
+            Ok(types[0].clone())
+        },
+        evaluate: |args| Ok(Value::Text(args[0].to_string().trim().to_string())),
+    });
+    registry.register(ScalarFunction {
+        name: "LENGTH",
+        min_args: 1,
+        max_args: Some(1),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("LENGTH", types, &[(is_text, "text")])?;
+            Ok(Some(ColumnType::Integer))
+        },
+        evaluate: |args| Ok(Value::Integer(args[0].to_string().chars().count() as i64)),
+    });
+    // SUBSTR(text, start [, length]) with 1-based character positions
+    registry.register(ScalarFunction {
+        name: "SUBSTR",
+        min_args: 2,
+        max_args: Some(3),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("SUBSTR", types, &[(is_text, "text"), (is_integer, "an integer")])?;
+            Ok(types[0].clone())
+        },
+        evaluate: |args| {
+            let text = args[0].to_string();
+            let start = as_integer("SUBSTR", &args[1])?;
+            let end = match args.get(2) {
+                Some(length) => {
+                    let length = as_integer("SUBSTR", length)?;
+                    if length < 0 {
+                        return Err(QueryError::InvalidValue(format!("SUBSTR length must not be negative, got {}", length)));
+                    }
+                    start.saturating_add(length)
+                }
+                None => i64::MAX,
+            };
+
+            // Positions before the first character count towards the length but select nothing
+            let skip = (start.max(1) - 1) as usize;
+            let take = (end - start.max(1)).max(0) as usize;
+            Ok(Value::Text(text.chars().skip(skip).take(take).collect()))
+        },
+    });
+    registry.register(ScalarFunction {
+        name: "REPLACE",
+        min_args: 3,
+        max_args: Some(3),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("REPLACE", types, &[(is_text, "text")])?;
+            Ok(Some(ColumnType::Varchar(
+                text_length(&types[0]).zip(text_length(&types[2])).map(|(text, to)| text.saturating_mul(to.max(1)))
+            )))
+        },
+        evaluate: |args| {
+            let from = args[1].to_string();
+            if from.is_empty() {
+                return Ok(Value::Text(args[0].to_string()));
+            }
+            Ok(Value::Text(args[0].to_string().replace(&from, &args[2].to_string())))
+        },
+    });
+    // CONCAT skips NULL arguments, unlike ||
+    registry.register(ScalarFunction {
+        name: "CONCAT",
+        min_args: 1,
+        max_args: None,
+        null_on_null: false,
+        return_type: |types| Ok(Some(ColumnType::Varchar(
+            types.iter().map(text_length).try_fold(0usize, |total, length| length.map(|length| total.saturating_add(length)))
+        ))),
+        evaluate: |args| Ok(Value::Text(
+            args.iter().filter(|arg| !arg.is_null()).map(Value::to_string).collect()
+        )),
+    });
+    // MATCH(column, ..., query) holds when the columns share a word with the query; it needs a
+    // FULLTEXT index on those columns, through which the rows come back best BM25 match first
+    registry.register(ScalarFunction {
+        name: "MATCH",
+        min_args: 2,
+        max_args: None,
+        null_on_null: false,
+        return_type: |types| {
+            expect_arguments("MATCH", types, &[(is_text, "text")])?;
+            Ok(Some(ColumnType::Boolean))
+        },
+        evaluate: |args| {
+            let (query, texts) = args.split_last().expect("MATCH takes at least two arguments");
+            if query.is_null() {
+                return Ok(Value::Null);
+            }
+            let terms: HashSet<String> = tokenize(&query.to_string()).into_iter().collect();
+            Ok(Value::Boolean(texts.iter()
+                .filter(|text| !text.is_null())
+                .any(|text| tokenize(&text.to_string()).iter().any(|word| terms.contains(word)))))
+        },
+    });
+}
+
+fn register_numeric_functions(registry: &mut FunctionRegistry) {
+    registry.register(ScalarFunction {
+        name: "ABS",
+        min_args: 1,
+        max_args: Some(1),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("ABS", types, &[(is_numeric, "a number")])?;
+            Ok(types[0].clone())
+        },
+        evaluate: |args| match &args[0] {
+            Value::Integer(num) => num.checked_abs()
+                .map(Value::Integer)
+                .ok_or_else(|| QueryError::InvalidValue(format!("Integer overflow in ABS({})", num))),
+            other => Ok(Value::Float(as_number("ABS", other)?.abs())),
+        },
+    });
+    // ROUND(number [, digits]) rounds half away from zero; negative digits round left of the point,
+    // the only way an integer changes
+    registry.register(ScalarFunction {
+        name: "ROUND",
+        min_args: 1,
+        max_args: Some(2),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("ROUND", types, &[(is_numeric, "a number"), (is_integer, "an integer")])?;
+            Ok(types[0].clone())
+        },
+        evaluate: |args| {
+            let digits = match args.get(1) {
+                Some(digits) => as_integer("ROUND", digits)?,
+                None => 0,
+            };
+            match &args[0] {
+                Value::Integer(num) if digits >= 0 => Ok(Value::Integer(*num)),
+                Value::Integer(num) => {
+                    // Beyond 10^18 every i64 rounds to 0
+                    let Some(factor) = 10i64.checked_pow(digits.unsigned_abs().min(19) as u32) else {
+                        return Ok(Value::Integer(0));
+                    };
+                    let (quotient, remainder) = (num / factor, num % factor);
+                    let quotient = if remainder.unsigned_abs() * 2 >= factor.unsigned_abs() { quotient + num.signum() } else { quotient };
+                    quotient.checked_mul(factor)
+                        .map(Value::Integer)
+                        .ok_or_else(|| QueryError::InvalidValue(format!("Integer overflow in ROUND({}, {})", num, digits)))
+                }
+                other => {
+                    let number = as_number("ROUND", other)?;
+                    // Dividing by a whole power of ten keeps ROUND(1234.5, -1) exact
+                    let rounded = if digits >= 0 {
+                        let scale = 10f64.powi(digits.min(308) as i32);
+                        (number * scale).round() / scale
+                    } else {
+                        let scale = 10f64.powi(digits.unsigned_abs().min(308) as i32);
+                        (number / scale).round() * scale
+                    };
+                    Ok(Value::Float(rounded))
+                }
+            }
+        },
+    });
+    registry.register(ScalarFunction {
+        name: "FLOOR",
+        min_args: 1,
+        max_args: Some(1),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("FLOOR", types, &[(is_numeric, "a number")])?;
+            Ok(Some(ColumnType::Integer))
+        },
+        evaluate: |args| float_to_integer("FLOOR", as_number("FLOOR", &args[0])?.floor()),
+    });
+    registry.register(ScalarFunction {
+        name: "CEIL",
+        min_args: 1,
+        max_args: Some(1),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("CEIL", types, &[(is_numeric, "a number")])?;
+            Ok(Some(ColumnType::Integer))
+        },
+        evaluate: |args| float_to_integer("CEIL", as_number("CEIL", &args[0])?.ceil()),
+    });
+    registry.register(ScalarFunction {
+        name: "MOD",
+        min_args: 2,
+        max_args: Some(2),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("MOD", types, &[(is_numeric, "a number")])?;
+            match (&types[0], &types[1]) {
+                (Some(ColumnType::Integer), Some(ColumnType::Integer)) => Ok(Some(ColumnType::Integer)),
+                _ => Ok(Some(ColumnType::Float)),
+            }
+        },
+        evaluate: |args| {
+            as_number("MOD", &args[0])?;
+            as_number("MOD", &args[1])?;
+            args[0].arithmetic("%", &args[1])
+        },
+    });
+    registry.register(ScalarFunction {
+        name: "POWER",
+        min_args: 2,
+        max_args: Some(2),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("POWER", types, &[(is_numeric, "a number")])?;
+            Ok(Some(ColumnType::Float))
+        },
+        evaluate: |args| {
+            let (base, exponent) = (as_number("POWER", &args[0])?, as_number("POWER", &args[1])?);
+            let result = base.powf(exponent);
+            // A FLOAT can't hold infinity or NaN, so overflow and roots of negative numbers are errors
+            if !result.is_finite() {
+                return Err(QueryError::InvalidValue(format!("POWER({}, {}) is out of range", args[0], args[1])));
+            }
+            Ok(Value::Float(result))
+        },
+    });
+}
+
+fn register_null_functions(registry: &mut FunctionRegistry) {
+    // COALESCE(a, b, ...) gives the first argument that isn't NULL
+    registry.register(ScalarFunction {
+        name: "COALESCE",
+        min_args: 1,
+        max_args: None,
+        null_on_null: false,
+        return_type: |types| {
+            types[1..].iter().try_fold(types[0].clone(), |common, data_type| {
+                Expression::common_type(&common, data_type, |common, data_type| format!(
+                    "COALESCE arguments have incompatible types {} and {}", common, data_type
+                ))
+            })
+        },
+        evaluate: |args| Ok(args.iter().find(|arg| !arg.is_null()).cloned().unwrap_or(Value::Null)),
+    });
+    // NULLIF(a, b) gives NULL when a equals b, otherwise a
+    registry.register(ScalarFunction {
+        name: "NULLIF",
+        min_args: 2,
+        max_args: Some(2),
+        null_on_null: false,
+        return_type: |types| {
+            Expression::common_type(&types[0], &types[1], |left, right| format!(
+                "Cannot compare {} with {} in NULLIF", left, right
+            ))?;
+            Ok(types[0].clone())
+        },
+        evaluate: |args| {
+            let equal = !args[1].is_null() && args[0].compare(&args[1]) == Some(std::cmp::Ordering::Equal);
+            Ok(if equal { Value::Null } else { args[0].clone() })
+        },
+    });
+}
+
+fn register_date_functions(registry: &mut FunctionRegistry) {
+    registry.register(ScalarFunction {
+        name: "NOW",
+        min_args: 0,
+        max_args: Some(0),
+        null_on_null: false,
+        return_type: |_| Ok(Some(ColumnType::Timestamp)),
+        evaluate: |_| Ok(Value::Timestamp(Utc::now().timestamp())),
+    });
+    // DATE_TRUNC('month', ts) gives the start of the unit the timestamp falls in
+    registry.register(ScalarFunction {
+        name: "DATE_TRUNC",
+        min_args: 2,
+        max_args: Some(2),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("DATE_TRUNC", types, &[(is_text, "a unit name"), (is_timestamp, "a timestamp")])?;
+            Ok(Some(ColumnType::Timestamp))
+        },
+        evaluate: |args| {
+            let datetime = as_datetime("DATE_TRUNC", &args[1])?;
+            let ts = datetime.timestamp();
+            let date = datetime.date_naive();
+            let start_of = |date: Option<NaiveDate>| date.and_then(|d| d.and_hms_opt(0, 0, 0)).map(|d| d.and_utc().timestamp());
+            let truncated = match args[0].to_string().to_lowercase().as_str() {
+                "year" => start_of(date.with_ordinal(1)),
+                "quarter" => start_of(NaiveDate::from_ymd_opt(date.year(), (date.month0() / 3) * 3 + 1, 1)),
+                "month" => start_of(date.with_day(1)),
+                "week" => start_of(date.checked_sub_days(chrono::Days::new(date.weekday().num_days_from_monday().into()))),
+                "day" => start_of(Some(date)),
+                "hour" => Some(ts - ts.rem_euclid(3_600)),
+                "minute" => Some(ts - ts.rem_euclid(60)),
+                "second" => Some(ts),
+                unit => return Err(QueryError::InvalidValue(format!("Unknown DATE_TRUNC unit: {}", unit))),
+            };
+            truncated.map(Value::Timestamp)
+                .ok_or_else(|| QueryError::InvalidValue(format!("Cannot truncate '{}'", args[1])))
+        },
+    });
+    // EXTRACT(field FROM ts) is parsed into EXTRACT('field', ts)
+    registry.register(ScalarFunction {
+        name: "EXTRACT",
+        min_args: 2,
+        max_args: Some(2),
+        null_on_null: true,
+        return_type: |types| {
+            expect_arguments("EXTRACT", types, &[(is_text, "a field name"), (is_timestamp, "a timestamp")])?;
+            Ok(Some(ColumnType::Integer))
+        },
+        evaluate: |args| {
+            let datetime = as_datetime("EXTRACT", &args[1])?;
+            let field = match args[0].to_string().to_lowercase().as_str() {
+                "year" => datetime.year().into(),
+                "quarter" => (datetime.month0() / 3 + 1).into(),
+                "month" => datetime.month().into(),
+                "day" => datetime.day().into(),
+                "hour" => datetime.hour().into(),
+                "minute" => datetime.minute().into(),
+                "second" => datetime.second().into(),
+                "dow" => datetime.weekday().num_days_from_sunday().into(),
+                "doy" => datetime.ordinal().into(),
+                "week" => datetime.iso_week().week().into(),
+                "epoch" => datetime.timestamp(),
+                field => return Err(QueryError::InvalidValue(format!("Unknown EXTRACT field: {}", field))),
+            };
+            Ok(Value::Integer(field))
+        },
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::QueryError;
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE products (id INTEGER, name VARCHAR 20, price FLOAT, qty INTEGER)",
+        "INSERT INTO products VALUES (1, 'Widget', 2.75, 10)",
+        "INSERT INTO products VALUES (2, 'gadget', -4.5, 0)",
+        "INSERT INTO products VALUES (3, 'Gizmo', 10.0, 7)",
+    ];
+
+    #[test]
+    fn test_string_functions() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT UPPER(name), LOWER(name), LENGTH(name), SUBSTR(name, 2, 3), REPLACE(name, 'g', 'G') \
+                     FROM products WHERE id = 2";
+        assert_eq!(rows(&mut engine, query), vec![vec!["GADGET", "gadget", "6", "adg", "GadGet"]]);
+
+        assert_eq!(
+            rows(&mut engine, "SELECT CONCAT(name, '-', id), SUBSTR(name, 0, 3), SUBSTR(name, 4), TRIM('  x  ') FROM products WHERE id = 1"),
+            vec![vec!["Widget-1", "Wi", "get", "x"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM products WHERE LOWER(name) = 'gizmo'"),
+            vec![vec!["Gizmo"]]
+        );
+    }
+
+    #[test]
+    fn test_numeric_functions() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT ABS(price), ROUND(price, 1), FLOOR(price), CEIL(price), MOD(qty, 3), POWER(qty, 2) \
+                     FROM products ORDER BY id";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["2.75", "2.8", "2", "3", "1", "100"],
+            vec!["4.5", "-4.5", "-5", "-4", "0", "0"],
+            vec!["10", "10", "10", "10", "1", "49"],
+        ]);
+
+        // Aggregates inside a function still group the rows
+        assert_eq!(rows(&mut engine, "SELECT ROUND(AVG(qty), 2) FROM products"), vec![vec!["5.67"]]);
+
+        assert!(matches!(engine.execute("SELECT MOD(qty, 0) FROM products"), Err(QueryError::InvalidValue(_))));
+
+        // Negative digits round left of the decimal point, integers included
+        assert_eq!(
+            rows(&mut engine, "SELECT ROUND(1234.5, -1), ROUND(-1250.0, -2), ROUND(1250, -2), ROUND(-1249, -2), ROUND(qty, -1), ROUND(5, -19) \
+                               FROM products WHERE id = 3"),
+            vec![vec!["1230", "-1300", "1300", "-1200", "10", "0"]]
+        );
+        assert!(matches!(engine.execute("SELECT ROUND(9223372036854775807, -1) FROM products"), Err(QueryError::InvalidValue(_))));
+
+        // The smallest INTEGER is read as an INTEGER, so its absolute value overflows
+        assert_eq!(
+            rows(&mut engine, "SELECT -9223372036854775808, ABS(-9223372036854775807), - -5 FROM products WHERE id = 1"),
+            vec![vec!["-9223372036854775808", "9223372036854775807", "5"]]
+        );
+        assert!(matches!(
+            engine.execute("SELECT ABS(-9223372036854775808) FROM products"),
+            Err(QueryError::InvalidValue(message)) if message == "Integer overflow in ABS(-9223372036854775808)"
+        ));
+        assert!(matches!(engine.execute("SELECT -(-9223372036854775808) FROM products"), Err(QueryError::InvalidValue(_))));
+
+        // A FLOAT can't hold what overflows or has no real value
+        for query in [
+            "SELECT POWER(10, 400) FROM products",
+            "SELECT POWER(price, 0.5) FROM products WHERE id = 2",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::InvalidValue(_))), "{}", query);
+        }
+        assert!(matches!(engine.execute("UPDATE products SET price = POWER(price, 1000)"), Err(QueryError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_null_functions_cast_and_case() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT id, NULLIF(qty, 0), COALESCE(NULLIF(qty, 0), -1), CAST(price AS INTEGER), CAST(id AS VARCHAR 5), \
+                     CASE WHEN qty = 0 THEN 'none' WHEN qty < 8 THEN 'few' ELSE 'many' END AS stock, \
+                     CASE id WHEN 1 THEN 'one' WHEN 2 THEN 'two' END \
+                     FROM products ORDER BY id";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["1", "10", "10", "3", "1", "many", "one"],
+            vec!["2", "NULL", "-1", "-5", "2", "none", "two"],
+            vec!["3", "7", "7", "10", "3", "few", "NULL"],
+        ]);
+
+        assert_eq!(
+            rows(&mut engine, "SELECT CAST('42' AS INTEGER) + 1, CAST(qty AS FLOAT) / 4 FROM products WHERE id = 1"),
+            vec![vec!["43", "2.5"]]
+        );
+        assert!(matches!(
+            engine.execute("SELECT CAST(name AS INTEGER) FROM products"),
+            Err(QueryError::InvalidValue(_))
+        ));
+
+        // VARCHAR without a length holds text of any length
+        assert_eq!(
+            rows(&mut engine, "SELECT CAST(price AS VARCHAR), CAST(id AS varchar) || name, LENGTH(CAST(qty * 1000000 AS VARCHAR)) FROM products WHERE id = 1"),
+            vec![vec!["2.75", "1Widget", "8"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT CONCAT(CAST(id AS VARCHAR), name), REPLACE(CAST(qty AS VARCHAR), '0', name) FROM products WHERE id = 1"),
+            vec![vec!["1Widget", "1Widget"]]
+        );
+        assert!(matches!(
+            engine.execute("SELECT CAST(id AS VARCHAR) + 1 FROM products"),
+            Err(QueryError::TypeMismatch(message)) if message == "Cannot apply '+' to VARCHAR and INTEGER"
+        ));
+    }
+
+    #[test]
+    fn test_function_type_checks() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("CREATE TABLE notes (id INTEGER, note VARCHAR 0)").unwrap();
+
+        for query in [
+            "SELECT UPPER(qty) FROM products",
+            "SELECT ABS(name) FROM products",
+            "SELECT SUBSTR(name, 'a') FROM products",
+            "SELECT COALESCE(qty, name) FROM products",
+            "SELECT CASE WHEN qty > 1 THEN name ELSE qty END FROM products",
+            "SELECT CASE WHEN qty THEN 1 END FROM products",
+            "SELECT ABS(note) FROM notes",
+            "SELECT MOD(id, note) FROM notes",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::TypeMismatch(_))), "{}", query);
+        }
+
+        // NULL has no type of its own, so it passes as any argument
+        assert_eq!(rows(&mut engine, "SELECT ABS(NULL), UPPER(NULL), COALESCE(NULL, qty) FROM products WHERE id = 1"),
+            vec![vec!["NULL".to_string(), "NULL".to_string(), "10".to_string()]]);
+
+        for query in [
+            "SELECT UPPER(name, name) FROM products",
+            "SELECT NO_SUCH_FUNCTION(name) FROM products",
+            "SELECT CASE qty END FROM products",
+            "SELECT CAST(qty AS BLOB) FROM products",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_date_functions() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("CREATE TABLE events (id INTEGER, happened TIMESTAMP)").unwrap();
+        engine.execute("INSERT INTO events VALUES (1, '2024-05-01 12:30:45')").unwrap();
+        engine.execute("INSERT INTO events VALUES (2, '2024-01-31T08:00:00Z')").unwrap();
+        engine.execute("INSERT INTO events VALUES (3, '2023-12-25')").unwrap();
+
+        assert_eq!(rows(&mut engine, "SELECT * FROM events WHERE id = 1"), vec![vec!["1", "2024-05-01 12:30:45"]]);
+        assert_eq!(
+            rows(&mut engine, "SELECT id FROM events WHERE happened >= '2024-01-31' ORDER BY happened"),
+            vec![vec!["2"], vec!["1"]]
+        );
+
+        let query = "SELECT DATE_TRUNC('month', happened), DATE_TRUNC('hour', happened), \
+                     EXTRACT(YEAR FROM happened), EXTRACT(month FROM happened), EXTRACT(DOW FROM happened) \
+                     FROM events ORDER BY id";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["2024-05-01 00:00:00", "2024-05-01 12:00:00", "2024", "5", "3"],
+            vec!["2024-01-01 00:00:00", "2024-01-31 08:00:00", "2024", "1", "3"],
+            vec!["2023-12-01 00:00:00", "2023-12-25 00:00:00", "2023", "12", "1"],
+        ]);
+
+        // Month arithmetic keeps the day where the month allows it
+        let query = "SELECT happened + INTERVAL '1 month', happened - INTERVAL '2 days 3 hours', happened + INTERVAL '90' MINUTE \
+                     FROM events WHERE id = 2";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["2024-02-29 08:00:00", "2024-01-29 05:00:00", "2024-01-31 09:30:00"],
+        ]);
+        assert_eq!(
+            rows(&mut engine, "SELECT CAST('2024-05-01' AS TIMESTAMP) + INTERVAL '1 year', EXTRACT(EPOCH FROM '1970-01-02') FROM events WHERE id = 1"),
+            vec![vec!["2025-05-01 00:00:00", "86400"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT COUNT(*) FROM events WHERE happened < NOW() AND NOW() - INTERVAL '1 day' > happened"),
+            vec![vec!["3"]]
+        );
+
+        assert!(matches!(
+            engine.execute("INSERT INTO events VALUES (4, 'next tuesday')"),
+            Err(QueryError::TypeMismatch(_))
+        ));
+        assert!(matches!(engine.execute("SELECT EXTRACT(YEAR FROM id) FROM events"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(engine.execute("SELECT happened + INTERVAL '2 fortnights' FROM events"), Err(QueryError::SyntaxError(_))));
+        assert!(matches!(engine.execute("SELECT DATE_TRUNC('decade', happened) FROM events"), Err(QueryError::InvalidValue(_))));
+        // The unit is only looked at once there is a timestamp to apply it to
+        engine.execute("INSERT INTO events VALUES (4, NULL)").unwrap();
+        assert_eq!(
+            rows(&mut engine, "SELECT DATE_TRUNC('day', happened), EXTRACT(CENTURY FROM happened), happened + INTERVAL '1 day' FROM events WHERE id = 4"),
+            vec![vec!["NULL", "NULL", "NULL"]]
+        );
+        assert!(matches!(engine.execute("SELECT EXTRACT(CENTURY FROM '2024-05-01') FROM events"), Err(QueryError::InvalidValue(_))));
+        for literal in ["2024-13-01", "2024-02-30", "2024-05-01 25:00:00", "2024-05", ""] {
+            assert!(
+                matches!(engine.execute(&format!("INSERT INTO events VALUES (4, '{}')", literal)), Err(QueryError::TypeMismatch(_))),
+                "{}", literal
+            );
+        }
+        for query in [
+            "SELECT EXTRACT(YEAR happened) FROM events",
+            "SELECT EXTRACT(FROM happened) FROM events",
+            "SELECT DATE_TRUNC('day') FROM events",
+            "SELECT happened + INTERVAL FROM events",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+
+        engine.execute("CREATE TABLE holidays (day TIMESTAMP)").unwrap();
+        assert!(rows(&mut engine, "SELECT DATE_TRUNC('month', day), day + INTERVAL '1 day' FROM holidays").is_empty());
+    }
+}
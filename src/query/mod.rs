@@ -1,8 +1,22 @@
 pub mod error;
 pub mod result;
+pub mod value;
+pub mod function;
+pub mod record;
+pub mod constraint;
+pub mod foreign_key;
+pub mod catalog;
+pub mod planner;
 pub mod engine;
 pub mod parser;
 
 pub use error::QueryError;
 pub use result::QueryResult;
-pub use engine::QueryEngine; 
\ No newline at end of file
+pub use value::Value;
+pub use function::FunctionRegistry;
+pub use record::{RecordCodec, Row};
+pub use constraint::ConstraintChecker;
+pub use foreign_key::ForeignKeyEnforcer;
+pub use catalog::Catalog;
+pub use planner::{AccessPlanner, AccessPath};
+pub use engine::{QueryEngine, SessionId}; 
\ No newline at end of file
@@ -1,8 +1,9 @@
-use std::collections::HashMap;
-use crate::metadata::{Table, ColumnType};
-use crate::storage::{LSMEngine, Record};
+use std::collections::{HashMap, HashSet};
+use crate::metadata::Table;
 use crate::query::error::QueryError;
-use crate::query::result::{QueryResult, AggregationResult};
+use crate::query::record::Row;
+use crate::query::value::Value;
+use super::expression::{Expression, ExpressionParser, ExpressionContext, JoinedRowContext};
 
 #[derive(Debug, Clone)]
 pub enum AggregateFunction {
@@ -11,29 +12,85 @@ pub enum AggregateFunction {
     Avg(String),      // column name
     Min(String),      // column name
     Max(String),      // column name
+    CountDistinct(String), // column name
+    SumDistinct(String),   // column name
+    AvgDistinct(String),   // column name
 }
 
+/// A group key with the rows that share it
+pub type RowGroup = (Vec<Option<String>>, Vec<Row>);
+
 #[derive(Debug)]
 pub struct AggregationClause {
     pub functions: Vec<AggregateFunction>,
     pub group_by_columns: Vec<String>,
+    pub having: Option<Expression>,
+}
+
+/// Resolves column and aggregate references against one group of rows
+/// Used for the SELECT list, HAVING and ORDER BY of a grouped query
+pub struct GroupContext<'a> {
+    pub parser: &'a AggregationParser,
+    pub tables: &'a [(&'a str, &'a Table)],
+    pub group_by_columns: &'a [String],
+    pub group_key: &'a [Option<String>],
+    pub rows: &'a [Row],
+}
+
+impl ExpressionContext for GroupContext<'_> {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError> {
+        // Compare resolved columns so that `dept` and `e.dept` refer to the same group key
+        let column_index = JoinedRowContext::column_index(self.tables, name)?;
+        let mut key_index = None;
+        for (i, group_column) in self.group_by_columns.iter().enumerate() {
+            if JoinedRowContext::column_index(self.tables, group_column)? == column_index {
+                key_index = Some(i);
+                break;
+            }
+        }
+        let key_index = key_index.ok_or_else(|| QueryError::SyntaxError(format!(
+            "Column '{}' must appear in GROUP BY or be used in an aggregate function", name
+        )))?;
+        let data_type = JoinedRowContext::column_type(self.tables, name)?;
+        Ok(Value::from_column(self.group_key[key_index].as_deref(), &data_type))
+    }
+
+    fn aggregate_value(&self, function: &AggregateFunction) -> Result<Value, QueryError> {
+        let (_, value) = self.parser.compute_aggregate_value(function, self.tables, self.rows)?;
+        Ok(Value::infer(value.as_deref()))
+    }
 }
 
 pub struct AggregationParser;
 
+impl Default for AggregationParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AggregationParser {
     pub fn new() -> Self {
         AggregationParser
     }
 
     /// Parse aggregation functions from SELECT clause tokens
-    /// Example: SUM(amount), COUNT(*), AVG(score)
+    /// Example: SUM(amount), COUNT(*), AVG(score), COUNT(DISTINCT region)
     pub fn parse_aggregation_functions(&self, select_tokens: &[&str]) -> Result<Vec<AggregateFunction>, QueryError> {
+        use super::column::ColumnParser;
+        let column_parser = ColumnParser::new();
+
+        let select_tokens = if column_parser.is_distinct(select_tokens) {
+            &select_tokens[1..]
+        } else {
+            select_tokens
+        };
+
         let mut functions = Vec::new();
         
-        for token in select_tokens {
-            if token.contains('(') && token.contains(')') {
-                let func = self.parse_single_function(token)?;
+        for item in column_parser.split_select_items(select_tokens) {
+            if self.is_aggregate_call(&item) {
+                let func = self.parse_single_function(&item)?;
                 functions.push(func);
             }
         }
@@ -41,6 +98,19 @@ impl AggregationParser {
         Ok(functions)
     }
 
+    /// Check whether a SELECT item is a call to one of the aggregate functions
+    /// A call with OVER is a window function and leaves the rows ungrouped
+    pub fn is_aggregate_call(&self, item: &str) -> bool {
+        match item.split_once('(') {
+            Some((name, rest)) => {
+                rest.contains(')')
+                    && matches!(name.trim().to_uppercase().as_str(), "SUM" | "COUNT" | "AVG" | "MIN" | "MAX")
+                    && !rest.split(|c: char| !c.is_alphanumeric() && c != '_').any(|word| word.eq_ignore_ascii_case("OVER"))
+            }
+            None => false,
+        }
+    }
+
     /// Parse GROUP BY clause
     /// Example: GROUP BY customer_id, region
     pub fn parse_group_by(&self, tokens: &[&str]) -> Result<Vec<String>, QueryError> {
@@ -57,7 +127,7 @@ impl AggregationParser {
         
         while i < tokens.len() {
             let token = tokens[i].trim_end_matches(',');
-            if token.to_uppercase() == "ORDER" || token.to_uppercase() == "HAVING" {
+            if matches!(token.to_uppercase().as_str(), "ORDER" | "HAVING" | "LIMIT") {
                 break;
             }
             group_columns.push(token.to_string());
@@ -71,177 +141,103 @@ impl AggregationParser {
         Ok(group_columns)
     }
 
-    fn parse_single_function(&self, token: &str) -> Result<AggregateFunction, QueryError> {
+    /// Parse HAVING clause if present
+    /// Example: HAVING COUNT(*) > 5 AND AVG(salary) > 50000
+    pub fn parse_having(&self, tokens: &[&str]) -> Result<Option<Expression>, QueryError> {
+        let having_index = match tokens.iter().position(|&t| t.to_uppercase() == "HAVING") {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let having_end = tokens.iter()
+            .skip(having_index + 1)
+            .position(|&t| matches!(t.to_uppercase().as_str(), "ORDER" | "LIMIT"))
+            .map(|offset| having_index + 1 + offset)
+            .unwrap_or(tokens.len());
+
+        let condition = ExpressionParser::new().parse(&tokens[having_index + 1..having_end])?;
+        Ok(Some(condition))
+    }
+
+    /// Parse a single aggregate call such as SUM(amount) or COUNT(DISTINCT id)
+    pub fn parse_single_function(&self, token: &str) -> Result<AggregateFunction, QueryError> {
         let open_paren = token.find('(')
             .ok_or_else(|| QueryError::SyntaxError("Invalid function syntax".to_string()))?;
-        let close_paren = token.find(')')
+        let close_paren = token.rfind(')')
             .ok_or_else(|| QueryError::SyntaxError("Invalid function syntax".to_string()))?;
 
-        let func_name = &token[..open_paren];
-        let column_name = &token[open_paren + 1..close_paren];
+        let func_name = token[..open_paren].trim();
+        let argument = token[open_paren + 1..close_paren].trim();
 
-        match func_name.to_uppercase().as_str() {
-            "SUM" => Ok(AggregateFunction::Sum(column_name.to_string())),
-            "COUNT" => Ok(AggregateFunction::Count(column_name.to_string())),
-            "AVG" => Ok(AggregateFunction::Avg(column_name.to_string())),
-            "MIN" => Ok(AggregateFunction::Min(column_name.to_string())),
-            "MAX" => Ok(AggregateFunction::Max(column_name.to_string())),
-            _ => Err(QueryError::SyntaxError(format!("Unsupported function: {}", func_name))),
-        }
-    }
+        // DISTINCT inside the parentheses: COUNT(DISTINCT col)
+        let (distinct, column_name) = match argument.split_once(char::is_whitespace) {
+            Some((keyword, rest)) if keyword.to_uppercase() == "DISTINCT" => (true, rest.trim()),
+            _ => (false, argument),
+        };
 
-    /// Execute aggregation query
-    pub fn execute_aggregation(
-        &self,
-        aggregation_clause: &AggregationClause,
-        table: &Table,
-        storage_engine: &mut LSMEngine,
-    ) -> Result<QueryResult, QueryError> {
-        // Get all records
-        let all_records = storage_engine.get_all_records()
-            .map_err(|e| QueryError::InternalError(format!("Failed to get all records: {}", e)))?;
-
-        // Parse all records to row data
-        let mut all_rows = Vec::new();
-        for record in all_records {
-            let row_data = self.parse_record_data(&record, table)?;
-            all_rows.push(row_data);
+        if column_name.is_empty() {
+            return Err(QueryError::SyntaxError(format!("Missing argument for function: {}", func_name)));
         }
-
-        if aggregation_clause.group_by_columns.is_empty() {
-            // No GROUP BY - single aggregation result
-            self.execute_single_aggregation(&aggregation_clause.functions, table, &all_rows)
-        } else {
-            // GROUP BY aggregation
-            self.execute_grouped_aggregation(aggregation_clause, table, &all_rows)
+        if distinct && column_name == "*" {
+            return Err(QueryError::SyntaxError(format!("{}(DISTINCT *) is not supported", func_name.to_uppercase())));
         }
-    }
 
-    /// Execute aggregation query with WHERE clause support
-    pub fn execute_aggregation_with_where(
-        &self,
-        aggregation_clause: &AggregationClause,
-        table: &Table,
-        storage_engine: &mut LSMEngine,
-        where_clause: Option<&super::r#where::WhereClause>,
-    ) -> Result<QueryResult, QueryError> {
-        use super::r#where::WhereParser;
-        let where_parser = WhereParser::new();
-
-        // Get all records
-        let all_records = storage_engine.get_all_records()
-            .map_err(|e| QueryError::InternalError(format!("Failed to get all records: {}", e)))?;
-
-        // Parse and filter records based on WHERE clause
-        let mut filtered_rows = Vec::new();
-        for record in all_records {
-            let row_data = self.parse_record_data(&record, table)?;
-            
-            // Apply WHERE clause filter if present
-            if let Some(where_clause) = where_clause {
-                if !where_parser.evaluate_where_clause(&row_data, table, where_clause)? {
-                    continue; // Skip this record
-                }
-            }
-            
-            filtered_rows.push(row_data);
-        }
-
-        if aggregation_clause.group_by_columns.is_empty() {
-            // No GROUP BY - single aggregation result
-            self.execute_single_aggregation(&aggregation_clause.functions, table, &filtered_rows)
-        } else {
-            // GROUP BY aggregation
-            self.execute_grouped_aggregation(aggregation_clause, table, &filtered_rows)
+        let column_name = column_name.to_string();
+        match (func_name.to_uppercase().as_str(), distinct) {
+            ("SUM", false) => Ok(AggregateFunction::Sum(column_name)),
+            ("SUM", true) => Ok(AggregateFunction::SumDistinct(column_name)),
+            ("COUNT", false) => Ok(AggregateFunction::Count(column_name)),
+            ("COUNT", true) => Ok(AggregateFunction::CountDistinct(column_name)),
+            ("AVG", false) => Ok(AggregateFunction::Avg(column_name)),
+            ("AVG", true) => Ok(AggregateFunction::AvgDistinct(column_name)),
+            // Duplicates never change the minimum or maximum
+            ("MIN", _) => Ok(AggregateFunction::Min(column_name)),
+            ("MAX", _) => Ok(AggregateFunction::Max(column_name)),
+            _ => Err(QueryError::SyntaxError(format!("Unsupported function: {}", func_name))),
         }
     }
 
-    fn execute_single_aggregation(
+    /// Split rows into groups by the GROUP BY columns, ordered by group key
+    /// Without GROUP BY all rows form a single group, even when there are none
+    pub fn group_rows(
         &self,
-        functions: &[AggregateFunction],
-        table: &Table,
-        rows: &[Vec<String>],
-    ) -> Result<QueryResult, QueryError> {
-        let mut result_row = Vec::new();
-        let mut headers = Vec::new();
-
-        for func in functions {
-            let (header, value) = self.compute_aggregate_value(func, table, rows)?;
-            headers.push(header);
-            result_row.push(value);
+        group_by_columns: &[String],
+        tables: &[(&str, &Table)],
+        rows: Vec<Row>,
+    ) -> Result<Vec<RowGroup>, QueryError> {
+        if group_by_columns.is_empty() {
+            return Ok(vec![(Vec::new(), rows)]);
         }
 
-        Ok(QueryResult::Aggregation(AggregationResult {
-            headers,
-            rows: vec![result_row],
-            group_by_columns: Vec::new(),
-        }))
-    }
-
-    fn execute_grouped_aggregation(
-        &self,
-        aggregation_clause: &AggregationClause,
-        table: &Table,
-        rows: &[Vec<String>],
-    ) -> Result<QueryResult, QueryError> {
         // Find group by column indices
         let mut group_col_indices = Vec::new();
-        for col_name in &aggregation_clause.group_by_columns {
-            let index = table.columns.iter()
-                .position(|c| c.name == *col_name)
-                .ok_or_else(|| QueryError::ColumnNotFound(col_name.clone()))?;
-            group_col_indices.push(index);
+        for col_name in group_by_columns {
+            group_col_indices.push(JoinedRowContext::column_index(tables, col_name)?);
         }
 
         // Group rows by group key
-        let mut groups: HashMap<Vec<String>, Vec<Vec<String>>> = HashMap::new();
-        
+        let mut groups: HashMap<Vec<Option<String>>, Vec<Row>> = HashMap::new();
         for row in rows {
-            let group_key: Vec<String> = group_col_indices.iter()
+            let group_key: Vec<Option<String>> = group_col_indices.iter()
                 .map(|&index| row[index].clone())
                 .collect();
-            
             groups.entry(group_key)
-                .or_insert_with(Vec::new)
-                .push(row.clone());
-        }
-
-        // Compute aggregations for each group
-        let mut result_rows = Vec::new();
-        let mut headers = aggregation_clause.group_by_columns.clone();
-        
-        // Add aggregate function headers
-        for func in &aggregation_clause.functions {
-            headers.push(self.get_function_header(func));
-        }
-
-        for (group_key, group_rows) in groups {
-            let mut result_row = group_key;
-            
-            for func in &aggregation_clause.functions {
-                let (_, value) = self.compute_aggregate_value(func, table, &group_rows)?;
-                result_row.push(value);
-            }
-            
-            result_rows.push(result_row);
+                .or_default()
+                .push(row);
         }
 
-        // Sort results for consistent output
-        result_rows.sort();
-
-        Ok(QueryResult::Aggregation(AggregationResult {
-            headers,
-            rows: result_rows,
-            group_by_columns: aggregation_clause.group_by_columns.clone(),
-        }))
+        // Sort groups for consistent output
+        let mut groups: Vec<_> = groups.into_iter().collect();
+        groups.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(groups)
     }
 
-    fn compute_aggregate_value(
+    pub fn compute_aggregate_value(
         &self,
         function: &AggregateFunction,
-        table: &Table,
-        rows: &[Vec<String>],
-    ) -> Result<(String, String), QueryError> {
+        tables: &[(&str, &Table)],
+        rows: &[Row],
+    ) -> Result<(String, Option<String>), QueryError> {
         match function {
             AggregateFunction::Count(col_name) => {
                 let header = if col_name == "*" {
@@ -253,84 +249,113 @@ impl AggregationParser {
                     rows.len()
                 } else {
                     // Count non-null values
-                    let col_index = table.columns.iter()
-                        .position(|c| c.name == *col_name)
-                        .ok_or_else(|| QueryError::ColumnNotFound(col_name.clone()))?;
+                    let col_index = JoinedRowContext::column_index(tables, col_name)?;
                     
                     rows.iter()
-                        .filter(|row| !row[col_index].is_empty() && row[col_index] != "null")
+                        .filter(|row| row[col_index].is_some())
                         .count()
                 };
-                Ok((header, count.to_string()))
+                Ok((header, Some(count.to_string())))
             },
             
             AggregateFunction::Sum(col_name) => {
                 let header = format!("SUM({})", col_name);
-                let col_index = table.columns.iter()
-                    .position(|c| c.name == *col_name)
-                    .ok_or_else(|| QueryError::ColumnNotFound(col_name.clone()))?;
+                let col_index = JoinedRowContext::column_index(tables, col_name)?;
                 
-                let sum: f64 = rows.iter()
-                    .filter_map(|row| row[col_index].parse::<f64>().ok())
-                    .sum();
+                let values: Vec<f64> = rows.iter()
+                    .filter_map(|row| row[col_index].as_deref()?.parse::<f64>().ok())
+                    .collect();
                 
-                Ok((header, sum.to_string()))
+                Ok((header, Self::sum_of(&values)))
             },
             
             AggregateFunction::Avg(col_name) => {
                 let header = format!("AVG({})", col_name);
-                let col_index = table.columns.iter()
-                    .position(|c| c.name == *col_name)
-                    .ok_or_else(|| QueryError::ColumnNotFound(col_name.clone()))?;
+                let col_index = JoinedRowContext::column_index(tables, col_name)?;
                 
                 let values: Vec<f64> = rows.iter()
-                    .filter_map(|row| row[col_index].parse::<f64>().ok())
+                    .filter_map(|row| row[col_index].as_deref()?.parse::<f64>().ok())
                     .collect();
                 
-                let avg = if values.is_empty() {
-                    0.0
+                Ok((header, Self::average_of(&values)))
+            },
+            
+            AggregateFunction::Min(col_name) | AggregateFunction::Max(col_name) => {
+                let header = self.get_function_header(function);
+                let col_index = JoinedRowContext::column_index(tables, col_name)?;
+                let data_type = JoinedRowContext::column_type(tables, col_name)?;
+
+                // Compare as typed values, so that 100 sorts after 60; no values give NULL
+                let values = rows.iter()
+                    .filter_map(|row| row[col_index].as_deref());
+                let compare = |a: &&str, b: &&str| {
+                    Value::from_column(Some(a), &data_type)
+                        .compare(&Value::from_column(Some(b), &data_type))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                };
+                let extreme = if matches!(function, AggregateFunction::Min(_)) {
+                    values.min_by(compare)
                 } else {
-                    values.iter().sum::<f64>() / values.len() as f64
+                    values.max_by(compare)
                 };
-                
-                Ok((header, avg.to_string()))
+
+                Ok((header, extreme.map(str::to_string)))
             },
-            
-            AggregateFunction::Min(col_name) => {
-                let header = format!("MIN({})", col_name);
-                let col_index = table.columns.iter()
-                    .position(|c| c.name == *col_name)
-                    .ok_or_else(|| QueryError::ColumnNotFound(col_name.clone()))?;
-                
-                let min_value = rows.iter()
-                    .map(|row| &row[col_index])
-                    .filter(|val| !val.is_empty() && *val != "null")
-                    .min()
-                    .unwrap_or(&"".to_string())
-                    .clone();
-                
-                Ok((header, min_value))
+
+            AggregateFunction::CountDistinct(col_name) => {
+                let header = self.get_function_header(function);
+                let values = self.distinct_column_values(col_name, tables, rows)?;
+                Ok((header, Some(values.len().to_string())))
             },
-            
-            AggregateFunction::Max(col_name) => {
-                let header = format!("MAX({})", col_name);
-                let col_index = table.columns.iter()
-                    .position(|c| c.name == *col_name)
-                    .ok_or_else(|| QueryError::ColumnNotFound(col_name.clone()))?;
-                
-                let max_value = rows.iter()
-                    .map(|row| &row[col_index])
-                    .filter(|val| !val.is_empty() && *val != "null")
-                    .max()
-                    .unwrap_or(&"".to_string())
-                    .clone();
-                
-                Ok((header, max_value))
+
+            AggregateFunction::SumDistinct(col_name) => {
+                let header = self.get_function_header(function);
+                let values: Vec<f64> = self.distinct_column_values(col_name, tables, rows)?
+                    .iter()
+                    .filter_map(|val| val.parse::<f64>().ok())
+                    .collect();
+                Ok((header, Self::sum_of(&values)))
+            },
+
+            AggregateFunction::AvgDistinct(col_name) => {
+                let header = self.get_function_header(function);
+                let values: Vec<f64> = self.distinct_column_values(col_name, tables, rows)?
+                    .iter()
+                    .filter_map(|val| val.parse::<f64>().ok())
+                    .collect();
+
+                Ok((header, Self::average_of(&values)))
             },
         }
     }
 
-    fn get_function_header(&self, function: &AggregateFunction) -> String {
+    /// SUM of the non-NULL values; NULL when there are none
+    fn sum_of(values: &[f64]) -> Option<String> {
+        (!values.is_empty()).then(|| values.iter().sum::<f64>().to_string())
+    }
+
+    /// AVG of the non-NULL values; NULL when there are none
+    fn average_of(values: &[f64]) -> Option<String> {
+        (!values.is_empty()).then(|| (values.iter().sum::<f64>() / values.len() as f64).to_string())
+    }
+
+    /// Collect the distinct non-null values of a column, in first-seen order
+    fn distinct_column_values<'a>(
+        &self,
+        col_name: &str,
+        tables: &[(&str, &Table)],
+        rows: &'a [Row],
+    ) -> Result<Vec<&'a str>, QueryError> {
+        let col_index = JoinedRowContext::column_index(tables, col_name)?;
+
+        let mut seen = HashSet::new();
+        Ok(rows.iter()
+            .filter_map(|row| row[col_index].as_deref())
+            .filter(|val| seen.insert(*val))
+            .collect())
+    }
+
+    pub fn get_function_header(&self, function: &AggregateFunction) -> String {
         match function {
             AggregateFunction::Count(col) => {
                 if col == "*" { "COUNT(*)".to_string() } else { format!("COUNT({})", col) }
@@ -339,78 +364,115 @@ impl AggregationParser {
             AggregateFunction::Avg(col) => format!("AVG({})", col),
             AggregateFunction::Min(col) => format!("MIN({})", col),
             AggregateFunction::Max(col) => format!("MAX({})", col),
+            AggregateFunction::CountDistinct(col) => format!("COUNT(DISTINCT {})", col),
+            AggregateFunction::SumDistinct(col) => format!("SUM(DISTINCT {})", col),
+            AggregateFunction::AvgDistinct(col) => format!("AVG(DISTINCT {})", col),
         }
     }
+}
 
-    fn parse_record_data(&self, record: &Record, table: &Table) -> Result<Vec<String>, QueryError> {
-        let mut offset = 0;
-        let row_data: Vec<String> = table.columns.iter().map(|col| {
-            let result = match col.data_type {
-                ColumnType::Integer => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0".to_string()
-                    }
-                },
-                ColumnType::Float => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = f64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0.0".to_string()
-                    }
-                },
-                ColumnType::Varchar(_max_len) => {
-                    if offset + 4 <= record.data.len() {
-                        let length_bytes = &record.data[offset..offset+4];
-                        let length = u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize;
-                        offset += 4;
-                        
-                        if offset + length <= record.data.len() {
-                            let string_bytes = &record.data[offset..offset+length];
-                            offset += length;
-                            String::from_utf8_lossy(string_bytes).to_string()
-                        } else {
-                            offset += length;
-                            String::new()
-                        }
-                    } else {
-                        offset += 4;
-                        String::new()
-                    }
-                },
-                ColumnType::Boolean => {
-                    let result = if offset < record.data.len() && record.data[offset] == 1 { 
-                        "true".to_string() 
-                    } else { 
-                        "false".to_string() 
-                    };
-                    offset += 1;
-                    result
-                },
-                ColumnType::Timestamp => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0".to_string()
-                    }
-                },
-            };
-            result
-        }).collect();
-
-        Ok(row_data)
+#[cfg(test)]
+mod tests {
+    use crate::query::QueryResult;
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows, shown};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE employees (id INTEGER, name VARCHAR 50, company_id INTEGER, salary INTEGER, department VARCHAR 30)",
+        "INSERT INTO employees VALUES (1, 'Sarah Connor', 1, 85000, 'Engineering')",
+        "INSERT INTO employees VALUES (2, 'John Doe', 1, 75000, 'Engineering')",
+        "INSERT INTO employees VALUES (3, 'Jane Smith', 2, 75000, 'Engineering')",
+        "INSERT INTO employees VALUES (4, 'Mike Johnson', 2, 70000, 'Marketing')",
+        "INSERT INTO employees VALUES (5, 'Lisa Brown', 2, 70000, 'Marketing')",
+    ];
+
+    #[test]
+    fn test_count_and_sum_distinct() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        match engine.execute("SELECT COUNT(DISTINCT salary), SUM(DISTINCT salary), COUNT(*) FROM employees").unwrap() {
+            QueryResult::Aggregation(result) => {
+                assert_eq!(result.headers, vec!["COUNT(DISTINCT salary)", "SUM(DISTINCT salary)", "COUNT(*)"]);
+                assert_eq!(shown(&result.rows), vec![vec!["3".to_string(), "230000".to_string(), "5".to_string()]]);
+            }
+            other => panic!("Expected aggregation result, got {:?}", other),
+        }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_distinct_aggregates_with_group_by() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        match engine.execute("SELECT DISTINCT department, COUNT(DISTINCT company_id) FROM employees GROUP BY department").unwrap() {
+            QueryResult::Aggregation(result) => {
+                assert_eq!(shown(&result.rows), vec![
+                    vec!["Engineering".to_string(), "2".to_string()],
+                    vec!["Marketing".to_string(), "1".to_string()],
+                ]);
+            }
+            other => panic!("Expected aggregation result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_having_on_aggregates() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT department, COUNT(*) FROM employees GROUP BY department HAVING COUNT(*) > 2 AND AVG(salary) > 50000";
+        match engine.execute(query).unwrap() {
+            QueryResult::Aggregation(result) => {
+                assert_eq!(shown(&result.rows), vec![vec!["Engineering".to_string(), "3".to_string()]]);
+            }
+            other => panic!("Expected aggregation result, got {:?}", other),
+        }
+
+        // Aggregates in HAVING do not need to appear in the SELECT list
+        let query = "SELECT company_id, COUNT(*) FROM employees GROUP BY company_id HAVING MAX(salary) < 80000 OR company_id = 1";
+        match engine.execute(query).unwrap() {
+            QueryResult::Aggregation(result) => {
+                assert_eq!(shown(&result.rows), vec![
+                    vec!["1".to_string(), "2".to_string()],
+                    vec!["2".to_string(), "3".to_string()],
+                ]);
+            }
+            other => panic!("Expected aggregation result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_having_on_select_aliases() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert_eq!(
+            rows(&mut engine, "SELECT department, COUNT(*) AS c FROM employees GROUP BY department HAVING c > 2"),
+            vec![vec!["Engineering", "3"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT company_id AS company, SUM(salary) / COUNT(*) AS average FROM employees \
+                               GROUP BY company_id HAVING average < 80000 AND company = 2"),
+            vec![vec!["2", "71666"]]
+        );
+
+        // Columns that are neither aliases nor grouped are still rejected
+        assert!(engine.execute("SELECT department, COUNT(*) AS c FROM employees GROUP BY department HAVING d > 2").is_err());
+    }
+
+    #[test]
+    fn test_having_rejects_ungrouped_column() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let result = engine.execute("SELECT department, COUNT(*) FROM employees GROUP BY department HAVING salary > 1000");
+        assert!(result.is_err());
+
+        // HAVING without GROUP BY filters the single aggregate row
+        match engine.execute("SELECT COUNT(*) FROM employees HAVING COUNT(*) > 10").unwrap() {
+            QueryResult::Aggregation(result) => assert!(result.rows.is_empty()),
+            other => panic!("Expected aggregation result, got {:?}", other),
+        }
+    }
+}
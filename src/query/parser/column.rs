@@ -1,23 +1,58 @@
 use crate::query::error::QueryError;
+use super::expression::{Expression, ExpressionParser};
+
+/// A single entry of the SELECT list
+#[derive(Debug)]
+pub enum SelectItem {
+    Wildcard,
+    Expression {
+        expression: Expression,
+        alias: Option<String>,
+        text: String,
+    },
+}
+
+impl SelectItem {
+    /// Column header for this item: the alias if given, otherwise the expression text
+    pub fn header(&self) -> String {
+        match self {
+            SelectItem::Wildcard => "*".to_string(),
+            SelectItem::Expression { alias: Some(alias), .. } => alias.clone(),
+            SelectItem::Expression { text, .. } => text.clone(),
+        }
+    }
+}
 
 pub struct ColumnParser;
 
+impl Default for ColumnParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ColumnParser {
     pub fn new() -> Self {
         ColumnParser
     }
 
+    /// Check whether the SELECT list starts with the DISTINCT keyword
+    pub fn is_distinct(&self, tokens: &[&str]) -> bool {
+        tokens.first().is_some_and(|t| t.to_uppercase() == "DISTINCT")
+    }
+
     pub fn parse_column_list(&self, tokens: &[&str]) -> Result<Vec<String>, QueryError> {
+        let tokens = if self.is_distinct(tokens) { &tokens[1..] } else { tokens };
+        let select_end = tokens.iter()
+            .position(|&t| t.to_uppercase() == "FROM")
+            .unwrap_or(tokens.len());
+
         let mut columns = Vec::new();
-        let mut i = 0;
-        
-        while i < tokens.len() && tokens[i].to_uppercase() != "FROM" {
-            let col = tokens[i].trim_matches(',');
-            if col.is_empty() {
+        for item in self.split_select_items(&tokens[..select_end]) {
+            if item.is_empty() {
                 return Err(QueryError::SyntaxError("Empty column name".to_string()));
             }
-            columns.push(col.to_string());
-            i += 1;
+            columns.push(item);
         }
 
         if columns.is_empty() {
@@ -26,4 +61,55 @@ impl ColumnParser {
 
         Ok(columns)
     }
-} 
\ No newline at end of file
+
+    /// Parse the SELECT list into expressions with optional aliases
+    /// Example: name, price * quantity AS total
+    pub fn parse_select_items(&self, tokens: &[&str]) -> Result<Vec<SelectItem>, QueryError> {
+        let expression_parser = ExpressionParser::new();
+        self.parse_column_list(tokens)?
+            .into_iter()
+            .map(|item| {
+                if item == "*" {
+                    return Ok(SelectItem::Wildcard);
+                }
+                let (expression, alias) = expression_parser.parse_with_alias(&item)?;
+                let text = match item.to_uppercase().rfind(" AS ") {
+                    Some(index) if alias.is_some() => item[..index].trim().to_string(),
+                    _ => item,
+                };
+                Ok(SelectItem::Expression { expression, alias, text })
+            })
+            .collect()
+    }
+
+    /// Split SELECT list tokens into items on top-level commas
+    /// Example: name, COUNT(DISTINCT id) -> ["name", "COUNT(DISTINCT id)"]
+    pub fn split_select_items(&self, tokens: &[&str]) -> Vec<String> {
+        let joined = tokens.join(" ");
+        let mut items = Vec::new();
+        let mut current = String::new();
+        let mut depth = 0;
+        let mut in_quotes = false;
+
+        for c in joined.chars() {
+            match c {
+                '\'' => in_quotes = !in_quotes,
+                '(' if !in_quotes => depth += 1,
+                ')' if !in_quotes => depth -= 1,
+                ',' if !in_quotes && depth == 0 => {
+                    items.push(current.trim().to_string());
+                    current.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            current.push(c);
+        }
+
+        if !current.trim().is_empty() || !items.is_empty() {
+            items.push(current.trim().to_string());
+        }
+
+        items
+    }
+}
\ No newline at end of file
@@ -1,10 +1,17 @@
-use crate::metadata::{Table, Column, ColumnType};
+use std::collections::HashSet;
+use crate::metadata::{Table, Column, ColumnType, ColumnConstraint, CheckConstraint, ForeignKey, ReferentialAction, type_name};
 use crate::query::error::QueryError;
-use super::column::ColumnParser;
-use crate::query::result::QueryResult;
+use super::expression::{ExpressionParser, RowContext};
+use super::lexer::{Lexer, Token};
 
 pub struct CreateParser;
 
+impl Default for CreateParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CreateParser {
     pub fn new() -> Self {
         CreateParser
@@ -28,26 +35,30 @@ impl CreateParser {
                 return Err(QueryError::SyntaxError("Expected column definitions in parentheses".to_string()));
             }
 
-            // Join all tokens between parentheses and split by commas
+            // Split the definitions on the commas outside parentheses
             let col_defs = tokens[3..].join(" ");
-            let col_defs = col_defs.trim_start_matches('(').trim_end_matches(')');
-            let col_defs: Vec<&str> = col_defs.split(',').map(|s| s.trim()).collect();
+            let col_defs = &col_defs[1..col_defs.len() - 1];
+            let col_tokens = Lexer::new().tokenize(col_defs)?;
+            let expression_parser = ExpressionParser::new();
 
             let mut table = Table::new(table_name.clone());
-            
-            for col_def in col_defs {
-                let parts: Vec<&str> = col_def.split_whitespace().collect();
-                if parts.len() < 2 {
-                    return Err(QueryError::SyntaxError(format!(
-                        "Invalid column definition: {}", col_def
-                    )));
+            let mut table_constraints = Vec::new();
+            for definition in expression_parser.split_on_commas(&col_tokens) {
+                match definition.first() {
+                    None => return Err(QueryError::SyntaxError("Empty column definition".to_string())),
+                    Some(first) if ["PRIMARY", "UNIQUE", "FOREIGN", "CHECK", "CONSTRAINT"].iter().any(|k| first.is_keyword(k)) => {
+                        table_constraints.push(definition);
+                    }
+                    Some(_) => table.add_column(self.parse_column_definition(definition)?),
                 }
+            }
 
-                let col_name = parts[0].to_string();
-                let col_type = self.parse_column_type(&parts[1..])?;
-                
-                table.add_column(Column::new(col_name, col_type));
+            for definition in table_constraints {
+                self.apply_table_constraint(&mut table, definition)?;
             }
+            self.check_primary_key(&mut table)?;
+            self.name_checks(&mut table)?;
+            self.validate_checks(&table)?;
 
             Ok((table_name.clone(), table))
         } else {
@@ -56,7 +67,292 @@ impl CreateParser {
         }
     }
 
-    fn parse_column_type(&self, parts: &[&str]) -> Result<ColumnType, QueryError> {
+    /// Parse `name TYPE [constraints...]`
+    /// Example: name VARCHAR(20) NOT NULL UNIQUE, qty INTEGER DEFAULT 0 CHECK (qty >= 0)
+    fn parse_column_definition(&self, definition: &[Token]) -> Result<Column, QueryError> {
+        let invalid = || QueryError::SyntaxError(format!(
+            "Invalid column definition: {}",
+            definition.iter().map(Token::to_sql).collect::<Vec<_>>().join(" ")
+        ));
+        let name = match definition.first() {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return Err(invalid()),
+        };
+
+        // The type is a name, optionally followed by a length with or without parentheses
+        let type_end = match definition.get(2..) {
+            Some([Token::Number(_), ..]) => 3,
+            Some([Token::LeftParen, Token::Number(_), Token::RightParen, ..]) => 5,
+            _ => 2,
+        };
+        let type_parts: Vec<String> = definition.get(1..type_end).ok_or_else(invalid)?.iter()
+            .filter(|t| !matches!(t, Token::LeftParen | Token::RightParen))
+            .map(Token::to_sql)
+            .collect();
+        let type_parts: Vec<&str> = type_parts.iter().map(String::as_str).collect();
+        let mut column = Column::new(name, self.parse_column_type(&type_parts)?);
+
+        let mut pos = type_end;
+        let mut constraint_name = None;
+        while let Some(token) = definition.get(pos) {
+            pos += 1;
+            let next_is = |keyword: &str| definition.get(pos).is_some_and(|t| t.is_keyword(keyword));
+            // CONSTRAINT name applies to the constraint right after it; only CHECK constraints keep their name
+            let name = constraint_name.take();
+            if token.is_keyword("CONSTRAINT") {
+                match definition.get(pos) {
+                    Some(Token::Identifier(name)) if pos + 1 < definition.len() => constraint_name = Some(name.clone()),
+                    _ => return Err(QueryError::SyntaxError(format!("Expected a name and a constraint after CONSTRAINT in column '{}'", column.name))),
+                }
+                pos += 1;
+            } else if token.is_keyword("CHECK") {
+                let condition = self.parse_check_condition(definition, &mut pos)?;
+                column = column.with_constraint(ColumnConstraint::Check(CheckConstraint {
+                    name: name.unwrap_or_default(),
+                    condition,
+                }));
+            } else if token.is_keyword("NOT") && next_is("NULL") {
+                pos += 1;
+                column = column.with_constraint(ColumnConstraint::NotNull);
+            } else if token.is_keyword("NULL") {
+                // Columns are nullable unless NOT NULL is given
+            } else if token.is_keyword("UNIQUE") {
+                column = column.with_constraint(ColumnConstraint::Unique);
+            } else if token.is_keyword("PRIMARY") && next_is("KEY") {
+                pos += 1;
+                column = column.with_constraint(ColumnConstraint::PrimaryKey);
+            } else if token.is_keyword("REFERENCES") {
+                let foreign_key = self.parse_references(definition, &mut pos)?;
+                column = column.with_constraint(ColumnConstraint::ForeignKey(foreign_key));
+            } else if token.is_keyword("DEFAULT") {
+                let default = self.parse_default(definition, &mut pos, &column)?;
+                column = column.with_default(default);
+            } else {
+                return Err(QueryError::SyntaxError(format!(
+                    "Unexpected '{}' in definition of column '{}'", token.to_sql(), column.name
+                )));
+            }
+        }
+
+        Ok(column)
+    }
+
+    /// Parse the literal after DEFAULT, kept in the form INSERT accepts
+    fn parse_default(&self, definition: &[Token], pos: &mut usize, column: &Column) -> Result<String, QueryError> {
+        let default = match definition.get(*pos..) {
+            Some([Token::Operator(sign), Token::Number(num), ..]) if sign == "-" => {
+                *pos += 2;
+                format!("-{}", num)
+            }
+            Some([Token::Number(num), ..]) => {
+                *pos += 1;
+                num.clone()
+            }
+            Some([token @ (Token::StringLiteral(_) | Token::Identifier(_)), ..]) => {
+                *pos += 1;
+                token.to_sql()
+            }
+            _ => return Err(QueryError::SyntaxError(format!("Expected a value after DEFAULT for column '{}'", column.name))),
+        };
+
+        if !default.eq_ignore_ascii_case("NULL") && !column.validate_value(&default) {
+            return Err(QueryError::TypeMismatch(format!(
+                "Invalid default value {} for column '{}'", default, column.name
+            )));
+        }
+        Ok(default)
+    }
+
+    /// Parse the parenthesized condition after CHECK into its SQL text
+    fn parse_check_condition(&self, definition: &[Token], pos: &mut usize) -> Result<String, QueryError> {
+        if definition.get(*pos) != Some(&Token::LeftParen) {
+            return Err(QueryError::SyntaxError("Expected a parenthesized condition after CHECK".to_string()));
+        }
+
+        let mut depth = 0;
+        for (offset, token) in definition[*pos..].iter().enumerate() {
+            match token {
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                let condition = &definition[*pos + 1..*pos + offset];
+                *pos += offset + 1;
+                if condition.is_empty() {
+                    return Err(QueryError::SyntaxError("Empty CHECK condition".to_string()));
+                }
+                return Ok(condition.iter().map(Token::to_sql).collect::<Vec<_>>().join(" "));
+            }
+        }
+        Err(QueryError::SyntaxError("Missing closing parenthesis for CHECK condition".to_string()))
+    }
+
+    /// Parse `table(column) [ON DELETE action] [ON UPDATE action]`, after REFERENCES
+    fn parse_references(&self, definition: &[Token], pos: &mut usize) -> Result<ForeignKey, QueryError> {
+        let (table, column) = match definition.get(*pos..) {
+            Some([Token::Identifier(table), Token::LeftParen, Token::Identifier(column), Token::RightParen, ..]) => {
+                *pos += 4;
+                (table.clone(), column.clone())
+            }
+            _ => return Err(QueryError::SyntaxError("Expected table(column) after REFERENCES".to_string())),
+        };
+
+        let mut foreign_key = ForeignKey {
+            table,
+            column,
+            on_delete: ReferentialAction::Restrict,
+            on_update: ReferentialAction::Restrict,
+        };
+        while definition.get(*pos).is_some_and(|t| t.is_keyword("ON")) {
+            let event = definition.get(*pos + 1).ok_or_else(|| QueryError::SyntaxError("Expected DELETE or UPDATE after ON".to_string()))?;
+            let words: Vec<String> = definition[*pos + 2..].iter().take(2).map(|t| t.to_sql().to_uppercase()).collect();
+            let (action, length) = match words.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+                ["CASCADE", ..] => (ReferentialAction::Cascade, 1),
+                ["RESTRICT", ..] => (ReferentialAction::Restrict, 1),
+                ["SET", "NULL"] => (ReferentialAction::SetNull, 2),
+                ["NO", "ACTION"] => (ReferentialAction::Restrict, 2),
+                _ => return Err(QueryError::SyntaxError(
+                    "Expected CASCADE, SET NULL, RESTRICT or NO ACTION in referential action".to_string()
+                )),
+            };
+            if event.is_keyword("DELETE") {
+                foreign_key.on_delete = action;
+            } else if event.is_keyword("UPDATE") {
+                foreign_key.on_update = action;
+            } else {
+                return Err(QueryError::SyntaxError(format!("Expected DELETE or UPDATE after ON, got {}", event.to_sql())));
+            }
+            *pos += 2 + length;
+        }
+        Ok(foreign_key)
+    }
+
+    /// Apply `PRIMARY KEY (column)`, `UNIQUE (column)` or
+    /// `FOREIGN KEY (column) REFERENCES table(column) ...` to the column it names,
+    /// or add `CHECK (condition)` to the table; any of them may start with `CONSTRAINT name`
+    fn apply_table_constraint(&self, table: &mut Table, definition: &[Token]) -> Result<(), QueryError> {
+        let (name, definition) = match definition {
+            [first, Token::Identifier(name), rest @ ..] if first.is_keyword("CONSTRAINT") && !rest.is_empty() => (Some(name.clone()), rest),
+            [first, ..] if first.is_keyword("CONSTRAINT") => {
+                return Err(QueryError::SyntaxError("Expected a name and a constraint after CONSTRAINT".to_string()));
+            }
+            _ => (None, definition),
+        };
+        if definition[0].is_keyword("CHECK") {
+            let mut pos = 1;
+            let condition = self.parse_check_condition(definition, &mut pos)?;
+            if let Some(token) = definition.get(pos) {
+                return Err(QueryError::SyntaxError(format!("Unexpected '{}' after CHECK condition", token.to_sql())));
+            }
+            table.checks.push(CheckConstraint { name: name.unwrap_or_default(), condition });
+            return Ok(());
+        }
+
+        let (constraint, columns) = match definition {
+            [first, second, rest @ ..] if first.is_keyword("PRIMARY") && second.is_keyword("KEY") => (ColumnConstraint::PrimaryKey, rest),
+            [first, second, rest @ ..] if first.is_keyword("FOREIGN") && second.is_keyword("KEY") => {
+                let references = rest.iter().position(|t| t.is_keyword("REFERENCES"))
+                    .ok_or_else(|| QueryError::SyntaxError("Expected REFERENCES in FOREIGN KEY".to_string()))?;
+                let mut pos = references + 1;
+                let foreign_key = self.parse_references(rest, &mut pos)?;
+                if pos < rest.len() {
+                    return Err(QueryError::SyntaxError(format!("Unexpected '{}' in FOREIGN KEY", rest[pos].to_sql())));
+                }
+                (ColumnConstraint::ForeignKey(foreign_key), &rest[..references])
+            }
+            [first, rest @ ..] if first.is_keyword("UNIQUE") => (ColumnConstraint::Unique, rest),
+            _ => return Err(QueryError::SyntaxError("Invalid table constraint".to_string())),
+        };
+        let name = match columns {
+            [Token::LeftParen, Token::Identifier(name), Token::RightParen] => name.as_str(),
+            [Token::LeftParen, .., Token::RightParen] => {
+                return Err(QueryError::SyntaxError("Constraints over several columns are not supported".to_string()));
+            }
+            _ => return Err(QueryError::SyntaxError("Expected a parenthesized column name in table constraint".to_string())),
+        };
+
+        let index = table.get_column_index(name)
+            .ok_or_else(|| QueryError::ColumnNotFound(name.to_string()))?;
+        table.columns[index].constraints.push(constraint);
+        Ok(())
+    }
+
+    /// Record the primary key on the table; a primary key column is implicitly NOT NULL
+    fn check_primary_key(&self, table: &mut Table) -> Result<(), QueryError> {
+        let mut keys = table.columns.iter().filter(|c| c.is_primary_key()).map(|c| c.name.clone());
+        let Some(key) = keys.next() else {
+            return Ok(());
+        };
+        if keys.next().is_some() {
+            return Err(QueryError::SyntaxError(format!("Table {} has more than one PRIMARY KEY", table.name)));
+        }
+        table.set_primary_key(key);
+        Ok(())
+    }
+
+    /// Give every unnamed CHECK constraint a name, `<table>_<column>_check` after the
+    /// column it is declared on or the first one its condition uses, numbered if already taken
+    fn name_checks(&self, table: &mut Table) -> Result<(), QueryError> {
+        let mut names = HashSet::new();
+        for check in table.check_constraints().filter(|check| !check.name.is_empty()) {
+            if !names.insert(check.name.clone()) {
+                return Err(QueryError::DuplicateKey(format!("Constraint {} is declared twice", check.name)));
+            }
+        }
+
+        let mut unique_name = |base: String| {
+            let name = (0..).map(|n| if n == 0 { base.clone() } else { format!("{}{}", base, n) })
+                .find(|name| !names.contains(name))
+                .unwrap();
+            names.insert(name.clone());
+            name
+        };
+        let table_name = table.name.clone();
+        for column in &mut table.columns {
+            for constraint in &mut column.constraints {
+                if let ColumnConstraint::Check(check) = constraint {
+                    if check.name.is_empty() {
+                        check.name = unique_name(format!("{}_{}_check", table_name, column.name));
+                    }
+                }
+            }
+        }
+        for check in &mut table.checks {
+            if check.name.is_empty() {
+                let tokens = Lexer::new().tokenize(&check.condition)?;
+                let first_column = ExpressionParser::new().parse_tokens(&tokens)?
+                    .column_names().first()
+                    .map(|name| format!("_{}", name.rsplit('.').next().unwrap_or(name)))
+                    .unwrap_or_default();
+                check.name = unique_name(format!("{}{}_check", table_name, first_column));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every CHECK condition is a boolean expression over the table's own columns
+    fn validate_checks(&self, table: &Table) -> Result<(), QueryError> {
+        let expression_parser = ExpressionParser::new();
+        for check in table.check_constraints() {
+            let condition = expression_parser.parse_tokens(&Lexer::new().tokenize(&check.condition)?)?;
+            if condition.has_subquery() || condition.has_aggregate() || !condition.window_functions().is_empty() {
+                return Err(QueryError::SyntaxError(format!(
+                    "CHECK constraint {} cannot use subqueries, aggregates or window functions", check.name
+                )));
+            }
+            let result_type = condition.check_type(&|name| RowContext::column_type(table, name).map(Some))?;
+            if !matches!(result_type, Some(ColumnType::Boolean) | None) {
+                return Err(QueryError::TypeMismatch(format!(
+                    "CHECK constraint {} must be a boolean condition, not {}", check.name, type_name(&result_type)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a type name such as INTEGER, VARCHAR 20 or VARCHAR(20)
+    pub fn parse_column_type(&self, parts: &[&str]) -> Result<ColumnType, QueryError> {
         let type_str = parts[0].to_uppercase();
         
         if type_str.starts_with("VARCHAR") {
@@ -68,14 +364,14 @@ impl CreateParser {
                 let len = length_str.parse::<usize>().map_err(|_| QueryError::SyntaxError(format!(
                     "Invalid length for VARCHAR: {}", length_str
                 )))?;
-                Ok(ColumnType::Varchar(len))
+                Ok(ColumnType::Varchar(Some(len)))
             } else if parts.len() >= 2 {
                 // Handle VARCHAR n format
                 let length_str = parts[1].trim_matches(|c| c == '(' || c == ')');
                 let len = length_str.parse::<usize>().map_err(|_| QueryError::SyntaxError(format!(
                     "Invalid length for VARCHAR: {}", length_str
                 )))?;
-                Ok(ColumnType::Varchar(len))
+                Ok(ColumnType::Varchar(Some(len)))
             } else {
                 Err(QueryError::SyntaxError("VARCHAR requires length specification".to_string()))
             }
@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use crate::metadata::Table;
+use crate::query::catalog::Catalog;
+use crate::query::error::QueryError;
+use crate::query::record::Row;
+use super::lexer::Lexer;
+use super::select::SelectParser;
+use super::subquery::{derived_table, SubqueryRunner};
+
+// Rounds a recursive CTE may run before it is assumed not to terminate
+const MAX_RECURSION_DEPTH: usize = 1000;
+
+/// A named query of a WITH clause
+/// Example: recent (id, total) AS (SELECT ...)
+#[derive(Debug)]
+pub struct CommonTableExpression {
+    pub name: String,
+    pub columns: Vec<String>, // renames the query's columns when given
+    pub query: String,
+}
+
+#[derive(Debug)]
+pub struct WithClause {
+    pub recursive: bool,
+    pub ctes: Vec<CommonTableExpression>,
+}
+
+pub struct CteParser;
+
+impl Default for CteParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CteParser {
+    pub fn new() -> Self {
+        CteParser
+    }
+
+    /// Parse the WITH clause at the start of a statement
+    /// Returns the clause and the index of the main query's first token
+    /// Expected format: WITH [RECURSIVE] name [(col, ...)] AS (SELECT ...) [, ...] SELECT ...
+    pub fn parse_with_clause(&self, tokens: &[&str]) -> Result<(WithClause, usize), QueryError> {
+        if !tokens.first().is_some_and(|t| t.eq_ignore_ascii_case("WITH")) {
+            return Err(QueryError::SyntaxError("Expected WITH".to_string()));
+        }
+
+        let recursive = tokens.get(1).is_some_and(|t| t.eq_ignore_ascii_case("RECURSIVE"));
+        let mut index = if recursive { 2 } else { 1 };
+        let mut ctes = Vec::new();
+
+        loop {
+            let as_index = tokens.iter()
+                .skip(index)
+                .position(|t| t.eq_ignore_ascii_case("AS"))
+                .map(|offset| index + offset)
+                .ok_or_else(|| QueryError::SyntaxError("Expected AS in WITH clause".to_string()))?;
+            let (name, columns) = self.parse_cte_name(&tokens[index..as_index].join(" "))?;
+
+            if ctes.iter().any(|cte: &CommonTableExpression| cte.name == name) {
+                return Err(QueryError::SyntaxError(format!("WITH query name '{}' is used more than once", name)));
+            }
+
+            // The query is a single token, possibly followed by the comma before the next one
+            let body = tokens.get(as_index + 1)
+                .ok_or_else(|| QueryError::SyntaxError(format!("Expected query for '{}'", name)))?;
+            let (body, comma) = match body.strip_suffix(',') {
+                Some(body) => (body, true),
+                None => (*body, tokens.get(as_index + 2) == Some(&",")),
+            };
+            let query = body.strip_prefix('(')
+                .and_then(|rest| rest.strip_suffix(')'))
+                .ok_or_else(|| QueryError::SyntaxError(format!("Expected parenthesized query for '{}'", name)))?;
+
+            ctes.push(CommonTableExpression { name, columns, query: query.trim().to_string() });
+            index = as_index + 2;
+
+            if !comma {
+                break;
+            }
+            if tokens.get(index) == Some(&",") {
+                index += 1;
+            }
+        }
+
+        if !tokens.get(index).is_some_and(|t| t.eq_ignore_ascii_case("SELECT")) {
+            return Err(QueryError::SyntaxError("Expected SELECT after WITH clause".to_string()));
+        }
+
+        Ok((WithClause { recursive, ctes }, index))
+    }
+
+    /// Parse `name` or `name (col, ...)`
+    fn parse_cte_name(&self, text: &str) -> Result<(String, Vec<String>), QueryError> {
+        let (name, columns) = match text.split_once('(') {
+            Some((name, rest)) => {
+                let list = rest.trim().strip_suffix(')')
+                    .ok_or_else(|| QueryError::SyntaxError(format!("Invalid column list: {}", text)))?;
+                let columns: Vec<String> = list.split(',').map(|col| col.trim().to_string()).collect();
+                if columns.iter().any(|col| col.is_empty()) {
+                    return Err(QueryError::SyntaxError(format!("Invalid column list: {}", text)));
+                }
+                (name.trim(), columns)
+            }
+            None => (text.trim(), Vec::new()),
+        };
+
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(QueryError::SyntaxError(format!("Invalid WITH query name: {}", text)));
+        }
+        Ok((name.to_string(), columns))
+    }
+
+    /// Run a CTE against the catalog, returning its result as a table
+    /// A recursive CTE is `anchor UNION [ALL] recursive part`: the anchor runs once, then the
+    /// recursive part runs on the rows the previous round added until it adds none
+    pub fn execute_cte(
+        &self,
+        cte: &CommonTableExpression,
+        recursive: bool,
+        select_parser: &SelectParser,
+        catalog: &mut Catalog,
+    ) -> Result<(Table, Vec<Row>), QueryError> {
+        let tokens = Lexer::new().split_statement(&cte.query);
+        let union_index = tokens.iter().position(|t| t.eq_ignore_ascii_case("UNION"));
+
+        let self_referencing = union_index.is_some_and(|union_index| {
+            tokens[union_index + 1..].iter()
+                .flat_map(|token| token.split(|c: char| !c.is_alphanumeric() && c != '_'))
+                .any(|word| word == cte.name)
+        });
+
+        let (anchor, recursive_part) = match union_index {
+            Some(union_index) if recursive && self_referencing => {
+                let union_all = tokens.get(union_index + 1).is_some_and(|t| t.eq_ignore_ascii_case("ALL"));
+                let recursive_start = if union_all { union_index + 2 } else { union_index + 1 };
+                (&tokens[..union_index], Some((&tokens[recursive_start..], union_all)))
+            }
+            _ => (&tokens[..], None),
+        };
+
+        let (result, types) = SubqueryRunner::new(select_parser, catalog).execute_typed_select(anchor)?;
+        let result = result.into_select_result()
+            .ok_or_else(|| QueryError::InternalError(format!("WITH query '{}' did not return rows", cte.name)))?;
+
+        let headers = if cte.columns.is_empty() {
+            result.headers
+        } else if cte.columns.len() == result.headers.len() {
+            cte.columns.clone()
+        } else {
+            return Err(QueryError::SyntaxError(format!(
+                "WITH query '{}' has {} columns but {} column names", cte.name, result.headers.len(), cte.columns.len()
+            )));
+        };
+
+        let mut rows = result.rows;
+        if let Some((recursive_part, union_all)) = recursive_part {
+            let mut seen: HashSet<Row> = HashSet::new();
+            if !union_all {
+                rows.retain(|row| seen.insert(row.clone()));
+            }
+
+            // Each round sees only the rows added by the previous one
+            let mut working_rows = rows.clone();
+            let mut depth = 0;
+            while !working_rows.is_empty() {
+                depth += 1;
+                if depth > MAX_RECURSION_DEPTH {
+                    return Err(QueryError::InvalidValue(format!(
+                        "Recursive WITH query '{}' did not finish within {} rounds", cte.name, MAX_RECURSION_DEPTH
+                    )));
+                }
+
+                catalog.add_table(derived_table(&cte.name, &headers, &types, &rows), working_rows);
+                let result = SubqueryRunner::new(select_parser, catalog)
+                    .execute_select(recursive_part, None)?
+                    .into_select_result()
+                    .ok_or_else(|| QueryError::InternalError(format!("WITH query '{}' did not return rows", cte.name)))?;
+
+                if result.headers.len() != headers.len() {
+                    return Err(QueryError::SyntaxError(format!(
+                        "Recursive part of '{}' returns {} columns, expected {}", cte.name, result.headers.len(), headers.len()
+                    )));
+                }
+
+                working_rows = result.rows;
+                if !union_all {
+                    working_rows.retain(|row| seen.insert(row.clone()));
+                }
+                rows.extend(working_rows.iter().cloned());
+            }
+        }
+
+        Ok((derived_table(&cte.name, &headers, &types, &rows), rows))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryError, QueryResult};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows, shown};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE staff (id INTEGER, name VARCHAR 20, manager_id INTEGER, salary INTEGER)",
+        "INSERT INTO staff VALUES (1, 'Ann', 0, 200)",
+        "INSERT INTO staff VALUES (2, 'Ben', 1, 120)",
+        "INSERT INTO staff VALUES (3, 'Cat', 1, 110)",
+        "INSERT INTO staff VALUES (4, 'Dan', 2, 90)",
+        "INSERT INTO staff VALUES (5, 'Eve', 4, 70)",
+    ];
+
+    #[test]
+    fn test_with_queries() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "WITH senior AS (SELECT id, name FROM staff WHERE salary > 100), \
+                     leads (lead_id, lead_name) AS (SELECT id, name FROM senior WHERE id > 1) \
+                     SELECT s.name, l.lead_name FROM staff s JOIN leads l ON s.manager_id = l.lead_id ORDER BY s.name";
+        match engine.execute(query).unwrap() {
+            QueryResult::Join(result) => {
+                assert_eq!(result.headers, vec!["s.name", "l.lead_name"]);
+                assert_eq!(shown(&result.rows), vec![vec!["Dan", "Ben"]]);
+            }
+            other => panic!("Expected join result, got {:?}", other),
+        }
+
+        // A WITH query shadows a table of the same name
+        assert_eq!(
+            rows(&mut engine, "WITH staff AS (SELECT name FROM staff WHERE id = 3) SELECT * FROM staff"),
+            vec![vec!["Cat"]]
+        );
+
+        assert!(matches!(
+            engine.execute("WITH x (a, b) AS (SELECT id FROM staff) SELECT * FROM x"),
+            Err(QueryError::SyntaxError(_))
+        ));
+        assert!(matches!(
+            engine.execute("WITH x AS (SELECT id FROM staff), x AS (SELECT id FROM staff) SELECT * FROM x"),
+            Err(QueryError::SyntaxError(_))
+        ));
+    }
+
+    #[test]
+    fn test_recursive_with_queries() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // Walk the management tree from the top
+        let query = "WITH RECURSIVE chain AS (\
+                        SELECT id, name, 0 AS depth FROM staff WHERE manager_id = 0 \
+                        UNION ALL \
+                        SELECT s.id, s.name, c.depth + 1 FROM staff s JOIN chain c ON s.manager_id = c.id) \
+                     SELECT name, depth FROM chain ORDER BY depth, name";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["Ann", "0"],
+            vec!["Ben", "1"],
+            vec!["Cat", "1"],
+            vec!["Dan", "2"],
+            vec!["Eve", "3"],
+        ]);
+
+        let query = "WITH RECURSIVE n (x) AS (SELECT id FROM staff WHERE id = 1 UNION ALL SELECT x + 1 FROM n WHERE x < 5) \
+                     SELECT SUM(x) FROM n";
+        assert_eq!(rows(&mut engine, query), vec![vec!["15"]]);
+
+        // UNION drops rows already produced, so a cycle ends
+        let query = "WITH RECURSIVE ids AS (SELECT id FROM staff WHERE id = 1 UNION SELECT id FROM ids) SELECT * FROM ids";
+        assert_eq!(rows(&mut engine, query), vec![vec!["1"]]);
+
+        assert!(matches!(
+            engine.execute("WITH RECURSIVE ids AS (SELECT id FROM staff WHERE id = 1 UNION ALL SELECT id FROM ids) SELECT * FROM ids"),
+            Err(QueryError::InvalidValue(_))
+        ));
+    }
+}
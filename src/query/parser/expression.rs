@@ -0,0 +1,1003 @@
+use std::cmp::Ordering;
+use std::rc::Rc;
+use crate::metadata::{Table, ColumnType, type_name};
+use crate::query::error::QueryError;
+use crate::query::function::FunctionRegistry;
+use crate::query::result::SelectResult;
+use crate::query::value::Value;
+use super::aggregation::{AggregationParser, AggregateFunction};
+use super::create::CreateParser;
+use super::lexer::{Lexer, Token};
+use super::window::{WindowFunction, WindowParser};
+
+#[derive(Debug, Clone)]
+pub enum Expression {
+    Column(String),
+    Literal(Value),
+    Aggregate(AggregateFunction),
+    Window(Box<WindowFunction>),
+    Negate(Box<Expression>),
+    Binary {
+        left: Box<Expression>,
+        operator: String, // + - * / % ||
+        right: Box<Expression>,
+    },
+    Comparison {
+        left: Box<Expression>,
+        operator: String,
+        right: Box<Expression>,
+    },
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    IsNull {
+        expression: Box<Expression>,
+        negated: bool, // IS NOT NULL
+    },
+    Function {
+        name: String, // upper case name in the function registry
+        arguments: Vec<Expression>,
+    },
+    Cast {
+        expression: Box<Expression>,
+        target: ColumnType,
+    },
+    Case {
+        operand: Option<Box<Expression>>,     // CASE x WHEN ... compares x with each WHEN value
+        branches: Vec<(Expression, Expression)>, // WHEN condition or value, THEN result
+        otherwise: Option<Box<Expression>>,
+    },
+    Subquery(String), // scalar subquery returning at most one value
+    Exists(String),
+    InSubquery {
+        expression: Box<Expression>,
+        subquery: String,
+        negated: bool,
+    },
+}
+
+/// Resolves column references against a decoded table row
+pub struct RowContext<'a> {
+    pub table: &'a Table,
+    pub row: &'a [Option<String>],
+}
+
+impl<'a> RowContext<'a> {
+    pub fn new(table: &'a Table, row: &'a [Option<String>]) -> Self {
+        RowContext { table, row }
+    }
+
+    /// Find the index of a plain or table-qualified column name
+    pub fn column_index(table: &Table, name: &str) -> Result<usize, QueryError> {
+        let column_name = match name.split_once('.') {
+            Some((qualifier, column)) if qualifier == table.name => column,
+            Some(_) => return Err(QueryError::ColumnNotFound(name.to_string())),
+            None => name,
+        };
+        table.get_column_index(column_name)
+            .ok_or_else(|| QueryError::ColumnNotFound(name.to_string()))
+    }
+
+    /// Look up the declared type of a column, for type checking
+    pub fn column_type(table: &Table, name: &str) -> Result<ColumnType, QueryError> {
+        let index = Self::column_index(table, name)?;
+        Ok(table.columns[index].data_type.clone())
+    }
+}
+
+impl ExpressionContext for RowContext<'_> {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError> {
+        let index = Self::column_index(self.table, name)?;
+        Ok(Value::from_column(self.row[index].as_deref(), &self.table.columns[index].data_type))
+    }
+}
+
+/// Resolves column references against a row built by joining several tables
+/// Each table is listed with its qualifier (alias or table name) in row order
+pub struct JoinedRowContext<'a> {
+    pub tables: &'a [(&'a str, &'a Table)],
+    pub row: &'a [Option<String>],
+}
+
+impl<'a> JoinedRowContext<'a> {
+    pub fn new(tables: &'a [(&'a str, &'a Table)], row: &'a [Option<String>]) -> Self {
+        JoinedRowContext { tables, row }
+    }
+
+    /// Find the index in the joined row of a plain or qualified column name
+    pub fn column_index(tables: &[(&str, &Table)], name: &str) -> Result<usize, QueryError> {
+        let (qualifier, column_name) = match name.split_once('.') {
+            Some((qualifier, column)) => (Some(qualifier), column),
+            None => (None, name),
+        };
+
+        let mut found = None;
+        let mut offset = 0;
+        for (table_qualifier, table) in tables {
+            if qualifier.is_none_or(|q| q == *table_qualifier) {
+                if let Some(index) = table.get_column_index(column_name) {
+                    if found.is_some() {
+                        return Err(QueryError::SyntaxError(format!("Column reference '{}' is ambiguous", name)));
+                    }
+                    found = Some(offset + index);
+                }
+            }
+            offset += table.columns.len();
+        }
+
+        found.ok_or_else(|| QueryError::ColumnNotFound(name.to_string()))
+    }
+
+    /// Look up the declared type of a column of the joined row, for type checking
+    pub fn column_type(tables: &[(&str, &Table)], name: &str) -> Result<ColumnType, QueryError> {
+        let mut index = Self::column_index(tables, name)?;
+        for (_, table) in tables {
+            if index < table.columns.len() {
+                return Ok(table.columns[index].data_type.clone());
+            }
+            index -= table.columns.len();
+        }
+        Err(QueryError::ColumnNotFound(name.to_string()))
+    }
+}
+
+impl ExpressionContext for JoinedRowContext<'_> {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError> {
+        let index = Self::column_index(self.tables, name)?;
+        let data_type = Self::column_type(self.tables, name)?;
+        Ok(Value::from_column(self.row[index].as_deref(), &data_type))
+    }
+}
+
+/// Supplies column and aggregate values while an expression is evaluated
+pub trait ExpressionContext {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError>;
+
+    fn aggregate_value(&self, function: &AggregateFunction) -> Result<Value, QueryError> {
+        Err(QueryError::SyntaxError(format!(
+            "Aggregate function {:?} is not allowed here", function
+        )))
+    }
+
+    /// Value of a window function for the current row
+    fn window_value(&self, function: &WindowFunction) -> Result<Value, QueryError> {
+        Err(QueryError::SyntaxError(format!(
+            "Window function {} is only allowed in the SELECT list and ORDER BY", function.text
+        )))
+    }
+
+    /// Run a subquery with this context as the scope of its outer column references
+    fn subquery_result(&self, query: &str) -> Result<Rc<SelectResult>, QueryError> {
+        Err(QueryError::SyntaxError(format!("Subqueries are not supported here: ({})", query)))
+    }
+}
+
+impl Expression {
+    pub fn evaluate(&self, context: &dyn ExpressionContext) -> Result<Value, QueryError> {
+        match self {
+            Expression::Column(name) => context.column_value(name),
+            Expression::Literal(value) => Ok(value.clone()),
+            Expression::Aggregate(function) => context.aggregate_value(function),
+            Expression::Window(window) => context.window_value(window),
+            Expression::Negate(inner) => match inner.evaluate(context)? {
+                Value::Integer(num) => num.checked_neg()
+                    .map(Value::Integer)
+                    .ok_or_else(|| QueryError::InvalidValue(format!("Integer overflow in -{}", num))),
+                Value::Float(num) => Ok(Value::Float(-num)),
+                Value::Null => Ok(Value::Null),
+                other => Err(QueryError::TypeMismatch(format!("Cannot negate value '{}'", other))),
+            },
+            Expression::Binary { left, operator, right } => {
+                let left = left.evaluate(context)?;
+                let right = right.evaluate(context)?;
+                if operator == "||" {
+                    if left.is_null() || right.is_null() {
+                        return Ok(Value::Null);
+                    }
+                    Ok(Value::Text(format!("{}{}", left, right)))
+                } else {
+                    left.arithmetic(operator, &right)
+                }
+            }
+            Expression::Comparison { left, operator, right } => {
+                let left = left.evaluate(context)?;
+                let right = right.evaluate(context)?;
+                // Comparing with NULL is unknown
+                if left.is_null() || right.is_null() {
+                    return Ok(Value::Null);
+                }
+                let ordering = left.compare(&right);
+                let result = match operator.as_str() {
+                    "=" => ordering == Some(Ordering::Equal),
+                    "!=" | "<>" => ordering != Some(Ordering::Equal),
+                    ">" => ordering == Some(Ordering::Greater),
+                    "<" => ordering == Some(Ordering::Less),
+                    ">=" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                    "<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    _ => return Err(QueryError::SyntaxError(format!("Invalid operator: {}", operator))),
+                };
+                Ok(Value::Boolean(result))
+            }
+            // AND, OR and NOT follow three-valued logic, with NULL as unknown
+            Expression::And(left, right) => {
+                let left = Self::truth_value(left.evaluate(context)?)?;
+                if left == Some(false) {
+                    return Ok(Value::Boolean(false));
+                }
+                match (left, Self::truth_value(right.evaluate(context)?)?) {
+                    (_, Some(false)) => Ok(Value::Boolean(false)),
+                    (Some(true), Some(true)) => Ok(Value::Boolean(true)),
+                    _ => Ok(Value::Null),
+                }
+            }
+            Expression::Or(left, right) => {
+                let left = Self::truth_value(left.evaluate(context)?)?;
+                if left == Some(true) {
+                    return Ok(Value::Boolean(true));
+                }
+                match (left, Self::truth_value(right.evaluate(context)?)?) {
+                    (_, Some(true)) => Ok(Value::Boolean(true)),
+                    (Some(false), Some(false)) => Ok(Value::Boolean(false)),
+                    _ => Ok(Value::Null),
+                }
+            }
+            Expression::Not(inner) => match Self::truth_value(inner.evaluate(context)?)? {
+                Some(value) => Ok(Value::Boolean(!value)),
+                None => Ok(Value::Null),
+            },
+            // IS [NOT] NULL is never unknown
+            Expression::IsNull { expression, negated } => Ok(Value::Boolean(expression.evaluate(context)?.is_null() != *negated)),
+            Expression::Function { name, arguments } => {
+                let values = arguments.iter()
+                    .map(|argument| argument.evaluate(context))
+                    .collect::<Result<Vec<_>, _>>()?;
+                FunctionRegistry::builtins().call(name, &values)
+            }
+            Expression::Cast { expression, target } => expression.evaluate(context)?.cast_to(target),
+            Expression::Case { operand, branches, otherwise } => {
+                let operand = match operand {
+                    Some(operand) => Some(operand.evaluate(context)?),
+                    None => None,
+                };
+                for (when, then) in branches {
+                    let matched = match &operand {
+                        // NULL never equals a WHEN value
+                        Some(operand) => {
+                            let value = when.evaluate(context)?;
+                            !operand.is_null() && !value.is_null() && operand.compare(&value) == Some(Ordering::Equal)
+                        }
+                        None => when.is_satisfied(context)?,
+                    };
+                    if matched {
+                        return then.evaluate(context);
+                    }
+                }
+                match otherwise {
+                    Some(otherwise) => otherwise.evaluate(context),
+                    None => Ok(Value::Null),
+                }
+            }
+            Expression::Subquery(query) => {
+                let result = Self::single_column_result(context, query)?;
+                match result.rows.as_slice() {
+                    [] => Ok(Value::Null),
+                    [row] => Ok(Value::infer(row[0].as_deref())),
+                    _ => Err(QueryError::InvalidValue(format!("Scalar subquery returned more than one row: ({})", query))),
+                }
+            }
+            Expression::Exists(query) => Ok(Value::Boolean(!context.subquery_result(query)?.rows.is_empty())),
+            Expression::InSubquery { expression, subquery, negated } => {
+                let value = expression.evaluate(context)?;
+                let result = Self::single_column_result(context, subquery)?;
+
+                // x IN (...) is true on a match, unknown if x or any candidate is NULL, false otherwise
+                let mut found = Some(false);
+                for row in &result.rows {
+                    let candidate = Value::infer(row[0].as_deref());
+                    if value.is_null() || candidate.is_null() {
+                        found = None;
+                    } else if value.compare(&candidate) == Some(Ordering::Equal) {
+                        found = Some(true);
+                        break;
+                    }
+                }
+
+                Ok(match found {
+                    Some(found) => Value::Boolean(found != *negated),
+                    None => Value::Null,
+                })
+            }
+        }
+    }
+
+    /// Run a subquery that must produce exactly one column
+    fn single_column_result(context: &dyn ExpressionContext, query: &str) -> Result<Rc<SelectResult>, QueryError> {
+        let result = context.subquery_result(query)?;
+        if result.headers.len() != 1 {
+            return Err(QueryError::SyntaxError(format!(
+                "Subquery must return exactly one column, got {}: ({})", result.headers.len(), query
+            )));
+        }
+        Ok(result)
+    }
+
+    /// Split a condition into its top-level ANDed terms
+    pub fn conjuncts(&self) -> Vec<&Expression> {
+        match self {
+            Expression::And(left, right) => {
+                let mut terms = left.conjuncts();
+                terms.extend(right.conjuncts());
+                terms
+            }
+            other => vec![other],
+        }
+    }
+
+    /// The expressions directly inside this one; aggregates, window functions and subqueries are not looked into
+    fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Column(_) | Expression::Literal(_) | Expression::Aggregate(_) | Expression::Window(_)
+            | Expression::Subquery(_) | Expression::Exists(_) => Vec::new(),
+            Expression::Negate(inner) | Expression::Not(inner) => vec![inner],
+            Expression::Cast { expression, .. } | Expression::InSubquery { expression, .. }
+            | Expression::IsNull { expression, .. } => vec![expression],
+            Expression::Binary { left, right, .. } | Expression::Comparison { left, right, .. }
+            | Expression::And(left, right) | Expression::Or(left, right) => vec![left, right],
+            Expression::Function { arguments, .. } => arguments.iter().collect(),
+            Expression::Case { operand, branches, otherwise } => operand.iter().map(Box::as_ref)
+                .chain(branches.iter().flat_map(|(when, then)| [when, then]))
+                .chain(otherwise.iter().map(Box::as_ref))
+                .collect(),
+        }
+    }
+
+    /// Whether the expression contains a subquery anywhere
+    pub fn has_subquery(&self) -> bool {
+        matches!(self, Expression::Subquery(_) | Expression::Exists(_) | Expression::InSubquery { .. })
+            || self.children().iter().any(|child| child.has_subquery())
+    }
+
+    /// Whether the expression calls an aggregate function, other than as a window function
+    pub fn has_aggregate(&self) -> bool {
+        matches!(self, Expression::Aggregate(_)) || self.children().iter().any(|child| child.has_aggregate())
+    }
+
+    /// Names of the columns the expression references, outside of aggregates, window functions and subqueries
+    pub fn column_names(&self) -> Vec<&str> {
+        match self {
+            Expression::Column(name) => vec![name.as_str()],
+            other => other.children().into_iter().flat_map(Expression::column_names).collect(),
+        }
+    }
+
+    /// The arguments of each call of the scalar function `function` in the expression
+    pub fn function_calls(&self, function: &str) -> Vec<&[Expression]> {
+        let mut calls: Vec<&[Expression]> = self.children().into_iter().flat_map(|child| child.function_calls(function)).collect();
+        if let Expression::Function { name, arguments } = self {
+            if name == function {
+                calls.insert(0, arguments);
+            }
+        }
+        calls
+    }
+
+    /// The window functions the expression calls
+    pub fn window_functions(&self) -> Vec<&WindowFunction> {
+        match self {
+            Expression::Window(window) => vec![window.as_ref()],
+            other => other.children().into_iter().flat_map(Expression::window_functions).collect(),
+        }
+    }
+
+    /// Read a condition result as true, false or unknown (None)
+    fn truth_value(value: Value) -> Result<Option<bool>, QueryError> {
+        match value {
+            Value::Null => Ok(None),
+            other => other.as_bool().map(Some),
+        }
+    }
+
+    /// Evaluate the expression as a filter condition; unknown counts as not satisfied
+    pub fn is_satisfied(&self, context: &dyn ExpressionContext) -> Result<bool, QueryError> {
+        Ok(Self::truth_value(self.evaluate(context)?)?.unwrap_or(false))
+    }
+
+    /// Evaluate the expression as a CHECK constraint; unknown counts as satisfied, only false fails
+    pub fn is_allowed(&self, context: &dyn ExpressionContext) -> Result<bool, QueryError> {
+        Ok(Self::truth_value(self.evaluate(context)?)?.unwrap_or(true))
+    }
+
+    /// Work out the result type of the expression, rejecting invalid operand types
+    /// The type is None when it is only known once the query runs, as for NULL and scalar subqueries
+    pub fn check_type(&self, column_type: &dyn Fn(&str) -> Result<Option<ColumnType>, QueryError>) -> Result<Option<ColumnType>, QueryError> {
+        match self {
+            Expression::Column(name) => column_type(name),
+            Expression::Literal(value) => Ok(value.data_type()),
+            Expression::Aggregate(function) => match function {
+                AggregateFunction::Count(_) | AggregateFunction::CountDistinct(_) => Ok(Some(ColumnType::Integer)),
+                AggregateFunction::Sum(_) | AggregateFunction::SumDistinct(_)
+                | AggregateFunction::Avg(_) | AggregateFunction::AvgDistinct(_) => Ok(Some(ColumnType::Float)),
+                AggregateFunction::Min(col) | AggregateFunction::Max(col) => column_type(col),
+            },
+            Expression::Window(window) => window.check_type(column_type),
+            Expression::Negate(inner) => {
+                let inner_type = inner.check_type(column_type)?;
+                if !matches!(inner_type, Some(ColumnType::Integer | ColumnType::Float) | None) {
+                    return Err(QueryError::TypeMismatch(format!("Cannot negate value of type {}", type_name(&inner_type))));
+                }
+                Ok(inner_type)
+            }
+            Expression::Binary { left, operator, right } => {
+                let left_type = left.check_type(column_type)?;
+                let right_type = right.check_type(column_type)?;
+                if operator == "||" {
+                    let length = |data_type: &Option<ColumnType>| data_type.as_ref().map_or(Some(0), Self::display_length);
+                    return Ok(Some(ColumnType::Varchar(
+                        length(&left_type).zip(length(&right_type)).map(|(a, b)| a.saturating_add(b))
+                    )));
+                }
+                // Interval literals shift a timestamp, or a date written as text
+                let is_interval = |side: &Expression| matches!(side, Expression::Literal(Value::Interval { .. }));
+                let is_date = |data_type: &Option<ColumnType>| matches!(data_type, Some(ColumnType::Timestamp | ColumnType::Varchar(_)) | None);
+                if is_interval(right) && is_date(&left_type) && (operator == "+" || operator == "-")
+                    || is_interval(left) && is_date(&right_type) && operator == "+"
+                {
+                    return Ok(Some(ColumnType::Timestamp));
+                }
+                match (&left_type, &right_type) {
+                    // An operand of unknown type is checked once its value is known
+                    (None, None) => Ok(None),
+                    (Some(known @ (ColumnType::Integer | ColumnType::Float | ColumnType::Timestamp)), None)
+                    | (None, Some(known @ (ColumnType::Integer | ColumnType::Float | ColumnType::Timestamp))) => Ok(Some(known.clone())),
+                    (Some(ColumnType::Timestamp), Some(ColumnType::Integer)) if operator == "+" || operator == "-" => Ok(Some(ColumnType::Timestamp)),
+                    (Some(ColumnType::Timestamp), Some(ColumnType::Timestamp)) if operator == "-" => Ok(Some(ColumnType::Integer)),
+                    (Some(ColumnType::Integer), Some(ColumnType::Integer)) => Ok(Some(ColumnType::Integer)),
+                    (Some(ColumnType::Integer | ColumnType::Float), Some(ColumnType::Integer | ColumnType::Float)) => Ok(Some(ColumnType::Float)),
+                    _ => Err(QueryError::TypeMismatch(format!(
+                        "Cannot apply '{}' to {} and {}", operator, type_name(&left_type), type_name(&right_type)
+                    ))),
+                }
+            }
+            Expression::Comparison { left, right, .. } => {
+                left.check_type(column_type)?;
+                right.check_type(column_type)?;
+                Ok(Some(ColumnType::Boolean))
+            }
+            Expression::And(left, right) | Expression::Or(left, right) => {
+                for side in [left, right] {
+                    let side_type = side.check_type(column_type)?;
+                    if !matches!(side_type, Some(ColumnType::Boolean) | None) {
+                        return Err(QueryError::TypeMismatch(format!("Expected boolean condition, got {}", type_name(&side_type))));
+                    }
+                }
+                Ok(Some(ColumnType::Boolean))
+            }
+            Expression::Not(inner) => {
+                let inner_type = inner.check_type(column_type)?;
+                if !matches!(inner_type, Some(ColumnType::Boolean) | None) {
+                    return Err(QueryError::TypeMismatch(format!("Expected boolean condition, got {}", type_name(&inner_type))));
+                }
+                Ok(Some(ColumnType::Boolean))
+            }
+            Expression::Function { name, arguments } => {
+                let argument_types = arguments.iter()
+                    .map(|argument| argument.check_type(column_type))
+                    .collect::<Result<Vec<_>, _>>()?;
+                FunctionRegistry::builtins().check_call(name, &argument_types)
+            }
+            Expression::Cast { expression, target } => {
+                expression.check_type(column_type)?;
+                Ok(Some(target.clone()))
+            }
+            Expression::Case { operand, branches, otherwise } => {
+                let operand_type = match operand {
+                    Some(operand) => Some(operand.check_type(column_type)?),
+                    None => None,
+                };
+
+                let mut result_type = None;
+                for (when, then) in branches {
+                    let when_type = when.check_type(column_type)?;
+                    match &operand_type {
+                        Some(operand_type) => {
+                            Self::common_type(operand_type, &when_type, |operand_type, when_type| format!(
+                                "Cannot compare CASE value of type {} with {}", operand_type, when_type
+                            ))?;
+                        }
+                        None => if !matches!(when_type, Some(ColumnType::Boolean) | None) {
+                            return Err(QueryError::TypeMismatch(format!("Expected boolean WHEN condition, got {}", type_name(&when_type))));
+                        },
+                    }
+                    result_type = Self::case_result_type(&result_type, &then.check_type(column_type)?)?;
+                }
+                if let Some(otherwise) = otherwise {
+                    result_type = Self::case_result_type(&result_type, &otherwise.check_type(column_type)?)?;
+                }
+                Ok(result_type)
+            }
+            // Like NULL, a scalar subquery has no type of its own until it runs
+            Expression::Subquery(_) => Ok(None),
+            Expression::Exists(_) => Ok(Some(ColumnType::Boolean)),
+            Expression::InSubquery { expression, .. } | Expression::IsNull { expression, .. } => {
+                expression.check_type(column_type)?;
+                Ok(Some(ColumnType::Boolean))
+            }
+        }
+    }
+
+    fn case_result_type(left: &Option<ColumnType>, right: &Option<ColumnType>) -> Result<Option<ColumnType>, QueryError> {
+        Self::common_type(left, right, |left, right| format!(
+            "CASE results have incompatible types {} and {}", left, right
+        ))
+    }
+
+    /// The type values of two types share, such as Float for Integer and Float
+    /// An unknown type fits any other; two known types with nothing in common are a mismatch,
+    /// described by `mismatch`
+    pub fn common_type(
+        left: &Option<ColumnType>,
+        right: &Option<ColumnType>,
+        mismatch: impl FnOnce(&ColumnType, &ColumnType) -> String,
+    ) -> Result<Option<ColumnType>, QueryError> {
+        let (left, right) = match (left, right) {
+            (Some(left), Some(right)) => (left, right),
+            (known, None) | (None, known) => return Ok(known.clone()),
+        };
+        match (left, right) {
+            (ColumnType::Varchar(a), ColumnType::Varchar(b)) => Ok(Some(ColumnType::Varchar(a.zip(*b).map(|(a, b)| a.max(b))))),
+            _ if left.accepts(right) => Ok(Some(left.clone())),
+            _ if right.accepts(left) => Ok(Some(right.clone())),
+            _ => Err(QueryError::TypeMismatch(mismatch(left, right))),
+        }
+    }
+
+    /// Maximum number of characters a value of this type renders to, none for text of any length
+    pub fn display_length(data_type: &ColumnType) -> Option<usize> {
+        match data_type {
+            ColumnType::Varchar(len) => *len,
+            ColumnType::Boolean => Some(5),
+            ColumnType::Integer | ColumnType::Timestamp => Some(20),
+            ColumnType::Float => Some(24),
+        }
+    }
+}
+
+pub struct ExpressionParser;
+
+impl Default for ExpressionParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExpressionParser {
+    pub fn new() -> Self {
+        ExpressionParser
+    }
+
+    /// Parse an expression from query tokens
+    /// Example: COUNT(*) > 5 AND AVG(salary) > 50000
+    pub fn parse(&self, tokens: &[&str]) -> Result<Expression, QueryError> {
+        let (expression, alias) = self.parse_with_alias(&tokens.join(" "))?;
+        if let Some(alias) = alias {
+            return Err(QueryError::SyntaxError(format!("Unexpected alias in expression: {}", alias)));
+        }
+        Ok(expression)
+    }
+
+    /// Parse an expression from lexed tokens, all of which must be used
+    pub fn parse_tokens(&self, tokens: &[Token]) -> Result<Expression, QueryError> {
+        if tokens.is_empty() {
+            return Err(QueryError::SyntaxError("Expected expression".to_string()));
+        }
+
+        let mut pos = 0;
+        let expression = self.parse_or(tokens, &mut pos)?;
+        if pos < tokens.len() {
+            return Err(QueryError::SyntaxError(format!(
+                "Unexpected token in expression: {}", tokens[pos].to_sql()
+            )));
+        }
+        Ok(expression)
+    }
+
+    /// Parse a SELECT list item with an optional alias
+    /// Example: price * quantity AS total
+    pub fn parse_with_alias(&self, text: &str) -> Result<(Expression, Option<String>), QueryError> {
+        let lexed = Lexer::new().tokenize(text)?;
+        if lexed.is_empty() {
+            return Err(QueryError::SyntaxError("Expected expression".to_string()));
+        }
+
+        let mut pos = 0;
+        let expression = self.parse_or(&lexed, &mut pos)?;
+
+        let mut alias = None;
+        if pos < lexed.len() && lexed[pos].is_keyword("AS") {
+            match lexed.get(pos + 1) {
+                Some(Token::Identifier(name)) => {
+                    alias = Some(name.clone());
+                    pos += 2;
+                }
+                _ => return Err(QueryError::SyntaxError("Expected alias after AS".to_string())),
+            }
+        }
+
+        if pos < lexed.len() {
+            return Err(QueryError::SyntaxError(format!(
+                "Unexpected token in expression: {}", lexed[pos].to_sql()
+            )));
+        }
+        Ok((expression, alias))
+    }
+
+    fn parse_or(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        let mut left = self.parse_and(tokens, pos)?;
+        while *pos < tokens.len() && tokens[*pos].is_keyword("OR") {
+            *pos += 1;
+            let right = self.parse_and(tokens, pos)?;
+            left = Expression::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        let mut left = self.parse_not(tokens, pos)?;
+        while *pos < tokens.len() && tokens[*pos].is_keyword("AND") {
+            *pos += 1;
+            let right = self.parse_not(tokens, pos)?;
+            left = Expression::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        if *pos < tokens.len() && tokens[*pos].is_keyword("NOT") {
+            *pos += 1;
+            let inner = self.parse_not(tokens, pos)?;
+            return Ok(Expression::Not(Box::new(inner)));
+        }
+        self.parse_comparison(tokens, pos)
+    }
+
+    fn parse_comparison(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        let left = self.parse_concat(tokens, pos)?;
+
+        // expr IS [NOT] NULL
+        if tokens.get(*pos).is_some_and(|t| t.is_keyword("IS")) {
+            *pos += 1;
+            let negated = tokens.get(*pos).is_some_and(|t| t.is_keyword("NOT"));
+            if negated {
+                *pos += 1;
+            }
+            self.expect_keyword(tokens, pos, "NULL")?;
+            return Ok(Expression::IsNull { expression: Box::new(left), negated });
+        }
+
+        // expr [NOT] IN (SELECT ...)
+        let negated = tokens.get(*pos).is_some_and(|t| t.is_keyword("NOT"))
+            && tokens.get(*pos + 1).is_some_and(|t| t.is_keyword("IN"));
+        if negated || tokens.get(*pos).is_some_and(|t| t.is_keyword("IN")) {
+            *pos += if negated { 2 } else { 1 };
+            return match tokens.get(*pos) {
+                Some(Token::Subquery(query)) => {
+                    *pos += 1;
+                    Ok(Expression::InSubquery { expression: Box::new(left), subquery: query.clone(), negated })
+                }
+                _ => Err(QueryError::SyntaxError("Expected a subquery after IN".to_string())),
+            };
+        }
+
+        if let Some(Token::Operator(op)) = tokens.get(*pos) {
+            if matches!(op.as_str(), "=" | "!=" | "<>" | "<" | "<=" | ">" | ">=") {
+                let operator = op.clone();
+                *pos += 1;
+                let right = self.parse_concat(tokens, pos)?;
+                return Ok(Expression::Comparison {
+                    left: Box::new(left),
+                    operator,
+                    right: Box::new(right),
+                });
+            }
+        }
+
+        Ok(left)
+    }
+
+    fn parse_concat(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        self.parse_binary_level(tokens, pos, &["||"], Self::parse_additive)
+    }
+
+    fn parse_additive(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        self.parse_binary_level(tokens, pos, &["+", "-"], Self::parse_multiplicative)
+    }
+
+    fn parse_multiplicative(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        self.parse_binary_level(tokens, pos, &["*", "/", "%"], Self::parse_unary)
+    }
+
+    /// Parse a left-associative chain of binary operators of the same precedence
+    fn parse_binary_level(
+        &self,
+        tokens: &[Token],
+        pos: &mut usize,
+        operators: &[&str],
+        next_level: fn(&Self, &[Token], &mut usize) -> Result<Expression, QueryError>,
+    ) -> Result<Expression, QueryError> {
+        let mut left = next_level(self, tokens, pos)?;
+        while let Some(Token::Operator(op)) = tokens.get(*pos) {
+            if !operators.contains(&op.as_str()) {
+                break;
+            }
+            let operator = op.clone();
+            *pos += 1;
+            let right = next_level(self, tokens, pos)?;
+            left = Expression::Binary {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        match tokens.get(*pos) {
+            Some(Token::Operator(op)) if op == "-" => {
+                *pos += 1;
+                // The smallest INTEGER only fits with its sign, so a negative number is read whole
+                if let Some(Token::Number(num)) = tokens.get(*pos) {
+                    if let Ok(value) = format!("-{}", num).parse::<i64>() {
+                        *pos += 1;
+                        return Ok(Expression::Literal(Value::Integer(value)));
+                    }
+                }
+                let inner = self.parse_unary(tokens, pos)?;
+                // Fold negative numeric literals
+                Ok(match inner {
+                    Expression::Literal(Value::Integer(num)) if num != i64::MIN => Expression::Literal(Value::Integer(-num)),
+                    Expression::Literal(Value::Float(num)) => Expression::Literal(Value::Float(-num)),
+                    other => Expression::Negate(Box::new(other)),
+                })
+            }
+            Some(Token::Operator(op)) if op == "+" => {
+                *pos += 1;
+                self.parse_unary(tokens, pos)
+            }
+            _ => self.parse_primary(tokens, pos),
+        }
+    }
+
+    fn parse_primary(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        let token = tokens.get(*pos)
+            .ok_or_else(|| QueryError::SyntaxError("Unexpected end of expression".to_string()))?;
+        *pos += 1;
+
+        match token {
+            Token::Number(num) => {
+                if let Ok(value) = num.parse::<i64>() {
+                    Ok(Expression::Literal(Value::Integer(value)))
+                } else {
+                    num.parse::<f64>()
+                        .map(|value| Expression::Literal(Value::Float(value)))
+                        .map_err(|_| QueryError::SyntaxError(format!("Invalid number: {}", num)))
+                }
+            }
+            Token::StringLiteral(text) => Ok(Expression::Literal(Value::Text(text.clone()))),
+            Token::Subquery(query) => Ok(Expression::Subquery(query.clone())),
+            Token::Identifier(name) if name.eq_ignore_ascii_case("EXISTS") => match tokens.get(*pos) {
+                Some(Token::Subquery(query)) => {
+                    *pos += 1;
+                    Ok(Expression::Exists(query.clone()))
+                }
+                _ => Err(QueryError::SyntaxError("Expected a subquery after EXISTS".to_string())),
+            },
+            Token::LeftParen => {
+                let inner = self.parse_or(tokens, pos)?;
+                self.expect_right_paren(tokens, pos)?;
+                Ok(inner)
+            }
+            Token::Identifier(name) if name.eq_ignore_ascii_case("CASE") => self.parse_case(tokens, pos),
+            Token::Identifier(name) if name.eq_ignore_ascii_case("INTERVAL") => self.parse_interval(tokens, pos),
+            Token::Identifier(name) if name.eq_ignore_ascii_case("TRUE") => Ok(Expression::Literal(Value::Boolean(true))),
+            Token::Identifier(name) if name.eq_ignore_ascii_case("FALSE") => Ok(Expression::Literal(Value::Boolean(false))),
+            Token::Identifier(name) if name.eq_ignore_ascii_case("NULL") => Ok(Expression::Literal(Value::Null)),
+            Token::Identifier(name) => {
+                if tokens.get(*pos) == Some(&Token::LeftParen) {
+                    self.parse_function_call(name, tokens, pos)
+                } else {
+                    Ok(Expression::Column(name.clone()))
+                }
+            }
+            other => Err(QueryError::SyntaxError(format!("Unexpected token in expression: {}", other.to_sql()))),
+        }
+    }
+
+    fn parse_function_call(&self, name: &str, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        // Collect the raw argument tokens up to the matching parenthesis
+        *pos += 1;
+        let arguments_start = *pos;
+        let mut depth = 1;
+        let mut argument = Vec::new();
+        while *pos < tokens.len() {
+            match &tokens[*pos] {
+                Token::LeftParen => depth += 1,
+                Token::RightParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            argument.push(tokens[*pos].to_sql());
+            *pos += 1;
+        }
+        let arguments = &tokens[arguments_start..*pos];
+        self.expect_right_paren(tokens, pos)?;
+
+        if tokens.get(*pos).is_some_and(|t| t.is_keyword("OVER")) {
+            return self.parse_window(name, arguments, tokens, pos);
+        }
+        if name.eq_ignore_ascii_case("CAST") {
+            return self.parse_cast(arguments);
+        }
+        // EXTRACT(field FROM expression) is a call of EXTRACT('field', expression)
+        if name.eq_ignore_ascii_case("EXTRACT") && arguments.get(1).is_some_and(|t| t.is_keyword("FROM")) {
+            let field = Expression::Literal(Value::Text(arguments[0].to_sql()));
+            return Ok(Expression::Function {
+                name: "EXTRACT".to_string(),
+                arguments: vec![field, self.parse_tokens(&arguments[2..])?],
+            });
+        }
+
+        let registry = FunctionRegistry::builtins();
+        if registry.get(name).is_some() {
+            let arguments = if arguments.is_empty() { Vec::new() } else { self.split_on_commas(arguments) };
+            let function = registry.resolve(name, arguments.len())?;
+            let arguments = arguments.into_iter()
+                .map(|argument| self.parse_tokens(argument))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Expression::Function { name: function.name.to_string(), arguments });
+        }
+
+        let function = AggregationParser::new()
+            .parse_single_function(&format!("{}({})", name, argument.join(" ")))?;
+        Ok(Expression::Aggregate(function))
+    }
+
+    /// Parse `CAST(expression AS type)` from the tokens inside the parentheses
+    fn parse_cast(&self, arguments: &[Token]) -> Result<Expression, QueryError> {
+        let as_index = arguments.iter()
+            .rposition(|t| t.is_keyword("AS"))
+            .ok_or_else(|| QueryError::SyntaxError("Expected AS in CAST".to_string()))?;
+
+        let type_parts: Vec<String> = arguments[as_index + 1..].iter()
+            .filter(|t| !matches!(t, Token::LeftParen | Token::RightParen))
+            .map(Token::to_sql)
+            .collect();
+        if type_parts.is_empty() {
+            return Err(QueryError::SyntaxError("Expected type after AS in CAST".to_string()));
+        }
+        let type_parts: Vec<&str> = type_parts.iter().map(String::as_str).collect();
+        let target = match type_parts.as_slice() {
+            [name] if name.eq_ignore_ascii_case("VARCHAR") => ColumnType::Varchar(None),
+            _ => CreateParser::new().parse_column_type(&type_parts)?,
+        };
+
+        Ok(Expression::Cast { expression: Box::new(self.parse_tokens(&arguments[..as_index])?), target })
+    }
+
+    /// Parse `INTERVAL '1 day 2 hours'` or `INTERVAL '3' DAY`, after the INTERVAL keyword
+    fn parse_interval(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        let Some(Token::StringLiteral(text)) = tokens.get(*pos) else {
+            return Err(QueryError::SyntaxError("Expected a quoted interval after INTERVAL".to_string()));
+        };
+        *pos += 1;
+
+        // A trailing unit only belongs to the interval if the text is a bare amount
+        if let Some(Token::Identifier(unit)) = tokens.get(*pos) {
+            if let Ok(interval) = Value::parse_interval(&format!("{} {}", text, unit)) {
+                *pos += 1;
+                return Ok(Expression::Literal(interval));
+            }
+        }
+        Value::parse_interval(text).map(Expression::Literal)
+    }
+
+    /// Parse `CASE [value] WHEN ... THEN ... [ELSE ...] END`, after the CASE keyword
+    fn parse_case(&self, tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        let operand = if tokens.get(*pos).is_some_and(|t| t.is_keyword("WHEN")) {
+            None
+        } else {
+            Some(Box::new(self.parse_or(tokens, pos)?))
+        };
+
+        let mut branches = Vec::new();
+        while tokens.get(*pos).is_some_and(|t| t.is_keyword("WHEN")) {
+            *pos += 1;
+            let when = self.parse_or(tokens, pos)?;
+            self.expect_keyword(tokens, pos, "THEN")?;
+            branches.push((when, self.parse_or(tokens, pos)?));
+        }
+        if branches.is_empty() {
+            return Err(QueryError::SyntaxError("CASE requires at least one WHEN".to_string()));
+        }
+
+        let otherwise = if tokens.get(*pos).is_some_and(|t| t.is_keyword("ELSE")) {
+            *pos += 1;
+            Some(Box::new(self.parse_or(tokens, pos)?))
+        } else {
+            None
+        };
+        self.expect_keyword(tokens, pos, "END")?;
+
+        Ok(Expression::Case { operand, branches, otherwise })
+    }
+
+    /// Split tokens on the commas outside of parentheses
+    pub fn split_on_commas<'a>(&self, tokens: &'a [Token]) -> Vec<&'a [Token]> {
+        let mut parts = Vec::new();
+        let mut depth = 0;
+        let mut start = 0;
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth -= 1,
+                Token::Comma if depth == 0 => {
+                    parts.push(&tokens[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(&tokens[start..]);
+        parts
+    }
+
+    fn expect_keyword(&self, tokens: &[Token], pos: &mut usize, keyword: &str) -> Result<(), QueryError> {
+        if !tokens.get(*pos).is_some_and(|t| t.is_keyword(keyword)) {
+            return Err(QueryError::SyntaxError(format!("Expected {}", keyword)));
+        }
+        *pos += 1;
+        Ok(())
+    }
+
+    /// Parse the `OVER (...)` following a function call
+    fn parse_window(&self, name: &str, arguments: &[Token], tokens: &[Token], pos: &mut usize) -> Result<Expression, QueryError> {
+        *pos += 1;
+        if tokens.get(*pos) != Some(&Token::LeftParen) {
+            return Err(QueryError::SyntaxError("Expected window specification after OVER".to_string()));
+        }
+
+        *pos += 1;
+        let spec_start = *pos;
+        let mut depth = 1;
+        while *pos < tokens.len() {
+            match &tokens[*pos] {
+                Token::LeftParen => depth += 1,
+                Token::RightParen => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            *pos += 1;
+        }
+        let spec = &tokens[spec_start..*pos];
+        self.expect_right_paren(tokens, pos)?;
+
+        let window = WindowParser::new().parse_window(name, arguments, spec)?;
+        Ok(Expression::Window(Box::new(window)))
+    }
+
+    fn expect_right_paren(&self, tokens: &[Token], pos: &mut usize) -> Result<(), QueryError> {
+        if tokens.get(*pos) != Some(&Token::RightParen) {
+            return Err(QueryError::SyntaxError("Expected closing parenthesis".to_string()));
+        }
+        *pos += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::query::error::QueryError;
+use super::lexer::{Lexer, Token};
+
+/// A table named in the FROM clause, with the alias its columns are qualified by
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRef {
+    pub name: String,
+    pub alias: Option<String>,
+    pub subquery: Option<String>, // query of a derived table, whose name is its alias
+}
+
+impl TableRef {
+    /// Name used to qualify this table's columns: the alias if given, otherwise the table name
+    pub fn qualifier(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+// Keywords that may directly follow a table name and so can't be taken as an alias
+const RESERVED_WORDS: &[&str] = &[
+    "WHERE", "GROUP", "HAVING", "ORDER", "LIMIT", "ON", "JOIN",
+    "INNER", "LEFT", "RIGHT", "FULL", "OUTER", "CROSS",
+];
+
+// Clauses that may follow the table of a query without joins
+const CLAUSE_WORDS: &[&str] = &["WHERE", "GROUP", "HAVING", "ORDER", "LIMIT"];
+
+pub struct FromParser;
+
+impl Default for FromParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromParser {
+    pub fn new() -> Self {
+        FromParser
+    }
+
+    /// Parse the table following FROM in a query without joins, which only its clauses may follow
+    /// Example: FROM employees e WHERE ...
+    pub fn parse_from_table(&self, tokens: &[&str]) -> Result<TableRef, QueryError> {
+        let from_index = tokens.iter()
+            .position(|&t| t.to_uppercase() == "FROM")
+            .ok_or_else(|| QueryError::SyntaxError("Expected FROM clause".to_string()))?;
+
+        let (table_ref, next) = self.parse_table_ref(tokens, from_index + 1)?;
+        if let Some(token) = tokens.get(next).filter(|token| !CLAUSE_WORDS.contains(&token.to_uppercase().as_str())) {
+            return Err(QueryError::SyntaxError(format!("Unexpected {} after table {}", token, table_ref.name)));
+        }
+        Ok(table_ref)
+    }
+
+    /// Parse a table name with an optional alias starting at `index`
+    /// Returns the table reference and the index of the first token after it
+    /// Example: employees AS e, employees e, (SELECT ...) AS recent
+    pub fn parse_table_ref(&self, tokens: &[&str], index: usize) -> Result<(TableRef, usize), QueryError> {
+        let name = tokens.get(index)
+            .ok_or_else(|| QueryError::SyntaxError("Expected table name".to_string()))?;
+        if self.is_reserved(name) {
+            return Err(QueryError::SyntaxError(format!("Expected table name, found {}", name)));
+        }
+
+        let mut next = index + 1;
+        let alias = match tokens.get(next) {
+            Some(token) if token.to_uppercase() == "AS" => {
+                let alias = tokens.get(next + 1)
+                    .filter(|alias| !self.is_reserved(alias))
+                    .ok_or_else(|| QueryError::SyntaxError("Expected alias after AS".to_string()))?;
+                next += 2;
+                Some(alias.to_string())
+            }
+            Some(token) if !self.is_reserved(token) => {
+                next += 1;
+                Some(token.to_string())
+            }
+            _ => None,
+        };
+
+        // A derived table is known by its alias only
+        if let Some(query) = name.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+            let alias = alias.ok_or_else(|| QueryError::SyntaxError("A subquery in FROM must have an alias".to_string()))?;
+            return Ok((TableRef { name: alias.clone(), alias: Some(alias), subquery: Some(query.trim().to_string()) }, next));
+        }
+
+        Ok((TableRef { name: name.to_string(), alias, subquery: None }, next))
+    }
+
+    /// Names following FROM or JOIN in a query and in each of its subqueries, derived tables
+    /// and WITH queries; names in literals, aliases and function arguments don't count
+    /// Example: SELECT * FROM a JOIN b ON ... WHERE x IN (SELECT y FROM c) -> [a, b, c]
+    pub fn table_names(&self, query: &str) -> Result<Vec<String>, QueryError> {
+        let tokens = Lexer::new().tokenize(query)?;
+        let mut names = Vec::new();
+        let mut depth = 0usize;
+        for (index, token) in tokens.iter().enumerate() {
+            match token {
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth = depth.saturating_sub(1),
+                Token::Subquery(subquery) => names.extend(self.table_names(subquery)?),
+                Token::Identifier(name) if depth == 0 && index > 0
+                    && (tokens[index - 1].is_keyword("FROM") || tokens[index - 1].is_keyword("JOIN")) => {
+                    names.push(name.clone());
+                }
+                _ => {}
+            }
+        }
+        Ok(names)
+    }
+
+    fn is_reserved(&self, token: &str) -> bool {
+        RESERVED_WORDS.contains(&token.to_uppercase().as_str())
+    }
+}
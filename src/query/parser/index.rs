@@ -0,0 +1,92 @@
+use crate::metadata::IndexDefinition;
+use crate::query::error::QueryError;
+use super::expression::ExpressionParser;
+use super::lexer::{Lexer, Token};
+
+pub struct IndexParser;
+
+impl Default for IndexParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IndexParser {
+    pub fn new() -> Self {
+        IndexParser
+    }
+
+    /// Whether the statement is CREATE [UNIQUE | FULLTEXT] INDEX or DROP INDEX rather than a table statement
+    pub fn is_index_statement(&self, tokens: &[&str]) -> bool {
+        let word = |i: usize| tokens.get(i).map(|t| t.to_uppercase()).unwrap_or_default();
+        word(1) == "INDEX" || (matches!(word(1).as_str(), "UNIQUE" | "FULLTEXT") && word(2) == "INDEX")
+    }
+
+    /// Parse `CREATE [UNIQUE | FULLTEXT] INDEX [name] ON table (column, ...) [INCLUDE (column, ...)]`
+    /// into the table name and the index
+    /// Without a name, the index is named after its table and columns, e.g. products_name_fulltext
+    pub fn parse_create(&self, tokens: &[&str]) -> Result<(String, IndexDefinition), QueryError> {
+        let kind = tokens.get(1).map(|t| t.to_uppercase()).unwrap_or_default();
+        let (unique, fulltext) = (kind == "UNIQUE", kind == "FULLTEXT");
+        let rest = if unique || fulltext { &tokens[3..] } else { &tokens[2..] };
+        let statement = Lexer::new().tokenize(&rest.join(" "))?;
+
+        let invalid = || QueryError::SyntaxError(
+            "Expected CREATE [UNIQUE | FULLTEXT] INDEX [name] ON table (column, ...) [INCLUDE (column, ...)]".to_string()
+        );
+        let (statement, include) = match statement.iter().position(|token| token.is_keyword("INCLUDE")) {
+            Some(position) => (&statement[..position], Some(&statement[position + 1..])),
+            None => (&statement[..], None),
+        };
+        let (name, table, columns) = match statement {
+            [Token::Identifier(name), on, Token::Identifier(table), Token::LeftParen, columns @ .., Token::RightParen]
+                if on.is_keyword("ON") => (Some(name.clone()), table.clone(), columns),
+            [on, Token::Identifier(table), Token::LeftParen, columns @ .., Token::RightParen]
+                if on.is_keyword("ON") => (None, table.clone(), columns),
+            _ => return Err(invalid()),
+        };
+
+        let index_name = name.as_deref().unwrap_or(&table);
+        let column_names = self.parse_columns(columns, index_name)?;
+        let include_names = match include {
+            Some(_) if fulltext => return Err(QueryError::SyntaxError("A FULLTEXT index can't INCLUDE columns".to_string())),
+            Some([Token::LeftParen, columns @ .., Token::RightParen]) => self.parse_columns(columns, index_name)?,
+            Some(_) => return Err(invalid()),
+            None => Vec::new(),
+        };
+        if let Some(column) = include_names.iter().find(|column| column_names.contains(column)) {
+            return Err(QueryError::SyntaxError(format!("Column {} appears twice in index {}", column, index_name)));
+        }
+
+        let name = name.unwrap_or_else(|| {
+            format!("{}_{}_{}", table, column_names.join("_"), if fulltext { "fulltext" } else { "idx" })
+        });
+        Ok((table, IndexDefinition { name, columns: column_names, unique, include: include_names, fulltext }))
+    }
+
+    /// Parse the comma separated column names between the parentheses of a column list
+    fn parse_columns(&self, tokens: &[Token], index_name: &str) -> Result<Vec<String>, QueryError> {
+        let mut names = Vec::new();
+        for column in ExpressionParser::new().split_on_commas(tokens) {
+            match column {
+                [Token::Identifier(column)] if !names.contains(column) => names.push(column.clone()),
+                [Token::Identifier(column)] => {
+                    return Err(QueryError::SyntaxError(format!("Column {} appears twice in index {}", column, index_name)));
+                }
+                _ => return Err(QueryError::SyntaxError(format!("Expected a list of column names in index {}", index_name))),
+            }
+        }
+        if names.is_empty() {
+            return Err(QueryError::SyntaxError(format!("Expected a list of column names in index {}", index_name)));
+        }
+        Ok(names)
+    }
+
+    /// Parse `DROP INDEX name` into the index name
+    pub fn parse_drop(&self, tokens: &[&str]) -> Result<String, QueryError> {
+        match tokens {
+            [_, _, name] => Ok(name.to_string()),
+            _ => Err(QueryError::SyntaxError("Expected DROP INDEX name".to_string())),
+        }
+    }
+}
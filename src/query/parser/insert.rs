@@ -1,10 +1,22 @@
-use crate::metadata::{Table, Column, ColumnType};
+use crate::metadata::Table;
 use crate::storage::{Block, Record, LSMEngine};
+use crate::index::Index;
 use crate::query::error::QueryError;
 use crate::query::result::QueryResult;
+use crate::query::record::{RecordCodec, Row};
+use crate::query::constraint::ConstraintChecker;
+use crate::query::value::Value;
+use super::expression::{Expression, ExpressionContext, ExpressionParser};
+use super::lexer::{Lexer, Token};
 
 pub struct InsertParser;
 
+impl Default for InsertParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl InsertParser {
     pub fn new() -> Self {
         InsertParser
@@ -17,63 +29,22 @@ impl InsertParser {
         table: &Table,
         storage_blocks: &mut Vec<Block>,
     ) -> Result<QueryResult, QueryError> {
-        if tokens.len() < 4 {
-            return Err(QueryError::SyntaxError("Invalid INSERT syntax".to_string()));
-        }
-
-        // Parse INTO clause
-        if tokens[1].to_uppercase() != "INTO" {
-            return Err(QueryError::SyntaxError("Expected INTO clause".to_string()));
+        let rows = self.prepare_rows(tokens, table)?;
+
+        let codec = RecordCodec::new();
+        let checker = ConstraintChecker::new();
+        if checker.has_unique_columns(table) {
+            let mut all_rows = storage_blocks.iter()
+                .flat_map(|block| block.get_all())
+                .map(|record| codec.decode(record, table))
+                .collect::<Result<Vec<_>, _>>()?;
+            all_rows.extend(rows.iter().cloned());
+            checker.check_unique(&all_rows, table)?;
         }
 
-        // Parse VALUES clause
-        let values_start = tokens.iter()
-            .position(|&t| t.to_uppercase() == "VALUES")
-            .ok_or_else(|| QueryError::SyntaxError("Expected VALUES clause".to_string()))?;
-
-        // Parse column names if specified
-        let columns = if tokens[2].starts_with('(') {
-            let col_end = tokens.iter()
-                .position(|&t| t.ends_with(')'))
-                .ok_or_else(|| QueryError::SyntaxError("Expected closing parenthesis for columns".to_string()))?;
-            
-            let col_tokens = &tokens[2..=col_end];
-            self.parse_column_list(col_tokens)?
-        } else {
-            // Use all columns in table order
-            table.columns.iter().map(|c| c.name.clone()).collect()
-        };
-
-        // Parse values
-        let values_vec = self.parse_values(&tokens[values_start + 1..])?;
         let mut total_inserted = 0;
-        for values in values_vec {
-            if columns.len() != values.len() {
-                return Err(QueryError::SyntaxError(format!(
-                    "Column count ({}) does not match value count ({})",
-                    columns.len(),
-                    values.len()
-                )));
-            }
-
-            // Validate and convert values
-            let mut record_data = Vec::new();
-            for (col_name, value) in columns.iter().zip(values.iter()) {
-                let column = table.columns.iter()
-                    .find(|c| c.name == *col_name)
-                    .ok_or_else(|| QueryError::ColumnNotFound(col_name.clone()))?;
-
-                if !column.validate_value(value) {
-                    return Err(QueryError::TypeMismatch(format!(
-                        "Invalid value '{}' for column '{}'",
-                        value, col_name
-                    )));
-                }
-
-                // Convert value to bytes based on column type
-                let value_bytes = self.convert_value_to_bytes(value, column)?;
-                record_data.extend(value_bytes);
-            }
+        for row in rows {
+            let record_data = codec.encode(&row, table)?;
 
             // Create and insert record
             let record = Record::new(
@@ -110,7 +81,61 @@ impl InsertParser {
         tokens: &[&str],
         table: &Table,
         storage_engine: &mut LSMEngine,
+        indexes: &mut [Index],
     ) -> Result<QueryResult, QueryError> {
+        let rows = self.prepare_rows(tokens, table)?;
+        self.insert_rows(rows, table, storage_engine, indexes)
+    }
+
+    /// Store rows produced by `prepare_rows` and add them to the table's indexes
+    /// Uniqueness is checked by the unique indexes, those of PRIMARY KEY and UNIQUE columns included,
+    /// before any row is written
+    pub fn insert_rows(
+        &self,
+        rows: Vec<Row>,
+        table: &Table,
+        storage_engine: &mut LSMEngine,
+        indexes: &mut [Index],
+    ) -> Result<QueryResult, QueryError> {
+        // Generate unique record IDs (in production, you'd want better ID generation)
+        let mut last_id = 0;
+        let rows: Vec<(u64, Row)> = rows.into_iter()
+            .map(|row| {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos() as u64;
+                last_id = now.max(last_id + 1);
+                (last_id, row)
+            })
+            .collect();
+        let changes: Vec<(u64, Option<&[Option<String>]>)> = rows.iter().map(|(id, row)| (*id, Some(row.as_slice()))).collect();
+        for index in indexes.iter() {
+            index.check_unique(&changes)?;
+        }
+
+        let codec = RecordCodec::new();
+        let mut total_inserted = 0;
+        for (record_id, row) in &rows {
+            let record_data = codec.encode(row, table)?;
+
+            // Create and insert record using LSM engine
+            let record = Record::new(*record_id, record_data);
+            storage_engine.insert(record)
+                .map_err(|e| QueryError::InternalError(format!("Failed to insert record: {}", e)))?;
+            for index in indexes.iter_mut() {
+                index.insert(*record_id, row)?;
+            }
+
+            total_inserted += 1;
+        }
+
+        Ok(QueryResult::Insert(total_inserted))
+    }
+
+    /// Parse the column list and VALUES of an INSERT into rows in table column order
+    /// Each row is checked against the table's row level constraints and normalized as it will be stored
+    pub fn prepare_rows(&self, tokens: &[&str], table: &Table) -> Result<Vec<Row>, QueryError> {
         if tokens.len() < 4 {
             return Err(QueryError::SyntaxError("Invalid INSERT syntax".to_string()));
         }
@@ -126,12 +151,12 @@ impl InsertParser {
             .ok_or_else(|| QueryError::SyntaxError("Expected VALUES clause".to_string()))?;
 
         // Parse column names if specified
-        let columns = if tokens[2].starts_with('(') {
+        let columns = if tokens[3].starts_with('(') {
             let col_end = tokens.iter()
                 .position(|&t| t.ends_with(')'))
                 .ok_or_else(|| QueryError::SyntaxError("Expected closing parenthesis for columns".to_string()))?;
             
-            let col_tokens = &tokens[2..=col_end];
+            let col_tokens = &tokens[3..=col_end];
             self.parse_column_list(col_tokens)?
         } else {
             // Use all columns in table order
@@ -140,8 +165,9 @@ impl InsertParser {
 
         // Parse values
         let values_vec = self.parse_values(&tokens[values_start + 1..])?;
-        let mut total_inserted = 0;
-        
+        let codec = RecordCodec::new();
+        let checker = ConstraintChecker::new();
+        let mut rows = Vec::new();
         for values in values_vec {
             if columns.len() != values.len() {
                 return Err(QueryError::SyntaxError(format!(
@@ -151,40 +177,11 @@ impl InsertParser {
                 )));
             }
 
-            // Validate and convert values
-            let mut record_data = Vec::new();
-            for (col_name, value) in columns.iter().zip(values.iter()) {
-                let column = table.columns.iter()
-                    .find(|c| c.name == *col_name)
-                    .ok_or_else(|| QueryError::ColumnNotFound(col_name.clone()))?;
-
-                if !column.validate_value(value) {
-                    return Err(QueryError::TypeMismatch(format!(
-                        "Invalid value '{}' for column '{}'",
-                        value, col_name
-                    )));
-                }
-
-                // Convert value to bytes based on column type
-                let value_bytes = self.convert_value_to_bytes(value, column)?;
-                record_data.extend(value_bytes);
-            }
-
-            // Generate unique record ID (in production, you'd want better ID generation)
-            let record_id = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_nanos() as u64;
-
-            // Create and insert record using LSM engine
-            let record = Record::new(record_id, record_data);
-            storage_engine.insert(record)
-                .map_err(|e| QueryError::InternalError(format!("Failed to insert record: {}", e)))?;
-            
-            total_inserted += 1;
+            let row = codec.normalize(&self.build_row(&columns, &values, table)?, table)?;
+            checker.check_row(&row, table)?;
+            rows.push(row);
         }
-
-        Ok(QueryResult::Insert(total_inserted))
+        Ok(rows)
     }
 
     fn parse_column_list(&self, tokens: &[&str]) -> Result<Vec<String>, QueryError> {
@@ -204,77 +201,75 @@ impl InsertParser {
         Ok(columns)
     }
 
-    fn parse_values(&self, tokens: &[&str]) -> Result<Vec<Vec<String>>, QueryError> {
-        let joined = tokens.join(" ");
+    /// Parse the parenthesized groups after VALUES into one expression per value
+    /// Example: (1, 'a,b'), (2, UPPER('c')) -> [[1, 'a,b'], [2, UPPER('c')]]
+    fn parse_values(&self, tokens: &[&str]) -> Result<Vec<Vec<Expression>>, QueryError> {
+        let lexed = Lexer::new().tokenize(&tokens.join(" "))?;
+        let expression_parser = ExpressionParser::new();
         let mut values = Vec::new();
-        
-        let mut start = None;
-        for (i, c) in joined.char_indices() {
-            if c == '(' {
-                start = Some(i + 1);
-            } else if c == ')' {
-                if let Some(s) = start {
-                    let group = &joined[s..i];
-                    let row: Vec<String> = group
-                        .split(',')
-                        .map(|v| v.trim().to_string())
-                        .filter(|v| !v.is_empty())
-                        .collect();
-                    if row.is_empty() {
-                        return Err(QueryError::SyntaxError("No values specified in group".to_string()));
+        let mut pos = 0;
+
+        while pos < lexed.len() {
+            if !values.is_empty() {
+                if lexed[pos] != Token::Comma {
+                    return Err(QueryError::SyntaxError(format!("Unexpected token after VALUES group: {}", lexed[pos].to_sql())));
+                }
+                pos += 1;
+            }
+            if lexed.get(pos) != Some(&Token::LeftParen) {
+                return Err(QueryError::SyntaxError("Expected ( to start a VALUES group".to_string()));
+            }
+            pos += 1;
+
+            // Values are split at the commas of the group itself, not those inside function calls
+            let mut row = Vec::new();
+            let mut depth = 0;
+            let mut start = pos;
+            loop {
+                match lexed.get(pos) {
+                    None => return Err(QueryError::SyntaxError("Expected ) to close a VALUES group".to_string())),
+                    Some(Token::LeftParen) => depth += 1,
+                    Some(Token::RightParen) if depth > 0 => depth -= 1,
+                    Some(token @ (Token::Comma | Token::RightParen)) if depth == 0 => {
+                        if pos == start {
+                            return Err(QueryError::SyntaxError("No values specified in group".to_string()));
+                        }
+                        row.push(expression_parser.parse_tokens(&lexed[start..pos])?);
+                        start = pos + 1;
+                        if *token == Token::RightParen {
+                            pos += 1;
+                            break;
+                        }
                     }
-                    values.push(row);
-                    start = None;
+                    _ => {}
                 }
+                pos += 1;
             }
+            values.push(row);
         }
-    
+
         if values.is_empty() {
             return Err(QueryError::SyntaxError("No values specified".to_string()));
         }
-    
+
         Ok(values)
     }
 
-    fn convert_value_to_bytes(&self, value: &str, column: &Column) -> Result<Vec<u8>, QueryError> {
-        match column.data_type {
-            ColumnType::Integer => {
-                let value = value.parse::<i64>()
-                    .map_err(|_| QueryError::TypeMismatch(format!("Invalid integer value: {}", value)))?;
-                Ok(value.to_be_bytes().to_vec())
-            }
-            ColumnType::Float => {
-                let value = value.parse::<f64>()
-                    .map_err(|_| QueryError::TypeMismatch(format!("Invalid float value: {}", value)))?;
-                Ok(value.to_be_bytes().to_vec())
-            }
-            ColumnType::Varchar(max_len) => {
-                // Remove quotes if present
-                let cleaned_value = value.trim_matches(|c| c == '\'' || c == '"');
-                if cleaned_value.len() > max_len {
-                    return Err(QueryError::TypeMismatch(format!(
-                        "String value exceeds maximum length of {}",
-                        max_len
-                    )));
-                }
-                let mut bytes = (cleaned_value.len() as u32).to_be_bytes().to_vec();
-                bytes.extend(cleaned_value.as_bytes());
-                Ok(bytes)
-            }
-            ColumnType::Boolean => {
-                // Remove quotes if present and convert to lowercase
-                let cleaned_value = value.trim_matches(|c| c == '\'' || c == '"').to_lowercase();
-                if cleaned_value != "true" && cleaned_value != "false" {
-                    return Err(QueryError::TypeMismatch(format!("Invalid boolean value: {}", value)));
-                }
-                Ok(vec![if cleaned_value == "true" { 1 } else { 0 }])
-            }
-            ColumnType::Timestamp => {
-                let value = value.parse::<i64>()
-                    .map_err(|_| QueryError::TypeMismatch(format!("Invalid timestamp value: {}", value)))?;
-                Ok(value.to_be_bytes().to_vec())
-            }
+    /// Arrange the given values in table column order, converting each to its column's type
+    /// Columns left out of the column list take their DEFAULT, or NULL without one
+    fn build_row(&self, columns: &[String], values: &[Expression], table: &Table) -> Result<Row, QueryError> {
+        let mut row: Row = table.columns.iter()
+            .map(|column| column.default_value.clone()
+                .filter(|default| !default.eq_ignore_ascii_case("NULL")))
+            .collect();
+        for (col_name, value) in columns.iter().zip(values) {
+            let index = table.get_column_index(col_name)
+                .ok_or_else(|| QueryError::ColumnNotFound(col_name.clone()))?;
+            row[index] = value.evaluate(&ValuesContext)?
+                .coerce_to(&table.columns[index].data_type)?
+                .to_text();
         }
+        Ok(row)
     }
 
     fn generate_record_id(&self, blocks: &[Block]) -> u64 {
@@ -286,4 +281,64 @@ impl InsertParser {
         }
         max_id + 1
     }
-} 
\ No newline at end of file
+}
+
+/// Evaluates VALUES expressions, which have no row to read columns from
+struct ValuesContext;
+
+impl ExpressionContext for ValuesContext {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError> {
+        Err(QueryError::SyntaxError(format!("Column references are not allowed in VALUES: {}", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::QueryError;
+    use tempfile::TempDir;
+    use crate::test_util::{rows, setup_engine};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE notes (id INTEGER, body VARCHAR 20, score FLOAT)",
+    ];
+
+    #[test]
+    fn test_insert_values() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // Quotes, commas and parentheses inside a literal are part of the value
+        engine.execute("INSERT INTO notes VALUES (1, 'it''s', 1.5), (2, 'a,b', 2)").unwrap();
+        engine.execute("INSERT INTO notes (body, id) VALUES ('(x) y', 3)").unwrap();
+        // Values may be any expression that needs no row
+        engine.execute("INSERT INTO notes VALUES (4, UPPER('c') || 'd', -1.5 * 2)").unwrap();
+        engine.execute("INSERT INTO notes VALUES (5, NULL, NULL)").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT id, body, score FROM notes ORDER BY id"), vec![
+            vec!["1", "it's", "1.5"],
+            vec!["2", "a,b", "2"],
+            vec!["3", "(x) y", "NULL"],
+            vec!["4", "Cd", "-3"],
+            vec!["5", "NULL", "NULL"],
+        ]);
+    }
+
+    #[test]
+    fn test_insert_values_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        for query in [
+            "INSERT INTO notes VALUES ()",
+            "INSERT INTO notes VALUES (1, 'a', 1.0",
+            "INSERT INTO notes VALUES (1, 'a', 1.0) (2, 'b', 2.0)",
+            "INSERT INTO notes VALUES (1, 'a')",
+            "INSERT INTO notes VALUES (1, 'unterminated, 1.0)",
+            "INSERT INTO notes VALUES (1, body, 1.0)",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+        assert!(matches!(engine.execute("INSERT INTO notes VALUES ('one', 'a', 1.0)"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(engine.execute("INSERT INTO notes VALUES (1, 'a note that is far too long', 1.0)"), Err(QueryError::TypeMismatch(_))));
+        assert!(rows(&mut engine, "SELECT id FROM notes").is_empty());
+    }
+}
@@ -1,271 +1,605 @@
 use std::collections::HashMap;
-use crate::metadata::{Table, ColumnType};
-use crate::storage::{LSMEngine, Record};
+use crate::metadata::{ColumnType, Table, type_name};
 use crate::query::error::QueryError;
-use crate::query::result::{QueryResult, JoinResult};
+use crate::query::record::Row;
+use super::expression::{Expression, ExpressionParser, JoinedRowContext};
+use super::from::{FromParser, TableRef};
 
+/// The tables of a FROM clause, joined left to right
+/// Example: FROM orders o JOIN users u ON ... LEFT JOIN products p ON ...
 #[derive(Debug)]
 pub struct JoinClause {
-    pub join_type: JoinType,
-    pub left_table: String,
-    pub right_table: String,
-    pub left_column: String,
-    pub right_column: String,
+    pub base_table: TableRef,
+    pub joins: Vec<JoinStep>,
 }
 
+/// One JOIN of the chain, adding `table` to the tables joined so far
 #[derive(Debug)]
+pub struct JoinStep {
+    pub join_type: JoinType,
+    pub table: TableRef,
+    pub condition: Expression, // may reference `table` and any earlier table
+}
+
+impl JoinClause {
+    /// All tables of the clause in join order
+    pub fn tables(&self) -> Vec<&TableRef> {
+        std::iter::once(&self.base_table)
+            .chain(self.joins.iter().map(|step| &step.table))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum JoinType {
     Inner,
-    // Can extend with Left, Right, Full later
+    Left,  // keeps unmatched left rows
+    Right, // keeps unmatched right rows
+    Full,  // keeps unmatched rows of both sides
+    Semi,  // keeps each left row with a match, without right columns
+    Anti,  // keeps each left row without a match, without right columns
+}
+
+/// One input of a join: its rows, the positions of the equality key columns and the row width
+pub struct JoinSide<'a> {
+    pub rows: &'a [Row],
+    pub key_indices: Vec<usize>,
+    pub width: usize,
 }
 
 pub struct JoinParser;
 
+impl Default for JoinParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl JoinParser {
     pub fn new() -> Self {
         JoinParser
     }
 
-    /// Parse JOIN clause from tokens
-    /// Expected format: table1 INNER JOIN table2 ON table1.col = table2.col
+    /// Parse a chain of JOIN clauses from tokens
+    /// Expected format: FROM table1 [[AS] t1] [INNER | LEFT | RIGHT | FULL [OUTER]] JOIN table2 [[AS] t2] ON condition [JOIN ...]
     pub fn parse_join_clause(&self, tokens: &[&str]) -> Result<JoinClause, QueryError> {
-        if tokens.len() < 6 {
-            return Err(QueryError::SyntaxError("Invalid JOIN syntax".to_string()));
-        }
+        let from_parser = FromParser::new();
+        let from_index = tokens.iter()
+            .position(|&t| t.to_uppercase() == "FROM")
+            .ok_or_else(|| QueryError::SyntaxError("Expected FROM clause".to_string()))?;
 
-        // Find INNER, JOIN, and ON keywords
-        let join_type_index = tokens.iter()
-            .position(|&t| t.to_uppercase() == "INNER")
-            .ok_or_else(|| QueryError::SyntaxError("Expected INNER keyword".to_string()))?;
+        let (base_table, mut index) = from_parser.parse_table_ref(tokens, from_index + 1)?;
+        let mut join_clause = JoinClause { base_table, joins: Vec::new() };
 
-        let join_index = tokens.iter()
-            .position(|&t| t.to_uppercase() == "JOIN")
-            .ok_or_else(|| QueryError::SyntaxError("Expected JOIN keyword".to_string()))?;
+        while index < tokens.len() && !self.is_clause_end(tokens[index]) {
+            let (join_type, join_index) = self.parse_join_type(tokens, index)?;
+            let (table, on_index) = from_parser.parse_table_ref(tokens, join_index + 1)?;
 
-        let on_index = tokens.iter()
-            .position(|&t| t.to_uppercase() == "ON")
-            .ok_or_else(|| QueryError::SyntaxError("Expected ON keyword".to_string()))?;
+            if join_clause.tables().iter().any(|t| t.qualifier() == table.qualifier()) {
+                return Err(QueryError::SyntaxError(format!(
+                    "Table name '{}' is used more than once; give it an alias", table.qualifier()
+                )));
+            }
 
-        if join_type_index + 1 != join_index || join_index + 2 != on_index {
-            return Err(QueryError::SyntaxError("Invalid JOIN syntax order".to_string()));
+            if tokens.get(on_index).map(|t| t.to_uppercase()) != Some("ON".to_string()) {
+                return Err(QueryError::SyntaxError("Expected ON keyword".to_string()));
+            }
+
+            // The ON condition runs up to the next JOIN or clause keyword
+            let on_end = tokens.iter()
+                .skip(on_index + 1)
+                .position(|&t| self.is_clause_end(t) || self.is_join_keyword(t))
+                .map(|offset| on_index + 1 + offset)
+                .unwrap_or(tokens.len());
+
+            if on_end == on_index + 1 {
+                return Err(QueryError::SyntaxError("Invalid ON clause".to_string()));
+            }
+
+            let condition = ExpressionParser::new().parse(&tokens[on_index + 1..on_end])?;
+            join_clause.joins.push(JoinStep { join_type, table, condition });
+            index = on_end;
         }
 
-        let left_table = tokens[join_type_index - 1].to_string();
-        let right_table = tokens[join_index + 1].to_string();
+        if join_clause.joins.is_empty() {
+            return Err(QueryError::SyntaxError("Expected JOIN clause".to_string()));
+        }
+
+        Ok(join_clause)
+    }
 
-        // Parse ON condition: table1.col = table2.col
-        if on_index + 3 >= tokens.len() {
-            return Err(QueryError::SyntaxError("Invalid ON clause".to_string()));
+    fn is_clause_end(&self, token: &str) -> bool {
+        matches!(token.to_uppercase().as_str(), "WHERE" | "GROUP" | "HAVING" | "ORDER" | "LIMIT")
+    }
+
+    fn is_join_keyword(&self, token: &str) -> bool {
+        matches!(token.to_uppercase().as_str(), "JOIN" | "INNER" | "LEFT" | "RIGHT" | "FULL")
+    }
+
+    /// Parse the join type keywords starting at `index`
+    /// Returns the join type and the index of the JOIN keyword
+    fn parse_join_type(&self, tokens: &[&str], index: usize) -> Result<(JoinType, usize), QueryError> {
+        let keyword = |offset: usize| tokens.get(index + offset).map(|t| t.to_uppercase()).unwrap_or_default();
+
+        let (join_type, mut join_index) = match keyword(0).as_str() {
+            // A bare JOIN is an inner join
+            "JOIN" => return Ok((JoinType::Inner, index)),
+            "INNER" => (JoinType::Inner, index + 1),
+            "LEFT" => (JoinType::Left, index + 1),
+            "RIGHT" => (JoinType::Right, index + 1),
+            "FULL" => (JoinType::Full, index + 1),
+            other => return Err(QueryError::SyntaxError(format!("Expected JOIN, found {}", other))),
+        };
+
+        if join_type != JoinType::Inner && keyword(join_index - index) == "OUTER" {
+            join_index += 1;
         }
 
-        let left_condition = tokens[on_index + 1];
-        let operator = tokens[on_index + 2];
-        let right_condition = tokens[on_index + 3];
+        if keyword(join_index - index) != "JOIN" {
+            return Err(QueryError::SyntaxError("Invalid JOIN syntax order".to_string()));
+        }
+
+        Ok((join_type, join_index))
+    }
 
-        if operator != "=" {
-            return Err(QueryError::SyntaxError("Only equality joins are supported".to_string()));
+    /// Join the rows built so far with the rows of the step's table
+    /// `tables` lists every table up to and including the step's table, in row order
+    pub fn execute_join_step(
+        &self,
+        step: &JoinStep,
+        tables: &[(&str, &Table)],
+        left_rows: &[Row],
+        right_rows: &[Row],
+    ) -> Result<Vec<Row>, QueryError> {
+        let condition_type = step.condition.check_type(&|name| JoinedRowContext::column_type(tables, name).map(Some))?;
+        if !matches!(condition_type, Some(ColumnType::Boolean) | None) {
+            return Err(QueryError::TypeMismatch(format!("ON clause must be a condition, got {}", type_name(&condition_type))));
         }
 
-        // Parse table.column format
-        let left_parts: Vec<&str> = left_condition.split('.').collect();
-        let right_parts: Vec<&str> = right_condition.split('.').collect();
+        let (joined_tables, new_table) = tables.split_at(tables.len() - 1);
+        let left_width = joined_tables.iter().map(|(_, table)| table.columns.len()).sum();
 
-        if left_parts.len() != 2 || right_parts.len() != 2 {
-            return Err(QueryError::SyntaxError("Expected table.column format in ON clause".to_string()));
+        // ANDed equalities between the two sides form a composite hash key
+        let mut left_keys = Vec::new();
+        let mut right_keys = Vec::new();
+        for conjunct in step.condition.conjuncts() {
+            if let Some((left_index, right_index)) = self.equality_key(conjunct, tables, left_width)? {
+                left_keys.push(left_index);
+                right_keys.push(right_index - left_width);
+            }
         }
 
-        Ok(JoinClause {
-            join_type: JoinType::Inner,
-            left_table,
-            right_table,
-            left_column: left_parts[1].to_string(),
-            right_column: right_parts[1].to_string(),
-        })
+        self.join_rows(
+            step.join_type,
+            JoinSide { rows: left_rows, key_indices: left_keys, width: left_width },
+            JoinSide { rows: right_rows, key_indices: right_keys, width: new_table[0].1.columns.len() },
+            &|row| step.condition.is_satisfied(&JoinedRowContext::new(tables, row)),
+        )
     }
 
-    /// Execute hash join algorithm
-    pub fn execute_hash_join(
+    /// Join two sets of rows, keeping the pairs whose combined row satisfies `predicate`
+    /// With equality keys this is a hash join: the smaller side is hashed and the larger
+    /// side probes it. Without keys every pair is tried, as in a nested-loop join.
+    /// Rows missing from the other side of an outer join are padded with NULLs
+    /// Output columns are the left row followed by the right row, except for semi and anti joins
+    pub fn join_rows(
         &self,
-        join_clause: &JoinClause,
-        left_table: &Table,
-        right_table: &Table,
-        left_engine: &mut LSMEngine,
-        right_engine: &mut LSMEngine,
-        selected_columns: &[String],
-    ) -> Result<QueryResult, QueryError> {
-        // Get all records from both tables
-        let left_records = left_engine.get_all_records()
-            .map_err(|e| QueryError::InternalError(format!("Failed to get left table records: {}", e)))?;
-        
-        let right_records = right_engine.get_all_records()
-            .map_err(|e| QueryError::InternalError(format!("Failed to get right table records: {}", e)))?;
-
-        // Find column indices for join condition
-        let left_join_col_index = left_table.columns.iter()
-            .position(|c| c.name == join_clause.left_column)
-            .ok_or_else(|| QueryError::ColumnNotFound(join_clause.left_column.clone()))?;
-
-        let right_join_col_index = right_table.columns.iter()
-            .position(|c| c.name == join_clause.right_column)
-            .ok_or_else(|| QueryError::ColumnNotFound(join_clause.right_column.clone()))?;
-
-        // Build hash table from smaller table (right table for simplicity)
-        let mut hash_table: HashMap<String, Vec<Vec<String>>> = HashMap::new();
-        
-        for record in &right_records {
-            let row_data = self.parse_record_data(record, right_table)?;
-            let join_key = row_data[right_join_col_index].clone();
-            
-            hash_table.entry(join_key)
-                .or_insert_with(Vec::new)
-                .push(row_data);
+        join_type: JoinType,
+        left: JoinSide,
+        right: JoinSide,
+        predicate: &dyn Fn(&Row) -> Result<bool, QueryError>,
+    ) -> Result<Vec<Row>, QueryError> {
+        let keep_left = matches!(join_type, JoinType::Left | JoinType::Full);
+        let keep_right = matches!(join_type, JoinType::Right | JoinType::Full);
+
+        // Semi and anti joins return left rows in their original order, so they always build on the right
+        let filter_left = matches!(join_type, JoinType::Semi | JoinType::Anti);
+
+        // Build on the right unless the left side is smaller
+        let build_left = !filter_left && left.rows.len() < right.rows.len();
+        let (build, probe) = if build_left { (&left, &right) } else { (&right, &left) };
+        let (keep_build, keep_probe) = if build_left { (keep_left, keep_right) } else { (keep_right, keep_left) };
+
+        let combine = |probe_row: &[Option<String>], build_row: &[Option<String>]| -> Row {
+            let (left_row, right_row) = if build_left { (build_row, probe_row) } else { (probe_row, build_row) };
+            let mut combined_row = left_row.to_vec();
+            combined_row.extend(right_row.iter().cloned());
+            combined_row
+        };
+        let nulls = |width: usize| vec![None; width];
+
+        // NULL keys never match anything
+        let join_key = |row: &[Option<String>], key_indices: &[usize]| -> Option<Vec<String>> {
+            key_indices.iter()
+                .map(|&index| row[index].clone())
+                .collect()
+        };
+
+        let nested_loop = build.key_indices.is_empty();
+        let all_build_rows: Vec<usize> = if nested_loop { (0..build.rows.len()).collect() } else { Vec::new() };
+
+        let mut hash_table: HashMap<Vec<String>, Vec<usize>> = HashMap::new();
+        if !nested_loop {
+            for (i, row) in build.rows.iter().enumerate() {
+                if let Some(key) = join_key(row, &build.key_indices) {
+                    hash_table.entry(key)
+                        .or_default()
+                        .push(i);
+                }
+            }
         }
 
-        // Probe left table and build results
+        let mut build_matched = vec![false; build.rows.len()];
         let mut result_rows = Vec::new();
-        
-        for record in &left_records {
-            let left_row_data = self.parse_record_data(record, left_table)?;
-            let join_key = &left_row_data[left_join_col_index];
-            
-            if let Some(matching_right_rows) = hash_table.get(join_key) {
-                for right_row_data in matching_right_rows {
-                    // Combine left and right row data
-                    let mut combined_row = left_row_data.clone();
-                    combined_row.extend(right_row_data.iter().cloned());
+
+        for probe_row in probe.rows {
+            let candidates = if nested_loop {
+                all_build_rows.as_slice()
+            } else {
+                join_key(probe_row, &probe.key_indices)
+                    .and_then(|key| hash_table.get(&key))
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+            };
+
+            // The full condition is checked on every candidate, covering any non-equality predicates
+            let mut probe_matched = false;
+            for &i in candidates {
+                let combined_row = combine(probe_row, &build.rows[i]);
+                if predicate(&combined_row)? {
+                    probe_matched = true;
+                    if filter_left {
+                        break;
+                    }
                     result_rows.push(combined_row);
+                    build_matched[i] = true;
                 }
             }
-        }
 
-        // Build headers for result
-        let mut headers = Vec::new();
-        for col in &left_table.columns {
-            headers.push(format!("{}.{}", join_clause.left_table, col.name));
+            match join_type {
+                JoinType::Semi if probe_matched => result_rows.push(probe_row.clone()),
+                JoinType::Anti if !probe_matched => result_rows.push(probe_row.clone()),
+                _ if !probe_matched && keep_probe => result_rows.push(combine(probe_row, &nulls(build.width))),
+                _ => {}
+            }
         }
-        for col in &right_table.columns {
-            headers.push(format!("{}.{}", join_clause.right_table, col.name));
+
+        if keep_build {
+            for (build_row, matched) in build.rows.iter().zip(build_matched) {
+                if !matched {
+                    result_rows.push(combine(&nulls(probe.width), build_row));
+                }
+            }
         }
 
-        // Filter columns if specific columns were selected
-        let (filtered_headers, filtered_rows) = if selected_columns.is_empty() || selected_columns[0] == "*" {
-            (headers, result_rows)
-        } else {
-            self.filter_selected_columns(&headers, &result_rows, selected_columns)?
+        Ok(result_rows)
+    }
+
+    /// Recognise `a.x = b.y` comparing a column of the tables joined so far with a column of the new table
+    /// Returns the positions of both columns in the combined row
+    fn equality_key(
+        &self,
+        term: &Expression,
+        tables: &[(&str, &Table)],
+        left_width: usize,
+    ) -> Result<Option<(usize, usize)>, QueryError> {
+        let (first, second) = match term {
+            Expression::Comparison { left, operator, right } if operator == "=" => match (left.as_ref(), right.as_ref()) {
+                (Expression::Column(first), Expression::Column(second)) => (first, second),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
         };
 
-        Ok(QueryResult::Join(JoinResult {
-            headers: filtered_headers,
-            rows: filtered_rows,
-        }))
+        // The condition may name the tables in either order
+        let first_index = JoinedRowContext::column_index(tables, first)?;
+        let second_index = JoinedRowContext::column_index(tables, second)?;
+        Ok(match (first_index < left_width, second_index < left_width) {
+            (true, false) => Some((first_index, second_index)),
+            (false, true) => Some((second_index, first_index)),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryError, QueryResult};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows, shown};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE companies (id INTEGER, name VARCHAR 30)",
+        "CREATE TABLE employees (id INTEGER, name VARCHAR 30, company_id INTEGER)",
+        "INSERT INTO companies VALUES (1, 'Acme')",
+        "INSERT INTO companies VALUES (2, 'Globex')",
+        "INSERT INTO employees VALUES (1, 'Alice', 2)",
+        "INSERT INTO employees VALUES (2, 'Bob', 1)",
+        "INSERT INTO employees VALUES (3, 'Carol', 1)",
+    ];
+
+    #[test]
+    fn test_join_with_table_and_column_aliases() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT e.name AS employee, c.name AS company FROM employees e INNER JOIN companies AS c ON c.id = e.company_id ORDER BY company, employee DESC";
+        match engine.execute(query).unwrap() {
+            QueryResult::Join(result) => {
+                assert_eq!(result.headers, vec!["employee".to_string(), "company".to_string()]);
+                assert_eq!(shown(&result.rows), vec![
+                    vec!["Carol".to_string(), "Acme".to_string()],
+                    vec!["Bob".to_string(), "Acme".to_string()],
+                    vec!["Alice".to_string(), "Globex".to_string()],
+                ]);
+            }
+            other => panic!("Expected join result, got {:?}", other),
+        }
+
+        match engine.execute("SELECT * FROM employees e INNER JOIN companies c ON e.company_id = c.id ORDER BY e.id").unwrap() {
+            QueryResult::Join(result) => {
+                assert_eq!(result.headers, vec!["e.id", "e.name", "e.company_id", "c.id", "c.name"]);
+                assert_eq!(shown(&result.rows)[0], vec!["1", "Alice", "2", "2", "Globex"]);
+            }
+            other => panic!("Expected join result, got {:?}", other),
+        }
     }
 
-    fn parse_record_data(&self, record: &Record, table: &Table) -> Result<Vec<String>, QueryError> {
-        let mut offset = 0;
-        let row_data: Vec<String> = table.columns.iter().map(|col| {
-            let result = match col.data_type {
-                ColumnType::Integer => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0".to_string()
-                    }
-                },
-                ColumnType::Float => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = f64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0.0".to_string()
-                    }
-                },
-                ColumnType::Varchar(_max_len) => {
-                    if offset + 4 <= record.data.len() {
-                        let length_bytes = &record.data[offset..offset+4];
-                        let length = u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize;
-                        offset += 4;
-                        
-                        if offset + length <= record.data.len() {
-                            let string_bytes = &record.data[offset..offset+length];
-                            offset += length;
-                            String::from_utf8_lossy(string_bytes).to_string()
-                        } else {
-                            offset += length;
-                            String::new()
-                        }
-                    } else {
-                        offset += 4;
-                        String::new()
-                    }
-                },
-                ColumnType::Boolean => {
-                    let result = if offset < record.data.len() && record.data[offset] == 1 { 
-                        "true".to_string() 
-                    } else { 
-                        "false".to_string() 
-                    };
-                    offset += 1;
-                    result
-                },
-                ColumnType::Timestamp => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0".to_string()
-                    }
-                },
-            };
-            result
-        }).collect();
+    #[test]
+    fn test_join_alias_resolution_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
 
-        Ok(row_data)
+        // Unqualified column present in both tables
+        assert!(matches!(
+            engine.execute("SELECT name FROM employees e INNER JOIN companies c ON e.company_id = c.id"),
+            Err(QueryError::SyntaxError(_))
+        ));
+        // Table name used in place of its alias
+        assert!(matches!(
+            engine.execute("SELECT e.name FROM employees e INNER JOIN companies c ON employees.company_id = c.id"),
+            Err(QueryError::ColumnNotFound(_))
+        ));
     }
 
-    fn filter_selected_columns(
-        &self,
-        headers: &[String],
-        rows: &[Vec<String>],
-        selected_columns: &[String],
-    ) -> Result<(Vec<String>, Vec<Vec<String>>), QueryError> {
-        let mut selected_indices = Vec::new();
-        let mut filtered_headers = Vec::new();
-
-        for col_name in selected_columns {
-            // Handle table.column format or just column name
-            let column_index = if col_name.contains('.') {
-                headers.iter().position(|h| h == col_name)
-            } else {
-                headers.iter().position(|h| h.ends_with(&format!(".{}", col_name)))
-            };
+    #[test]
+    fn test_outer_joins_fill_nulls() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("INSERT INTO companies VALUES (3, 'Initech')").unwrap();
+        engine.execute("INSERT INTO employees VALUES (4, 'Dave', 9)").unwrap();
 
-            match column_index {
-                Some(index) => {
-                    selected_indices.push(index);
-                    filtered_headers.push(headers[index].clone());
-                }
-                None => return Err(QueryError::ColumnNotFound(col_name.clone())),
+        let query = |join: &str| format!(
+            "SELECT e.name, c.name FROM employees e {} companies c ON e.company_id = c.id ORDER BY e.name, c.name", join
+        );
+
+        // A bare JOIN is an inner join
+        assert_eq!(rows(&mut engine, &query("JOIN")).len(), 3);
+        assert_eq!(rows(&mut engine, &query("INNER JOIN")).len(), 3);
+
+        let left = rows(&mut engine, &query("LEFT JOIN"));
+        assert_eq!(left.len(), 4);
+        assert_eq!(left[3], vec!["Dave", "NULL"]);
+
+        let right = rows(&mut engine, &query("RIGHT OUTER JOIN"));
+        assert_eq!(right.len(), 4);
+        assert_eq!(right[3], vec!["NULL", "Initech"]);
+
+        let full = rows(&mut engine, &query("FULL OUTER JOIN"));
+        assert_eq!(full.len(), 5);
+        assert!(full.contains(&vec!["Dave".to_string(), "NULL".to_string()]));
+        assert!(full.contains(&vec!["NULL".to_string(), "Initech".to_string()]));
+
+        // Against an empty table, outer joins keep the other side's rows and inner joins keep none
+        engine.execute("CREATE TABLE teams (id INTEGER, company_id INTEGER)").unwrap();
+        let query = "SELECT e.name, t.id FROM employees e LEFT JOIN teams t ON t.company_id = e.company_id ORDER BY e.name";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["Alice", "NULL"], vec!["Bob", "NULL"], vec!["Carol", "NULL"], vec!["Dave", "NULL"],
+        ]);
+        let query = "SELECT t.id, c.name FROM teams t FULL OUTER JOIN companies c ON t.company_id = c.id ORDER BY c.name";
+        assert_eq!(rows(&mut engine, query), vec![vec!["NULL", "Acme"], vec!["NULL", "Globex"], vec!["NULL", "Initech"]]);
+        assert!(rows(&mut engine, "SELECT * FROM employees e RIGHT JOIN teams t ON t.company_id = e.company_id").is_empty());
+
+        for query in [
+            "SELECT * FROM employees e LEFT JOIN companies c",
+            "SELECT * FROM employees e OUTER JOIN companies c ON e.company_id = c.id",
+            "SELECT * FROM employees e LEFT companies c ON e.company_id = c.id",
+            "SELECT * FROM employees e FULL JOIN ON e.company_id = c.id",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_multi_way_join() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("CREATE TABLE products (id INTEGER, title VARCHAR 30)").unwrap();
+        engine.execute("CREATE TABLE orders (id INTEGER, employee_id INTEGER, product_id INTEGER)").unwrap();
+        engine.execute("INSERT INTO products VALUES (1, 'Widget')").unwrap();
+        engine.execute("INSERT INTO products VALUES (2, 'Gadget')").unwrap();
+        engine.execute("INSERT INTO orders VALUES (10, 1, 1)").unwrap();
+        engine.execute("INSERT INTO orders VALUES (11, 1, 2)").unwrap();
+        engine.execute("INSERT INTO orders VALUES (12, 3, 2)").unwrap();
+        engine.execute("INSERT INTO orders VALUES (13, 7, 1)").unwrap();
+
+        let query = "SELECT o.id, e.name, p.title, c.name AS company FROM orders o \
+                     JOIN employees e ON o.employee_id = e.id \
+                     INNER JOIN products p ON p.id = o.product_id \
+                     JOIN companies c ON e.company_id = c.id ORDER BY o.id";
+        match engine.execute(query).unwrap() {
+            QueryResult::Join(result) => {
+                assert_eq!(result.headers, vec!["o.id", "e.name", "p.title", "company"]);
+                assert_eq!(shown(&result.rows), vec![
+                    vec!["10", "Alice", "Widget", "Globex"],
+                    vec!["11", "Alice", "Gadget", "Globex"],
+                    vec!["12", "Carol", "Gadget", "Acme"],
+                ]);
             }
+            other => panic!("Expected join result, got {:?}", other),
         }
 
-        let filtered_rows: Vec<Vec<String>> = rows.iter()
-            .map(|row| {
-                selected_indices.iter()
-                    .map(|&index| row[index].clone())
-                    .collect()
-            })
-            .collect();
+        // The small products table is hashed on the left of a RIGHT JOIN chain
+        let query = "SELECT p.title, o.id, e.name FROM products p RIGHT JOIN orders o ON p.id = o.product_id \
+                     LEFT JOIN employees e ON e.id = o.employee_id ORDER BY o.id";
+        match engine.execute(query).unwrap() {
+            QueryResult::Join(result) => assert_eq!(shown(&result.rows), vec![
+                vec!["Widget", "10", "Alice"],
+                vec!["Gadget", "11", "Alice"],
+                vec!["Gadget", "12", "Carol"],
+                vec!["Widget", "13", "NULL"],
+            ]),
+            other => panic!("Expected join result, got {:?}", other),
+        }
 
-        Ok((filtered_headers, filtered_rows))
+        // The same table twice needs distinct aliases
+        assert!(matches!(
+            engine.execute("SELECT * FROM employees JOIN companies ON employees.company_id = companies.id JOIN companies ON employees.id = companies.id"),
+            Err(QueryError::SyntaxError(_))
+        ));
+
+        // An empty table in the middle of an inner chain leaves no rows, and an outer one fills it with NULLs
+        engine.execute("CREATE TABLE shipments (order_id INTEGER, carrier VARCHAR 20)").unwrap();
+        let query = "SELECT o.id FROM orders o JOIN shipments s ON s.order_id = o.id JOIN products p ON p.id = o.product_id";
+        assert!(rows(&mut engine, query).is_empty());
+        let query = "SELECT o.id, s.carrier, p.title FROM orders o LEFT JOIN shipments s ON s.order_id = o.id \
+                     JOIN products p ON p.id = o.product_id ORDER BY o.id";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["10", "NULL", "Widget"],
+            vec!["11", "NULL", "Gadget"],
+            vec!["12", "NULL", "Gadget"],
+            vec!["13", "NULL", "Widget"],
+        ]);
+
+        // A condition can only refer to tables joined before it
+        assert!(matches!(
+            engine.execute("SELECT * FROM orders o JOIN employees e ON p.id = o.product_id JOIN products p ON e.id = o.employee_id"),
+            Err(QueryError::ColumnNotFound(_))
+        ));
+        for query in [
+            "SELECT * FROM orders o JOIN employees e ON e.id = o.employee_id JOIN products p",
+            "SELECT * FROM orders o JOIN employees e ON e.id = o.employee_id JOIN",
+            "SELECT * FROM orders o JOIN employees e ON e.id = o.employee_id JOIN ON p.id = o.product_id",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_join_feeds_filter_group_and_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("INSERT INTO companies VALUES (3, 'Initech')").unwrap();
+
+        let query = "SELECT c.name, COUNT(*) AS staff, MAX(e.id) FROM employees e JOIN companies c ON e.company_id = c.id \
+                     GROUP BY c.name ORDER BY staff DESC LIMIT 1";
+        match engine.execute(query).unwrap() {
+            QueryResult::Aggregation(result) => {
+                assert_eq!(result.headers, vec!["c.name", "staff", "MAX(e.id)"]);
+                assert_eq!(shown(&result.rows), vec![vec!["Acme", "2", "3"]]);
+            }
+            other => panic!("Expected aggregation result, got {:?}", other),
+        }
+
+        // WHERE runs on the joined rows before grouping, HAVING on the groups
+        let query = "SELECT c.name, SUM(e.id) FROM employees e JOIN companies c ON e.company_id = c.id \
+                     WHERE e.name <> 'Carol' GROUP BY c.name HAVING SUM(e.id) > 1";
+        match engine.execute(query).unwrap() {
+            QueryResult::Aggregation(result) => assert_eq!(shown(&result.rows), vec![vec!["Acme", "2"]]),
+            other => panic!("Expected aggregation result, got {:?}", other),
+        }
+
+        // COUNT of a column skips the NULLs an outer join fills in
+        let query = "SELECT c.name, COUNT(e.id) AS staff FROM companies c LEFT JOIN employees e ON c.id = e.company_id \
+                     GROUP BY c.name ORDER BY staff, c.name";
+        match engine.execute(query).unwrap() {
+            QueryResult::Aggregation(result) => assert_eq!(shown(&result.rows), vec![
+                vec!["Initech", "0"],
+                vec!["Globex", "1"],
+                vec!["Acme", "2"],
+            ]),
+            other => panic!("Expected aggregation result, got {:?}", other),
+        }
+
+        // Filtering and limiting an ungrouped join
+        let query = "SELECT e.name FROM employees e JOIN companies c ON e.company_id = c.id WHERE c.name = 'Acme' ORDER BY e.name DESC LIMIT 1";
+        match engine.execute(query).unwrap() {
+            QueryResult::Join(result) => assert_eq!(shown(&result.rows), vec![vec!["Carol"]]),
+            other => panic!("Expected join result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_multi_condition_and_non_equi_joins() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("CREATE TABLE badges (company_id INTEGER, employee_id INTEGER, label VARCHAR 10)").unwrap();
+        engine.execute("INSERT INTO badges VALUES (1, 2, 'gold')").unwrap();
+        engine.execute("INSERT INTO badges VALUES (2, 2, 'silver')").unwrap();
+
+        // Two equalities make a composite key
+        let query = "SELECT e.name, b.label FROM employees e JOIN badges b ON b.company_id = e.company_id AND b.employee_id = e.id";
+        match engine.execute(query).unwrap() {
+            QueryResult::Join(result) => assert_eq!(shown(&result.rows), vec![vec!["Bob", "gold"]]),
+            other => panic!("Expected join result, got {:?}", other),
+        }
+
+        // Extra predicates in ON decide matches, so an outer join still keeps unmatched rows
+        let query = "SELECT c.name, e.name FROM companies c LEFT JOIN employees e ON c.id = e.company_id AND e.name = 'Bob' ORDER BY c.name";
+        match engine.execute(query).unwrap() {
+            QueryResult::Join(result) => assert_eq!(shown(&result.rows), vec![
+                vec!["Acme", "Bob"],
+                vec!["Globex", "NULL"],
+            ]),
+            other => panic!("Expected join result, got {:?}", other),
+        }
+
+        // A range condition without any equality runs as a nested loop
+        let query = "SELECT e.name, c.name FROM employees e JOIN companies c ON e.company_id < c.id OR e.id = c.id ORDER BY e.name, c.name";
+        match engine.execute(query).unwrap() {
+            QueryResult::Join(result) => assert_eq!(shown(&result.rows), vec![
+                vec!["Alice", "Acme"],
+                vec!["Bob", "Globex"],
+                vec!["Carol", "Globex"],
+            ]),
+            other => panic!("Expected join result, got {:?}", other),
+        }
+
+        assert!(matches!(
+            engine.execute("SELECT * FROM employees e JOIN companies c ON e.id + c.id"),
+            Err(QueryError::TypeMismatch(_))
+        ));
+
+        for query in [
+            "SELECT * FROM employees e JOIN companies c ON",
+            "SELECT * FROM employees e JOIN companies c ON e.company_id =",
+            "SELECT * FROM employees e JOIN companies c ON e.company_id = c.id AND",
+            "SELECT * FROM employees e JOIN companies c ON (e.company_id = c.id",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_null_join_keys() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("INSERT INTO employees VALUES (4, 'Dave', NULL)").unwrap();
+
+        // A NULL key matches nothing, not even another NULL, but an outer join keeps its row
+        let query = "SELECT e.name, c.name FROM employees e LEFT JOIN companies c ON e.company_id = c.id ORDER BY e.id";
+        assert_eq!(rows(&mut engine, query)[3], vec!["Dave", "NULL"]);
+        let query = "SELECT a.name, b.name FROM employees a JOIN employees b ON a.company_id = b.company_id AND a.id <> b.id ORDER BY a.name";
+        assert_eq!(rows(&mut engine, query), vec![vec!["Bob", "Carol"], vec!["Carol", "Bob"]]);
+        let query = "SELECT e.name FROM employees e JOIN companies c ON c.id = e.company_id AND e.company_id = NULL";
+        assert!(rows(&mut engine, query).is_empty());
+
+        let query = "SELECT e.name, c.name FROM employees e JOIN companies c ON e.company_id < c.id OR e.company_id IS NULL ORDER BY e.name, c.name";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["Bob", "Globex"],
+            vec!["Carol", "Globex"],
+            vec!["Dave", "Acme"],
+            vec!["Dave", "Globex"],
+        ]);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::query::error::QueryError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Identifier(String),    // column names, keywords and table.column references
+    Number(String),
+    StringLiteral(String), // quotes already stripped
+    Operator(String),      // = != <> < <= > >= + - * / % ||
+    LeftParen,
+    RightParen,
+    Comma,
+    Subquery(String),      // text of a parenthesized SELECT, parentheses stripped
+}
+
+impl Token {
+    /// Check whether this token is the given keyword (case-insensitive)
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Identifier(name) if name.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Render the token back to SQL text
+    pub fn to_sql(&self) -> String {
+        match self {
+            Token::Identifier(name) => name.clone(),
+            Token::Number(num) => num.clone(),
+            Token::StringLiteral(text) => format!("'{}'", text.replace('\'', "''")),
+            Token::Operator(op) => op.clone(),
+            Token::LeftParen => "(".to_string(),
+            Token::RightParen => ")".to_string(),
+            Token::Comma => ",".to_string(),
+            Token::Subquery(query) => format!("({})", query),
+        }
+    }
+}
+
+pub struct Lexer;
+
+impl Default for Lexer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lexer {
+    pub fn new() -> Self {
+        Lexer
+    }
+
+    /// Split a statement into whitespace separated words, keeping each quoted literal,
+    /// parenthesized subquery and window specification in a single word so
+    /// its keywords stay hidden from the clause lookups of the enclosing statement
+    /// Example: WHERE id IN (SELECT id FROM t) -> [WHERE, id, IN, (SELECT id FROM t)]
+    pub fn split_statement<'a>(&self, query: &'a str) -> Vec<&'a str> {
+        let mut words = Vec::new();
+        let mut start = None;
+        let mut quote = None;
+        let mut i = 0;
+
+        while i < query.len() {
+            let c = query[i..].chars().next().unwrap();
+            if c.is_whitespace() && quote.is_none() {
+                if let Some(word_start) = start.take() {
+                    words.push(&query[word_start..i]);
+                }
+                i += c.len_utf8();
+                continue;
+            }
+
+            start.get_or_insert(i);
+            match (quote, c) {
+                (Some(q), _) if c == q => quote = None,
+                (None, '\'' | '"') => quote = Some(c),
+                _ => {}
+            }
+
+            if quote.is_none() && c == '(' && (self.starts_subquery(&query[i + 1..]) || self.follows_grouping_word(&query[..i])) {
+                i = self.find_closing_paren(query, i).map_or(query.len(), |end| end + 1);
+            } else {
+                i += c.len_utf8();
+            }
+        }
+
+        if let Some(word_start) = start {
+            words.push(&query[word_start..]);
+        }
+        words
+    }
+
+    /// Parenthesis depth at the start of each word of a split statement,
+    /// counting only the parentheses outside quoted literals
+    /// Example: [COALESCE(a,, ')', b), UNION] -> [0, 1, 1, 0]
+    pub fn depths(&self, words: &[&str]) -> Vec<usize> {
+        let mut depth = 0usize;
+        let mut quote = None;
+        words.iter()
+            .map(|word| {
+                let start = depth;
+                for c in word.chars() {
+                    match (quote, c) {
+                        (Some(q), _) if c == q => quote = None,
+                        (Some(_), _) => {}
+                        (None, '\'' | '"') => quote = Some(c),
+                        (None, '(') => depth += 1,
+                        (None, ')') => depth = depth.saturating_sub(1),
+                        _ => {}
+                    }
+                }
+                start
+            })
+            .collect()
+    }
+
+    /// Check whether a split statement has one of the keywords outside quoted literals and parentheses
+    pub fn has_keyword(&self, words: &[&str], keywords: &[&str]) -> bool {
+        words.iter()
+            .zip(self.depths(words))
+            .any(|(word, depth)| depth == 0 && keywords.iter().any(|keyword| word.eq_ignore_ascii_case(keyword)))
+    }
+
+    /// Check whether the text after an opening parenthesis begins a subquery
+    fn starts_subquery(&self, text: &str) -> bool {
+        let word: String = text.trim_start()
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+        word.eq_ignore_ascii_case("SELECT") || word.eq_ignore_ascii_case("WITH")
+    }
+
+    /// Check whether the text before an opening parenthesis ends with a word whose group stays whole:
+    /// the OVER of a window function, or EXTRACT whose arguments contain FROM
+    fn follows_grouping_word(&self, text: &str) -> bool {
+        let text = text.trim_end();
+        let word_start = text.char_indices()
+            .rev()
+            .take_while(|(_, c)| c.is_alphanumeric() || *c == '_')
+            .last()
+            .map_or(text.len(), |(i, _)| i);
+        ["OVER", "EXTRACT"].iter().any(|word| text[word_start..].eq_ignore_ascii_case(word))
+    }
+
+    /// Byte position of the parenthesis closing the one at `open`, skipping quoted text
+    fn find_closing_paren(&self, text: &str, open: usize) -> Option<usize> {
+        let mut depth = 0;
+        let mut quote = None;
+        for (i, c) in text[open..].char_indices() {
+            match (quote, c) {
+                (Some(q), _) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '\'' | '"') => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(open + i);
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Split SQL text into tokens
+    /// Example: price * 2 >= 'abc' -> [price, *, 2, >=, 'abc']
+    pub fn tokenize(&self, input: &str) -> Result<Vec<Token>, QueryError> {
+        let chars: Vec<char> = input.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+
+            if c.is_whitespace() {
+                i += 1;
+            } else if c == '\'' || c == '"' {
+                // String literal, a doubled quote escapes itself
+                let quote = c;
+                let mut text = String::new();
+                i += 1;
+                loop {
+                    if i >= chars.len() {
+                        return Err(QueryError::SyntaxError("Unterminated string literal".to_string()));
+                    }
+                    if chars[i] == quote {
+                        if i + 1 < chars.len() && chars[i + 1] == quote {
+                            text.push(quote);
+                            i += 2;
+                            continue;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    text.push(chars[i]);
+                    i += 1;
+                }
+                tokens.push(Token::StringLiteral(text));
+            } else if c.is_ascii_digit() || (c == '.' && i + 1 < chars.len() && chars[i + 1].is_ascii_digit()) {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                tokens.push(Token::Number(chars[start..i].iter().collect()));
+            } else if c.is_alphanumeric() || c == '_' {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+                tokens.push(Token::Identifier(chars[start..i].iter().collect()));
+            } else {
+                match c {
+                    '(' if self.starts_subquery(&input[self.byte_offset(&chars, i) + 1..]) => {
+                        let open = self.byte_offset(&chars, i);
+                        let close = self.find_closing_paren(input, open)
+                            .ok_or_else(|| QueryError::SyntaxError("Unterminated subquery".to_string()))?;
+                        tokens.push(Token::Subquery(input[open + 1..close].trim().to_string()));
+                        i += input[open..=close].chars().count() - 1;
+                    }
+                    '(' => tokens.push(Token::LeftParen),
+                    ')' => tokens.push(Token::RightParen),
+                    ',' => tokens.push(Token::Comma),
+                    '=' | '+' | '-' | '*' | '/' | '%' => tokens.push(Token::Operator(c.to_string())),
+                    '<' | '>' | '!' | '|' => {
+                        let next = chars.get(i + 1).copied();
+                        let op = match (c, next) {
+                            ('<', Some('=')) | ('>', Some('=')) | ('!', Some('=')) | ('<', Some('>')) | ('|', Some('|')) => {
+                                i += 1;
+                                format!("{}{}", c, next.unwrap())
+                            }
+                            ('<', _) | ('>', _) => c.to_string(),
+                            _ => return Err(QueryError::SyntaxError(format!("Unexpected character: {}", c))),
+                        };
+                        tokens.push(Token::Operator(op));
+                    }
+                    _ => return Err(QueryError::SyntaxError(format!("Unexpected character: {}", c))),
+                }
+                i += 1;
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    /// Byte position in `input` of the character at `index`
+    fn byte_offset(&self, chars: &[char], index: usize) -> usize {
+        chars[..index].iter().map(|c| c.len_utf8()).sum()
+    }
+}
@@ -5,15 +5,33 @@ pub mod insert;
 pub mod update;
 pub mod delete;
 pub mod create;
+pub mod index;
 pub mod join;
 pub mod aggregation;
+pub mod lexer;
+pub mod expression;
+pub mod from;
+pub mod order;
+pub mod subquery;
+pub mod cte;
+pub mod set_operation;
+pub mod window;
 
 pub use select::SelectParser;
-pub use r#where::WhereClause;
-pub use column::ColumnParser;
+pub use r#where::{WhereClause, WhereParser};
+pub use column::{ColumnParser, SelectItem};
 pub use insert::InsertParser;
 pub use update::UpdateParser;
 pub use delete::DeleteParser;
 pub use create::CreateParser;
-pub use join::{JoinParser, JoinClause, JoinType};
-pub use aggregation::{AggregationParser, AggregationClause, AggregateFunction}; 
\ No newline at end of file
+pub use index::IndexParser;
+pub use join::{JoinParser, JoinClause, JoinType, JoinSide};
+pub use aggregation::{AggregationParser, AggregationClause, AggregateFunction};
+pub use lexer::{Lexer, Token};
+pub use expression::{Expression, ExpressionParser, ExpressionContext, RowContext, JoinedRowContext};
+pub use from::{FromParser, TableRef};
+pub use order::{OrderByParser, OrderByItem};
+pub use subquery::{Scope, ScopeContext, SubqueryRunner};
+pub use cte::{CteParser, CommonTableExpression, WithClause};
+pub use set_operation::{SetOperationParser, SetOperation, SetOperator};
+pub use window::{WindowParser, WindowFunction, WindowKind, WindowFrame, FrameBound}; 
\ No newline at end of file
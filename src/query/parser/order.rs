@@ -0,0 +1,175 @@
+use std::cmp::Ordering;
+use crate::query::error::QueryError;
+use crate::query::record::Row;
+use crate::query::value::Value;
+use super::aggregation::{AggregationParser, AggregateFunction};
+use super::column::ColumnParser;
+use super::expression::{Expression, ExpressionParser, ExpressionContext};
+use super::window::WindowFunction;
+
+#[derive(Debug, Clone)]
+pub struct OrderByItem {
+    pub expression: Expression,
+    pub descending: bool,
+}
+
+/// Resolves ORDER BY references against an output row
+/// Output column names and aliases take precedence over the columns of the source row
+pub struct OutputContext<'a> {
+    pub headers: &'a [String],
+    pub row: &'a [Option<String>],
+    pub source: Option<&'a dyn ExpressionContext>,
+}
+
+impl ExpressionContext for OutputContext<'_> {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError> {
+        if let Some(index) = self.headers.iter().position(|h| h == name) {
+            return Ok(Value::infer(self.row[index].as_deref()));
+        }
+        if let Some(source) = self.source {
+            return source.column_value(name);
+        }
+
+        // Without a source row, `e.dept` may still match an output column named `dept`
+        let unqualified = name.rsplit('.').next().unwrap_or(name);
+        self.headers.iter()
+            .position(|h| h.rsplit('.').next() == Some(unqualified))
+            .map(|index| Value::infer(self.row[index].as_deref()))
+            .ok_or_else(|| QueryError::ColumnNotFound(name.to_string()))
+    }
+
+    fn aggregate_value(&self, function: &AggregateFunction) -> Result<Value, QueryError> {
+        let header = AggregationParser::new().get_function_header(function);
+        match self.headers.iter().position(|h| *h == header) {
+            Some(index) => Ok(Value::infer(self.row[index].as_deref())),
+            None => match self.source {
+                Some(source) => source.aggregate_value(function),
+                None => Err(QueryError::SyntaxError(format!(
+                    "ORDER BY aggregate {} must appear in the SELECT list", header
+                ))),
+            },
+        }
+    }
+
+    fn window_value(&self, function: &WindowFunction) -> Result<Value, QueryError> {
+        match self.source {
+            Some(source) => source.window_value(function),
+            None => Err(QueryError::SyntaxError(format!(
+                "ORDER BY window function {} must appear in the SELECT list", function.text
+            ))),
+        }
+    }
+}
+
+pub struct OrderByParser;
+
+impl Default for OrderByParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderByParser {
+    pub fn new() -> Self {
+        OrderByParser
+    }
+
+    /// Parse ORDER BY clause if present
+    /// Example: ORDER BY total DESC, e.name
+    pub fn parse_order_by(&self, tokens: &[&str]) -> Result<Vec<OrderByItem>, QueryError> {
+        let order_index = match tokens.iter().position(|&t| t.to_uppercase() == "ORDER") {
+            Some(index) => index,
+            None => return Ok(Vec::new()),
+        };
+
+        if tokens.get(order_index + 1).map(|t| t.to_uppercase()) != Some("BY".to_string()) {
+            return Err(QueryError::SyntaxError("Expected BY after ORDER".to_string()));
+        }
+
+        let order_end = tokens.iter()
+            .skip(order_index + 2)
+            .position(|&t| t.to_uppercase() == "LIMIT")
+            .map(|offset| order_index + 2 + offset)
+            .unwrap_or(tokens.len());
+
+        let expression_parser = ExpressionParser::new();
+        let mut items = Vec::new();
+        for item in ColumnParser::new().split_select_items(&tokens[order_index + 2..order_end]) {
+            let (text, descending) = match item.rsplit_once(char::is_whitespace) {
+                Some((rest, direction)) if direction.eq_ignore_ascii_case("DESC") => (rest, true),
+                Some((rest, direction)) if direction.eq_ignore_ascii_case("ASC") => (rest, false),
+                _ => (item.as_str(), false),
+            };
+
+            if text.trim().is_empty() {
+                return Err(QueryError::SyntaxError("Empty ORDER BY item".to_string()));
+            }
+
+            let expression = expression_parser.parse(&[text.trim()])?;
+            items.push(OrderByItem { expression, descending });
+        }
+
+        if items.is_empty() {
+            return Err(QueryError::SyntaxError("ORDER BY must specify at least one expression".to_string()));
+        }
+
+        Ok(items)
+    }
+
+    /// Parse LIMIT clause if present
+    /// Example: LIMIT 10
+    pub fn parse_limit(&self, tokens: &[&str]) -> Result<Option<usize>, QueryError> {
+        let limit_index = match tokens.iter().position(|&t| t.to_uppercase() == "LIMIT") {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        match &tokens[limit_index + 1..] {
+            [count] => count.parse::<usize>()
+                .map(Some)
+                .map_err(|_| QueryError::SyntaxError(format!("Invalid LIMIT value: {}", count))),
+            _ => Err(QueryError::SyntaxError("LIMIT expects a single row count".to_string())),
+        }
+    }
+
+    /// Evaluate the sort key of a row
+    pub fn sort_key(&self, items: &[OrderByItem], context: &dyn ExpressionContext) -> Result<Vec<Value>, QueryError> {
+        items.iter()
+            .map(|item| item.expression.evaluate(context))
+            .collect()
+    }
+
+    /// Compare two sort keys, honouring the direction of each item
+    pub fn compare_keys(&self, items: &[OrderByItem], a: &[Value], b: &[Value]) -> Ordering {
+        for (item, (left, right)) in items.iter().zip(a.iter().zip(b.iter())) {
+            let ordering = left.compare(right).unwrap_or(Ordering::Equal);
+            let ordering = if item.descending { ordering.reverse() } else { ordering };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Stable sort of rows by their precomputed sort keys
+    pub fn sort_rows(&self, items: &[OrderByItem], mut keyed_rows: Vec<(Vec<Value>, Row)>) -> Vec<Row> {
+        keyed_rows.sort_by(|(a, _), (b, _)| self.compare_keys(items, a, b));
+
+        keyed_rows.into_iter().map(|(_, row)| row).collect()
+    }
+
+    /// Sort finished result rows, resolving ORDER BY against the result headers only
+    pub fn sort_output(&self, items: &[OrderByItem], headers: &[String], rows: Vec<Row>) -> Result<Vec<Row>, QueryError> {
+        if items.is_empty() {
+            return Ok(rows);
+        }
+
+        let mut keyed_rows = Vec::with_capacity(rows.len());
+        for row in rows {
+            let context = OutputContext { headers, row: &row, source: None };
+            let key = self.sort_key(items, &context)?;
+            keyed_rows.push((key, row));
+        }
+        Ok(self.sort_rows(items, keyed_rows))
+    }
+}
@@ -1,18 +1,41 @@
 use crate::metadata::{Table, ColumnType};
-use crate::storage::{Block, LSMEngine, Record};
+use crate::storage::{Block, LSMEngine};
 use crate::query::error::QueryError;
-use crate::query::result::QueryResult;
+use crate::query::record::{RecordCodec, Row};
+use crate::query::result::{QueryResult, SelectResult, JoinResult, AggregationResult};
 use super::r#where::WhereParser;
-use super::column::ColumnParser;
-use super::join::{JoinParser, JoinClause};
-use super::aggregation::{AggregationParser, AggregationClause, AggregateFunction};
-use std::collections::HashMap;
+use super::column::{ColumnParser, SelectItem};
+use crate::query::catalog::Catalog;
+use crate::query::value::Value;
+use super::expression::{Expression, ExpressionContext, JoinedRowContext};
+use super::aggregation::{AggregationParser, AggregationClause, GroupContext};
+use super::cte::CteParser;
+use super::from::{FromParser, TableRef};
+use super::join::{JoinParser, JoinSide, JoinType};
+use super::lexer::Lexer;
+use super::order::{OrderByParser, OutputContext};
+use super::set_operation::{SetOperation, SetOperationParser, SetOperator};
+use super::subquery::{derived_table, Scope, ScopeContext, SubqueryRunner};
+use super::window::{WindowContext, WindowFunction, WindowParser};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashSet;
+
+/// The tables of a FROM clause with their qualifiers, in the order their columns appear in rows
+type FromTables = Vec<(String, Table)>;
 
 pub struct SelectParser {
     where_parser: WhereParser,
     pub column_parser: ColumnParser,
-    join_parser: JoinParser,
     aggregation_parser: AggregationParser,
+    from_parser: FromParser,
+    pub order_parser: OrderByParser,
+}
+
+impl Default for SelectParser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SelectParser {
@@ -20,8 +43,9 @@ impl SelectParser {
         SelectParser {
             where_parser: WhereParser::new(),
             column_parser: ColumnParser::new(),
-            join_parser: JoinParser::new(),
             aggregation_parser: AggregationParser::new(),
+            from_parser: FromParser::new(),
+            order_parser: OrderByParser::new(),
         }
     }
 
@@ -55,51 +79,8 @@ impl SelectParser {
         let mut results = Vec::new();
         for block in storage_blocks {
             for record in block.get_all() {
-                // Build row data as Vec<String> for WHERE evaluation
-                let mut offset = 0;
-                let row_data: Vec<String> = table.columns.iter().map(|col| {
-                    let result = match col.data_type {
-                        ColumnType::Integer => {
-                            let bytes = &record.data[offset..offset+8];
-                            let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                            offset += 8;
-                            num.to_string()
-                        },
-                        ColumnType::Float => {
-                            let bytes = &record.data[offset..offset+8];
-                            let num = f64::from_be_bytes(bytes.try_into().unwrap());
-                            offset += 8;
-                            num.to_string()
-                        },
-                        ColumnType::Varchar(_max_len) => {
-                            // Read length prefix (4 bytes)
-                            let length_bytes = &record.data[offset..offset+4];
-                            let length = u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize;
-                            offset += 4;
-                            
-                            // Read string data
-                            let string_bytes = &record.data[offset..offset+length];
-                            offset += length;
-                            String::from_utf8_lossy(string_bytes).to_string()
-                        },
-                        ColumnType::Boolean => {
-                            let result = if !record.data.is_empty() && record.data[offset] == 1 { 
-                                "true".to_string() 
-                            } else { 
-                                "false".to_string() 
-                            };
-                            offset += 1;
-                            result
-                        },
-                        ColumnType::Timestamp => {
-                            let bytes = &record.data[offset..offset+8];
-                            let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                            offset += 8;
-                            num.to_string()
-                        },
-                    };
-                    result
-                }).collect();
+                // Decode the row for WHERE evaluation
+                let row_data = RecordCodec::new().decode(record, table)?;
 
                 if let Some(ref where_clause) = where_clause {
                     if !self.where_parser.evaluate_where_clause(&row_data, table, where_clause)? {
@@ -107,7 +88,7 @@ impl SelectParser {
                     }
                 }
 
-                // Build result row for selected columns as Vec<String>
+                // Build result row for selected columns
                 let mut row = Vec::new();
                 for column in &columns {
                     let idx = table.columns.iter().position(|c| c.name == column.name).unwrap();
@@ -117,10 +98,13 @@ impl SelectParser {
             }
         }
 
-        Ok(QueryResult::Select(results))
+        Ok(QueryResult::Select(SelectResult {
+            headers: columns.iter().map(|c| c.name.clone()).collect(),
+            rows: results,
+        }))
     }
 
-    // Enhanced LSM engine method that supports JOIN and aggregation
+    // Enhanced LSM engine method that supports aggregation
     pub fn parse_and_execute_lsm(
         &mut self,
         tokens: &[&str],
@@ -128,8 +112,17 @@ impl SelectParser {
         storage_engine: &mut LSMEngine,
     ) -> Result<QueryResult, QueryError> {
         // This method is for single table queries - should not be used for joins
-        let mut tables_and_engines = [(table, storage_engine)];
-        self.parse_and_execute_lsm_with_tables(tokens, &mut tables_and_engines)
+        // Get all records from the LSM engine
+        let all_records = storage_engine.get_all_records()
+            .map_err(|e| QueryError::InternalError(format!("Failed to get all records: {}", e)))?;
+
+        let rows = all_records.iter()
+            .map(|record| RecordCodec::new().decode(record, table))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut catalog = Catalog::new();
+        catalog.add_table(table.clone(), rows);
+        self.execute_statement(tokens, catalog)
     }
 
     /// Enhanced method that can handle both single table and join queries
@@ -139,54 +132,177 @@ impl SelectParser {
         tables_and_engines: &mut [(&Table, &mut LSMEngine)],
     ) -> Result<QueryResult, QueryError> {
         // Check if this is a JOIN query
-        let has_join = tokens.iter().any(|&t| t.to_uppercase() == "JOIN");
+        let has_join = Lexer::new().has_keyword(tokens, &["JOIN"]);
         
         if has_join {
             // For JOIN queries, we need to handle this differently due to borrowing restrictions
-            return Err(QueryError::SyntaxError("JOIN queries should use the QueryEngine directly".to_string()));
+            Err(QueryError::SyntaxError("JOIN queries should use the QueryEngine directly".to_string()))
         } else {
-            // Check if this is an aggregation query
-            let select_end = tokens.iter()
-                .position(|&t| t.to_uppercase() == "FROM")
-                .unwrap_or(tokens.len());
-            
-            let select_tokens = &tokens[1..select_end];
-            let aggregate_functions = self.aggregation_parser.parse_aggregation_functions(select_tokens)?;
-            
-            let has_group_by = tokens.iter().any(|&t| t.to_uppercase() == "GROUP");
+            // Regular single table query
+            if tables_and_engines.len() != 1 {
+                return Err(QueryError::SyntaxError("Expected single table for non-join query".to_string()));
+            }
             
-            if !aggregate_functions.is_empty() || has_group_by {
-                self.execute_aggregation_query(tokens, tables_and_engines)
+            let (table, storage_engine) = &mut tables_and_engines[0];
+            self.parse_and_execute_lsm(tokens, table, storage_engine)
+        }
+    }
+
+    /// Run a SELECT statement, with any WITH clause in front of it, against a catalog
+    /// Each WITH query is added to the catalog as a table for the queries after it
+    pub fn execute_statement(&self, tokens: &[&str], mut catalog: Catalog) -> Result<QueryResult, QueryError> {
+        let mut tokens = tokens;
+        if tokens.first().is_some_and(|t| t.eq_ignore_ascii_case("WITH")) {
+            let cte_parser = CteParser::new();
+            let (with_clause, main_index) = cte_parser.parse_with_clause(tokens)?;
+            for cte in &with_clause.ctes {
+                let (table, rows) = cte_parser.execute_cte(cte, with_clause.recursive, self, &mut catalog)?;
+                catalog.add_table(table, rows);
+            }
+            tokens = &tokens[main_index..];
+        }
+
+        SubqueryRunner::new(self, &catalog).execute_select(tokens, None)
+    }
+
+    /// Run a SELECT against the tables of the scope's catalog
+    pub fn execute_select(&self, tokens: &[&str], scope: Scope) -> Result<QueryResult, QueryError> {
+        Ok(self.execute_typed_select(tokens, scope)?.0)
+    }
+
+    /// Run a SELECT, or queries combined by set operators, along with the type of each output column
+    pub fn execute_typed_select(&self, tokens: &[&str], scope: Scope) -> Result<(QueryResult, Vec<Option<ColumnType>>), QueryError> {
+        if let Some(set_operation) = SetOperationParser::new().parse_set_operation(tokens)? {
+            return self.execute_set_operation(&set_operation, scope);
+        }
+
+        let (sources, rows) = self.execute_from(tokens, scope)?;
+        let tables: Vec<(&str, &Table)> = sources.iter()
+            .map(|(qualifier, table)| (qualifier.as_str(), table))
+            .collect();
+        self.execute_pipeline(tokens, &tables, rows, scope)
+    }
+
+    /// Run each query of a set operation and combine their rows
+    /// INTERSECT binds tighter than UNION and EXCEPT, which apply left to right
+    /// The result takes its headers from the first query
+    fn execute_set_operation(&self, set_operation: &SetOperation, scope: Scope) -> Result<(QueryResult, Vec<Option<ColumnType>>), QueryError> {
+        let set_parser = SetOperationParser::new();
+        let (first, mut types) = self.execute_typed_select(set_operation.queries[0], scope)?;
+        let first = first.into_select_result()
+            .ok_or_else(|| QueryError::InternalError("Set operation query did not return rows".to_string()))?;
+        let headers = first.headers;
+
+        // Fold INTERSECT into its left operand first, keeping the other operators for a second pass
+        let mut operands = vec![first.rows];
+        let mut operators = Vec::new();
+        for (&(operator, all), query) in set_operation.operators.iter().zip(&set_operation.queries[1..]) {
+            let (result, query_types) = self.execute_typed_select(query, scope)?;
+            types = set_parser.unify_types(&types, &query_types)?;
+            let rows = result.into_select_result()
+                .ok_or_else(|| QueryError::InternalError("Set operation query did not return rows".to_string()))?
+                .rows;
+
+            if operator == SetOperator::Intersect {
+                let left = operands.pop().unwrap_or_default();
+                operands.push(set_parser.combine(operator, all, left, rows));
             } else {
-                // Regular single table query
-                if tables_and_engines.len() != 1 {
-                    return Err(QueryError::SyntaxError("Expected single table for non-join query".to_string()));
-                }
-                
-                let (table, storage_engine) = &mut tables_and_engines[0];
-                self.execute_regular_select(tokens, table, storage_engine)
+                operands.push(rows);
+                operators.push((operator, all));
             }
         }
+
+        let mut operands = operands.into_iter();
+        let mut rows = operands.next().unwrap_or_default();
+        for ((operator, all), right) in operators.into_iter().zip(operands) {
+            rows = set_parser.combine(operator, all, rows, right);
+        }
+
+        let order_by = self.order_parser.parse_order_by(set_operation.trailing)?;
+        let mut rows = self.order_parser.sort_output(&order_by, &headers, rows)?;
+        if let Some(limit) = self.order_parser.parse_limit(set_operation.trailing)? {
+            rows.truncate(limit);
+        }
+
+        Ok((QueryResult::Select(SelectResult { headers, rows }), types))
     }
 
-    fn execute_aggregation_query(
-        &mut self,
-        tokens: &[&str],
-        tables_and_engines: &mut [(&Table, &mut LSMEngine)],
-    ) -> Result<QueryResult, QueryError> {
-        if tables_and_engines.len() != 1 {
-            return Err(QueryError::SyntaxError("Aggregation queries support only single table".to_string()));
+    /// Produce the rows of the FROM clause: a single table, a derived table or a chain of joins
+    /// The rows of a single table are borrowed from the catalog
+    fn execute_from<'s>(&self, tokens: &[&str], scope: Scope<'s>) -> Result<(FromTables, Cow<'s, [Row]>), QueryError> {
+        let has_join = Lexer::new().has_keyword(tokens, &["JOIN"]);
+        if !has_join {
+            let table_ref = self.from_parser.parse_from_table(tokens)?;
+            let (table, rows) = self.table_rows(&table_ref, scope)?;
+            return Ok((vec![(table_ref.qualifier().to_string(), table)], rows));
         }
 
-        let (table, storage_engine) = &mut tables_and_engines[0];
+        let join_clause = JoinParser::new().parse_join_clause(tokens)?;
+        let (base_table, mut rows) = self.table_rows(&join_clause.base_table, scope)?;
+        let mut sources = vec![(join_clause.base_table.qualifier().to_string(), base_table)];
+
+        // Join left to right: each step joins the rows built so far with the next table
+        for step in &join_clause.joins {
+            let (table, right_rows) = self.table_rows(&step.table, scope)?;
+            sources.push((step.table.qualifier().to_string(), table));
+
+            let tables: Vec<(&str, &Table)> = sources.iter()
+                .map(|(qualifier, table)| (qualifier.as_str(), table))
+                .collect();
+            rows = Cow::Owned(JoinParser::new().execute_join_step(step, &tables, &rows, &right_rows)?);
+        }
 
-        // Parse aggregation functions
+        Ok((sources, rows))
+    }
+
+    /// Metadata and rows of a table named in FROM, running the query of a derived table
+    fn table_rows<'s>(&self, table_ref: &TableRef, scope: Scope<'s>) -> Result<(Table, Cow<'s, [Row]>), QueryError> {
+        match &table_ref.subquery {
+            Some(query) => {
+                let tokens = Lexer::new().split_statement(query);
+                let (result, types) = self.execute_typed_select(&tokens, scope)?;
+                let result = result.into_select_result()
+                    .ok_or_else(|| QueryError::InternalError("Derived table did not return rows".to_string()))?;
+                Ok((derived_table(&table_ref.name, &result.headers, &types, &result.rows), Cow::Owned(result.rows)))
+            }
+            None => scope.runner.catalog()
+                .get_table(&table_ref.name)
+                .map(|(table, rows)| (table.clone(), Cow::Borrowed(rows)))
+                .ok_or_else(|| QueryError::TableNotFound(table_ref.name.clone())),
+        }
+    }
+
+    /// Run the rows produced by the FROM clause through the rest of the query:
+    /// WHERE, GROUP BY and aggregates, HAVING, the SELECT list, ORDER BY, DISTINCT and LIMIT
+    /// `tables` lists each table with its qualifier, in the order its columns appear in the rows
+    /// Returns the result along with the type of each output column
+    pub fn execute_pipeline(
+        &self,
+        tokens: &[&str],
+        tables: &[(&str, &Table)],
+        rows: Cow<[Row]>,
+        scope: Scope,
+    ) -> Result<(QueryResult, Vec<Option<ColumnType>>), QueryError> {
         let select_end = tokens.iter()
             .position(|&t| t.to_uppercase() == "FROM")
             .unwrap_or(tokens.len());
-        
         let select_tokens = &tokens[1..select_end];
-        let aggregate_functions = self.aggregation_parser.parse_aggregation_functions(select_tokens)?;
+
+        // Parse the projection and type check it against the table schemas
+        let distinct = self.column_parser.is_distinct(select_tokens);
+        let select_items = self.column_parser.parse_select_items(select_tokens)?;
+        let mut column_types = Vec::new();
+        for item in &select_items {
+            match item {
+                SelectItem::Wildcard => column_types.extend(tables.iter()
+                    .flat_map(|(_, table)| table.columns.iter().map(|col| Some(col.data_type.clone())))),
+                SelectItem::Expression { expression, .. } => {
+                    column_types.push(expression.check_type(&|name| scope.column_type(tables, name))?);
+                }
+            }
+        }
+
+        let headers = self.output_headers(&select_items, tables);
 
         // Parse WHERE clause if present
         let where_index = tokens.iter()
@@ -199,146 +315,542 @@ impl SelectParser {
             None
         };
 
-        // Parse GROUP BY clause if present
-        let group_by_columns = if tokens.iter().any(|&t| t.to_uppercase() == "GROUP") {
-            self.aggregation_parser.parse_group_by(tokens)?
-        } else {
-            Vec::new()
+        let order_by = self.order_parser.parse_order_by(tokens)?;
+        let limit = self.order_parser.parse_limit(tokens)?;
+
+        // Window functions of the SELECT list and ORDER BY, computed once all rows are filtered
+        let windows: Vec<&WindowFunction> = select_items.iter()
+            .filter_map(|item| match item {
+                SelectItem::Expression { expression, .. } => Some(expression),
+                SelectItem::Wildcard => None,
+            })
+            .chain(order_by.iter().map(|item| &item.expression))
+            .flat_map(|expression| expression.window_functions())
+            .collect();
+
+        let filtered_rows = match &where_clause {
+            Some(where_clause) => self.filter_rows(&where_clause.condition, tables, rows, scope)?,
+            None => rows,
         };
 
-        let aggregation_clause = AggregationClause {
-            functions: aggregate_functions,
-            group_by_columns,
+        // Any aggregate, GROUP BY or HAVING makes this a grouped query
+        let aggregate_functions = self.aggregation_parser.parse_aggregation_functions(select_tokens)?;
+        let is_grouped = !aggregate_functions.is_empty()
+            || select_items.iter().any(|item| matches!(item, SelectItem::Expression { expression, .. } if expression.has_aggregate()))
+            || tokens.iter().any(|&t| t.to_uppercase() == "GROUP" || t.to_uppercase() == "HAVING");
+
+        let mut keyed_rows = Vec::new();
+        let mut group_by_columns = Vec::new();
+
+        if is_grouped {
+            if select_items.iter().any(|item| matches!(item, SelectItem::Wildcard)) {
+                return Err(QueryError::SyntaxError("SELECT * can't be used with GROUP BY or aggregates".to_string()));
+            }
+            if !windows.is_empty() {
+                return Err(QueryError::SyntaxError("Window functions can't be used with GROUP BY or aggregates".to_string()));
+            }
+
+            // Parse GROUP BY clause if present
+            if tokens.iter().any(|&t| t.to_uppercase() == "GROUP") {
+                group_by_columns = self.aggregation_parser.parse_group_by(tokens)?;
+            }
+
+            let aggregation_clause = AggregationClause {
+                functions: aggregate_functions,
+                group_by_columns: group_by_columns.clone(),
+                having: self.aggregation_parser.parse_having(tokens)?,
+            };
+
+            for (group_key, group_rows) in self.aggregation_parser.group_rows(&group_by_columns, tables, filtered_rows.into_owned())? {
+                let group = GroupContext {
+                    parser: &self.aggregation_parser,
+                    tables,
+                    group_by_columns: &aggregation_clause.group_by_columns,
+                    group_key: &group_key,
+                    rows: &group_rows,
+                };
+                let context = ScopeContext { row: &group, scope };
+
+                let row = self.project_row(&select_items, &context, &[])?;
+                let output = OutputContext {
+                    headers: &headers,
+                    row: &row,
+                    source: Some(&context),
+                };
+
+                // Apply HAVING once the group is complete, where SELECT aliases name output columns as in ORDER BY
+                if let Some(having) = &aggregation_clause.having {
+                    if !having.is_satisfied(&output)? {
+                        continue;
+                    }
+                }
+
+                let key = self.order_parser.sort_key(&order_by, &output)?;
+                keyed_rows.push((key, row));
+            }
+        } else {
+            let window_values = if windows.is_empty() {
+                vec![Vec::new(); filtered_rows.len()]
+            } else {
+                WindowParser::new().compute_windows(&windows, tables, &filtered_rows, &|expression, index| {
+                    expression.evaluate(&ScopeContext { row: &JoinedRowContext::new(tables, &filtered_rows[index]), scope })
+                })?
+            };
+
+            for (source_row, values) in filtered_rows.iter().zip(&window_values) {
+                let row = JoinedRowContext::new(tables, source_row);
+                let context = ScopeContext { row: &WindowContext { row: &row, windows: &windows, values }, scope };
+                let row = self.project_row(&select_items, &context, source_row)?;
+                let key = self.order_parser.sort_key(&order_by, &OutputContext {
+                    headers: &headers,
+                    row: &row,
+                    source: Some(&context),
+                })?;
+                keyed_rows.push((key, row));
+            }
+        }
+
+        let mut rows = self.order_parser.sort_rows(&order_by, keyed_rows);
+        if distinct {
+            rows = self.distinct_rows(rows);
+        }
+        if let Some(limit) = limit {
+            rows.truncate(limit);
+        }
+
+        let result = if is_grouped {
+            QueryResult::Aggregation(AggregationResult { headers, rows, group_by_columns })
+        } else if tables.len() > 1 {
+            QueryResult::Join(JoinResult { headers, rows })
+        } else {
+            QueryResult::Select(SelectResult { headers, rows })
         };
+        Ok((result, column_types))
+    }
+
+    /// Keep the rows satisfying a WHERE condition, copying only those of borrowed rows
+    /// IN and EXISTS subqueries among its ANDed terms are run once as semi or anti
+    /// joins where possible, instead of once per row
+    fn filter_rows<'r>(
+        &self,
+        condition: &Expression,
+        tables: &[(&str, &Table)],
+        mut rows: Cow<'r, [Row]>,
+        scope: Scope,
+    ) -> Result<Cow<'r, [Row]>, QueryError> {
+        let mut remaining = Vec::new();
+        for term in condition.conjuncts() {
+            let joined = match term {
+                Expression::InSubquery { expression, subquery, negated } => {
+                    self.in_subquery_join(expression, subquery, *negated, tables, &rows, scope)?
+                }
+                Expression::Exists(subquery) => self.exists_join(subquery, false, tables, &rows, scope)?,
+                Expression::Not(inner) => match inner.as_ref() {
+                    Expression::Exists(subquery) => self.exists_join(subquery, true, tables, &rows, scope)?,
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            match joined {
+                Some(joined) => rows = Cow::Owned(joined),
+                None => remaining.push(term),
+            }
+        }
 
-        // We need to clone the table to avoid borrowing issues
-        let table_clone = (*table).clone();
-        self.aggregation_parser.execute_aggregation_with_where(&aggregation_clause, &table_clone, storage_engine, where_clause.as_ref())
+        let mut keep = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            let context = ScopeContext { row: &JoinedRowContext::new(tables, row), scope };
+            let mut satisfied = true;
+            for term in &remaining {
+                if !term.is_satisfied(&context)? {
+                    satisfied = false;
+                    break;
+                }
+            }
+            keep.push(satisfied);
+        }
+
+        Ok(Cow::Owned(match rows {
+            Cow::Borrowed(rows) => rows.iter().zip(keep).filter(|(_, keep)| *keep).map(|(row, _)| row.clone()).collect(),
+            Cow::Owned(mut rows) => {
+                let mut keep = keep.into_iter();
+                rows.retain(|_| keep.next().unwrap_or(false));
+                rows
+            }
+        }))
     }
 
-    fn execute_regular_select(
-        &mut self,
-        tokens: &[&str],
-        table: &Table,
-        storage_engine: &mut LSMEngine,
-    ) -> Result<QueryResult, QueryError> {
-        // Parse columns
-        let columns = if tokens[1] == "*" {
-            table.columns.clone()
-        } else {
-            let col_names = self.column_parser.parse_column_list(&tokens[1..])?;
-            table.columns.iter().filter(|c| col_names.contains(&c.name)).cloned().collect::<Vec<_>>()
+    /// Apply `x [NOT] IN (SELECT ...)` as a semi or anti join when the subquery doesn't depend on the row
+    fn in_subquery_join(
+        &self,
+        expression: &Expression,
+        subquery: &str,
+        negated: bool,
+        tables: &[(&str, &Table)],
+        rows: &[Row],
+        scope: Scope,
+    ) -> Result<Option<Vec<Row>>, QueryError> {
+        if expression.has_subquery() {
+            return Ok(None);
+        }
+
+        // Run the subquery with no current row; if it needs one it is evaluated per row instead
+        let probe = RowlessContext { tables, outer: scope.outer, correlated: Cell::new(false) };
+        let result = match scope.runner.run(subquery, &probe) {
+            Ok(result) => result,
+            Err(_) if probe.correlated.get() => return Ok(None),
+            Err(e) => return Err(e),
         };
+        if result.headers.len() != 1 {
+            return Ok(None);
+        }
 
-        // Parse WHERE clause if present
-        let where_index = tokens.iter()
-            .position(|&t| t.to_uppercase() == "WHERE")
+        let width: usize = tables.iter().map(|(_, table)| table.columns.len()).sum();
+        if negated {
+            // NOT IN an empty set holds for every row, and NOT IN a set holding NULL never does
+            if result.rows.is_empty() {
+                return Ok(Some(rows.to_vec()));
+            }
+            if result.rows.iter().any(|row| row[0].is_none()) {
+                return Ok(Some(Vec::new()));
+            }
+        }
+
+        // Append the value being looked up to each row as its join key
+        let mut keyed_rows = Vec::with_capacity(rows.len());
+        for row in rows {
+            let key = expression.evaluate(&ScopeContext { row: &JoinedRowContext::new(tables, row), scope })?;
+            // NULL NOT IN a non-empty set is unknown, so the row is dropped
+            if negated && key.is_null() {
+                continue;
+            }
+            let mut keyed_row = row.clone();
+            keyed_row.push(key.to_text());
+            keyed_rows.push(keyed_row);
+        }
+
+        let joined = JoinParser::new().join_rows(
+            if negated { JoinType::Anti } else { JoinType::Semi },
+            JoinSide { rows: &keyed_rows, key_indices: vec![width], width: width + 1 },
+            JoinSide { rows: &result.rows, key_indices: vec![0], width: 1 },
+            &|_| Ok(true),
+        )?;
+
+        Ok(Some(joined.into_iter().map(|mut row| {
+            row.truncate(width);
+            row
+        }).collect()))
+    }
+
+    /// Apply `[NOT] EXISTS (SELECT ...)` as a semi or anti join
+    /// This handles subqueries whose WHERE relates them to the row only through ANDed
+    /// equalities such as `o.customer_id = c.id`, which become the join keys
+    fn exists_join(
+        &self,
+        subquery: &str,
+        negated: bool,
+        tables: &[(&str, &Table)],
+        rows: &[Row],
+        scope: Scope,
+    ) -> Result<Option<Vec<Row>>, QueryError> {
+        let tokens = Lexer::new().split_statement(subquery);
+        let select_end = tokens.iter()
+            .position(|&t| t.to_uppercase() == "FROM")
             .unwrap_or(tokens.len());
 
-        let where_clause = if where_index < tokens.len() {
-            Some(self.where_parser.parse_where_clause(&tokens[where_index + 1..])?)
-        } else {
-            None
+        // Aggregates, grouping and LIMIT change which rows exist, so those are evaluated per row
+        let has_grouping = tokens.iter().any(|&t| matches!(t.to_uppercase().as_str(), "GROUP" | "HAVING" | "LIMIT"));
+        if !tokens.first().is_some_and(|t| t.eq_ignore_ascii_case("SELECT"))
+            || select_end == tokens.len()
+            || has_grouping
+            || !self.aggregation_parser.parse_aggregation_functions(&tokens[1..select_end])?.is_empty()
+        {
+            return Ok(None);
+        }
+
+        let (sources, inner_rows) = self.execute_from(&tokens, scope)?;
+        let inner_tables: Vec<(&str, &Table)> = sources.iter()
+            .map(|(qualifier, table)| (qualifier.as_str(), table))
+            .collect();
+
+        let condition = match tokens.iter().position(|&t| t.to_uppercase() == "WHERE") {
+            Some(where_index) => Some(self.where_parser.parse_where_clause(&tokens[where_index + 1..])?.condition),
+            None => None,
         };
 
-        // Get all records from the LSM engine
-        let all_records = storage_engine.get_all_records()
-            .map_err(|e| QueryError::InternalError(format!("Failed to get all records: {}", e)))?;
-        
-        let mut results = Vec::new();
-        
-        for record in all_records {
-            let row_data = self.parse_record_data(&record, table)?;
+        // Split the WHERE terms into filters on the subquery's own tables and equalities with the outer row
+        let resolves = |tables: &[(&str, &Table)], name: &str| JoinedRowContext::column_index(tables, name).ok();
+        let mut filters = Vec::new();
+        let mut outer_keys = Vec::new();
+        let mut inner_keys = Vec::new();
+        for term in condition.iter().flat_map(|condition| condition.conjuncts()) {
+            if term.has_subquery() {
+                return Ok(None);
+            }
+            if term.column_names().iter().all(|name| resolves(&inner_tables, name).is_some()) {
+                filters.push(term);
+                continue;
+            }
+
+            let (first, second) = match term {
+                Expression::Comparison { left, operator, right } if operator == "=" => match (left.as_ref(), right.as_ref()) {
+                    (Expression::Column(first), Expression::Column(second)) => (first, second),
+                    _ => return Ok(None),
+                },
+                _ => return Ok(None),
+            };
 
-            if let Some(ref where_clause) = where_clause {
-                if !self.where_parser.evaluate_where_clause(&row_data, table, where_clause)? {
-                    continue;
+            // Columns of the subquery's own tables take precedence over the outer row
+            let outer_index = |name: &str| match resolves(&inner_tables, name) {
+                Some(_) => None,
+                None => resolves(tables, name),
+            };
+            match (resolves(&inner_tables, first), outer_index(second), resolves(&inner_tables, second), outer_index(first)) {
+                (Some(inner), Some(outer), _, _) | (_, _, Some(inner), Some(outer)) => {
+                    inner_keys.push(inner);
+                    outer_keys.push(outer);
                 }
+                _ => return Ok(None),
             }
+        }
 
-            // Build result row for selected columns
-            let mut row = Vec::new();
-            for column in &columns {
-                let idx = table.columns.iter().position(|c| c.name == column.name).unwrap();
-                row.push(row_data[idx].clone());
+        let mut matching_rows = Vec::new();
+        'rows: for row in inner_rows.iter() {
+            let context = JoinedRowContext::new(&inner_tables, row);
+            for filter in &filters {
+                if !filter.is_satisfied(&context)? {
+                    continue 'rows;
+                }
             }
-            results.push(row);
+            matching_rows.push(row.clone());
+        }
+
+        // An uncorrelated EXISTS is the same for every row
+        if outer_keys.is_empty() {
+            let exists = !matching_rows.is_empty();
+            return Ok(Some(if exists != negated { rows.to_vec() } else { Vec::new() }));
         }
 
-        Ok(QueryResult::Select(results))
+        let width = tables.iter().map(|(_, table)| table.columns.len()).sum();
+        let inner_width = inner_tables.iter().map(|(_, table)| table.columns.len()).sum();
+        let joined = JoinParser::new().join_rows(
+            if negated { JoinType::Anti } else { JoinType::Semi },
+            JoinSide { rows, key_indices: outer_keys, width },
+            JoinSide { rows: &matching_rows, key_indices: inner_keys, width: inner_width },
+            &|_| Ok(true),
+        )?;
+        Ok(Some(joined))
     }
 
-    fn parse_record_data(&self, record: &Record, table: &Table) -> Result<Vec<String>, QueryError> {
-        let mut offset = 0;
-        let row_data: Vec<String> = table.columns.iter().map(|col| {
-            let result = match col.data_type {
-                ColumnType::Integer => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0".to_string()
+    /// Column headers of the SELECT list; `*` expands to every column,
+    /// qualified by its table when several tables are involved
+    fn output_headers(&self, select_items: &[SelectItem], tables: &[(&str, &Table)]) -> Vec<String> {
+        let mut headers = Vec::new();
+        for item in select_items {
+            match item {
+                SelectItem::Wildcard => {
+                    for (qualifier, table) in tables {
+                        headers.extend(table.columns.iter().map(|col| {
+                            if tables.len() > 1 { format!("{}.{}", qualifier, col.name) } else { col.name.clone() }
+                        }));
                     }
-                },
-                ColumnType::Float => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = f64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0.0".to_string()
-                    }
-                },
-                ColumnType::Varchar(_max_len) => {
-                    if offset + 4 <= record.data.len() {
-                        // Read length prefix (4 bytes)
-                        let length_bytes = &record.data[offset..offset+4];
-                        let length = u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize;
-                        offset += 4;
-                        
-                        if offset + length <= record.data.len() {
-                            // Read string data
-                            let string_bytes = &record.data[offset..offset+length];
-                            offset += length;
-                            String::from_utf8_lossy(string_bytes).to_string()
-                        } else {
-                            offset += length;
-                            String::new()
-                        }
-                    } else {
-                        offset += 4;
-                        String::new()
-                    }
-                },
-                ColumnType::Boolean => {
-                    let result = if offset < record.data.len() && record.data[offset] == 1 { 
-                        "true".to_string() 
-                    } else { 
-                        "false".to_string() 
-                    };
-                    offset += 1;
-                    result
-                },
-                ColumnType::Timestamp => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0".to_string()
-                    }
-                },
-            };
-            result
-        }).collect();
+                }
+                SelectItem::Expression { .. } => headers.push(item.header()),
+            }
+        }
+        headers
+    }
+
+    /// Build an output row by evaluating each SELECT item
+    fn project_row(
+        &self,
+        select_items: &[SelectItem],
+        context: &dyn ExpressionContext,
+        source_row: &[Option<String>],
+    ) -> Result<Row, QueryError> {
+        let mut row = Vec::new();
+        for item in select_items {
+            match item {
+                SelectItem::Wildcard => row.extend(source_row.iter().cloned()),
+                SelectItem::Expression { expression, .. } => row.push(expression.evaluate(context)?.to_text()),
+            }
+        }
+        Ok(row)
+    }
+
+    /// Remove duplicate rows, keeping the first occurrence of each
+    pub fn distinct_rows(&self, rows: Vec<Row>) -> Vec<Row> {
+        let mut seen = HashSet::new();
+        rows.into_iter()
+            .filter(|row| seen.insert(row.clone()))
+            .collect()
+    }
 
-        Ok(row_data)
+} 
+/// Stands in for the outer row while checking whether a subquery depends on it
+/// Any column of the current tables marks the subquery as correlated
+struct RowlessContext<'a> {
+    tables: &'a [(&'a str, &'a Table)],
+    outer: Option<&'a dyn ExpressionContext>,
+    correlated: Cell<bool>,
+}
+
+impl ExpressionContext for RowlessContext<'_> {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError> {
+        if JoinedRowContext::column_index(self.tables, name).is_ok() {
+            self.correlated.set(true);
+            return Err(QueryError::InternalError(format!("No current row to read {} from", name)));
+        }
+        match self.outer {
+            Some(outer) => outer.column_value(name),
+            None => Err(QueryError::ColumnNotFound(name.to_string())),
+        }
     }
-} 
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryError, QueryResult};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, shown};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE products (id INTEGER, name VARCHAR 30, price INTEGER, category VARCHAR 20)",
+        "INSERT INTO products VALUES (1, 'Laptop', 1200, 'Electronics')",
+        "INSERT INTO products VALUES (2, 'Phone', 800, 'Electronics')",
+        "INSERT INTO products VALUES (3, 'Book', 25, 'Education')",
+        "INSERT INTO products VALUES (4, 'Desk', 300, 'Furniture')",
+        "INSERT INTO products VALUES (5, 'Notebook', 25, 'Education')",
+    ];
+
+    #[test]
+    fn test_select_distinct() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        match engine.execute("SELECT DISTINCT category FROM products").unwrap() {
+            QueryResult::Select(result) => {
+                assert_eq!(shown(&result.rows), vec![
+                    vec!["Electronics".to_string()],
+                    vec!["Education".to_string()],
+                    vec!["Furniture".to_string()],
+                ]);
+            }
+            other => panic!("Expected select result, got {:?}", other),
+        }
+
+        match engine.execute("SELECT DISTINCT price, category FROM products WHERE price < 1000").unwrap() {
+            QueryResult::Select(result) => assert_eq!(result.rows.len(), 3),
+            other => panic!("Expected select result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_select_expressions() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT name, price * 2 AS double_price, -id, name || ' (' || category || ')' FROM products WHERE price * 2 > 1000 AND id % 2 = 1";
+        match engine.execute(query).unwrap() {
+            QueryResult::Select(result) => {
+                assert_eq!(shown(&result.rows), vec![vec![
+                    "Laptop".to_string(),
+                    "2400".to_string(),
+                    "-1".to_string(),
+                    "Laptop (Electronics)".to_string(),
+                ]]);
+            }
+            other => panic!("Expected select result, got {:?}", other),
+        }
+
+        match engine.execute("SELECT category, id FROM products WHERE price / 2 = 12.5 OR price / 2 = 12").unwrap() {
+            QueryResult::Select(result) => assert_eq!(shown(&result.rows), vec![
+                vec!["Education".to_string(), "3".to_string()],
+                vec!["Education".to_string(), "5".to_string()],
+            ]),
+            other => panic!("Expected select result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_select_expression_type_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert!(matches!(engine.execute("SELECT name + 1 FROM products"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(engine.execute("SELECT -category FROM products"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(engine.execute("SELECT missing * 2 FROM products"), Err(QueryError::ColumnNotFound(_))));
+        assert!(matches!(engine.execute("SELECT price / 0 FROM products"), Err(QueryError::InvalidValue(_))));
+    }
+
+    #[test]
+    fn test_aliases_and_order_by() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT p.name AS product, p.price * 2 AS doubled FROM products AS p WHERE p.price > 100 ORDER BY doubled DESC";
+        match engine.execute(query).unwrap() {
+            QueryResult::Select(result) => {
+                assert_eq!(result.headers, vec!["product".to_string(), "doubled".to_string()]);
+                assert_eq!(shown(&result.rows), vec![
+                    vec!["Laptop".to_string(), "2400".to_string()],
+                    vec!["Phone".to_string(), "1600".to_string()],
+                    vec!["Desk".to_string(), "600".to_string()],
+                ]);
+            }
+            other => panic!("Expected select result, got {:?}", other),
+        }
+
+        // Sort on a column that is not projected, with a tie broken by a second key
+        match engine.execute("SELECT name FROM products p ORDER BY p.price, name DESC").unwrap() {
+            QueryResult::Select(result) => assert_eq!(shown(&result.rows), vec![
+                vec!["Notebook".to_string()],
+                vec!["Book".to_string()],
+                vec!["Desk".to_string()],
+                vec!["Phone".to_string()],
+                vec!["Laptop".to_string()],
+            ]),
+            other => panic!("Expected select result, got {:?}", other),
+        }
+
+        // Once aliased, the table name no longer qualifies its columns
+        assert!(matches!(engine.execute("SELECT products.name FROM products p"), Err(QueryError::ColumnNotFound(_))));
+    }
+
+    #[test]
+    fn test_select_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        match engine.execute("SELECT name FROM products ORDER BY price DESC LIMIT 2").unwrap() {
+            QueryResult::Select(result) => assert_eq!(shown(&result.rows), vec![
+                vec!["Laptop".to_string()],
+                vec!["Phone".to_string()],
+            ]),
+            other => panic!("Expected select result, got {:?}", other),
+        }
+
+        assert!(matches!(engine.execute("SELECT name FROM products LIMIT ten"), Err(QueryError::SyntaxError(_))));
+        assert!(matches!(engine.execute("SELECT name FROM products LIMIT"), Err(QueryError::SyntaxError(_))));
+    }
+
+    #[test]
+    fn test_grouped_aliases_and_order_by() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT p.category AS kind, COUNT(*) AS items, SUM(p.price) FROM products p GROUP BY p.category ORDER BY items DESC, kind";
+        match engine.execute(query).unwrap() {
+            QueryResult::Aggregation(result) => {
+                assert_eq!(result.headers, vec!["kind".to_string(), "items".to_string(), "SUM(p.price)".to_string()]);
+                assert_eq!(shown(&result.rows), vec![
+                    vec!["Education".to_string(), "2".to_string(), "50".to_string()],
+                    vec!["Electronics".to_string(), "2".to_string(), "2000".to_string()],
+                    vec!["Furniture".to_string(), "1".to_string(), "300".to_string()],
+                ]);
+            }
+            other => panic!("Expected aggregation result, got {:?}", other),
+        }
+    }
+}
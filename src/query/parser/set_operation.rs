@@ -0,0 +1,289 @@
+use std::collections::{HashMap, HashSet};
+use crate::metadata::ColumnType;
+use crate::query::error::QueryError;
+use crate::query::record::Row;
+use super::expression::Expression;
+use super::lexer::Lexer;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except,
+}
+
+/// Queries combined by UNION, INTERSECT or EXCEPT
+/// Example: SELECT id FROM a UNION ALL SELECT id FROM b ORDER BY id LIMIT 5
+#[derive(Debug)]
+pub struct SetOperation<'a> {
+    pub queries: Vec<&'a [&'a str]>,
+    pub operators: Vec<(SetOperator, bool)>, // operator between queries[i] and queries[i + 1], and whether ALL was given
+    pub trailing: &'a [&'a str],            // ORDER BY and LIMIT of the combined result
+}
+
+pub struct SetOperationParser;
+
+impl Default for SetOperationParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SetOperationParser {
+    pub fn new() -> Self {
+        SetOperationParser
+    }
+
+    /// Split a statement at its set operators, or return None if it has none
+    /// Only operators outside quoted literals and parentheses split the statement
+    /// ORDER BY and LIMIT after the last query apply to the combined result
+    pub fn parse_set_operation<'a>(&self, tokens: &'a [&'a str]) -> Result<Option<SetOperation<'a>>, QueryError> {
+        let depths = Lexer::new().depths(tokens);
+        let mut queries = Vec::new();
+        let mut operators = Vec::new();
+        let mut start = 0;
+        let mut index = 0;
+
+        while index < tokens.len() {
+            let operator = match tokens[index].to_uppercase().as_str() {
+                "UNION" if depths[index] == 0 => SetOperator::Union,
+                "INTERSECT" if depths[index] == 0 => SetOperator::Intersect,
+                "EXCEPT" if depths[index] == 0 => SetOperator::Except,
+                _ => {
+                    index += 1;
+                    continue;
+                }
+            };
+
+            let all = tokens.get(index + 1).is_some_and(|t| t.eq_ignore_ascii_case("ALL"));
+            queries.push(&tokens[start..index]);
+            operators.push((operator, all));
+            index += if all { 2 } else { 1 };
+            start = index;
+        }
+
+        if operators.is_empty() {
+            return Ok(None);
+        }
+
+        let last = &tokens[start..];
+        let trailing_start = last.iter()
+            .position(|t| t.eq_ignore_ascii_case("ORDER") || t.eq_ignore_ascii_case("LIMIT"))
+            .unwrap_or(last.len());
+        queries.push(&last[..trailing_start]);
+
+        for query in &queries {
+            if !query.first().is_some_and(|t| t.eq_ignore_ascii_case("SELECT")) {
+                return Err(QueryError::SyntaxError("Expected SELECT on both sides of a set operation".to_string()));
+            }
+            if query.iter().any(|t| t.eq_ignore_ascii_case("ORDER") || t.eq_ignore_ascii_case("LIMIT")) {
+                return Err(QueryError::SyntaxError(
+                    "ORDER BY and LIMIT apply to the whole set operation and must come after the last query".to_string()
+                ));
+            }
+        }
+
+        Ok(Some(SetOperation { queries, operators, trailing: &last[trailing_start..] }))
+    }
+
+    /// Check that two queries return the same number of columns with compatible types,
+    /// returning the type of each column of the combined result
+    pub fn unify_types(&self, left: &[Option<ColumnType>], right: &[Option<ColumnType>]) -> Result<Vec<Option<ColumnType>>, QueryError> {
+        if left.len() != right.len() {
+            return Err(QueryError::SyntaxError(format!(
+                "Queries of a set operation must return the same number of columns, got {} and {}", left.len(), right.len()
+            )));
+        }
+
+        left.iter().zip(right).enumerate()
+            .map(|(index, (left_type, right_type))| {
+                Expression::common_type(left_type, right_type, |left_type, right_type| format!(
+                    "Column {} of a set operation has incompatible types {} and {}", index + 1, left_type, right_type
+                ))
+            })
+            .collect()
+    }
+
+    /// Combine the rows of two queries
+    /// Without ALL the result has no duplicates; with ALL duplicates are kept,
+    /// INTERSECT ALL keeping as many copies as both sides share and EXCEPT ALL removing one per copy on the right
+    pub fn combine(&self, operator: SetOperator, all: bool, left: Vec<Row>, right: Vec<Row>) -> Vec<Row> {
+        let mut right_counts: HashMap<Row, usize> = HashMap::new();
+        if operator != SetOperator::Union {
+            for row in &right {
+                *right_counts.entry(row.clone()).or_default() += 1;
+            }
+        }
+
+        let combined: Vec<Row> = match operator {
+            SetOperator::Union => left.into_iter().chain(right).collect(),
+            SetOperator::Intersect => left.into_iter()
+                .filter(|row| match right_counts.get_mut(row) {
+                    Some(count) if *count > 0 => {
+                        *count -= 1;
+                        true
+                    }
+                    _ => false,
+                })
+                .collect(),
+            SetOperator::Except => left.into_iter()
+                .filter(|row| match right_counts.get_mut(row) {
+                    Some(count) if *count > 0 => {
+                        *count -= 1;
+                        false
+                    }
+                    _ => true,
+                })
+                .collect(),
+        };
+
+        if all {
+            return combined;
+        }
+
+        // Without ALL, EXCEPT removes a row whenever the right side has it at all
+        let mut seen = HashSet::new();
+        combined.into_iter()
+            .filter(|row| !(operator == SetOperator::Except && right_counts.contains_key(row)))
+            .filter(|row| seen.insert(row.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryError, QueryResult};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows, shown};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE customers (id INTEGER, name VARCHAR 20, city VARCHAR 20)",
+        "INSERT INTO customers VALUES (1, 'Ann', 'Kyiv')",
+        "INSERT INTO customers VALUES (2, 'Ben', 'Lviv')",
+        "INSERT INTO customers VALUES (3, 'Cat', 'Kyiv')",
+        "CREATE TABLE suppliers (id INTEGER, name VARCHAR 30, rating FLOAT)",
+        "INSERT INTO suppliers VALUES (1, 'Ann', 4.5)",
+        "INSERT INTO suppliers VALUES (4, 'Dan', 3.0)",
+        "INSERT INTO suppliers VALUES (4, 'Dan', 3.0)",
+    ];
+
+    #[test]
+    fn test_union() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        match engine.execute("SELECT id, name FROM customers UNION SELECT id, name FROM suppliers ORDER BY id DESC").unwrap() {
+            QueryResult::Select(result) => {
+                assert_eq!(result.headers, vec!["id", "name"]);
+                assert_eq!(shown(&result.rows), vec![
+                    vec!["4", "Dan"],
+                    vec!["3", "Cat"],
+                    vec!["2", "Ben"],
+                    vec!["1", "Ann"],
+                ]);
+            }
+            other => panic!("Expected select result, got {:?}", other),
+        }
+
+        assert_eq!(rows(&mut engine, "SELECT name FROM customers UNION ALL SELECT name FROM suppliers").len(), 6);
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM customers UNION ALL SELECT name FROM suppliers ORDER BY name LIMIT 3"),
+            vec![vec!["Ann"], vec!["Ann"], vec!["Ben"]]
+        );
+
+        // Integer and float columns combine
+        assert_eq!(
+            rows(&mut engine, "SELECT id FROM customers WHERE id = 1 UNION SELECT rating FROM suppliers WHERE id = 1"),
+            vec![vec!["1"], vec!["4.5"]]
+        );
+    }
+
+    #[test]
+    fn test_intersect_and_except() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM customers INTERSECT SELECT name FROM suppliers"),
+            vec![vec!["Ann"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM customers EXCEPT SELECT name FROM suppliers ORDER BY name DESC"),
+            vec![vec!["Cat"], vec!["Ben"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM suppliers EXCEPT ALL SELECT name FROM customers"),
+            vec![vec!["Dan"], vec!["Dan"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT city FROM customers INTERSECT ALL SELECT city FROM customers WHERE id > 1"),
+            vec![vec!["Kyiv"], vec!["Lviv"]]
+        );
+
+        // INTERSECT binds tighter than UNION
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM customers WHERE id = 2 UNION SELECT name FROM customers INTERSECT SELECT name FROM suppliers"),
+            vec![vec!["Ben"], vec!["Ann"]]
+        );
+
+        // Set operations work inside subqueries and derived tables
+        assert_eq!(
+            rows(&mut engine, "SELECT COUNT(*) FROM (SELECT name FROM customers UNION SELECT name FROM suppliers) AS people"),
+            vec![vec!["4"]]
+        );
+    }
+
+    #[test]
+    fn test_operators_in_literals_and_parentheses() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // Keywords inside a quoted literal don't split the query
+        assert!(rows(&mut engine, "SELECT name FROM customers WHERE name = 'x UNION y'").is_empty());
+        engine.execute("UPDATE customers SET name = 'Ann  UNION  Ben' WHERE id = 1").unwrap();
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM customers WHERE name = 'Ann  UNION  Ben' UNION SELECT name FROM suppliers WHERE id = 4"),
+            vec![vec!["Ann  UNION  Ben"], vec!["Dan"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT id FROM customers WHERE city = 'Kyiv' AND name <> 'a JOIN b' ORDER BY id"),
+            vec![vec!["1"], vec!["3"]]
+        );
+
+        // Nor do they inside parentheses
+        assert_eq!(
+            rows(&mut engine, "SELECT COUNT(*) FROM customers WHERE id IN (SELECT id FROM suppliers UNION SELECT id FROM customers WHERE id = 3)"),
+            vec![vec!["2"]]
+        );
+    }
+
+    #[test]
+    fn test_set_operation_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert!(matches!(
+            engine.execute("SELECT id, name FROM customers UNION SELECT id FROM suppliers"),
+            Err(QueryError::SyntaxError(_))
+        ));
+        assert!(matches!(
+            engine.execute("SELECT id FROM customers UNION SELECT name FROM suppliers"),
+            Err(QueryError::TypeMismatch(_))
+        ));
+        engine.execute("CREATE TABLE notes (id INTEGER, note VARCHAR 0)").unwrap();
+        assert!(matches!(
+            engine.execute("SELECT id FROM notes UNION SELECT note FROM notes"),
+            Err(QueryError::TypeMismatch(_))
+        ));
+        assert_eq!(rows(&mut engine, "SELECT id FROM customers WHERE id = 1 UNION SELECT NULL FROM suppliers WHERE id = 1"),
+            vec![vec!["1".to_string()], vec!["NULL".to_string()]]);
+        assert!(matches!(
+            engine.execute("SELECT id FROM customers ORDER BY id UNION SELECT id FROM suppliers"),
+            Err(QueryError::SyntaxError(_))
+        ));
+        assert!(matches!(
+            engine.execute("SELECT id FROM customers UNION"),
+            Err(QueryError::SyntaxError(_))
+        ));
+    }
+}
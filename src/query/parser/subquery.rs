@@ -0,0 +1,305 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::metadata::{Column, ColumnType, Table};
+use crate::query::catalog::Catalog;
+use crate::query::error::QueryError;
+use crate::query::record::Row;
+use crate::query::result::{QueryResult, SelectResult};
+use crate::query::value::Value;
+use super::aggregation::AggregateFunction;
+use super::expression::{Expression, ExpressionContext, JoinedRowContext};
+use super::lexer::Lexer;
+use super::select::SelectParser;
+use super::window::WindowFunction;
+
+/// What a query can see besides its own tables: the runner for its
+/// subqueries and, for a subquery, the row of the enclosing query
+#[derive(Clone, Copy)]
+pub struct Scope<'a> {
+    pub runner: &'a SubqueryRunner<'a>,
+    pub outer: Option<&'a dyn ExpressionContext>,
+}
+
+impl Scope<'_> {
+    /// Type of a column, looking in the enclosing query when the tables don't have it
+    pub fn column_type(&self, tables: &[(&str, &Table)], name: &str) -> Result<Option<ColumnType>, QueryError> {
+        match (JoinedRowContext::column_type(tables, name), self.outer) {
+            (Err(QueryError::ColumnNotFound(_)), Some(outer)) => outer.column_value(name).map(|value| value.data_type()),
+            (result, _) => result.map(Some),
+        }
+    }
+}
+
+/// Evaluates expressions against a row of a query: columns the row doesn't have
+/// are looked up in the enclosing query, and subqueries see this row as their outer row
+pub struct ScopeContext<'a> {
+    pub row: &'a dyn ExpressionContext,
+    pub scope: Scope<'a>,
+}
+
+impl ExpressionContext for ScopeContext<'_> {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError> {
+        match (self.row.column_value(name), self.scope.outer) {
+            (Err(QueryError::ColumnNotFound(_)), Some(outer)) => outer.column_value(name),
+            (result, _) => result,
+        }
+    }
+
+    fn aggregate_value(&self, function: &AggregateFunction) -> Result<Value, QueryError> {
+        self.row.aggregate_value(function)
+    }
+
+    fn window_value(&self, function: &WindowFunction) -> Result<Value, QueryError> {
+        self.row.window_value(function)
+    }
+
+    fn subquery_result(&self, query: &str) -> Result<Rc<SelectResult>, QueryError> {
+        self.scope.runner.run(query, self)
+    }
+}
+
+/// Records whether a subquery looked at its outer row
+struct CorrelationTracker<'a> {
+    outer: &'a dyn ExpressionContext,
+    used: Cell<bool>,
+}
+
+impl ExpressionContext for CorrelationTracker<'_> {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError> {
+        self.used.set(true);
+        self.outer.column_value(name)
+    }
+
+    fn aggregate_value(&self, function: &AggregateFunction) -> Result<Value, QueryError> {
+        self.used.set(true);
+        self.outer.aggregate_value(function)
+    }
+
+    fn subquery_result(&self, query: &str) -> Result<Rc<SelectResult>, QueryError> {
+        self.outer.subquery_result(query)
+    }
+}
+
+/// Runs the queries of a statement, including its subqueries, against the tables of a catalog
+/// A subquery that doesn't refer to its outer row gives the same result for every row, so it is run only once
+pub struct SubqueryRunner<'a> {
+    parser: &'a SelectParser,
+    catalog: &'a Catalog,
+    uncorrelated: RefCell<HashMap<String, Rc<SelectResult>>>,
+}
+
+impl<'a> SubqueryRunner<'a> {
+    pub fn new(parser: &'a SelectParser, catalog: &'a Catalog) -> Self {
+        SubqueryRunner { parser, catalog, uncorrelated: RefCell::new(HashMap::new()) }
+    }
+
+    pub fn catalog(&self) -> &Catalog {
+        self.catalog
+    }
+
+    /// Run a SELECT, resolving the columns its tables don't have against `outer`
+    pub fn execute_select(&self, tokens: &[&str], outer: Option<&dyn ExpressionContext>) -> Result<QueryResult, QueryError> {
+        self.parser.execute_select(tokens, Scope { runner: self, outer })
+    }
+
+    /// Run a SELECT without an outer row, along with the type of each output column
+    pub fn execute_typed_select(&self, tokens: &[&str]) -> Result<(QueryResult, Vec<Option<ColumnType>>), QueryError> {
+        self.parser.execute_typed_select(tokens, Scope { runner: self, outer: None })
+    }
+
+    /// Run the text of a subquery for one row of the enclosing query
+    pub fn run(&self, query: &str, outer: &dyn ExpressionContext) -> Result<Rc<SelectResult>, QueryError> {
+        if let Some(result) = self.uncorrelated.borrow().get(query) {
+            return Ok(result.clone());
+        }
+
+        let tokens = Lexer::new().split_statement(query);
+        if !tokens.first().is_some_and(|t| t.eq_ignore_ascii_case("SELECT")) {
+            return Err(QueryError::SyntaxError(format!("Expected SELECT in subquery: ({})", query)));
+        }
+
+        let tracker = CorrelationTracker { outer, used: Cell::new(false) };
+        let result = self.execute_select(&tokens, Some(&tracker))?
+            .into_select_result()
+            .ok_or_else(|| QueryError::InternalError("Subquery did not return rows".to_string()))?;
+        let result = Rc::new(result);
+
+        if !tracker.used.get() {
+            self.uncorrelated.borrow_mut().insert(query.to_string(), result.clone());
+        }
+        Ok(result)
+    }
+}
+
+/// Describe the result of a subquery in FROM as a table
+/// Each column is named after its header, without any qualifier, and typed by the values it holds
+/// A column without values takes the type the query gives it, or is text when that is unknown too
+pub fn derived_table(name: &str, headers: &[String], types: &[Option<ColumnType>], rows: &[Row]) -> Table {
+    let mut table = Table::new(name.to_string());
+    for (index, header) in headers.iter().enumerate() {
+        let column_name = header.rsplit('.').next().unwrap_or(header);
+        let data_type = rows.iter()
+            .filter_map(|row| Value::infer(row[index].as_deref()).data_type())
+            .reduce(|merged, data_type| match (merged, data_type) {
+                (ColumnType::Integer, ColumnType::Integer) => ColumnType::Integer,
+                (ColumnType::Integer | ColumnType::Float, ColumnType::Integer | ColumnType::Float) => ColumnType::Float,
+                (ColumnType::Boolean, ColumnType::Boolean) => ColumnType::Boolean,
+                (merged, data_type) => ColumnType::Varchar(
+                    Expression::display_length(&merged).zip(Expression::display_length(&data_type)).map(|(a, b)| a.max(b))
+                ),
+            })
+            .or_else(|| types.get(index).cloned().flatten())
+            .unwrap_or(ColumnType::Varchar(Some(0)));
+        table.add_column(Column::new(column_name.to_string(), data_type));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryError, QueryResult};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows, shown};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE departments (id INTEGER, name VARCHAR 20)",
+        "CREATE TABLE employees (id INTEGER, name VARCHAR 20, dept_id INTEGER, salary INTEGER)",
+        "INSERT INTO departments VALUES (1, 'Engineering')",
+        "INSERT INTO departments VALUES (2, 'Sales')",
+        "INSERT INTO departments VALUES (3, 'Legal')",
+        "INSERT INTO employees VALUES (1, 'Alice', 1, 100)",
+        "INSERT INTO employees VALUES (2, 'Bob', 1, 80)",
+        "INSERT INTO employees VALUES (3, 'Carol', 2, 60)",
+        "INSERT INTO employees VALUES (4, 'Dave', 2, 90)",
+    ];
+
+    #[test]
+    fn test_scalar_subqueries() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM employees WHERE salary > (SELECT AVG(salary) FROM employees) ORDER BY name"),
+            vec![vec!["Alice"], vec!["Dave"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT name, salary - (SELECT MIN(salary) FROM employees) AS above_min FROM employees WHERE id = 1"),
+            vec![vec!["Alice", "40"]]
+        );
+
+        assert!(matches!(
+            engine.execute("SELECT name FROM employees WHERE salary > (SELECT salary FROM employees)"),
+            Err(QueryError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            engine.execute("SELECT name FROM employees WHERE salary > (SELECT id, salary FROM employees)"),
+            Err(QueryError::SyntaxError(_))
+        ));
+    }
+
+    #[test]
+    fn test_correlated_subqueries() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // Highest paid employee of each department
+        let query = "SELECT e.name FROM employees e WHERE e.salary = \
+                     (SELECT MAX(salary) FROM employees i WHERE i.dept_id = e.dept_id) ORDER BY e.name";
+        assert_eq!(rows(&mut engine, query), vec![vec!["Alice"], vec!["Dave"]]);
+
+        // Unqualified columns resolve to the subquery's own table first
+        let query = "SELECT d.name, (SELECT COUNT(*) FROM employees WHERE dept_id = d.id) AS staff FROM departments d ORDER BY d.id";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["Engineering", "2"],
+            vec!["Sales", "2"],
+            vec!["Legal", "0"],
+        ]);
+    }
+
+    #[test]
+    fn test_in_and_exists_subqueries() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM departments WHERE id IN (SELECT dept_id FROM employees WHERE salary > 85) ORDER BY name"),
+            vec![vec!["Engineering"], vec!["Sales"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM departments WHERE id NOT IN (SELECT dept_id FROM employees)"),
+            vec![vec!["Legal"]]
+        );
+        // Under OR the subquery is checked row by row, with the same result
+        assert_eq!(
+            rows(&mut engine, "SELECT name FROM departments WHERE id IN (SELECT dept_id FROM employees WHERE salary < 70) OR name = 'Legal' ORDER BY id"),
+            vec![vec!["Sales"], vec!["Legal"]]
+        );
+        // NOT IN a set holding NULL is never true
+        assert!(rows(&mut engine, "SELECT name FROM departments WHERE id NOT IN \
+                                   (SELECT e.dept_id FROM departments d LEFT JOIN employees e ON d.id = e.dept_id)").is_empty());
+
+        assert_eq!(
+            rows(&mut engine, "SELECT d.name FROM departments d WHERE EXISTS \
+                               (SELECT * FROM employees e WHERE e.dept_id = d.id AND e.salary < 70)"),
+            vec![vec!["Sales"]]
+        );
+        assert_eq!(
+            rows(&mut engine, "SELECT d.name FROM departments d WHERE NOT EXISTS \
+                               (SELECT * FROM employees e WHERE e.dept_id = d.id AND e.salary < 70) ORDER BY d.id"),
+            vec![vec!["Engineering"], vec!["Legal"]]
+        );
+        // A correlated condition other than equality is evaluated per row
+        assert_eq!(
+            rows(&mut engine, "SELECT e.name FROM employees e WHERE EXISTS \
+                               (SELECT * FROM employees o WHERE o.dept_id = e.dept_id AND o.salary > e.salary) ORDER BY e.name"),
+            vec![vec!["Bob"], vec!["Carol"]]
+        );
+    }
+
+    #[test]
+    fn test_derived_tables() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT t.dept_id, t.total FROM (SELECT dept_id, SUM(salary) AS total FROM employees GROUP BY dept_id) AS t \
+                     WHERE t.total > 150";
+        assert_eq!(rows(&mut engine, query), vec![vec!["1", "180"]]);
+
+        let query = "SELECT d.name, t.top FROM departments d \
+                     JOIN (SELECT dept_id, MAX(salary) AS top FROM employees GROUP BY dept_id) t ON t.dept_id = d.id ORDER BY t.top DESC";
+        match engine.execute(query).unwrap() {
+            QueryResult::Join(result) => {
+                assert_eq!(result.headers, vec!["d.name", "t.top"]);
+                assert_eq!(shown(&result.rows), vec![vec!["Engineering", "100"], vec!["Sales", "90"]]);
+            }
+            other => panic!("Expected join result, got {:?}", other),
+        }
+
+        assert!(matches!(
+            engine.execute("SELECT * FROM (SELECT name FROM employees) WHERE name = 'Bob'"),
+            Err(QueryError::SyntaxError(_))
+        ));
+    }
+
+    #[test]
+    fn test_only_tables_read_are_loaded() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // Table names in literals and aliases don't make the query read those tables
+        let query = "SELECT name FROM employees WHERE name <> 'departments' AND salary > (SELECT AVG(salary) FROM employees) ORDER BY name";
+        assert_eq!(rows(&mut engine, &format!("EXPLAIN {}", query)), vec![vec!["Full Scan on employees"]]);
+        assert_eq!(rows(&mut engine, query), vec![vec!["Alice"], vec!["Dave"]]);
+        assert_eq!(
+            rows(&mut engine, "SELECT employees.name FROM departments employees ORDER BY employees.id"),
+            vec![vec!["Engineering"], vec!["Sales"], vec!["Legal"]]
+        );
+
+        // Tables read by subqueries, derived tables and WITH queries are loaded
+        let query = "WITH top AS (SELECT dept_id FROM employees WHERE salary >= 90) \
+                     SELECT name FROM departments WHERE id IN (SELECT dept_id FROM top) ORDER BY id";
+        assert_eq!(rows(&mut engine, &format!("EXPLAIN {}", query)), vec![vec!["Full Scan on departments"], vec!["Full Scan on employees"]]);
+        assert_eq!(rows(&mut engine, query), vec![vec!["Engineering"], vec!["Sales"]]);
+    }
+}
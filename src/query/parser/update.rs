@@ -1,13 +1,31 @@
-use crate::metadata::{Table, Column, ColumnType};
+use crate::metadata::{Table, Column, type_name};
 use crate::storage::{Block, LSMEngine};
+use crate::index::Index;
 use crate::query::error::QueryError;
 use crate::query::result::QueryResult;
+use crate::query::record::{RecordCodec, Row};
+use crate::query::constraint::ConstraintChecker;
 use super::r#where::WhereParser;
+use super::column::ColumnParser;
+use super::expression::{Expression, ExpressionParser, RowContext};
+
+/// A single `column = expression` entry of the SET clause
+#[derive(Debug)]
+pub struct Assignment {
+    pub column_index: usize,
+    pub value: Expression,
+}
 
 pub struct UpdateParser {
     where_parser: WhereParser,
 }
 
+impl Default for UpdateParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UpdateParser {
     pub fn new() -> Self {
         UpdateParser {
@@ -20,7 +38,7 @@ impl UpdateParser {
         &mut self,
         tokens: &[&str],
         table: &Table,
-        storage_blocks: &mut Vec<Block>,
+        storage_blocks: &mut [Block],
     ) -> Result<QueryResult, QueryError> {
         // Parse SET clause
         let set_index = tokens.iter()
@@ -42,11 +60,10 @@ impl UpdateParser {
             }
 
             let value = tokens[current_index + 2];
-            let column = table.columns.iter().find(|c| c.name == column_name)
+            let index = table.get_column_index(column_name)
                 .ok_or_else(|| QueryError::ColumnNotFound(column_name.to_string()))?;
-
-            let value_bytes = Self::parse_value(value, column)?;
-            updates.push((column_name.to_string(), value_bytes));
+            let value = (!value.eq_ignore_ascii_case("NULL")).then(|| value.to_string());
+            updates.push((index, value));
 
             current_index += 3;
             if current_index < tokens.len() && tokens[current_index] == "," {
@@ -59,23 +76,30 @@ impl UpdateParser {
             self.where_parser.parse_where_clause(&tokens[current_index + 1..])?;
         }
 
-        // Execute update
-        let mut updated_count = 0;
-        for block in storage_blocks.iter_mut() {
-            // Update record values
-            let record_ids: Vec<u64> = block.get_all().iter().map(|r| r.id).collect();
-            for record_id in record_ids {
-                let mut updated = false;
-                for (col_name, new_value) in &updates {
-                    if let Some(idx) = table.get_column_index(&col_name) {
-                        let offset = table.get_column_offset(idx);
-                        block.update_record(record_id, offset, new_value);
-                        updated = true;
-                    }
-                }
-                if updated {
-                    updated_count += 1;
+        // Execute update, checking the constraints on the new rows before any is written
+        let codec = RecordCodec::new();
+        let checker = ConstraintChecker::new();
+        let mut changes = Vec::new();
+        for (block_index, block) in storage_blocks.iter().enumerate() {
+            for record in block.get_all() {
+                let mut row = codec.decode(record, table)?;
+                for (index, value) in &updates {
+                    row[*index] = value.clone();
                 }
+                let row = codec.normalize(&row, table)?;
+                checker.check_row(&row, table)?;
+                changes.push((block_index, record.id, row));
+            }
+        }
+        if checker.has_unique_columns(table) {
+            let all_rows: Vec<Row> = changes.iter().map(|(_, _, row)| row.clone()).collect();
+            checker.check_unique(&all_rows, table)?;
+        }
+
+        let mut updated_count = 0;
+        for (block_index, record_id, row) in changes {
+            if storage_blocks[block_index].update(record_id, codec.encode(&row, table)?) {
+                updated_count += 1;
             }
         }
 
@@ -88,191 +112,252 @@ impl UpdateParser {
         tokens: &[&str],
         table: &Table,
         storage_engine: &mut LSMEngine,
+        indexes: &mut [Index],
     ) -> Result<QueryResult, QueryError> {
+        let changes = self.plan_update(tokens, table, storage_engine, None)?;
+        self.write_updates(changes, table, storage_engine, indexes)
+    }
+
+    /// Work out the new version of every row the UPDATE changes, as (record id, new row)
+    /// Every new row is checked first, so a constraint violation leaves the table untouched;
+    /// uniqueness is left to the unique indexes when the rows are written
+    /// `candidates` are the only records the WHERE clause can match, when an index narrowed them down
+    pub fn plan_update(
+        &mut self,
+        tokens: &[&str],
+        table: &Table,
+        storage_engine: &mut LSMEngine,
+        candidates: Option<&[u64]>,
+    ) -> Result<Vec<(u64, Row)>, QueryError> {
         // Parse SET clause
         let set_index = tokens.iter()
             .position(|&t| t.to_uppercase() == "SET")
             .ok_or_else(|| QueryError::SyntaxError("Expected SET clause".to_string()))?;
 
-        // Parse column updates - for simplicity, we'll only handle single column updates
-        if set_index + 3 >= tokens.len() {
-            return Err(QueryError::SyntaxError("Invalid SET clause".to_string()));
-        }
+        // The assignments run up to WHERE
+        let where_index = tokens.iter()
+            .skip(set_index + 1)
+            .position(|&t| t.to_uppercase() == "WHERE")
+            .map(|offset| set_index + 1 + offset)
+            .unwrap_or(tokens.len());
 
-        let column_name = tokens[set_index + 1];
-        if tokens[set_index + 2] != "=" {
-            return Err(QueryError::SyntaxError("Expected = after column name".to_string()));
-        }
-
-        let value = tokens[set_index + 3];
-        let column = table.columns.iter().find(|c| c.name == column_name)
-            .ok_or_else(|| QueryError::ColumnNotFound(column_name.to_string()))?;
+        let assignments = self.parse_assignments(&tokens[set_index + 1..where_index], table)?;
 
         // Parse WHERE clause if present
-        let where_clause = if set_index + 4 < tokens.len() && tokens[set_index + 4].to_uppercase() == "WHERE" {
-            Some(self.where_parser.parse_where_clause(&tokens[set_index + 5..])?)
+        let where_clause = if where_index < tokens.len() {
+            Some(self.where_parser.parse_where_clause(&tokens[where_index + 1..])?)
         } else {
             None
         };
 
-        // For simplicity in this demo, we'll scan and update matching records
-        // In a production system, you'd want more efficient indexing
-        let mut updated_count = 0;
-        
-        // Get all records from the LSM engine
-        let all_records = storage_engine.get_all_records()
-            .map_err(|e| QueryError::InternalError(format!("Failed to get all records: {}", e)))?;
-        
-        for record in all_records {
-            // Parse record to check WHERE clause
+        let records = match candidates {
+            Some(ids) => storage_engine.get_records(ids),
+            None => storage_engine.get_all_records(),
+        }.map_err(|e| QueryError::InternalError(format!("Failed to get all records: {}", e)))?;
+
+        let codec = RecordCodec::new();
+        let checker = ConstraintChecker::new();
+        let mut changed = Vec::new();
+        for record in &records {
+            let row_data = codec.decode(record, table)?;
             if let Some(ref where_clause) = where_clause {
-                let row_data = self.parse_record_data(&record, table)?;
                 if !self.where_parser.evaluate_where_clause(&row_data, table, where_clause)? {
                     continue;
                 }
             }
 
-            // Build new record data with updated value
-            let new_data = self.build_updated_record_data(&record, table, column_name, value)?;
-            
+            let new_row = codec.normalize(&self.build_updated_row(&row_data, table, &assignments)?, table)?;
+            checker.check_row(&new_row, table)?;
+            changed.push((record.id, new_row));
+        }
+
+        Ok(changed)
+    }
+
+    /// Store the new rows produced by `plan_update` and move their index entries
+    pub fn write_updates(
+        &self,
+        changes: Vec<(u64, Row)>,
+        table: &Table,
+        storage_engine: &mut LSMEngine,
+        indexes: &mut [Index],
+    ) -> Result<QueryResult, QueryError> {
+        let new_rows: Vec<(u64, Option<&[Option<String>]>)> = changes.iter().map(|(id, row)| (*id, Some(row.as_slice()))).collect();
+        for index in indexes.iter() {
+            index.check_unique(&new_rows)?;
+        }
+
+        let codec = RecordCodec::new();
+        let mut updated_count = 0;
+        for (record_id, row) in changes {
             // Update in LSM engine (this actually inserts a new version)
-            if storage_engine.update(record.id, new_data)
+            if storage_engine.update(record_id, codec.encode(&row, table)?)
                 .map_err(|e| QueryError::InternalError(format!("Failed to update record: {}", e)))? {
                 updated_count += 1;
             }
+            for index in indexes.iter_mut() {
+                index.insert(record_id, &row)?;
+            }
         }
 
         Ok(QueryResult::Update(updated_count))
     }
 
-    fn parse_record_data(&self, record: &crate::storage::Record, table: &Table) -> Result<Vec<String>, QueryError> {
-        let mut offset = 0;
-        let row_data: Vec<String> = table.columns.iter().map(|col| {
-            let result = match col.data_type {
-                ColumnType::Integer => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0".to_string()
-                    }
-                },
-                ColumnType::Float => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = f64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0.0".to_string()
-                    }
-                },
-                ColumnType::Varchar(_max_len) => {
-                    if offset + 4 <= record.data.len() {
-                        let length_bytes = &record.data[offset..offset+4];
-                        let length = u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize;
-                        offset += 4;
-                        
-                        if offset + length <= record.data.len() {
-                            let string_bytes = &record.data[offset..offset+length];
-                            offset += length;
-                            String::from_utf8_lossy(string_bytes).to_string()
-                        } else {
-                            offset += length;
-                            String::new()
-                        }
-                    } else {
-                        offset += 4;
-                        String::new()
-                    }
-                },
-                ColumnType::Boolean => {
-                    let result = if offset < record.data.len() && record.data[offset] == 1 { 
-                        "true".to_string() 
-                    } else { 
-                        "false".to_string() 
-                    };
-                    offset += 1;
-                    result
-                },
-                ColumnType::Timestamp => {
-                    if offset + 8 <= record.data.len() {
-                        let bytes = &record.data[offset..offset+8];
-                        let num = i64::from_be_bytes(bytes.try_into().unwrap());
-                        offset += 8;
-                        num.to_string()
-                    } else {
-                        offset += 8;
-                        "0".to_string()
-                    }
-                },
-            };
-            result
-        }).collect();
-
-        Ok(row_data)
+    /// Parse the comma-separated assignments of the SET clause
+    /// Example: name = 'X', age = age + 1
+    pub fn parse_assignments(&self, tokens: &[&str], table: &Table) -> Result<Vec<Assignment>, QueryError> {
+        let expression_parser = ExpressionParser::new();
+        let mut assignments: Vec<Assignment> = Vec::new();
+
+        for item in ColumnParser::new().split_select_items(tokens) {
+            let (column_name, value_text) = item.split_once('=')
+                .ok_or_else(|| QueryError::SyntaxError("Expected = after column name".to_string()))?;
+            let column_name = column_name.trim();
+            if column_name.is_empty() || value_text.trim().is_empty() {
+                return Err(QueryError::SyntaxError("Invalid SET clause".to_string()));
+            }
+
+            let column_index = RowContext::column_index(table, column_name)?;
+            let column = &table.columns[column_index];
+            if assignments.iter().any(|a| a.column_index == column_index) {
+                return Err(QueryError::SyntaxError(format!(
+                    "Column '{}' is assigned more than once", column.name
+                )));
+            }
+
+            let value = expression_parser.parse(&[value_text.trim()])?;
+            self.check_assignment_type(&value, column, table)?;
+            assignments.push(Assignment { column_index, value });
+        }
+
+        if assignments.is_empty() {
+            return Err(QueryError::SyntaxError("Invalid SET clause".to_string()));
+        }
+
+        Ok(assignments)
     }
 
-    fn build_updated_record_data(&self, original_record: &crate::storage::Record, table: &Table, update_column: &str, new_value: &str) -> Result<Vec<u8>, QueryError> {
-        // For simplicity, rebuild the entire record with the updated value
-        // In production, you might want to optimize this
-        
-        let row_data = self.parse_record_data(original_record, table)?;
-        let mut new_data = Vec::new();
-        
-        for (i, column) in table.columns.iter().enumerate() {
-            let value = if column.name == update_column {
-                new_value
-            } else {
-                &row_data[i]
-            };
-            
-            let value_bytes = Self::parse_value(value, column)?;
-            new_data.extend(value_bytes);
+    /// Reject assignments whose expression type can't be stored in the column
+    fn check_assignment_type(&self, value: &Expression, column: &Column, table: &Table) -> Result<(), QueryError> {
+        // Literals are checked by converting them up front, so no row is rewritten before a bad value is found
+        if let Expression::Literal(literal) = value {
+            literal.coerce_to(&column.data_type)?;
+            return Ok(());
         }
-        
-        Ok(new_data)
+
+        // A value of unknown type, such as a scalar subquery, is converted when the row is rewritten
+        let value_type = value.check_type(&|name| RowContext::column_type(table, name).map(Some))?;
+        if value_type.as_ref().is_some_and(|value_type| !column.data_type.accepts(value_type)) {
+            return Err(QueryError::TypeMismatch(format!(
+                "Cannot assign {} value to column '{}' of type {}",
+                type_name(&value_type), column.name, column.data_type
+            )));
+        }
+        Ok(())
     }
 
-    fn parse_value(value: &str, column: &Column) -> Result<Vec<u8>, QueryError> {
-        match column.data_type {
-            ColumnType::Integer => {
-                let num = value.parse::<i64>()
-                    .map_err(|_| QueryError::TypeMismatch(format!("Invalid integer value: {}", value)))?;
-                Ok(num.to_be_bytes().to_vec())
-            },
-            ColumnType::Float => {
-                let num = value.parse::<f64>()
-                    .map_err(|_| QueryError::TypeMismatch(format!("Invalid float value: {}", value)))?;
-                Ok(num.to_be_bytes().to_vec())
-            },
-            ColumnType::Varchar(max_len) => {
-                let cleaned_value = value.trim_matches(|c| c == '\'' || c == '"');
-                if cleaned_value.len() > max_len {
-                    return Err(QueryError::TypeMismatch(format!(
-                        "Value '{}' exceeds column length of {}", cleaned_value, max_len
-                    )));
-                }
-                let mut bytes = (cleaned_value.len() as u32).to_be_bytes().to_vec();
-                bytes.extend(cleaned_value.as_bytes());
-                Ok(bytes)
-            },
-            ColumnType::Boolean => {
-                let cleaned_value = value.trim_matches(|c| c == '\'' || c == '"').to_lowercase();
-                if cleaned_value != "true" && cleaned_value != "false" {
-                    return Err(QueryError::TypeMismatch(format!("Invalid boolean value: {}", value)));
-                }
-                Ok(vec![if cleaned_value == "true" { 1 } else { 0 }])
-            },
-            ColumnType::Timestamp => {
-                let num = value.parse::<i64>()
-                    .map_err(|_| QueryError::TypeMismatch(format!("Invalid timestamp value: {}", value)))?;
-                Ok(num.to_be_bytes().to_vec())
-            },
+    fn build_updated_row(&self, row_data: &[Option<String>], table: &Table, assignments: &[Assignment]) -> Result<Row, QueryError> {
+        // Every assignment is evaluated against the old row, so
+        // SET a = b, b = a swaps the two columns
+        let context = RowContext::new(table, row_data);
+
+        let mut new_row = row_data.to_vec();
+        for assignment in assignments {
+            let column = &table.columns[assignment.column_index];
+            new_row[assignment.column_index] = assignment.value.evaluate(&context)?
+                .coerce_to(&column.data_type)?
+                .to_text();
+        }
+
+        Ok(new_row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryError, QueryResult};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, shown};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE accounts (id INTEGER, owner VARCHAR 20, balance INTEGER, rate FLOAT)",
+        "INSERT INTO accounts VALUES (1, 'Alice', 500, 1.5)",
+        "INSERT INTO accounts VALUES (2, 'Bob', 80, 2.0)",
+    ];
+
+    #[test]
+    fn test_update_with_expression() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let result = engine.execute("UPDATE accounts SET balance = balance - 100 WHERE balance >= 100").unwrap();
+        assert!(matches!(result, QueryResult::Update(1)));
+
+        engine.execute("UPDATE accounts SET owner = owner || ' Smith' WHERE id = 2").unwrap();
+        engine.execute("UPDATE accounts SET rate = rate * 2").unwrap();
+
+        match engine.execute("SELECT id, owner, balance, rate FROM accounts").unwrap() {
+            QueryResult::Select(result) => assert_eq!(shown(&result.rows), vec![
+                vec!["1".to_string(), "Alice".to_string(), "400".to_string(), "3".to_string()],
+                vec!["2".to_string(), "Bob Smith".to_string(), "80".to_string(), "4".to_string()],
+            ]),
+            other => panic!("Expected select result, got {:?}", other),
         }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_update_rejects_mismatched_types() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert!(matches!(engine.execute("UPDATE accounts SET balance = owner || 'x'"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(engine.execute("UPDATE accounts SET balance = rate * 2"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(engine.execute("UPDATE accounts SET owner = 'a name that is far too long'"), Err(QueryError::TypeMismatch(_))));
+
+        // Types are named as SQL writes them, and NULL's as unknown
+        let mut message = |query: &str| match engine.execute(query) {
+            Err(QueryError::TypeMismatch(message)) => message,
+            other => panic!("Expected a type mismatch, got {:?}", other.map(|_| ())),
+        };
+        assert_eq!(message("UPDATE accounts SET balance = balance + 'x'"), "Cannot apply '+' to INTEGER and VARCHAR(1)");
+        assert_eq!(message("UPDATE accounts SET balance = NULL + owner"), "Cannot apply '+' to unknown and VARCHAR(20)");
+        assert_eq!(message("UPDATE accounts SET balance = rate"), "Cannot assign FLOAT value to column 'balance' of type INTEGER");
+    }
+
+    #[test]
+    fn test_update_multiple_columns() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let result = engine.execute("UPDATE accounts SET owner = 'Bob, Jr.', balance = balance + 20, rate = 0.5 WHERE id = 2").unwrap();
+        assert!(matches!(result, QueryResult::Update(1)));
+
+        // Every assignment sees the old row
+        engine.execute("UPDATE accounts SET balance = id, id = balance WHERE id = 1").unwrap();
+
+        match engine.execute("SELECT id, owner, balance, rate FROM accounts ORDER BY balance").unwrap() {
+            QueryResult::Select(result) => assert_eq!(shown(&result.rows), vec![
+                vec!["500".to_string(), "Alice".to_string(), "1".to_string(), "1.5".to_string()],
+                vec!["2".to_string(), "Bob, Jr.".to_string(), "100".to_string(), "0.5".to_string()],
+            ]),
+            other => panic!("Expected select result, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_update_rejects_invalid_assignment_lists() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert!(matches!(engine.execute("UPDATE accounts SET balance = 1, balance = 2"), Err(QueryError::SyntaxError(_))));
+        assert!(matches!(engine.execute("UPDATE accounts SET balance = 1, missing = 2"), Err(QueryError::ColumnNotFound(_))));
+
+        // A bad later assignment fails before any row is rewritten
+        assert!(matches!(engine.execute("UPDATE accounts SET balance = 0, rate = 'abc'"), Err(QueryError::TypeMismatch(_))));
+        match engine.execute("SELECT balance FROM accounts").unwrap() {
+            QueryResult::Select(result) => assert_eq!(shown(&result.rows), vec![vec!["500".to_string()], vec!["80".to_string()]]),
+            other => panic!("Expected select result, got {:?}", other),
+        }
+    }
+}
+
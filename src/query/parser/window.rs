@@ -0,0 +1,486 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::metadata::{ColumnType, Table};
+use crate::query::error::QueryError;
+use crate::query::record::Row;
+use crate::query::result::SelectResult;
+use crate::query::value::Value;
+use super::aggregation::{AggregationParser, AggregateFunction};
+use super::expression::{Expression, ExpressionParser, ExpressionContext};
+use super::lexer::Token;
+use super::order::{OrderByItem, OrderByParser};
+
+#[derive(Debug, Clone)]
+pub enum WindowKind {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Lag { expression: Box<Expression>, offset: usize, default: Box<Expression> },
+    Lead { expression: Box<Expression>, offset: usize, default: Box<Expression> },
+    FirstValue(Box<Expression>),
+    Aggregate(AggregateFunction),
+}
+
+/// One end of a ROWS frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(usize),
+    CurrentRow,
+    Following(usize),
+    UnboundedFollowing,
+}
+
+/// The first and last row of a ROWS frame
+pub type WindowFrame = (FrameBound, FrameBound);
+
+/// PARTITION BY, ORDER BY and frame of a window specification
+type WindowSpec = (Vec<Expression>, Vec<OrderByItem>, Option<WindowFrame>);
+
+/// A function computed over the rows related to the current one
+/// Example: SUM(amount) OVER (PARTITION BY region ORDER BY day ROWS BETWEEN 2 PRECEDING AND CURRENT ROW)
+#[derive(Debug, Clone)]
+pub struct WindowFunction {
+    pub kind: WindowKind,
+    pub partition_by: Vec<Expression>,
+    pub order_by: Vec<OrderByItem>,
+    pub frame: Option<WindowFrame>, // ROWS frame; by default a window ends at the current row's last peer
+    pub text: String,               // identifies the call when its value is looked up
+}
+
+impl WindowFunction {
+    /// Work out the result type, checking the arguments and the window specification
+    pub fn check_type(&self, column_type: &dyn Fn(&str) -> Result<Option<ColumnType>, QueryError>) -> Result<Option<ColumnType>, QueryError> {
+        for expression in self.partition_by.iter().chain(self.order_by.iter().map(|item| &item.expression)) {
+            expression.check_type(column_type)?;
+        }
+
+        match &self.kind {
+            WindowKind::RowNumber | WindowKind::Rank | WindowKind::DenseRank => Ok(Some(ColumnType::Integer)),
+            WindowKind::Lag { expression, default, .. } | WindowKind::Lead { expression, default, .. } => {
+                let value_type = expression.check_type(column_type)?;
+                default.check_type(column_type)?;
+                Ok(value_type)
+            }
+            WindowKind::FirstValue(expression) => expression.check_type(column_type),
+            WindowKind::Aggregate(function) => Expression::Aggregate(function.clone()).check_type(column_type),
+        }
+    }
+}
+
+/// Supplies the precomputed window function values of one row
+pub struct WindowContext<'a> {
+    pub row: &'a dyn ExpressionContext,
+    pub windows: &'a [&'a WindowFunction],
+    pub values: &'a [Value],
+}
+
+impl ExpressionContext for WindowContext<'_> {
+    fn column_value(&self, name: &str) -> Result<Value, QueryError> {
+        self.row.column_value(name)
+    }
+
+    fn aggregate_value(&self, function: &AggregateFunction) -> Result<Value, QueryError> {
+        self.row.aggregate_value(function)
+    }
+
+    fn window_value(&self, function: &WindowFunction) -> Result<Value, QueryError> {
+        self.windows.iter()
+            .position(|window| window.text == function.text)
+            .map(|index| self.values[index].clone())
+            .ok_or_else(|| QueryError::InternalError(format!("Window function {} was not computed", function.text)))
+    }
+
+    fn subquery_result(&self, query: &str) -> Result<Rc<SelectResult>, QueryError> {
+        self.row.subquery_result(query)
+    }
+}
+
+pub struct WindowParser;
+
+impl Default for WindowParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WindowParser {
+    pub fn new() -> Self {
+        WindowParser
+    }
+
+    /// Parse a window function call from the tokens inside its argument and OVER parentheses
+    /// Expected format: name(args) OVER ([PARTITION BY expr, ...] [ORDER BY expr [ASC|DESC], ...] [ROWS frame])
+    pub fn parse_window(&self, name: &str, arguments: &[Token], spec: &[Token]) -> Result<WindowFunction, QueryError> {
+        let render = |tokens: &[Token]| tokens.iter().map(Token::to_sql).collect::<Vec<_>>().join(" ");
+        let text = format!("{}({}) OVER ({})", name.to_uppercase(), render(arguments), render(spec));
+
+        let expression_parser = ExpressionParser::new();
+        let argument_list = if arguments.is_empty() { Vec::new() } else { expression_parser.split_on_commas(arguments) };
+        let upper_name = name.to_uppercase();
+
+        let kind = match (upper_name.as_str(), argument_list.as_slice()) {
+            ("ROW_NUMBER", []) => WindowKind::RowNumber,
+            ("RANK", []) => WindowKind::Rank,
+            ("DENSE_RANK", []) => WindowKind::DenseRank,
+            ("ROW_NUMBER" | "RANK" | "DENSE_RANK", _) => {
+                return Err(QueryError::SyntaxError(format!("{}() takes no arguments", upper_name)));
+            }
+            ("LAG" | "LEAD", [expression, rest @ ..]) if rest.len() <= 2 => {
+                let expression = Box::new(expression_parser.parse_tokens(expression)?);
+                let offset = match rest.first() {
+                    Some([Token::Number(num)]) => num.parse::<usize>()
+                        .map_err(|_| QueryError::SyntaxError(format!("Invalid {} offset: {}", upper_name, num)))?,
+                    Some(other) => {
+                        return Err(QueryError::SyntaxError(format!("Invalid {} offset: {}", upper_name, render(other))));
+                    }
+                    None => 1,
+                };
+                let default = Box::new(match rest.get(1) {
+                    Some(default) => expression_parser.parse_tokens(default)?,
+                    None => Expression::Literal(Value::Null),
+                });
+
+                if upper_name == "LAG" {
+                    WindowKind::Lag { expression, offset, default }
+                } else {
+                    WindowKind::Lead { expression, offset, default }
+                }
+            }
+            ("FIRST_VALUE", [expression]) => WindowKind::FirstValue(Box::new(expression_parser.parse_tokens(expression)?)),
+            ("SUM" | "AVG" | "MIN" | "MAX" | "COUNT", _) => WindowKind::Aggregate(
+                AggregationParser::new().parse_single_function(&format!("{}({})", name, render(arguments)))?
+            ),
+            _ => return Err(QueryError::SyntaxError(format!("Unsupported window function: {}", text))),
+        };
+
+        let (partition_by, order_by, frame) = self.parse_window_spec(spec)?;
+        Ok(WindowFunction { kind, partition_by, order_by, frame, text })
+    }
+
+    /// Parse the PARTITION BY, ORDER BY and ROWS parts of a window specification
+    fn parse_window_spec(&self, spec: &[Token]) -> Result<WindowSpec, QueryError> {
+        let keyword_index = |keyword: &str| spec.iter().position(|t| t.is_keyword(keyword));
+        if keyword_index("RANGE").is_some() || keyword_index("GROUPS").is_some() {
+            return Err(QueryError::SyntaxError("Only ROWS window frames are supported".to_string()));
+        }
+
+        let partition_index = keyword_index("PARTITION");
+        let order_index = keyword_index("ORDER");
+        let rows_index = keyword_index("ROWS");
+
+        let mut sections = [partition_index, order_index, rows_index].into_iter().flatten().peekable();
+        if sections.peek().is_some_and(|&first| first != 0) || !sections.clone().is_sorted() {
+            return Err(QueryError::SyntaxError(
+                "Expected window specification of the form [PARTITION BY ...] [ORDER BY ...] [ROWS ...]".to_string()
+            ));
+        }
+        let section_end = |start: usize| [order_index, rows_index].into_iter()
+            .flatten()
+            .find(|&index| index > start)
+            .unwrap_or(spec.len());
+
+        let expression_parser = ExpressionParser::new();
+        let mut partition_by = Vec::new();
+        if let Some(start) = partition_index {
+            for expression in expression_parser.split_on_commas(self.after_by(spec, start, section_end(start))?) {
+                partition_by.push(expression_parser.parse_tokens(expression)?);
+            }
+        }
+
+        let mut order_by = Vec::new();
+        if let Some(start) = order_index {
+            for item in expression_parser.split_on_commas(self.after_by(spec, start, section_end(start))?) {
+                let (item, descending) = match item.split_last() {
+                    Some((last, rest)) if last.is_keyword("DESC") => (rest, true),
+                    Some((last, rest)) if last.is_keyword("ASC") => (rest, false),
+                    _ => (item, false),
+                };
+                order_by.push(OrderByItem { expression: expression_parser.parse_tokens(item)?, descending });
+            }
+        }
+
+        let frame = match rows_index {
+            Some(start) => Some(self.parse_frame(&spec[start + 1..])?),
+            None => None,
+        };
+
+        Ok((partition_by, order_by, frame))
+    }
+
+    /// The tokens after `KEYWORD BY`, up to the next section
+    fn after_by<'a>(&self, spec: &'a [Token], start: usize, end: usize) -> Result<&'a [Token], QueryError> {
+        if !spec.get(start + 1).is_some_and(|t| t.is_keyword("BY")) {
+            return Err(QueryError::SyntaxError(format!("Expected BY after {}", spec[start].to_sql())));
+        }
+        if start + 2 >= end {
+            return Err(QueryError::SyntaxError(format!("Expected expression after {} BY", spec[start].to_sql())));
+        }
+        Ok(&spec[start + 2..end])
+    }
+
+    /// Parse `BETWEEN start AND end`, or a single start bound that ends at the current row
+    fn parse_frame(&self, tokens: &[Token]) -> Result<WindowFrame, QueryError> {
+        let (start, end) = if tokens.first().is_some_and(|t| t.is_keyword("BETWEEN")) {
+            let and_index = tokens.iter()
+                .position(|t| t.is_keyword("AND"))
+                .ok_or_else(|| QueryError::SyntaxError("Expected AND in window frame".to_string()))?;
+            (self.parse_frame_bound(&tokens[1..and_index])?, self.parse_frame_bound(&tokens[and_index + 1..])?)
+        } else {
+            (self.parse_frame_bound(tokens)?, FrameBound::CurrentRow)
+        };
+
+        // Compare the bounds as offsets from the current row
+        let offset = |bound: FrameBound| match bound {
+            FrameBound::UnboundedPreceding => i64::MIN,
+            FrameBound::Preceding(n) => -(n as i64),
+            FrameBound::CurrentRow => 0,
+            FrameBound::Following(n) => n as i64,
+            FrameBound::UnboundedFollowing => i64::MAX,
+        };
+        if start == FrameBound::UnboundedFollowing || end == FrameBound::UnboundedPreceding || offset(start) > offset(end) {
+            return Err(QueryError::SyntaxError("Window frame starts after it ends".to_string()));
+        }
+        Ok((start, end))
+    }
+
+    fn parse_frame_bound(&self, tokens: &[Token]) -> Result<FrameBound, QueryError> {
+        match tokens {
+            [first, second] if first.is_keyword("UNBOUNDED") && second.is_keyword("PRECEDING") => Ok(FrameBound::UnboundedPreceding),
+            [first, second] if first.is_keyword("UNBOUNDED") && second.is_keyword("FOLLOWING") => Ok(FrameBound::UnboundedFollowing),
+            [first, second] if first.is_keyword("CURRENT") && second.is_keyword("ROW") => Ok(FrameBound::CurrentRow),
+            [Token::Number(num), direction] if direction.is_keyword("PRECEDING") || direction.is_keyword("FOLLOWING") => {
+                let count = num.parse::<usize>()
+                    .map_err(|_| QueryError::SyntaxError(format!("Invalid window frame offset: {}", num)))?;
+                Ok(if direction.is_keyword("PRECEDING") { FrameBound::Preceding(count) } else { FrameBound::Following(count) })
+            }
+            _ => Err(QueryError::SyntaxError(format!(
+                "Invalid window frame bound: {}", tokens.iter().map(Token::to_sql).collect::<Vec<_>>().join(" ")
+            ))),
+        }
+    }
+
+    /// Compute the window functions for every row, giving each row its values in the order of `windows`
+    /// `evaluate` evaluates an expression against the row at an index; window aggregates read `rows` directly
+    pub fn compute_windows(
+        &self,
+        windows: &[&WindowFunction],
+        tables: &[(&str, &Table)],
+        rows: &[Row],
+        evaluate: &dyn Fn(&Expression, usize) -> Result<Value, QueryError>,
+    ) -> Result<Vec<Vec<Value>>, QueryError> {
+        let mut values = vec![Vec::with_capacity(windows.len()); rows.len()];
+        for window in windows {
+            let window_values = self.compute_window(window, tables, rows, evaluate)?;
+            for (row_values, value) in values.iter_mut().zip(window_values) {
+                row_values.push(value);
+            }
+        }
+        Ok(values)
+    }
+
+    fn compute_window(
+        &self,
+        window: &WindowFunction,
+        tables: &[(&str, &Table)],
+        rows: &[Row],
+        evaluate: &dyn Fn(&Expression, usize) -> Result<Value, QueryError>,
+    ) -> Result<Vec<Value>, QueryError> {
+        // Split the rows into partitions, keeping them in order of first appearance
+        let mut partitions: Vec<Vec<usize>> = Vec::new();
+        let mut partition_of: HashMap<Vec<Option<String>>, usize> = HashMap::new();
+        for index in 0..rows.len() {
+            let key = window.partition_by.iter()
+                .map(|expression| evaluate(expression, index).map(|value| value.to_text()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let partition = *partition_of.entry(key).or_insert_with(|| {
+                partitions.push(Vec::new());
+                partitions.len() - 1
+            });
+            partitions[partition].push(index);
+        }
+
+        let order_parser = OrderByParser::new();
+        let mut values = vec![Value::Null; rows.len()];
+        for partition in partitions {
+            let mut keyed: Vec<(Vec<Value>, usize)> = Vec::with_capacity(partition.len());
+            for index in partition {
+                let key = window.order_by.iter()
+                    .map(|item| evaluate(&item.expression, index))
+                    .collect::<Result<Vec<_>, _>>()?;
+                keyed.push((key, index));
+            }
+            keyed.sort_by(|(a, _), (b, _)| order_parser.compare_keys(&window.order_by, a, b));
+
+            // Rows with equal sort keys are peers: they share a rank and a default frame
+            let is_peer = |a: usize, b: usize| order_parser.compare_keys(&window.order_by, &keyed[a].0, &keyed[b].0) == Ordering::Equal;
+            let mut peer_start = vec![0; keyed.len()];
+            let mut peer_end = vec![keyed.len(); keyed.len()];
+            let mut dense_rank = vec![1; keyed.len()];
+            for position in 1..keyed.len() {
+                if is_peer(position - 1, position) {
+                    peer_start[position] = peer_start[position - 1];
+                    dense_rank[position] = dense_rank[position - 1];
+                } else {
+                    peer_start[position] = position;
+                    dense_rank[position] = dense_rank[position - 1] + 1;
+                }
+            }
+            for position in (0..keyed.len().saturating_sub(1)).rev() {
+                peer_end[position] = if is_peer(position, position + 1) { peer_end[position + 1] } else { position + 1 };
+            }
+
+            for (position, &(_, index)) in keyed.iter().enumerate() {
+                let frame = self.frame_range(window, position, peer_end[position], keyed.len());
+                values[index] = match &window.kind {
+                    WindowKind::RowNumber => Value::Integer(position as i64 + 1),
+                    WindowKind::Rank => Value::Integer(peer_start[position] as i64 + 1),
+                    WindowKind::DenseRank => Value::Integer(dense_rank[position]),
+                    WindowKind::Lag { expression, offset, default } => match position.checked_sub(*offset) {
+                        Some(target) => evaluate(expression, keyed[target].1)?,
+                        None => evaluate(default, index)?,
+                    },
+                    WindowKind::Lead { expression, offset, default } => match keyed.get(position + offset) {
+                        Some(&(_, target)) => evaluate(expression, target)?,
+                        None => evaluate(default, index)?,
+                    },
+                    WindowKind::FirstValue(expression) => match keyed.get(frame.start) {
+                        Some(&(_, first)) if !frame.is_empty() => evaluate(expression, first)?,
+                        _ => Value::Null,
+                    },
+                    WindowKind::Aggregate(function) => {
+                        let frame_rows: Vec<Row> = keyed[frame].iter()
+                            .map(|&(_, row)| rows[row].clone())
+                            .collect();
+                        let (_, value) = AggregationParser::new().compute_aggregate_value(function, tables, &frame_rows)?;
+                        Value::infer(value.as_deref())
+                    }
+                };
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Positions of the partition in the frame of the row at `position`
+    fn frame_range(&self, window: &WindowFunction, position: usize, peer_end: usize, len: usize) -> std::ops::Range<usize> {
+        let (start, end) = match window.frame {
+            Some(frame) => frame,
+            // Without a frame the window runs to the current row's last peer, or over the whole partition when unordered
+            None => return 0..peer_end,
+        };
+
+        let bound_index = |bound: FrameBound| -> i64 {
+            match bound {
+                FrameBound::UnboundedPreceding => 0,
+                FrameBound::Preceding(n) => position as i64 - n as i64,
+                FrameBound::CurrentRow => position as i64,
+                FrameBound::Following(n) => position as i64 + n as i64,
+                FrameBound::UnboundedFollowing => len as i64 - 1,
+            }
+        };
+        let start = bound_index(start).clamp(0, len as i64) as usize;
+        let end = (bound_index(end) + 1).clamp(0, len as i64) as usize;
+        start..end.max(start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryError, QueryResult};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows, shown};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE sales (id INTEGER, region VARCHAR 10, day INTEGER, amount INTEGER)",
+        "INSERT INTO sales VALUES (1, 'north', 1, 100)",
+        "INSERT INTO sales VALUES (2, 'north', 2, 300)",
+        "INSERT INTO sales VALUES (3, 'north', 3, 300)",
+        "INSERT INTO sales VALUES (4, 'south', 1, 50)",
+        "INSERT INTO sales VALUES (5, 'south', 2, 70)",
+    ];
+
+    #[test]
+    fn test_ranking_functions() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT id, ROW_NUMBER() OVER (PARTITION BY region ORDER BY amount DESC) AS rn, \
+                     RANK() OVER (ORDER BY amount DESC) AS rnk, DENSE_RANK() OVER (ORDER BY amount DESC) AS dense \
+                     FROM sales ORDER BY id";
+        match engine.execute(query).unwrap() {
+            QueryResult::Select(result) => {
+                assert_eq!(result.headers, vec!["id", "rn", "rnk", "dense"]);
+                assert_eq!(shown(&result.rows), vec![
+                    vec!["1", "3", "3", "2"],
+                    vec!["2", "1", "1", "1"],
+                    vec!["3", "2", "1", "1"],
+                    vec!["4", "2", "5", "4"],
+                    vec!["5", "1", "4", "3"],
+                ]);
+            }
+            other => panic!("Expected select result, got {:?}", other),
+        }
+
+        // Window functions see the rows left by WHERE, and ORDER BY can use them
+        assert_eq!(
+            rows(&mut engine, "SELECT id FROM sales WHERE region = 'north' ORDER BY ROW_NUMBER() OVER (ORDER BY day DESC)"),
+            vec![vec!["3"], vec!["2"], vec!["1"]]
+        );
+    }
+
+    #[test]
+    fn test_offset_and_aggregate_windows() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        let query = "SELECT id, LAG(amount) OVER (PARTITION BY region ORDER BY day) AS prev, \
+                     LEAD(amount, 1, 0) OVER (PARTITION BY region ORDER BY day) AS next, \
+                     FIRST_VALUE(amount) OVER (PARTITION BY region ORDER BY day) AS first \
+                     FROM sales ORDER BY id";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["1", "NULL", "300", "100"],
+            vec!["2", "100", "300", "100"],
+            vec!["3", "300", "0", "100"],
+            vec!["4", "NULL", "70", "50"],
+            vec!["5", "50", "0", "50"],
+        ]);
+
+        // A running total includes the peers of the current row unless the frame says otherwise
+        let query = "SELECT id, SUM(amount) OVER (ORDER BY amount) AS running, \
+                     SUM(amount) OVER (ORDER BY id ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) AS pair, \
+                     COUNT(*) OVER (PARTITION BY region) AS total, \
+                     MAX(amount) OVER (PARTITION BY region ORDER BY day ROWS BETWEEN CURRENT ROW AND UNBOUNDED FOLLOWING) AS peak \
+                     FROM sales ORDER BY id";
+        assert_eq!(rows(&mut engine, query), vec![
+            vec!["1", "220", "100", "3", "300"],
+            vec!["2", "820", "400", "3", "300"],
+            vec!["3", "820", "600", "3", "300"],
+            vec!["4", "50", "350", "2", "70"],
+            vec!["5", "120", "120", "2", "70"],
+        ]);
+
+        assert_eq!(
+            rows(&mut engine, "SELECT AVG(amount) OVER (PARTITION BY region ORDER BY day ROWS 1 PRECEDING) FROM sales WHERE region = 'south'"),
+            vec![vec!["50"], vec!["60"]]
+        );
+    }
+
+    #[test]
+    fn test_window_function_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        for query in [
+            "SELECT RANK(amount) OVER (ORDER BY amount) FROM sales",
+            "SELECT NTILE(2) OVER (ORDER BY amount) FROM sales",
+            "SELECT SUM(amount) OVER (ORDER BY day RANGE BETWEEN 1 PRECEDING AND CURRENT ROW) FROM sales",
+            "SELECT SUM(amount) OVER (ORDER BY day ROWS BETWEEN 1 FOLLOWING AND CURRENT ROW) FROM sales",
+            "SELECT region, ROW_NUMBER() OVER (ORDER BY region) FROM sales GROUP BY region",
+            "SELECT id FROM sales WHERE ROW_NUMBER() OVER (ORDER BY id) = 1",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,597 @@
+use std::collections::HashSet;
+use std::slice;
+use crate::index::{Index, encode_key};
+use crate::metadata::{ColumnType, Table};
+use crate::storage::LSMEngine;
+use super::error::QueryError;
+use super::parser::{Expression, FromParser, Lexer, WhereParser};
+use super::record::{RecordCodec, Row};
+use super::value::Value;
+
+/// How a statement reads the rows of a table
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessPath {
+    /// Read every record of the table
+    FullScan,
+    /// Read the records whose key in `index` is at least `lower` and below `upper`, or with no upper end for None
+    IndexScan {
+        index: String,
+        lower: Vec<u8>,
+        upper: Option<Vec<u8>>,
+        lookup: bool,      // the range comes from equalities only
+        covering: bool,    // the index holds every column the statement reads, so the table isn't read
+        condition: String, // the WHERE terms the range was built from
+    },
+    /// Read the records whose texts in the FULLTEXT `index` hold a word of `query`, best match first
+    FullTextSearch {
+        index: String,
+        query: String,
+        condition: String, // the MATCH term of the WHERE clause
+    },
+}
+
+impl AccessPath {
+    /// Ids of the records to read, or None to read them all
+    /// An index scan gives them in id order, so rows are read in the same order as by a full scan;
+    /// a full text search gives them by rank
+    pub fn record_ids(&self, indexes: &[Index]) -> Option<Vec<u64>> {
+        let find = |name: &str| indexes.iter().find(|candidate| candidate.name() == name);
+        match self {
+            AccessPath::FullScan => None,
+            AccessPath::IndexScan { index, lower, upper, .. } => {
+                let mut ids = find(index)?.range(lower, upper.as_deref());
+                ids.sort_unstable();
+                Some(ids)
+            }
+            AccessPath::FullTextSearch { index, query, .. } => {
+                Some(find(index)?.search(query).into_iter().map(|(id, _)| id).collect())
+            }
+        }
+    }
+
+    /// Decode the rows read this way, in table column order
+    /// An index only scan leaves the columns the index doesn't cover NULL, as the statement doesn't read them
+    pub fn read_rows(
+        &self,
+        table: &Table,
+        storage_engine: &mut LSMEngine,
+        indexes: &[Index],
+    ) -> Result<Vec<Row>, QueryError> {
+        if let AccessPath::IndexScan { index, covering: true, .. } = self {
+            if let (Some(index), Some(ids)) = (indexes.iter().find(|candidate| candidate.name() == index), self.record_ids(indexes)) {
+                storage_engine.record_read();
+                return Ok(ids.into_iter().filter_map(|id| index.covered_row(id, table.columns.len())).collect());
+            }
+        }
+
+        let records = match self.record_ids(indexes) {
+            Some(ids) => storage_engine.get_records(&ids),
+            None => storage_engine.get_all_records(),
+        }.map_err(|e| QueryError::InternalError(format!("Failed to get records of table {}: {}", table.name, e)))?;
+        records.iter()
+            .map(|record| RecordCodec::new().decode(record, table))
+            .collect()
+    }
+
+    /// The line EXPLAIN shows for reading `table_name` this way
+    pub fn describe(&self, table_name: &str) -> String {
+        match self {
+            AccessPath::FullScan => format!("Full Scan on {}", table_name),
+            AccessPath::IndexScan { index, lookup, covering, condition, .. } => {
+                let kind = match (covering, lookup) {
+                    (true, _) => "Index Only Scan",
+                    (false, true) => "Index Lookup",
+                    (false, false) => "Index Range Scan",
+                };
+                format!("{} on {} using {} ({})", kind, table_name, index, condition)
+            }
+            AccessPath::FullTextSearch { index, condition, .. } => {
+                format!("Full Text Search on {} using {} ({})", table_name, index, condition)
+            }
+        }
+    }
+}
+
+/// A `column operator literal` term of a WHERE clause, with the operator read column first
+struct KeyTerm<'a> {
+    column: usize,
+    operator: &'a str,
+    value: &'a Value,
+}
+
+/// Chooses between a full scan and an index scan for the rows a statement reads
+pub struct AccessPlanner;
+
+impl Default for AccessPlanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccessPlanner {
+    pub fn new() -> Self {
+        AccessPlanner
+    }
+
+    /// Parse the condition following WHERE, if the statement has one
+    pub fn where_condition(&self, tokens: &[&str]) -> Result<Option<Expression>, QueryError> {
+        match tokens.iter().position(|t| t.eq_ignore_ascii_case("WHERE")) {
+            Some(where_index) => Ok(Some(WhereParser::new().parse_where_clause(&tokens[where_index + 1..])?.condition)),
+            None => Ok(None),
+        }
+    }
+
+    /// The table of a SELECT that reads only that one table: no joins, set operations,
+    /// derived tables or subqueries reading it again. Only then can its rows be narrowed down
+    /// before the query runs, as nothing else in the query sees them.
+    pub fn single_table<'a>(&self, tokens: &[&'a str]) -> Option<&'a str> {
+        if !tokens.first()?.eq_ignore_ascii_case("SELECT")
+            || Lexer::new().has_keyword(tokens, &["JOIN", "UNION", "INTERSECT", "EXCEPT"]) {
+            return None;
+        }
+
+        let from_index = tokens.iter().position(|t| t.eq_ignore_ascii_case("FROM"))?;
+        let (table_ref, next) = FromParser::new().parse_table_ref(tokens, from_index + 1).ok()?;
+        let follows_table = tokens.get(next)
+            .is_none_or(|t| matches!(t.to_uppercase().as_str(), "WHERE" | "GROUP" | "HAVING" | "ORDER" | "LIMIT"));
+        if table_ref.subquery.is_some() || !follows_table {
+            return None;
+        }
+
+        let read_again = tokens.iter().enumerate().any(|(i, token)| {
+            i != from_index + 1 && token.contains('(')
+                && token.split(|c: char| !c.is_alphanumeric() && c != '_').any(|word| word == table_ref.name)
+        });
+        if read_again {
+            return None;
+        }
+        Some(tokens[from_index + 1])
+    }
+
+    /// Positions of the columns a single table SELECT reads, or None if it reads them all
+    /// Any word of the query naming a column counts as reading it
+    pub fn referenced_columns(&self, tokens: &[&str], table: &Table) -> Option<Vec<usize>> {
+        if tokens.iter().any(|token| token.replace("(*)", "").contains('*')) {
+            return None;
+        }
+        let words: HashSet<&str> = tokens.iter()
+            .flat_map(|token| token.split(|c: char| !c.is_alphanumeric() && c != '_'))
+            .collect();
+        Some((0..table.columns.len()).filter(|&position| words.contains(table.columns[position].name.as_str())).collect())
+    }
+
+    /// Choose how to read the rows of `table` that may satisfy `condition`
+    /// An index can be used when terms compare a leading run of its key columns with literals.
+    /// The condition is still checked on every row read, so the index only has to narrow the rows
+    /// down. When the statement reads only the `needed` columns and an index holds them all,
+    /// the rows come from the index alone.
+    pub fn choose(&self, table: &Table, condition: Option<&Expression>, indexes: &[Index], needed: Option<&[usize]>) -> AccessPath {
+        let Some(condition) = condition else {
+            return AccessPath::FullScan;
+        };
+        // A full text search sets the order rows come back in, so it is taken whenever it can be
+        if let Some(path) = condition.conjuncts().into_iter().find_map(|term| self.fulltext_search(term, table, indexes)) {
+            return path;
+        }
+        let terms: Vec<KeyTerm> = condition.conjuncts().into_iter()
+            .filter_map(|term| self.key_term(term, table))
+            .collect();
+
+        // Prefer an equality on every column of a UNIQUE index, then more equalities, then a range
+        // on the next column, then an index the table needn't be read with
+        let mut best = ((false, 0, false, false), AccessPath::FullScan);
+        for index in indexes {
+            let covering = needed.is_some_and(|needed| index.covers(needed));
+            let Some((equalities, path)) = self.index_scan(index, table, &terms, covering) else {
+                continue;
+            };
+            let AccessPath::IndexScan { lookup, .. } = path else {
+                continue;
+            };
+            let unique = index.is_unique() && lookup && equalities == index.definition().columns.len();
+            let rank = (unique, equalities, !lookup, covering);
+            if rank > best.0 {
+                best = (rank, path);
+            }
+        }
+        best.1
+    }
+
+    /// The scan of `index` bounded by equalities on its leading key columns and by bounds on the
+    /// column after them, if there is a term to bound it; returns the number of equalities with it
+    fn index_scan(&self, index: &Index, table: &Table, terms: &[KeyTerm], covering: bool) -> Option<(usize, AccessPath)> {
+        if index.is_fulltext() {
+            return None;
+        }
+
+        // Keys sort column by column, so the rows equal on the leading columns are the keys
+        // starting with their encoded values
+        let mut prefix = Vec::new();
+        let mut equalities = 0;
+        let mut used = Vec::new();
+        let mut lower: Option<Vec<u8>> = None;
+        let mut upper: Option<Vec<u8>> = None;
+        for column_name in &index.definition().columns {
+            let column = table.get_column_index(column_name)?;
+            let column_type = &table.columns[column].data_type;
+            let keyed: Vec<(&KeyTerm, Vec<u8>)> = terms.iter()
+                .filter(|term| term.column == column)
+                .filter_map(|term| Some((term, self.literal_key(term.value, column_type)?)))
+                .collect();
+
+            if let Some((term, key)) = keyed.iter().find(|(term, _)| term.operator == "=") {
+                prefix.extend(key);
+                equalities += 1;
+                used.push(format!("{} = {}", column_name, literal_sql(term.value)));
+                continue;
+            }
+
+            for (term, key) in keyed {
+                let key = [prefix.as_slice(), &key].concat();
+                let (from, to) = match term.operator {
+                    ">" => (Some(successor(&key)), None),
+                    ">=" => (Some(key), None),
+                    "<" => (None, Some(key)),
+                    "<=" => (None, Some(successor(&key))),
+                    _ => continue,
+                };
+                if let Some(from) = from {
+                    lower = Some(lower.map_or(from.clone(), |lower| lower.max(from)));
+                }
+                if let Some(to) = to {
+                    upper = Some(upper.map_or(to.clone(), |upper| upper.min(to)));
+                }
+                used.push(format!("{} {} {}", column_name, term.operator, literal_sql(term.value)));
+            }
+            break;
+        }
+
+        if used.is_empty() {
+            return None;
+        }
+        // NULL keys sort first and never satisfy a comparison, so a range starts above them
+        let lookup = lower.is_none() && upper.is_none();
+        let lower = lower.unwrap_or_else(|| if lookup { prefix.clone() } else { [prefix.as_slice(), &[1]].concat() });
+        let upper = upper.or_else(|| (!prefix.is_empty()).then(|| successor(&prefix)));
+        Some((equalities, AccessPath::IndexScan {
+            index: index.name().to_string(),
+            lower,
+            upper,
+            lookup,
+            covering,
+            condition: used.join(" AND "),
+        }))
+    }
+
+    /// The search of a FULLTEXT index on exactly the columns of a `MATCH(column, ..., 'query')` term
+    fn fulltext_search(&self, term: &Expression, table: &Table, indexes: &[Index]) -> Option<AccessPath> {
+        let Expression::Function { name, arguments } = term else {
+            return None;
+        };
+        let (Expression::Literal(Value::Text(query)), columns) = arguments.split_last()? else {
+            return None;
+        };
+        if name != "MATCH" {
+            return None;
+        }
+
+        let column_names = columns.iter()
+            .map(|column| match column {
+                Expression::Column(column) => table.get_column(column.rsplit('.').next().unwrap_or(column)).map(|c| c.name.as_str()),
+                _ => None,
+            })
+            .collect::<Option<Vec<&str>>>()?;
+        let condition = format!("MATCH({}, {})", column_names.join(", "), literal_sql(&Value::Text(query.clone())));
+
+        let index = self.fulltext_index(&column_names, indexes)?;
+        Some(AccessPath::FullTextSearch { index: index.name().to_string(), query: query.clone(), condition })
+    }
+
+    /// The FULLTEXT index on exactly `columns`, in any order
+    fn fulltext_index<'i>(&self, columns: &[&str], indexes: &'i [Index]) -> Option<&'i Index> {
+        let mut columns = columns.to_vec();
+        columns.sort_unstable();
+        indexes.iter().find(|index| {
+            let mut index_columns: Vec<&str> = index.definition().columns.iter().map(String::as_str).collect();
+            index_columns.sort_unstable();
+            index.is_fulltext() && index_columns == columns
+        })
+    }
+
+    /// Check that every MATCH of `condition` searches columns of one of `tables` with a FULLTEXT index on them
+    /// MATCH is only meant as a search of that index, so it isn't run as a plain filter
+    pub fn check_fulltext_terms(&self, condition: &Expression, tables: &[(&Table, &[Index])]) -> Result<(), QueryError> {
+        for arguments in condition.function_calls("MATCH") {
+            let Some((_, columns)) = arguments.split_last() else {
+                continue;
+            };
+            let column_names = columns.iter()
+                .map(|column| match column {
+                    Expression::Column(column) => Ok(column.rsplit('.').next().unwrap_or(column)),
+                    _ => Err(QueryError::SyntaxError("MATCH can only search columns".to_string())),
+                })
+                .collect::<Result<Vec<&str>, QueryError>>()?;
+            let indexed = tables.iter().any(|(table, indexes)| {
+                column_names.iter().all(|name| table.get_column(name).is_some()) && self.fulltext_index(&column_names, indexes).is_some()
+            });
+            if !indexed && !column_names.is_empty() {
+                return Err(QueryError::InvalidValue(format!(
+                    "MATCH needs a FULLTEXT index on {}", column_names.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn key_term<'a>(&self, term: &'a Expression, table: &Table) -> Option<KeyTerm<'a>> {
+        let Expression::Comparison { left, operator, right } = term else {
+            return None;
+        };
+        let (column, operator, value) = match (left.as_ref(), right.as_ref()) {
+            (Expression::Column(column), Expression::Literal(value)) => (column, operator.as_str(), value),
+            (Expression::Literal(value), Expression::Column(column)) => {
+                let flipped = match operator.as_str() {
+                    "<" => ">",
+                    "<=" => ">=",
+                    ">" => "<",
+                    ">=" => "<=",
+                    other => other,
+                };
+                (column, flipped, value)
+            }
+            _ => return None,
+        };
+
+        // Only one table is read, so a qualifier can only name it
+        let name = column.rsplit('.').next().unwrap_or(column);
+        Some(KeyTerm { column: table.get_column_index(name)?, operator, value })
+    }
+
+    /// The key of a literal, if it compares with the column's values in the order of their keys
+    fn literal_key(&self, value: &Value, column_type: &ColumnType) -> Option<Vec<u8>> {
+        let is_quoted = |text: &str| text.starts_with(['\'', '"']) || text.ends_with(['\'', '"']);
+        let text = match (value, column_type) {
+            (Value::Integer(number), ColumnType::Integer | ColumnType::Float) => number.to_string(),
+            (Value::Float(number), ColumnType::Float) if !number.is_nan() => number.to_string(),
+            (Value::Float(number), ColumnType::Integer) if number.fract() == 0.0 && number.abs() < 9e18 => {
+                (*number as i64).to_string()
+            }
+            // Keys of text are stored without surrounding quotes
+            (Value::Text(text), ColumnType::Varchar(_)) if !is_quoted(text) => text.clone(),
+            (Value::Text(text), ColumnType::Timestamp) => text.clone(),
+            (Value::Timestamp(_), ColumnType::Timestamp) | (Value::Boolean(_), ColumnType::Boolean) => value.to_string(),
+            _ => return None,
+        };
+        encode_key(&[Some(&text)], slice::from_ref(column_type)).ok()
+    }
+}
+
+/// The smallest key above every key that starts with `key`
+/// Keys of non-NULL values start with 1, so there always is one
+fn successor(key: &[u8]) -> Vec<u8> {
+    let mut next = key.to_vec();
+    while next.last() == Some(&0xFF) {
+        next.pop();
+    }
+    if let Some(last) = next.last_mut() {
+        *last += 1;
+    }
+    next
+}
+
+/// A literal the way it is written in a query
+fn literal_sql(value: &Value) -> String {
+    match value {
+        Value::Text(_) | Value::Timestamp(_) => format!("'{}'", value.to_string().replace('\'', "''")),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryEngine, QueryError, QueryResult};
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, name VARCHAR 20, age INTEGER, score FLOAT)",
+        "INSERT INTO users VALUES (1, 'Ann', 30, 1.5), (2, 'Bob', 25, NULL), (3, 'Cid', NULL, -2.0), \
+            (4, 'Dee', 41, 0.0), (5, 'Eve', 30, 9.5), (6, 'Al', 25, 3.0)",
+    ];
+
+    fn plan(engine: &mut QueryEngine, query: &str) -> Vec<String> {
+        rows(engine, &format!("EXPLAIN {}", query)).into_iter().map(|row| row[0].clone()).collect()
+    }
+
+    #[test]
+    fn test_access_path_choice() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        assert_eq!(plan(&mut engine, "SELECT * FROM users WHERE age = 30"), vec!["Full Scan on users"]);
+
+        engine.execute("CREATE INDEX users_age ON users (age)").unwrap();
+        engine.execute("CREATE UNIQUE INDEX users_name ON users (name)").unwrap();
+        for (query, expected) in [
+            ("SELECT * FROM users WHERE age = 30", "Index Lookup on users using users_age (age = 30)"),
+            ("SELECT name FROM users u WHERE 20 < u.age AND age <= 30 ORDER BY name",
+             "Index Range Scan on users using users_age (age > 20 AND age <= 30)"),
+            // An equality on a UNIQUE index beats one on a plain index
+            ("SELECT * FROM users WHERE age = 30 AND name = 'Ann'", "Index Lookup on users using users_name (name = 'Ann')"),
+            ("SELECT * FROM users WHERE age > 20 AND name = 'Ann'", "Index Lookup on users using users_name (name = 'Ann')"),
+            ("UPDATE users SET score = 0.0 WHERE age >= 41", "Index Range Scan on users using users_age (age >= 41)"),
+            ("DELETE FROM users WHERE name < 'B'", "Index Range Scan on users using users_name (name < 'B')"),
+            // Terms an index can't bound leave a full scan
+            ("SELECT * FROM users WHERE age = 30 OR id = 2", "Full Scan on users"),
+            ("SELECT * FROM users WHERE age + 1 = 31", "Full Scan on users"),
+            ("SELECT * FROM users WHERE age = '30'", "Full Scan on users"),
+            ("SELECT * FROM users WHERE age != 30", "Full Scan on users"),
+            ("SELECT * FROM users WHERE score > 1", "Full Scan on users"),
+            // So do queries where the table isn't read only once
+            ("SELECT * FROM users WHERE age = (SELECT MAX(age) FROM users)", "Full Scan on users"),
+            ("SELECT * FROM users WHERE age = 30 UNION SELECT * FROM users WHERE age = 25", "Full Scan on users"),
+            // Keywords inside a literal are only text
+            ("SELECT * FROM users WHERE name = 'a JOIN b'", "Index Lookup on users using users_name (name = 'a JOIN b')"),
+        ] {
+            assert_eq!(plan(&mut engine, query), vec![expected], "{}", query);
+        }
+
+        engine.execute("CREATE TABLE orders (id INTEGER, user_id INTEGER)").unwrap();
+        assert_eq!(
+            plan(&mut engine, "SELECT * FROM users JOIN orders ON users.id = orders.user_id WHERE age = 30"),
+            vec!["Full Scan on orders", "Full Scan on users"]
+        );
+        assert!(matches!(engine.execute("EXPLAIN INSERT INTO users VALUES (7, 'Fay', 1, 1.0)"), Err(QueryError::SyntaxError(_))));
+        assert!(matches!(engine.execute("EXPLAIN DELETE FROM missing WHERE id = 1"), Err(QueryError::TableNotFound(_))));
+        for query in ["EXPLAIN", "EXPLAIN SELECT", "EXPLAIN SELECT * FROM", "EXPLAIN SELECT name users", "EXPLAIN UPDATE", "EXPLAIN CREATE TABLE t (id INTEGER)"] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+        assert!(matches!(engine.execute("EXPLAIN SELECT * FROM missing"), Err(QueryError::TableNotFound(_))));
+        assert!(matches!(
+            engine.execute("EXPLAIN SELECT * FROM users u LEFT orders o ON o.user_id = u.id"),
+            Err(QueryError::SyntaxError(_))
+        ));
+    }
+
+    #[test]
+    fn test_index_scans_match_full_scans() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        let queries = [
+            "SELECT id FROM users WHERE age = 30",
+            "SELECT id FROM users WHERE age > 25",
+            "SELECT id FROM users WHERE age >= 25 AND age < 41",
+            "SELECT id FROM users WHERE age <= 30 AND 30 <= age",
+            "SELECT id FROM users WHERE age > 41",
+            "SELECT id FROM users WHERE age > 30 AND age < 25",
+            "SELECT id FROM users WHERE age = 30.0 AND name > 'Al'",
+            "SELECT id FROM users WHERE name >= 'Al' AND name <= 'Bob'",
+            "SELECT id FROM users WHERE name > 'A' AND name < 'Ann'",
+            "SELECT id FROM users WHERE score < 1",
+            "SELECT id FROM users WHERE score >= -2.0 AND score <= 0",
+            "SELECT COUNT(*), SUM(id) FROM users WHERE age <= 30 GROUP BY age ORDER BY age",
+        ];
+        let expected: Vec<_> = queries.iter().map(|query| rows(&mut engine, query)).collect();
+
+        engine.execute("CREATE INDEX users_age ON users (age)").unwrap();
+        engine.execute("CREATE INDEX users_name_age ON users (name, age)").unwrap();
+        engine.execute("CREATE INDEX users_score ON users (score)").unwrap();
+        for (query, expected) in queries.iter().zip(&expected) {
+            assert!(plan(&mut engine, query)[0].starts_with("Index"), "{}", query);
+            assert_eq!(&rows(&mut engine, query), expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_index_scans_in_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("CREATE INDEX users_age ON users (age)").unwrap();
+        engine.execute("CREATE INDEX users_name ON users (name)").unwrap();
+
+        assert!(matches!(engine.execute("UPDATE users SET age = 26 WHERE age = 25 AND id > 2"), Ok(QueryResult::Update(1))));
+        assert!(matches!(engine.execute("DELETE FROM users WHERE age >= 30 AND name <> 'Eve'"), Ok(QueryResult::Delete(2))));
+        assert_eq!(rows(&mut engine, "SELECT id, age FROM users ORDER BY id"), vec![
+            vec!["2", "25"],
+            vec!["3", "NULL"],
+            vec!["5", "30"],
+            vec!["6", "26"],
+        ]);
+
+        // UNIQUE columns are still checked against rows the index didn't return
+        assert!(matches!(engine.execute("UPDATE users SET id = 2 WHERE name = 'Eve'"), Err(QueryError::UniqueViolation(_))));
+
+        // The index follows the writes made through it
+        engine.execute("UPDATE users SET age = 31 WHERE age = 30").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT id FROM users WHERE age = 30"), Vec::<Vec<String>>::new());
+        assert_eq!(rows(&mut engine, "SELECT id FROM users WHERE age > 30"), vec![vec!["5"]]);
+    }
+
+    #[test]
+    fn test_composite_and_covering_indexes() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        engine.execute("CREATE TABLE employees (id INTEGER PRIMARY KEY, company_id INTEGER, department VARCHAR 20, \
+                        salary FLOAT, name VARCHAR 20)").unwrap();
+        engine.execute("INSERT INTO employees VALUES (1, 1, 'Sales', 100.0, 'Ann'), (2, 1, 'Sales', 120.0, 'Bob'), \
+                        (3, 1, 'Ops', 90.0, 'Cid'), (4, 2, 'Sales', 80.0, 'Dee'), (5, 2, NULL, 70.0, 'Eve'), \
+                        (6, 1, 'Research', NULL, 'Fay'), (7, NULL, 'Sales', 60.0, 'Gus')").unwrap();
+        let queries = [
+            ("SELECT salary FROM employees WHERE company_id = 1 AND department = 'Sales'",
+             "Index Only Scan on employees using employees_company_department (company_id = 1 AND department = 'Sales')"),
+            ("SELECT department, salary FROM employees WHERE company_id = 2",
+             "Index Only Scan on employees using employees_company_department (company_id = 2)"),
+            ("SELECT department, SUM(salary) FROM employees WHERE company_id = 1 AND department > 'Ops' GROUP BY department ORDER BY department",
+             "Index Only Scan on employees using employees_company_department (company_id = 1 AND department > 'Ops')"),
+            ("SELECT COUNT(*) FROM employees WHERE company_id >= 1 AND company_id < 2 AND salary > 95",
+             "Index Only Scan on employees using employees_company_department (company_id >= 1 AND company_id < 2)"),
+            ("SELECT name FROM employees WHERE company_id = 1 AND department = 'Sales'",
+             "Index Lookup on employees using employees_company_department (company_id = 1 AND department = 'Sales')"),
+            ("SELECT * FROM employees WHERE company_id = 1 AND department <= 'Research'",
+             "Index Range Scan on employees using employees_company_department (company_id = 1 AND department <= 'Research')"),
+            // Without the leading key column the index can't be used
+            ("SELECT salary FROM employees WHERE department = 'Sales'", "Full Scan on employees"),
+        ];
+        let expected: Vec<_> = queries.iter().map(|(query, _)| rows(&mut engine, query)).collect();
+
+        engine.execute("CREATE INDEX employees_company ON employees (company_id)").unwrap();
+        engine.execute("CREATE INDEX employees_company_department ON employees (company_id, department) INCLUDE (salary)").unwrap();
+        for ((query, plan_line), expected) in queries.iter().zip(&expected) {
+            assert_eq!(plan(&mut engine, query), vec![*plan_line], "{}", query);
+            assert_eq!(&rows(&mut engine, query), expected, "{}", query);
+        }
+
+        // Included values follow updates, and are persisted with the index
+        engine.execute("UPDATE employees SET salary = salary + 1 WHERE id = 2").unwrap();
+        engine.execute("UPDATE employees SET department = 'Ops' WHERE id = 1").unwrap();
+        drop(engine);
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert_eq!(rows(&mut engine, queries[0].0), vec![vec!["121"]]);
+        assert_eq!(
+            rows(&mut engine, "SELECT department, salary FROM employees WHERE company_id = 1 AND department < 'P' ORDER BY salary"),
+            vec![vec!["Ops", "90"], vec!["Ops", "100"]]
+        );
+
+        for query in [
+            "CREATE INDEX employees_x ON employees (company_id) INCLUDE (company_id)",
+            "CREATE INDEX employees_x ON employees (company_id) INCLUDE (salary, salary)",
+            "CREATE INDEX employees_x ON employees (company_id) INCLUDE salary",
+            "CREATE INDEX employees_x ON employees (company_id) INCLUDE ()",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+        assert!(engine.execute("CREATE INDEX employees_x ON employees (company_id) INCLUDE (missing)").is_err());
+        assert!(engine.get_index("employees_x").is_none());
+    }
+
+    #[test]
+    fn test_composite_index_with_nulls_and_empty_tables() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        engine.execute("CREATE TABLE shifts (id INTEGER, site INTEGER, day INTEGER, hours FLOAT)").unwrap();
+
+        // An index built over an empty table is filled by later inserts
+        engine.execute("CREATE INDEX shifts_site_day ON shifts (site, day) INCLUDE (hours)").unwrap();
+        let query = "SELECT hours FROM shifts WHERE site = 1 AND day = 2";
+        assert_eq!(plan(&mut engine, query), vec!["Index Only Scan on shifts using shifts_site_day (site = 1 AND day = 2)"]);
+        assert!(rows(&mut engine, query).is_empty());
+        engine.execute("INSERT INTO shifts VALUES (1, 1, 2, 8.0), (2, 1, NULL, 4.0), (3, NULL, 2, NULL), (4, NULL, NULL, 6.0)").unwrap();
+        assert_eq!(rows(&mut engine, query), vec![vec!["8"]]);
+
+        // NULL key parts never satisfy a comparison, but a prefix lookup still finds their rows
+        assert!(rows(&mut engine, "SELECT hours FROM shifts WHERE site = 1 AND day = NULL").is_empty());
+        assert!(rows(&mut engine, "SELECT hours FROM shifts WHERE site = NULL").is_empty());
+        assert_eq!(rows(&mut engine, "SELECT id FROM shifts WHERE site = 1 AND day >= 0"), vec![vec!["1"]]);
+        assert_eq!(rows(&mut engine, "SELECT id, hours FROM shifts WHERE site = 1 ORDER BY id"), vec![vec!["1", "8"], vec!["2", "4"]]);
+        assert_eq!(rows(&mut engine, "SELECT id FROM shifts WHERE site IS NULL ORDER BY id"), vec![vec!["3"], vec!["4"]]);
+
+        for query in [
+            "CREATE INDEX shifts_x ON shifts ()",
+            "CREATE INDEX shifts_x ON shifts (site,)",
+            "CREATE INDEX shifts_x ON shifts (site day)",
+            "CREATE INDEX shifts_x ON shifts site, day",
+        ] {
+            assert!(matches!(engine.execute(query), Err(QueryError::SyntaxError(_))), "{}", query);
+        }
+        assert!(engine.get_index("shifts_x").is_none());
+    }
+}
@@ -0,0 +1,240 @@
+use crate::metadata::{Table, Column, ColumnType, parse_timestamp, format_timestamp};
+use crate::storage::Record;
+use super::error::QueryError;
+
+/// A decoded row in table column order, None for NULL
+pub type Row = Vec<Option<String>>;
+
+/// Converts rows between their text form and the bytes stored in a record
+///
+/// A record starts with a null bitmap, one bit per column in table order,
+/// followed by the values of the columns that aren't NULL:
+/// INTEGER, FLOAT and TIMESTAMP take 8 bytes, BOOLEAN 1 byte,
+/// and VARCHAR a 4 byte length followed by the text.
+pub struct RecordCodec;
+
+impl Default for RecordCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordCodec {
+    pub fn new() -> Self {
+        RecordCodec
+    }
+
+    fn bitmap_len(table: &Table) -> usize {
+        table.columns.len().div_ceil(8)
+    }
+
+    /// Encode a row given in table column order, None for NULL
+    pub fn encode(&self, row: &[Option<String>], table: &Table) -> Result<Vec<u8>, QueryError> {
+        if row.len() != table.columns.len() {
+            return Err(QueryError::InternalError(format!(
+                "Row has {} values but table {} has {} columns", row.len(), table.name, table.columns.len()
+            )));
+        }
+
+        let mut data = vec![0u8; Self::bitmap_len(table)];
+        for (index, (value, column)) in row.iter().zip(&table.columns).enumerate() {
+            match value {
+                Some(value) => data.extend(self.encode_value(value, column)?),
+                None => data[index / 8] |= 1 << (index % 8),
+            }
+        }
+        Ok(data)
+    }
+
+    /// Decode a record into its column values in table order, None for NULL
+    pub fn decode(&self, record: &Record, table: &Table) -> Result<Row, QueryError> {
+        let data = &record.data;
+        let mut offset = Self::bitmap_len(table);
+        let truncated = || QueryError::InternalError(format!(
+            "Record {} of table {} is shorter than its columns", record.id, table.name
+        ));
+        if data.len() < offset {
+            return Err(truncated());
+        }
+
+        let mut row = Vec::with_capacity(table.columns.len());
+        for (index, column) in table.columns.iter().enumerate() {
+            if data[index / 8] & (1 << (index % 8)) != 0 {
+                row.push(None);
+                continue;
+            }
+
+            let width = match column.data_type {
+                ColumnType::Integer | ColumnType::Float | ColumnType::Timestamp => 8,
+                ColumnType::Boolean => 1,
+                ColumnType::Varchar(_) => {
+                    let length_bytes = data.get(offset..offset + 4).ok_or_else(truncated)?;
+                    offset += 4;
+                    u32::from_be_bytes(length_bytes.try_into().unwrap()) as usize
+                }
+            };
+            let bytes = data.get(offset..offset + width).ok_or_else(truncated)?;
+            offset += width;
+
+            row.push(Some(match column.data_type {
+                ColumnType::Integer => i64::from_be_bytes(bytes.try_into().unwrap()).to_string(),
+                ColumnType::Float => f64::from_be_bytes(bytes.try_into().unwrap()).to_string(),
+                ColumnType::Timestamp => format_timestamp(i64::from_be_bytes(bytes.try_into().unwrap())),
+                ColumnType::Boolean => (bytes[0] == 1).to_string(),
+                ColumnType::Varchar(_) => String::from_utf8_lossy(bytes).to_string(),
+            }));
+        }
+        Ok(row)
+    }
+
+    /// The row as it reads back after being stored, e.g. 5.0 becomes 5
+    pub fn normalize(&self, row: &[Option<String>], table: &Table) -> Result<Row, QueryError> {
+        self.decode(&Record::new(0, self.encode(row, table)?), table)
+    }
+
+    /// Encode a single non-NULL value given in its text form, without quotes
+    fn encode_value(&self, value: &str, column: &Column) -> Result<Vec<u8>, QueryError> {
+        match column.data_type {
+            ColumnType::Integer => {
+                let num = value.parse::<i64>()
+                    .map_err(|_| QueryError::TypeMismatch(format!("Invalid integer value: {}", value)))?;
+                Ok(num.to_be_bytes().to_vec())
+            }
+            ColumnType::Float => {
+                let num = value.parse::<f64>()
+                    .map_err(|_| QueryError::TypeMismatch(format!("Invalid float value: {}", value)))?;
+                Ok(num.to_be_bytes().to_vec())
+            }
+            ColumnType::Varchar(max_len) => {
+                if let Some(max_len) = max_len.filter(|&max_len| value.chars().count() > max_len) {
+                    return Err(QueryError::TypeMismatch(format!(
+                        "Value '{}' exceeds column length of {}", value, max_len
+                    )));
+                }
+                let mut bytes = (value.len() as u32).to_be_bytes().to_vec();
+                bytes.extend(value.as_bytes());
+                Ok(bytes)
+            }
+            ColumnType::Boolean => {
+                let value = value.to_lowercase();
+                if value != "true" && value != "false" {
+                    return Err(QueryError::TypeMismatch(format!("Invalid boolean value: {}", value)));
+                }
+                Ok(vec![if value == "true" { 1 } else { 0 }])
+            }
+            ColumnType::Timestamp => {
+                let num = parse_timestamp(value)
+                    .ok_or_else(|| QueryError::TypeMismatch(format!("Invalid timestamp value: {}", value)))?;
+                Ok(num.to_be_bytes().to_vec())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::Column;
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE staff (id INTEGER, name VARCHAR 20, salary FLOAT, manager INTEGER)",
+        "INSERT INTO staff VALUES (1, 'Ann', 5000.0, NULL)",
+        "INSERT INTO staff VALUES (2, '', 3000.0, 1)",
+        "INSERT INTO staff (id, name, manager) VALUES (3, 'Cid', 1)",
+    ];
+
+    #[test]
+    fn test_encode_decode_with_nulls() {
+        let mut table = Table::new("t".to_string());
+        for (index, data_type) in [ColumnType::Integer, ColumnType::Varchar(Some(5)), ColumnType::Boolean].into_iter().cycle().take(9).enumerate() {
+            table.add_column(Column::new(format!("c{}", index), data_type));
+        }
+        let row: Vec<Option<String>> = [Some("7"), Some("'ab'"), Some("true"), None, None, None, Some("-1"), Some(""), None]
+            .iter().map(|v| v.map(str::to_string)).collect();
+
+        let codec = RecordCodec::new();
+        let data = codec.encode(&row, &table).unwrap();
+        // Two bitmap bytes, with the NULL columns 3, 4, 5 and 8 set
+        assert_eq!(&data[..2], &[0b0011_1000, 0b0000_0001]);
+
+        let decoded = codec.decode(&Record::new(1, data), &table).unwrap();
+        assert_eq!(decoded, [Some("7"), Some("'ab'"), Some("true"), None, None, None, Some("-1"), Some(""), None].map(|v| v.map(str::to_string)));
+
+        assert!(matches!(codec.decode(&Record::new(1, vec![0, 0, 1]), &table), Err(QueryError::InternalError(_))));
+    }
+
+    #[test]
+    fn test_null_values_in_queries() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // Missing columns and NULL literals read back as NULL, distinct from an empty string
+        assert_eq!(rows(&mut engine, "SELECT * FROM staff ORDER BY id"), vec![
+            vec!["1", "Ann", "5000", "NULL"],
+            vec!["2", "", "3000", "1"],
+            vec!["3", "Cid", "NULL", "1"],
+        ]);
+
+        // A comparison with NULL is unknown, so neither it nor its negation matches
+        assert_eq!(rows(&mut engine, "SELECT id FROM staff WHERE manager = 1 ORDER BY id"), vec![vec!["2"], vec!["3"]]);
+        assert_eq!(rows(&mut engine, "SELECT id FROM staff WHERE NOT manager = 1"), Vec::<Vec<String>>::new());
+        assert_eq!(rows(&mut engine, "SELECT id FROM staff WHERE manager IS NULL OR salary IS NULL ORDER BY id"), vec![vec!["1"], vec!["3"]]);
+        assert_eq!(rows(&mut engine, "SELECT id FROM staff WHERE salary IS NOT NULL AND manager IS NOT NULL"), vec![vec!["2"]]);
+
+        // Aggregates skip NULLs, and SUM/AVG over no values are NULL
+        assert_eq!(
+            rows(&mut engine, "SELECT COUNT(*), COUNT(salary), COUNT(name), SUM(salary), AVG(salary), MIN(manager) FROM staff"),
+            vec![vec!["3", "2", "3", "8000", "4000", "1"]]
+        );
+        assert_eq!(rows(&mut engine, "SELECT SUM(salary), AVG(salary) FROM staff WHERE salary IS NULL"), vec![vec!["NULL", "NULL"]]);
+
+        engine.execute("UPDATE staff SET manager = NULL, salary = 3500.0 WHERE id = 3").unwrap();
+        assert_eq!(rows(&mut engine, "SELECT salary, manager FROM staff WHERE id = 3"), vec![vec!["3500", "NULL"]]);
+    }
+
+    #[test]
+    fn test_null_text_is_not_null() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("INSERT INTO staff VALUES (4, 'NULL', 1000.0, 1)").unwrap();
+        engine.execute("UPDATE staff SET name = 'NULL' WHERE id = 3").unwrap();
+
+        // The text 'NULL' is a value like any other
+        assert_eq!(rows(&mut engine, "SELECT id FROM staff WHERE name IS NULL"), Vec::<Vec<String>>::new());
+        assert_eq!(rows(&mut engine, "SELECT COUNT(name) FROM staff WHERE name = 'NULL'"), vec![vec!["2"]]);
+        let result = engine.execute("SELECT name, manager FROM staff WHERE id = 1 OR id = 4 ORDER BY id").unwrap()
+            .into_select_result()
+            .expect("Expected rows");
+        assert_eq!(result.rows, vec![
+            vec![Some("Ann".to_string()), None],
+            vec![Some("NULL".to_string()), Some("1".to_string())],
+        ]);
+    }
+
+    #[test]
+    fn test_quotes_in_text_values() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("CREATE UNIQUE INDEX staff_name ON staff (name)").unwrap();
+        engine.execute("INSERT INTO staff (id, name) VALUES (4, '''quoted'''), (5, '\"dq\"'), (6, 'quoted')").unwrap();
+        engine.execute("UPDATE staff SET name = name || '''' WHERE id = 3").unwrap();
+
+        // Quotes inside a literal are part of the value, in the table and in its indexes
+        assert_eq!(rows(&mut engine, "SELECT id, name FROM staff WHERE id >= 3 ORDER BY id"), vec![
+            vec!["3", "Cid'"],
+            vec!["4", "'quoted'"],
+            vec!["5", "\"dq\""],
+            vec!["6", "quoted"],
+        ]);
+        assert_eq!(rows(&mut engine, "SELECT id FROM staff WHERE name = '''quoted'''"), vec![vec!["4"]]);
+        assert_eq!(rows(&mut engine, "SELECT id FROM staff WHERE name = 'Cid'''"), vec![vec!["3"]]);
+
+        // The length of a VARCHAR counts characters, not bytes
+        engine.execute("CREATE TABLE codes (code VARCHAR 3)").unwrap();
+        engine.execute("INSERT INTO codes VALUES ('äöü')").unwrap();
+        assert!(matches!(engine.execute("INSERT INTO codes VALUES ('äöüß')"), Err(QueryError::TypeMismatch(_))));
+        assert!(matches!(engine.execute("INSERT INTO codes VALUES ('''ab''')"), Err(QueryError::TypeMismatch(_))));
+    }
+}
@@ -1,26 +1,52 @@
+use super::record::Row;
+
 #[derive(Debug)]
 pub enum QueryResult {
-    Select(Vec<Vec<String>>),
+    Select(SelectResult),
     Insert(usize),
     Update(usize),
     Delete(usize),
     CreateTable,
     DropTable,
+    CreateIndex,
+    DropIndex,
+    Begin,
+    Commit,
+    Rollback,
+    Set,
     Error(String),
     // New variants for joins and aggregations
     Join(JoinResult),
     Aggregation(AggregationResult),
 }
 
+#[derive(Debug)]
+pub struct SelectResult {
+    pub headers: Vec<String>,
+    pub rows: Vec<Row>,
+}
+
+impl QueryResult {
+    /// Headers and rows of a result that returns rows
+    pub fn into_select_result(self) -> Option<SelectResult> {
+        match self {
+            QueryResult::Select(result) => Some(result),
+            QueryResult::Join(JoinResult { headers, rows })
+            | QueryResult::Aggregation(AggregationResult { headers, rows, .. }) => Some(SelectResult { headers, rows }),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct JoinResult {
     pub headers: Vec<String>,
-    pub rows: Vec<Vec<String>>,
+    pub rows: Vec<Row>,
 }
 
 #[derive(Debug)]
 pub struct AggregationResult {
     pub headers: Vec<String>,
-    pub rows: Vec<Vec<String>>,
+    pub rows: Vec<Row>,
     pub group_by_columns: Vec<String>,
 } 
\ No newline at end of file
@@ -0,0 +1,336 @@
+use std::cmp::Ordering;
+use std::fmt;
+use chrono::{DateTime, Months};
+use crate::metadata::{ColumnType, parse_timestamp, format_timestamp};
+use super::error::QueryError;
+
+/// How a NULL is shown in query output
+pub const NULL_TEXT: &str = "NULL";
+
+/// A typed value produced while evaluating expressions
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    Boolean(bool),
+    Timestamp(i64),
+    Interval { months: i32, seconds: i64 },
+    Null,
+}
+
+/// Seconds in each fixed-length interval unit
+const INTERVAL_UNITS: [(&str, i64); 5] = [("week", 604_800), ("day", 86_400), ("hour", 3_600), ("minute", 60), ("second", 1)];
+
+impl Value {
+    /// Convert a decoded row value, None for NULL, into a typed value based on its column type
+    pub fn from_column(raw: Option<&str>, data_type: &ColumnType) -> Value {
+        let Some(raw) = raw else {
+            return Value::Null;
+        };
+        match data_type {
+            ColumnType::Integer => raw.parse::<i64>().map(Value::Integer).unwrap_or_else(|_| Value::Text(raw.to_string())),
+            ColumnType::Float => raw.parse::<f64>().map(Value::Float).unwrap_or_else(|_| Value::Text(raw.to_string())),
+            ColumnType::Varchar(_) => Value::Text(raw.to_string()),
+            ColumnType::Boolean => match raw.to_lowercase().as_str() {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                _ => Value::Text(raw.to_string()),
+            },
+            ColumnType::Timestamp => parse_timestamp(raw).map(Value::Timestamp).unwrap_or_else(|| Value::Text(raw.to_string())),
+        }
+    }
+
+    /// Parse the text of an INTERVAL literal, e.g. '1 day 2 hours' or '-3 months'
+    pub fn parse_interval(text: &str) -> Result<Value, QueryError> {
+        let invalid = || QueryError::SyntaxError(format!("Invalid interval: '{}'", text));
+        let parts: Vec<&str> = text.split_whitespace().collect();
+        if parts.is_empty() || !parts.len().is_multiple_of(2) {
+            return Err(invalid());
+        }
+
+        let (mut months, mut seconds) = (0i32, 0i64);
+        for pair in parts.chunks(2) {
+            let amount = pair[0].parse::<i64>().map_err(|_| invalid())?;
+            let unit = pair[1].to_lowercase();
+            let unit = unit.strip_suffix('s').unwrap_or(&unit);
+            let added = match unit {
+                "year" => amount.checked_mul(12).and_then(|m| i32::try_from(m).ok())
+                    .and_then(|m| months.checked_add(m)).map(|m| months = m),
+                "month" => i32::try_from(amount).ok()
+                    .and_then(|m| months.checked_add(m)).map(|m| months = m),
+                _ => {
+                    let (_, unit_seconds) = INTERVAL_UNITS.iter().find(|(name, _)| *name == unit).ok_or_else(invalid)?;
+                    amount.checked_mul(*unit_seconds)
+                        .and_then(|s| seconds.checked_add(s)).map(|s| seconds = s)
+                }
+            };
+            added.ok_or_else(invalid)?;
+        }
+        Ok(Value::Interval { months, seconds })
+    }
+
+    /// Shift a timestamp by an interval; months are added first, keeping the day where the month allows
+    fn shift_timestamp(timestamp: i64, months: i32, seconds: i64) -> Option<i64> {
+        let datetime = DateTime::from_timestamp(timestamp, 0)?;
+        let datetime = if months >= 0 {
+            datetime.checked_add_months(Months::new(months.unsigned_abs()))?
+        } else {
+            datetime.checked_sub_months(Months::new(months.unsigned_abs()))?
+        };
+        datetime.timestamp().checked_add(seconds)
+    }
+
+    /// Guess the type of an untyped value (e.g. an aggregate result), None for NULL
+    pub fn infer(raw: Option<&str>) -> Value {
+        let Some(raw) = raw else {
+            return Value::Null;
+        };
+        if let Ok(num) = raw.parse::<i64>() {
+            Value::Integer(num)
+        } else if let Ok(num) = raw.parse::<f64>() {
+            Value::Float(num)
+        } else {
+            match raw.to_lowercase().as_str() {
+                "true" => Value::Boolean(true),
+                "false" => Value::Boolean(false),
+                _ => Value::Text(raw.to_string()),
+            }
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Integer(num) | Value::Timestamp(num) => Some(*num as f64),
+            Value::Float(num) => Some(*num),
+            Value::Text(text) => text.parse::<f64>().ok(),
+            Value::Boolean(_) | Value::Interval { .. } | Value::Null => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// The value as written in a decoded row, None for NULL
+    pub fn to_text(&self) -> Option<String> {
+        (!self.is_null()).then(|| self.to_string())
+    }
+
+    pub fn as_bool(&self) -> Result<bool, QueryError> {
+        match self {
+            Value::Boolean(b) => Ok(*b),
+            Value::Text(text) if text.eq_ignore_ascii_case("true") => Ok(true),
+            Value::Text(text) if text.eq_ignore_ascii_case("false") => Ok(false),
+            other => Err(QueryError::TypeMismatch(format!("Expected boolean value, got {}", other))),
+        }
+    }
+
+    /// The column type a value of this kind would be stored as
+    /// NULL has no type of its own and fits any column
+    pub fn data_type(&self) -> Option<ColumnType> {
+        match self {
+            Value::Integer(_) => Some(ColumnType::Integer),
+            Value::Float(_) => Some(ColumnType::Float),
+            Value::Text(text) => Some(ColumnType::Varchar(Some(text.chars().count()))),
+            Value::Boolean(_) => Some(ColumnType::Boolean),
+            Value::Timestamp(_) => Some(ColumnType::Timestamp),
+            // Intervals only appear in date arithmetic and are otherwise shown as text
+            Value::Interval { .. } => Some(ColumnType::Varchar(Some(self.to_string().len()))),
+            Value::Null => None,
+        }
+    }
+
+    /// Convert the value so it can be stored in a column of the given type
+    pub fn coerce_to(&self, data_type: &ColumnType) -> Result<Value, QueryError> {
+        let coerced = match (self, data_type) {
+            (Value::Null, _) => Some(Value::Null),
+            (Value::Integer(_), ColumnType::Integer) => Some(self.clone()),
+            (Value::Integer(num), ColumnType::Float) => Some(Value::Float(*num as f64)),
+            (Value::Float(_), ColumnType::Float) => Some(self.clone()),
+            (Value::Integer(num) | Value::Timestamp(num), ColumnType::Timestamp) => Some(Value::Timestamp(*num)),
+            (Value::Boolean(_), ColumnType::Boolean) => Some(self.clone()),
+            (Value::Text(text), ColumnType::Varchar(max_len)) => {
+                if let Some(max_len) = max_len.filter(|&max_len| text.chars().count() > max_len) {
+                    return Err(QueryError::TypeMismatch(format!(
+                        "Value '{}' exceeds column length of {}", text, max_len
+                    )));
+                }
+                Some(self.clone())
+            }
+            // String literals are accepted for any type if they parse, as in INSERT
+            (Value::Text(text), _) => match Value::from_column(Some(text), data_type) {
+                Value::Text(_) => None,
+                parsed => Some(parsed),
+            },
+            _ => None,
+        };
+
+        coerced.ok_or_else(|| QueryError::TypeMismatch(format!(
+            "Cannot store value '{}' in column of type {}", self, data_type
+        )))
+    }
+
+    /// Convert the value to another type, as CAST does
+    /// Floats round to the nearest integer and text is cut to the VARCHAR length
+    pub fn cast_to(&self, data_type: &ColumnType) -> Result<Value, QueryError> {
+        let invalid = || QueryError::InvalidValue(format!("Cannot cast '{}' to {}", self, data_type));
+
+        match (self, data_type) {
+            (Value::Null, _) => Ok(Value::Null),
+            (_, ColumnType::Varchar(None)) => Ok(Value::Text(self.to_string())),
+            (_, ColumnType::Varchar(Some(max_len))) => Ok(Value::Text(self.to_string().chars().take(*max_len).collect())),
+            (Value::Integer(num) | Value::Timestamp(num), ColumnType::Integer) => Ok(Value::Integer(*num)),
+            (Value::Float(num), ColumnType::Integer) => {
+                let rounded = num.round();
+                if rounded.is_finite() && rounded >= i64::MIN as f64 && rounded <= i64::MAX as f64 {
+                    Ok(Value::Integer(rounded as i64))
+                } else {
+                    Err(invalid())
+                }
+            }
+            (Value::Boolean(b), ColumnType::Integer) => Ok(Value::Integer(i64::from(*b))),
+            (Value::Text(text), ColumnType::Integer) => match text.trim().parse::<i64>() {
+                Ok(num) => Ok(Value::Integer(num)),
+                Err(_) => text.trim().parse::<f64>().map_err(|_| invalid())
+                    .and_then(|num| Value::Float(num).cast_to(data_type)),
+            },
+            (Value::Boolean(b), ColumnType::Float) => Ok(Value::Float(if *b { 1.0 } else { 0.0 })),
+            (Value::Text(text), ColumnType::Float) => text.trim().parse::<f64>().map(Value::Float).map_err(|_| invalid()),
+            (_, ColumnType::Float) => self.as_f64().map(Value::Float).ok_or_else(invalid),
+            (Value::Boolean(_), ColumnType::Boolean) => Ok(self.clone()),
+            (Value::Integer(num), ColumnType::Boolean) => Ok(Value::Boolean(*num != 0)),
+            (Value::Text(_), ColumnType::Boolean) => self.as_bool().map(Value::Boolean).map_err(|_| invalid()),
+            (Value::Integer(num) | Value::Timestamp(num), ColumnType::Timestamp) => Ok(Value::Timestamp(*num)),
+            (Value::Text(text), ColumnType::Timestamp) => parse_timestamp(text).map(Value::Timestamp).ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Apply an arithmetic operator (+ - * / %) to two values
+    pub fn arithmetic(&self, operator: &str, other: &Value) -> Result<Value, QueryError> {
+        let overflow = || QueryError::InvalidValue(format!("Integer overflow in {} {} {}", self, operator, other));
+
+        match (self, other) {
+            // Any arithmetic involving NULL is NULL
+            (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+            (Value::Integer(a), Value::Integer(b)) => {
+                if (operator == "/" || operator == "%") && *b == 0 {
+                    return Err(QueryError::InvalidValue("Division by zero".to_string()));
+                }
+                let result = match operator {
+                    "+" => a.checked_add(*b),
+                    "-" => a.checked_sub(*b),
+                    "*" => a.checked_mul(*b),
+                    "/" => a.checked_div(*b),
+                    "%" => a.checked_rem(*b),
+                    _ => return Err(QueryError::SyntaxError(format!("Invalid operator: {}", operator))),
+                };
+                result.map(Value::Integer).ok_or_else(overflow)
+            }
+            // Shifting a timestamp by a number of seconds keeps it a timestamp
+            (Value::Timestamp(ts), Value::Integer(secs)) if operator == "+" || operator == "-" => {
+                let result = if operator == "+" { ts.checked_add(*secs) } else { ts.checked_sub(*secs) };
+                result.map(Value::Timestamp).ok_or_else(overflow)
+            }
+            (Value::Timestamp(a), Value::Timestamp(b)) if operator == "-" => {
+                a.checked_sub(*b).map(Value::Integer).ok_or_else(overflow)
+            }
+            (Value::Timestamp(ts), Value::Interval { months, seconds }) if operator == "+" || operator == "-" => {
+                let (months, seconds) = if operator == "+" { (*months, *seconds) } else { (-months, -seconds) };
+                Self::shift_timestamp(*ts, months, seconds).map(Value::Timestamp)
+                    .ok_or_else(|| QueryError::InvalidValue(format!("Timestamp out of range in {} {} {}", self, operator, other)))
+            }
+            (Value::Interval { .. }, Value::Timestamp(_)) if operator == "+" => other.arithmetic(operator, self),
+            // A date written as text, e.g. '2024-05-01' + INTERVAL '1 day'
+            (Value::Text(text), Value::Interval { .. }) if operator == "+" || operator == "-" => match parse_timestamp(text) {
+                Some(ts) => Value::Timestamp(ts).arithmetic(operator, other),
+                None => Err(QueryError::TypeMismatch(format!("Cannot apply '{}' to {} and an interval", operator, self))),
+            },
+            (Value::Interval { months: a_months, seconds: a_seconds }, Value::Interval { months: b_months, seconds: b_seconds })
+                if operator == "+" || operator == "-" =>
+            {
+                let (b_months, b_seconds) = if operator == "+" { (*b_months, *b_seconds) } else { (-b_months, -b_seconds) };
+                match (a_months.checked_add(b_months), a_seconds.checked_add(b_seconds)) {
+                    (Some(months), Some(seconds)) => Ok(Value::Interval { months, seconds }),
+                    _ => Err(overflow()),
+                }
+            }
+            (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_)) => {
+                let a = self.as_f64().unwrap_or_default();
+                let b = other.as_f64().unwrap_or_default();
+                if (operator == "/" || operator == "%") && b == 0.0 {
+                    return Err(QueryError::InvalidValue("Division by zero".to_string()));
+                }
+                let result = match operator {
+                    "+" => a + b,
+                    "-" => a - b,
+                    "*" => a * b,
+                    "/" => a / b,
+                    "%" => a % b,
+                    _ => return Err(QueryError::SyntaxError(format!("Invalid operator: {}", operator))),
+                };
+                Ok(Value::Float(result))
+            }
+            _ => Err(QueryError::TypeMismatch(format!(
+                "Cannot apply '{}' to {} and {}", operator, self, other
+            ))),
+        }
+    }
+
+    /// Compare two values, numerically when both sides are numeric
+    /// NULL sorts after every other value
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Null, Value::Null) => Some(Ordering::Equal),
+            (Value::Null, _) => Some(Ordering::Greater),
+            (_, Value::Null) => Some(Ordering::Less),
+            (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+            (Value::Timestamp(a), Value::Timestamp(b)) => Some(a.cmp(b)),
+            // Dates written as text compare by the time they name
+            (Value::Timestamp(a), Value::Text(text)) => parse_timestamp(text).map(|b| a.cmp(&b))
+                .or_else(|| Some(self.to_string().cmp(text))),
+            (Value::Text(_), Value::Timestamp(_)) => other.compare(self).map(Ordering::reverse),
+            _ => match (self.as_f64(), other.as_f64()) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                _ => Some(self.to_string().cmp(&other.to_string())),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Integer(num) => write!(f, "{}", num),
+            Value::Float(num) => write!(f, "{}", num),
+            Value::Text(text) => write!(f, "{}", text),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Timestamp(ts) => write!(f, "{}", format_timestamp(*ts)),
+            Value::Interval { months, seconds } => {
+                let mut parts = Vec::new();
+                let (years, months) = (months / 12, months % 12);
+                for (amount, unit) in [(years as i64, "year"), (months as i64, "month")] {
+                    if amount != 0 {
+                        parts.push(format!("{} {}{}", amount, unit, if amount.abs() == 1 { "" } else { "s" }));
+                    }
+                }
+                let mut remaining = *seconds;
+                for (unit, unit_seconds) in INTERVAL_UNITS.iter().skip(1) {
+                    let amount = remaining / unit_seconds;
+                    remaining %= unit_seconds;
+                    if amount != 0 {
+                        parts.push(format!("{} {}{}", amount, unit, if amount.abs() == 1 { "" } else { "s" }));
+                    }
+                }
+                if parts.is_empty() {
+                    parts.push("0 seconds".to_string());
+                }
+                write!(f, "{}", parts.join(" "))
+            }
+            Value::Null => write!(f, "{}", NULL_TEXT),
+        }
+    }
+}
@@ -9,6 +9,12 @@ pub struct Block {
     records: Vec<Record>
 }
 
+impl Default for Block {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Block{
     pub fn new() -> Self {
         Self {
@@ -17,9 +23,6 @@ impl Block{
     }
     
     pub fn insert(&mut self, record: Record) -> bool {
-        if self.get(record.id).is_some() {
-            false;
-        }
         self.records.push(record);
         true
     }
@@ -64,7 +67,7 @@ impl Block{
     
     pub fn save_to_disk(&self, filename: &str) -> io::Result<()> {
         let encode = bincode::serialize(&self.records)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
         let mut file = OpenOptions::new()
             .write(true)
             .create(true)
@@ -79,7 +82,7 @@ impl Block{
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)?;
         let records: Vec<Record> = bincode::deserialize(&buffer)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
         Ok(Self{records})
     }
 
@@ -1,14 +1,51 @@
 use super::{Record, MemTable, WriteLog, SSTable, LogEntry};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::io;
 
+/// What the statements of a transaction read: the versions committed up to `snapshot`,
+/// and the writes `transaction` has made so far
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadView {
+    pub transaction: u64,
+    pub snapshot: u64,
+}
+
+/// A write buffered until its transaction commits
+#[derive(Debug)]
+struct PendingWrite {
+    data: Option<Vec<u8>>, // None for a delete
+    snapshot: u64,         // snapshot the record was first written under
+}
+
+/// A committed version of a record: the sequence number of its commit, and its data or None once deleted
+type Version = (u64, Option<Vec<u8>>);
+
 /// Simple LSM Tree Storage Engine
+///
+/// Under a `ReadView` writes are buffered per transaction and reads see a snapshot:
+/// records changed by later commits are read from the older versions kept in memory,
+/// until no snapshot needs them and compaction drops them.
+///
+/// Only the latest version of a record is ever written to the memtable and SSTables, so
+/// snapshot reads are served from the in-memory versions alone. Flushes and SSTable merges
+/// leave those untouched, and a restart loses them together with the transactions that
+/// could read them.
+///
+/// Stored records carry the sequence number of the commit that wrote them, and deletes are
+/// written as tombstones, which compaction drops once no older copy of the record is left.
 pub struct LSMEngine {
     memtable: MemTable,
     writelog: WriteLog,
     sstables: Vec<SSTable>,
     data_dir: String,
     next_sstable_id: u64,
+    view: Option<ReadView>,
+    pending: HashMap<u64, BTreeMap<u64, PendingWrite>>, // transaction -> record id -> write
+    versions: HashMap<u64, Vec<Version>>,               // record id -> versions, oldest first, in memory only
+    readers: HashSet<u64>,                              // transactions that read this table
+    last_commit: u64,                                   // sequence of the last commit that wrote here
+    horizon: u64,                                       // oldest snapshot still in use
 }
 
 impl LSMEngine {
@@ -26,6 +63,12 @@ impl LSMEngine {
             sstables: Vec::new(),
             data_dir: data_dir.to_string(),
             next_sstable_id: 1,
+            view: None,
+            pending: HashMap::new(),
+            versions: HashMap::new(),
+            readers: HashSet::new(),
+            last_commit: 0,
+            horizon: 0,
         };
         
         // Load existing SSTables
@@ -33,26 +76,35 @@ impl LSMEngine {
         
         // Replay write log
         engine.replay_write_log()?;
+
+        engine.last_commit = engine.latest_sequence();
         
         Ok(engine)
     }
 
     /// Insert a record
     pub fn insert(&mut self, record: Record) -> io::Result<()> {
+        if let Some(view) = self.view {
+            self.buffer(view, record.id, Some(record.data));
+            return Ok(());
+        }
+
         // Log the operation first (WAL)
+        let record = record.with_sequence(self.last_commit);
         self.writelog.log_insert(&record)?;
         
-        // Try to insert into memtable
-        if !self.memtable.insert(record.clone()) {
+        self.put(record)
+    }
+
+    /// Put a version of a record, or its tombstone, in the memtable, flushing it first when full
+    fn put(&mut self, record: Record) -> io::Result<()> {
+        if !self.memtable.put(record.clone()) {
             // Memtable is full, flush it to disk
             self.flush_memtable()?;
             
             // Now insert into the new empty memtable
-            if !self.memtable.insert(record) {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Failed to insert after flush"
-                ));
+            if !self.memtable.put(record) {
+                return Err(io::Error::other("Failed to write record after flush"));
             }
         }
         
@@ -61,60 +113,213 @@ impl LSMEngine {
 
     /// Get a record by ID
     pub fn get(&mut self, id: u64) -> io::Result<Option<Record>> {
+        if let Some(view) = self.view {
+            self.readers.insert(view.transaction);
+            // Writes of the transaction come first, then the version its snapshot sees
+            let write = self.pending.get(&view.transaction).and_then(|writes| writes.get(&id));
+            if let Some(data) = write.map(|write| &write.data).or_else(|| self.visible_version(id, view.snapshot)) {
+                return Ok(data.clone().map(|data| Record::new(id, data)));
+            }
+        }
+        self.get_latest(id)
+    }
+
+    /// The committed version of a record a snapshot sees, if the record changed since the engine started
+    fn visible_version(&self, id: u64, snapshot: u64) -> Option<&Option<Vec<u8>>> {
+        self.versions.get(&id)?
+            .iter()
+            .rev()
+            .find(|(sequence, _)| *sequence <= snapshot)
+            .map(|(_, data)| data)
+    }
+
+    /// Get the latest committed version of a record
+    /// The first copy found is the latest; a tombstone means the record was deleted
+    fn get_latest(&mut self, id: u64) -> io::Result<Option<Record>> {
         // First check memtable (most recent data)
         if let Some(record) = self.memtable.get(id) {
-            return Ok(Some(record.clone()));
+            return Ok(Some(record.clone()).filter(|record| !record.deleted));
         }
         
         // Then check SSTables (newest to oldest)
         for sstable in &mut self.sstables {
             if let Some(record) = sstable.get(id)? {
-                return Ok(Some(record.clone()));
+                return Ok(Some(record.clone()).filter(|record| !record.deleted));
             }
         }
         
         Ok(None)
     }
 
+    /// Get the records with the given IDs, in that order, skipping IDs with no record
+    pub fn get_records(&mut self, ids: &[u64]) -> io::Result<Vec<Record>> {
+        let mut records = Vec::with_capacity(ids.len());
+        for &id in ids {
+            if let Some(record) = self.get(id)? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
     /// Update a record
     pub fn update(&mut self, id: u64, new_data: Vec<u8>) -> io::Result<bool> {
-        // Log the operation first
-        self.writelog.log_update(id, &new_data)?;
-        
-        // Try to update in memtable first
-        if self.memtable.update(id, new_data.clone()) {
+        if let Some(view) = self.view {
+            self.buffer(view, id, Some(new_data));
             return Ok(true);
         }
+        self.store(id, new_data, self.last_commit)
+    }
+
+    /// Write the latest version of a record, as the commit with the given sequence number
+    fn store(&mut self, id: u64, new_data: Vec<u8>, sequence: u64) -> io::Result<bool> {
+        // Log the operation first
+        self.writelog.log_update(id, &new_data, sequence)?;
         
-        // If not in memtable, insert as new record (LSM semantics)
-        let record = Record::new(id, new_data);
-        if !self.memtable.insert(record.clone()) {
-            // Memtable full, flush and try again
-            self.flush_memtable()?;
-            if !self.memtable.insert(record) {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    "Failed to insert update after flush"
-                ));
-            }
-        }
+        // Replaces the version in the memtable, or shadows the ones in SSTables (LSM semantics)
+        self.put(Record::new(id, new_data).with_sequence(sequence))?;
         
         Ok(true)
     }
 
     /// Delete a record
     pub fn delete(&mut self, id: u64) -> io::Result<bool> {
+        if let Some(view) = self.view {
+            let existed = self.get(id)?.is_some();
+            self.buffer(view, id, None);
+            return Ok(existed);
+        }
+        self.erase(id, self.last_commit)
+    }
+
+    /// Delete the latest version of a record, as the commit with the given sequence number
+    /// A tombstone is written in its place, hiding the copies older SSTables still hold
+    fn erase(&mut self, id: u64, sequence: u64) -> io::Result<bool> {
+        let existed = self.get_latest(id)?.is_some();
+
         // Log the operation first
-        self.writelog.log_delete(id)?;
-        
-        // Try to delete from memtable
-        let deleted_from_mem = self.memtable.delete(id);
+        self.writelog.log_delete(id, sequence)?;
         
-        // In LSM trees, we typically use tombstones for deletions
-        // For simplicity, we'll just remove from memtable if present
-        // In a real implementation, you'd insert a tombstone record
+        self.put(Record::tombstone(id, sequence))?;
         
-        Ok(deleted_from_mem)
+        Ok(existed)
+    }
+
+    /// Read and write as the given transaction from now on, or directly with None
+    pub fn set_view(&mut self, view: Option<ReadView>) {
+        self.view = view;
+    }
+
+    fn buffer(&mut self, view: ReadView, id: u64, data: Option<Vec<u8>>) {
+        self.pending.entry(view.transaction)
+            .or_default()
+            .entry(id)
+            .and_modify(|write| write.data = data.clone())
+            .or_insert(PendingWrite { data, snapshot: view.snapshot });
+    }
+
+    /// Whether reads under the current view see exactly the latest committed records,
+    /// which is what indexes hold
+    pub fn reads_latest(&self) -> bool {
+        self.view.is_none_or(|view| !self.pending.contains_key(&view.transaction) && self.last_commit <= view.snapshot)
+    }
+
+    /// Note that the transaction of the current view read this table without reading its records
+    pub fn record_read(&mut self) {
+        if let Some(view) = self.view {
+            self.readers.insert(view.transaction);
+        }
+    }
+
+    /// Writes buffered by a transaction, by record ID, with None for a delete
+    pub fn pending_writes(&self, transaction: u64) -> Vec<(u64, Option<&[u8]>)> {
+        self.pending.get(&transaction)
+            .into_iter()
+            .flatten()
+            .map(|(id, write)| (*id, write.data.as_deref()))
+            .collect()
+    }
+
+    /// A record the transaction wrote that another transaction committed a change to
+    /// after the snapshot the write was made under
+    pub fn write_conflict(&self, transaction: u64) -> Option<u64> {
+        self.pending.get(&transaction)?
+            .iter()
+            .find(|(id, write)| {
+                self.versions.get(id)
+                    .and_then(|versions| versions.last())
+                    .is_some_and(|(sequence, _)| *sequence > write.snapshot)
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// Whether a commit after `snapshot` wrote to this table
+    pub fn changed_since(&self, snapshot: u64) -> bool {
+        self.last_commit > snapshot
+    }
+
+    /// Whether the transaction read this table
+    pub fn was_read_by(&self, transaction: u64) -> bool {
+        self.readers.contains(&transaction)
+    }
+
+    /// Apply the writes buffered by a transaction as the commit with the given sequence number,
+    /// keeping the versions they replace for older snapshots
+    pub fn commit_transaction(&mut self, transaction: u64, sequence: u64) -> io::Result<()> {
+        self.readers.remove(&transaction);
+        let Some(writes) = self.pending.remove(&transaction) else {
+            return Ok(());
+        };
+        for (id, write) in writes {
+            if !self.versions.contains_key(&id) {
+                let before = self.get_latest(id)?.map(|record| record.data);
+                self.versions.insert(id, vec![(0, before)]);
+            }
+            match &write.data {
+                Some(data) => {
+                    self.store(id, data.clone(), sequence)?;
+                }
+                None => {
+                    self.erase(id, sequence)?;
+                }
+            }
+            self.versions.entry(id).or_default().push((sequence, write.data));
+        }
+        self.last_commit = sequence;
+        Ok(())
+    }
+
+    /// Discard the writes buffered by a transaction
+    pub fn rollback_transaction(&mut self, transaction: u64) {
+        self.pending.remove(&transaction);
+        self.readers.remove(&transaction);
+    }
+
+    /// Let compaction drop the versions no snapshot at or after `oldest_snapshot` reads
+    pub fn set_horizon(&mut self, oldest_snapshot: u64) {
+        self.horizon = oldest_snapshot;
+    }
+
+    /// Drop the versions older than the one the oldest snapshot sees,
+    /// and forget records whose latest version is the only one left
+    fn collect_versions(&mut self) {
+        let horizon = self.horizon;
+        self.versions.retain(|_, versions| {
+            let oldest_needed = versions.iter().rposition(|(sequence, _)| *sequence <= horizon).unwrap_or(0);
+            versions.drain(..oldest_needed);
+            versions.len() > 1
+        });
+    }
+
+    /// Flush the memtable and merge every SSTable into one,
+    /// and drop the in-memory versions no snapshot needs
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.flush_memtable()?;
+        self.collect_versions();
+        while self.sstables.len() > 1 {
+            self.compact_sstables()?;
+        }
+        Ok(())
     }
 
     /// Flush memtable to disk as SSTable
@@ -148,6 +353,7 @@ impl LSMEngine {
 
     /// Simple compaction: merge oldest SSTables
     fn compact_sstables(&mut self) -> io::Result<()> {
+        self.collect_versions();
         if self.sstables.len() < 2 {
             return Ok(());
         }
@@ -156,9 +362,9 @@ impl LSMEngine {
         let mut sstable1 = self.sstables.pop().unwrap();
         let mut sstable2 = self.sstables.pop().unwrap();
         
-        // Merge them
+        // Merge them; nothing older is left for their tombstones to hide, so those are dropped
         let merged_path = format!("{}/sstable_{}.dat", self.data_dir, self.next_sstable_id);
-        let merged_sstable = sstable1.merge_with(&mut sstable2, &merged_path)?;
+        let merged_sstable = sstable1.merge_with(&mut sstable2, &merged_path, true)?;
         
         // Add merged SSTable back
         self.sstables.push(merged_sstable);
@@ -196,7 +402,7 @@ impl LSMEngine {
         // Load SSTables
         for path in sstable_files {
             if let Some(path_str) = path.to_str() {
-                let sstable = SSTable::new_lazy(path_str);
+                let sstable = SSTable::new_lazy(path_str)?;
                 self.sstables.push(sstable);
                 
                 // Update next_sstable_id
@@ -218,33 +424,29 @@ impl LSMEngine {
         let entries = self.writelog.replay()?;
         
         for entry in entries {
+            // If memtable is full during replay, put flushes it and continues
             match entry {
-                LogEntry::Insert(record) => {
-                    if !self.memtable.insert(record.clone()) {
-                        // If memtable is full during replay, flush and continue
-                        self.flush_memtable()?;
-                        self.memtable.insert(record);
-                    }
-                }
-                LogEntry::Update { id, data } => {
-                    if !self.memtable.update(id, data.clone()) {
-                        // Insert as new record if not found
-                        let record = Record::new(id, data);
-                        if !self.memtable.insert(record.clone()) {
-                            self.flush_memtable()?;
-                            self.memtable.insert(record);
-                        }
-                    }
-                }
-                LogEntry::Delete { id } => {
-                    self.memtable.delete(id);
-                }
+                LogEntry::Insert(record) => self.put(record)?,
+                LogEntry::Update { id, data, sequence } => self.put(Record::new(id, data).with_sequence(sequence))?,
+                LogEntry::Delete { id, sequence } => self.put(Record::tombstone(id, sequence))?,
             }
         }
         
         Ok(())
     }
 
+    /// Sequence number of the latest commit whose writes the memtable and SSTables hold
+    /// SSTables keep theirs in their header, so none of them is loaded for it
+    fn latest_sequence(&self) -> u64 {
+        let memtable = self.memtable.get_sorted_records().iter().map(|record| record.sequence).max().unwrap_or(0);
+        self.sstables.iter().map(SSTable::max_sequence).fold(memtable, u64::max)
+    }
+
+    /// Sequence number of the last commit that wrote to this table
+    pub fn last_commit(&self) -> u64 {
+        self.last_commit
+    }
+
     /// Force flush memtable (useful for shutdown)
     pub fn flush(&mut self) -> io::Result<()> {
         self.flush_memtable()
@@ -262,14 +464,13 @@ impl LSMEngine {
             memtable_size: self.memtable.size(),
             sstable_count: self.sstables.len(),
             total_records,
+            old_versions: self.versions.values().map(|versions| versions.len() - 1).sum(),
         })
     }
 
     /// Get all records from the LSM engine (memtable + SSTables)
     /// Returns the latest version of each record (by ID)
     pub fn get_all_records(&mut self) -> io::Result<Vec<Record>> {
-        use std::collections::HashMap;
-        
         let mut all_records: HashMap<u64, Record> = HashMap::new();
         
         // First, add all records from SSTables (oldest to newest)
@@ -285,6 +486,28 @@ impl LSMEngine {
         for record in self.memtable.get_sorted_records() {
             all_records.insert(record.id, record.clone());
         }
+
+        // Tombstones only hide the older copies
+        all_records.retain(|_, record| !record.deleted);
+        
+        // Under a view, records changed after its snapshot are read as of the snapshot,
+        // and the writes of its transaction come last
+        if let Some(view) = self.view {
+            self.readers.insert(view.transaction);
+            let visible = self.versions.keys().map(|id| (*id, self.visible_version(*id, view.snapshot)));
+            let written = self.pending.get(&view.transaction).into_iter().flatten().map(|(id, write)| (*id, Some(&write.data)));
+            for (id, data) in visible.chain(written) {
+                match data {
+                    Some(Some(data)) => {
+                        all_records.insert(id, Record::new(id, data.clone()));
+                    }
+                    Some(None) => {
+                        all_records.remove(&id);
+                    }
+                    None => {}
+                }
+            }
+        }
         
         // Convert to vector and sort by ID for consistent ordering
         let mut result: Vec<Record> = all_records.into_values().collect();
@@ -299,6 +522,7 @@ pub struct EngineStats {
     pub memtable_size: usize,
     pub sstable_count: usize,
     pub total_records: usize,
+    pub old_versions: usize, // versions kept for snapshots that may still read them
 }
 
 #[cfg(test)]
@@ -371,4 +595,94 @@ mod tests {
             assert_eq!(engine.get(2).unwrap().unwrap().data, vec![2]);
         }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_lsm_engine_tombstones() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().to_str().unwrap();
+        let mut engine = LSMEngine::new(data_dir, 5).unwrap();
+        engine.insert(Record::new(1, vec![1])).unwrap();
+        engine.insert(Record::new(2, vec![2])).unwrap();
+        engine.flush().unwrap();
+
+        // A delete of a flushed record leaves a tombstone over the copy in the SSTable
+        assert!(engine.delete(1).unwrap());
+        assert!(!engine.delete(3).unwrap());
+        assert!(engine.get(1).unwrap().is_none());
+        drop(engine);
+        let mut engine = LSMEngine::new(data_dir, 5).unwrap();
+        assert!(engine.get(1).unwrap().is_none());
+        engine.flush().unwrap();
+        assert_eq!(engine.get_all_records().unwrap(), vec![Record::new(2, vec![2])]);
+
+        // Compaction drops the tombstone together with the copies it hid
+        engine.compact().unwrap();
+        let stats = engine.stats().unwrap();
+        assert_eq!((stats.sstable_count, stats.total_records), (1, 1));
+        assert!(engine.get(1).unwrap().is_none());
+        drop(engine);
+
+        let mut engine = LSMEngine::new(data_dir, 5).unwrap();
+        assert!(engine.get(1).unwrap().is_none());
+        engine.insert(Record::new(1, vec![11])).unwrap();
+        assert_eq!(engine.get(1).unwrap().unwrap().data, vec![11]);
+    }
+
+    #[test]
+    fn test_lsm_engine_transactions() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().to_str().unwrap();
+        let mut engine = LSMEngine::new(data_dir, 5).unwrap();
+        engine.insert(Record::new(1, vec![1])).unwrap();
+        engine.insert(Record::new(2, vec![2])).unwrap();
+        let ids = |engine: &mut LSMEngine| engine.get_all_records().unwrap().iter().map(|record| record.id).collect::<Vec<_>>();
+
+        // Buffered writes are visible to their own transaction only, and never reach the write log before the commit
+        engine.set_view(Some(ReadView { transaction: 1, snapshot: 0 }));
+        engine.update(1, vec![10]).unwrap();
+        assert!(engine.delete(2).unwrap());
+        engine.insert(Record::new(3, vec![3])).unwrap();
+        assert_eq!(engine.get(1).unwrap().unwrap().data, vec![10]);
+        assert_eq!(ids(&mut engine), vec![1, 3]);
+        assert!(!engine.reads_latest());
+        engine.set_view(Some(ReadView { transaction: 2, snapshot: 0 }));
+        assert_eq!(engine.get(1).unwrap().unwrap().data, vec![1]);
+        assert_eq!(ids(&mut engine), vec![1, 2]);
+        engine.set_view(None);
+        assert_eq!(engine.pending_writes(1), vec![(1, Some(&[10u8][..])), (2, None), (3, Some(&[3u8][..]))]);
+        assert_eq!(LSMEngine::new(data_dir, 5).unwrap().get_all_records().unwrap().len(), 2);
+
+        // Transaction 2 keeps reading its snapshot after transaction 1 commits, flushed or not
+        engine.commit_transaction(1, 1).unwrap();
+        engine.flush().unwrap();
+        assert_eq!(ids(&mut engine), vec![1, 3]);
+        engine.set_view(Some(ReadView { transaction: 2, snapshot: 0 }));
+        assert_eq!(ids(&mut engine), vec![1, 2]);
+        assert_eq!(engine.get(1).unwrap().unwrap().data, vec![1]);
+        assert!(engine.was_read_by(2) && engine.changed_since(0) && !engine.reads_latest());
+
+        // Its write to a record committed since its snapshot loses to the first committer
+        engine.update(3, vec![30]).unwrap();
+        engine.update(1, vec![11]).unwrap();
+        engine.set_view(None);
+        assert_eq!(engine.write_conflict(2), Some(1));
+        engine.rollback_transaction(2);
+        assert!(engine.pending_writes(2).is_empty());
+
+        // Versions stay until compaction runs with no snapshot older than the commit
+        engine.compact().unwrap();
+        assert_eq!(engine.stats().unwrap().old_versions, 3);
+        engine.set_horizon(1);
+        engine.compact().unwrap();
+        assert_eq!(engine.stats().unwrap().old_versions, 0);
+        engine.set_view(Some(ReadView { transaction: 3, snapshot: 1 }));
+        assert_eq!(engine.get(1).unwrap().unwrap().data, vec![10]);
+        assert!(engine.reads_latest());
+        engine.set_view(None);
+        drop(engine);
+
+        let mut engine = LSMEngine::new(data_dir, 5).unwrap();
+        assert_eq!(engine.get(1).unwrap().unwrap().data, vec![10]);
+        assert_eq!(ids(&mut engine), vec![1, 3]);
+    }
+}
@@ -30,6 +30,15 @@ impl MemTable {
         success
     }
 
+    /// Insert a record, or replace the one with its ID; fails only when a new ID doesn't fit
+    pub fn put(&mut self, record: Record) -> bool {
+        if self.index.contains_key(&record.id) {
+            self.data.delete(record.id);
+            return self.data.insert(record);
+        }
+        self.insert(record)
+    }
+
     pub fn get(&self, id: u64) -> Option<&Record> {
         self.data.get(id)
     }
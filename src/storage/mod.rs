@@ -13,4 +13,4 @@ pub use table::Table;
 pub use memtable::MemTable;
 pub use writelog::{WriteLog, LogEntry};
 pub use sstable::SSTable;
-pub use lsm_engine::{LSMEngine, EngineStats};
\ No newline at end of file
+pub use lsm_engine::{LSMEngine, EngineStats, ReadView};
\ No newline at end of file
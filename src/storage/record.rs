@@ -3,11 +3,24 @@ use serde::{Serialize, Deserialize};
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Record {
     pub id: u64,
-    pub data: Vec<u8>
+    pub data: Vec<u8>,
+    pub sequence: u64, // commit that wrote this version
+    pub deleted: bool, // a tombstone, hiding older versions of the record
 }
 
 impl Record {
     pub fn new(id:u64, data: Vec<u8>) -> Self{
-        Self {id, data }
+        Self {id, data, sequence: 0, deleted: false }
+    }
+
+    /// A tombstone marking the record deleted by the commit with the given sequence number
+    pub fn tombstone(id: u64, sequence: u64) -> Self {
+        Self { id, data: Vec::new(), sequence, deleted: true }
+    }
+
+    /// The record as written by the commit with the given sequence number
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
     }
 }
\ No newline at end of file
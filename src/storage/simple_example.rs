@@ -93,8 +93,8 @@ pub fn component_example() -> io::Result<()> {
     let record = Record::new(100, b"logged data".to_vec());
     
     writelog.log_insert(&record)?;
-    writelog.log_update(100, b"updated logged data")?;
-    writelog.log_delete(100)?;
+    writelog.log_update(100, b"updated logged data", 0)?;
+    writelog.log_delete(100, 0)?;
     
     let entries = writelog.replay()?;
     println!("   Replayed {} log entries", entries.len());
@@ -1,11 +1,28 @@
 use super::{Block, Record};
-use std::io;
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::fs::{self, File};
+use std::io::{self, Read};
+
+/// Starts every SSTable file, followed by the highest sequence number of its records
+/// and then the records. Files without it were written before records carried a
+/// sequence number and a tombstone flag, and hold only their IDs and data.
+const MAGIC: &[u8; 8] = b"SSTABLE2";
+const HEADER_LEN: usize = MAGIC.len() + 8;
+
+/// A record as stored in SSTable files without a header
+#[derive(Deserialize)]
+struct LegacyRecord {
+    id: u64,
+    data: Vec<u8>,
+}
 
 /// Sorted String Table - immutable sorted storage on disk
 pub struct SSTable {
     block: Block,
     file_path: String,
     is_loaded: bool,
+    max_sequence: u64,
 }
 
 impl SSTable {
@@ -14,51 +31,79 @@ impl SSTable {
         // Ensure records are sorted by ID
         let mut sorted_records: Vec<_> = block.get_all().into_iter().cloned().collect();
         sorted_records.sort_by_key(|r| r.id);
-        
+        let max_sequence = sorted_records.iter().map(|r| r.sequence).max().unwrap_or(0);
+
+        // Save to disk
+        let mut data = MAGIC.to_vec();
+        data.extend(max_sequence.to_le_bytes());
+        data.extend(bincode::serialize(&sorted_records).map_err(io::Error::other)?);
+        fs::write(file_path, data)?;
+
         let mut sorted_block = Block::new();
         for record in sorted_records {
             sorted_block.insert(record);
         }
         
-        // Save to disk
-        sorted_block.save_to_disk(file_path)?;
-        
         Ok(Self {
             block: sorted_block,
             file_path: file_path.to_string(),
             is_loaded: true,
+            max_sequence,
         })
     }
 
     /// Load an existing SSTable from disk
     pub fn load_from_disk(file_path: &str) -> io::Result<Self> {
-        let block = Block::load_from_disk(file_path)?;
-        
-        Ok(Self {
-            block,
-            file_path: file_path.to_string(),
-            is_loaded: true,
-        })
+        let mut sstable = Self::new_lazy(file_path)?;
+        sstable.ensure_loaded()?;
+        Ok(sstable)
     }
 
-    /// Create an SSTable reference without loading data (lazy loading)
-    pub fn new_lazy(file_path: &str) -> Self {
-        Self {
+    /// Create an SSTable reference that only reads the file header (lazy loading)
+    pub fn new_lazy(file_path: &str) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        File::open(file_path)?.take(HEADER_LEN as u64).read_to_end(&mut header)?;
+        let max_sequence = match header.strip_prefix(MAGIC) {
+            Some(sequence) if sequence.len() == 8 => u64::from_le_bytes(sequence.try_into().unwrap()),
+            Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Truncated SSTable header in {}", file_path))),
+            // Records without a header were written before they carried a sequence
+            None => 0,
+        };
+
+        Ok(Self {
             block: Block::new(),
             file_path: file_path.to_string(),
             is_loaded: false,
-        }
+            max_sequence,
+        })
     }
 
     /// Ensure the SSTable is loaded in memory
     fn ensure_loaded(&mut self) -> io::Result<()> {
         if !self.is_loaded {
-            self.block = Block::load_from_disk(&self.file_path)?;
+            let data = fs::read(&self.file_path)?;
+            let records: Vec<Record> = match data.strip_prefix(MAGIC) {
+                Some(data) => bincode::deserialize(data.get(8..).unwrap_or_default()).map_err(io::Error::other)?,
+                None => bincode::deserialize::<Vec<LegacyRecord>>(&data)
+                    .map_err(io::Error::other)?
+                    .into_iter()
+                    .map(|record| Record::new(record.id, record.data))
+                    .collect(),
+            };
+            self.block = Block::new();
+            for record in records {
+                self.block.insert(record);
+            }
             self.is_loaded = true;
         }
         Ok(())
     }
 
+    /// The highest sequence number of the records, known without loading them
+    pub fn max_sequence(&self) -> u64 {
+        self.max_sequence
+    }
+
     /// Get a record by ID (binary search since records are sorted)
     pub fn get(&mut self, id: u64) -> io::Result<Option<&Record>> {
         self.ensure_loaded()?;
@@ -120,18 +165,23 @@ impl SSTable {
         Ok(records.binary_search_by_key(&id, |r| r.id).is_ok())
     }
 
-    /// Merge this SSTable with another to create a new SSTable
-    pub fn merge_with(&mut self, other: &mut SSTable, output_path: &str) -> io::Result<SSTable> {
+    /// Merge this SSTable with a newer one to create a new SSTable
+    /// With `drop_tombstones`, used when no older SSTable is left, deleted records are left out entirely
+    pub fn merge_with(&mut self, newer: &mut SSTable, output_path: &str, drop_tombstones: bool) -> io::Result<SSTable> {
         self.ensure_loaded()?;
-        other.ensure_loaded()?;
+        newer.ensure_loaded()?;
         
         let mut all_records = Vec::new();
+        all_records.extend(newer.block.get_all().iter().cloned());
         all_records.extend(self.block.get_all().iter().cloned());
-        all_records.extend(other.block.get_all().iter().cloned());
         
-        // Sort and deduplicate (keeping the latest version)
-        all_records.sort_by_key(|r| r.id);
+        // Sort and deduplicate, keeping the version of the latest commit,
+        // or of the newer SSTable for writes of the same commit
+        all_records.sort_by_key(|r| (r.id, Reverse(r.sequence)));
         all_records.dedup_by_key(|r| r.id);
+        if drop_tombstones {
+            all_records.retain(|r| !r.deleted);
+        }
         
         let mut merged_block = Block::new();
         for record in all_records {
@@ -193,6 +243,33 @@ mod tests {
         assert_eq!(range_records[2].id, 4);
     }
 
+    #[test]
+    fn test_sstable_merge() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = |name: &str| temp_dir.path().join(name).to_str().unwrap().to_string();
+
+        let mut older = Block::new();
+        older.insert(Record::new(1, vec![1]).with_sequence(1));
+        older.insert(Record::new(2, vec![2]).with_sequence(1));
+        older.insert(Record::new(3, vec![3]).with_sequence(1));
+        let mut newer = Block::new();
+        newer.insert(Record::new(1, vec![10]).with_sequence(2));
+        newer.insert(Record::tombstone(2, 2));
+        newer.insert(Record::new(3, vec![30]).with_sequence(1));
+        let mut older = SSTable::create_from_block(older, &path("older.dat")).unwrap();
+        let mut newer = SSTable::create_from_block(newer, &path("newer.dat")).unwrap();
+
+        // The latest version of each record wins, the newer SSTable breaking ties
+        let mut merged = older.merge_with(&mut newer, &path("merged.dat"), false).unwrap();
+        let data: Vec<_> = merged.get_all().unwrap().iter().map(|r| (r.id, r.data.clone(), r.deleted)).collect();
+        assert_eq!(data, vec![(1, vec![10], false), (2, vec![], true), (3, vec![30], false)]);
+
+        // Tombstones are only dropped when asked to
+        let mut merged = older.merge_with(&mut newer, &path("bottom.dat"), true).unwrap();
+        let ids: Vec<_> = merged.get_all().unwrap().iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+    }
+
     #[test]
     fn test_sstable_lazy_loading() {
         let temp_file = NamedTempFile::new().unwrap();
@@ -200,16 +277,40 @@ mod tests {
         
         // Create and save SSTable
         let mut block = Block::new();
-        block.insert(Record::new(1, vec![1]));
-        block.save_to_disk(file_path).unwrap();
+        block.insert(Record::new(1, vec![1]).with_sequence(4));
+        block.insert(Record::tombstone(2, 7));
+        SSTable::create_from_block(block, file_path).unwrap();
         
-        // Create lazy SSTable
-        let mut lazy_sstable = SSTable::new_lazy(file_path);
+        // Create lazy SSTable, which only reads the header
+        let mut lazy_sstable = SSTable::new_lazy(file_path).unwrap();
         assert!(!lazy_sstable.is_loaded);
+        assert_eq!(lazy_sstable.max_sequence(), 7);
         
         // Access should trigger loading
         let record = lazy_sstable.get(1).unwrap().unwrap();
         assert_eq!(record.data, vec![1]);
         assert!(lazy_sstable.is_loaded);
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_sstable_without_header() {
+        #[derive(serde::Serialize)]
+        struct LegacyRecord {
+            id: u64,
+            data: Vec<u8>,
+        }
+        let temp_file = NamedTempFile::new().unwrap();
+        let file_path = temp_file.path().to_str().unwrap();
+        let records = vec![LegacyRecord { id: 1, data: vec![1] }, LegacyRecord { id: 2, data: vec![2, 2] }];
+        std::fs::write(file_path, bincode::serialize(&records).unwrap()).unwrap();
+
+        // Files written before records carried a sequence hold live records of no particular commit
+        let mut sstable = SSTable::new_lazy(file_path).unwrap();
+        assert_eq!(sstable.max_sequence(), 0);
+        let loaded: Vec<_> = sstable.get_all().unwrap().into_iter().cloned().collect();
+        assert_eq!(loaded, vec![Record::new(1, vec![1]), Record::new(2, vec![2, 2])]);
+
+        std::fs::write(file_path, &MAGIC[..]).unwrap();
+        assert!(SSTable::new_lazy(file_path).is_err());
+    }
+}
@@ -19,6 +19,10 @@ impl Table {
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn add_block(&mut self, block: Block) {
         let block_index = self.blocks.len();
         for record in block.get_all() {
@@ -33,7 +37,7 @@ impl Table {
 
     pub fn save_to_disk(&self, filename: &str) -> io::Result<()> {
         let encoded = bincode::serialize(&self.blocks)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
         let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(filename)?;
         file.write_all(&encoded)?;
         Ok(())
@@ -45,7 +49,7 @@ impl Table {
         file.read_to_end(&mut buffer)?;
         
         let blocks: Vec<Block> = bincode::deserialize(&buffer)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(io::Error::other)?;
         
         let mut index = HashMap::new();
         for (i, block) in blocks.iter().enumerate() {
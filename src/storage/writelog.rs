@@ -23,28 +23,30 @@ impl WriteLog {
 
     pub fn log_insert(&mut self, record: &Record) -> io::Result<()> {
         use base64::{Engine as _, engine::general_purpose};
-        let log_entry = format!("INSERT,{},{}\n", 
+        let log_entry = format!("INSERT,{},{},{}\n", 
             record.id, 
-            general_purpose::STANDARD.encode(&record.data)
+            general_purpose::STANDARD.encode(&record.data),
+            record.sequence
         );
         self.log_file.write_all(log_entry.as_bytes())?;
         self.log_file.flush()?;
         Ok(())
     }
 
-    pub fn log_update(&mut self, id: u64, new_data: &[u8]) -> io::Result<()> {
+    pub fn log_update(&mut self, id: u64, new_data: &[u8], sequence: u64) -> io::Result<()> {
         use base64::{Engine as _, engine::general_purpose};
-        let log_entry = format!("UPDATE,{},{}\n", 
+        let log_entry = format!("UPDATE,{},{},{}\n", 
             id, 
-            general_purpose::STANDARD.encode(new_data)
+            general_purpose::STANDARD.encode(new_data),
+            sequence
         );
         self.log_file.write_all(log_entry.as_bytes())?;
         self.log_file.flush()?;
         Ok(())
     }
 
-    pub fn log_delete(&mut self, id: u64) -> io::Result<()> {
-        let log_entry = format!("DELETE,{}\n", id);
+    pub fn log_delete(&mut self, id: u64, sequence: u64) -> io::Result<()> {
+        let log_entry = format!("DELETE,{},{}\n", id, sequence);
         self.log_file.write_all(log_entry.as_bytes())?;
         self.log_file.flush()?;
         Ok(())
@@ -76,41 +78,44 @@ impl WriteLog {
     }
 }
 
+/// A logged write, with the sequence number of the commit that made it
 #[derive(Debug, Clone)]
 pub enum LogEntry {
     Insert(Record),
-    Update { id: u64, data: Vec<u8> },
-    Delete { id: u64 },
+    Update { id: u64, data: Vec<u8>, sequence: u64 },
+    Delete { id: u64, sequence: u64 },
 }
 
 impl LogEntry {
     fn parse(line: &str) -> Option<Self> {
         use base64::{Engine as _, engine::general_purpose};
         let parts: Vec<&str> = line.split(',').collect();
+        // Entries logged before writes carried a sequence number have none
+        let sequence = |index: usize| parts.get(index).map_or(Some(0), |sequence| sequence.parse().ok());
         
-        match parts.get(0).map(|s| *s)? {
+        match parts.first().copied()? {
             "INSERT" => {
-                if parts.len() == 3 {
+                if parts.len() == 3 || parts.len() == 4 {
                     let id = parts[1].parse().ok()?;
                     let data = general_purpose::STANDARD.decode(parts[2]).ok()?;
-                    Some(LogEntry::Insert(Record::new(id, data)))
+                    Some(LogEntry::Insert(Record::new(id, data).with_sequence(sequence(3)?)))
                 } else {
                     None
                 }
             }
             "UPDATE" => {
-                if parts.len() == 3 {
+                if parts.len() == 3 || parts.len() == 4 {
                     let id = parts[1].parse().ok()?;
                     let data = general_purpose::STANDARD.decode(parts[2]).ok()?;
-                    Some(LogEntry::Update { id, data })
+                    Some(LogEntry::Update { id, data, sequence: sequence(3)? })
                 } else {
                     None
                 }
             }
             "DELETE" => {
-                if parts.len() == 2 {
+                if parts.len() == 2 || parts.len() == 3 {
                     let id = parts[1].parse().ok()?;
-                    Some(LogEntry::Delete { id })
+                    Some(LogEntry::Delete { id, sequence: sequence(2)? })
                 } else {
                     None
                 }
@@ -135,8 +140,8 @@ mod tests {
             let record = Record::new(1, vec![1, 2, 3]);
             
             log.log_insert(&record).unwrap();
-            log.log_update(1, &[4, 5, 6]).unwrap();
-            log.log_delete(1).unwrap();
+            log.log_update(1, &[4, 5, 6], 2).unwrap();
+            log.log_delete(1, 3).unwrap();
         }
         
         // Test replay
@@ -151,5 +156,6 @@ mod tests {
             }
             _ => panic!("Expected insert"),
         }
+        assert!(matches!(entries[2], LogEntry::Delete { id: 1, sequence: 3 }));
     }
 } 
\ No newline at end of file
@@ -0,0 +1,27 @@
+use tempfile::TempDir;
+use crate::query::QueryEngine;
+use crate::query::value::NULL_TEXT;
+
+/// An engine keeping its tables in `temp_dir`, with `statements` already run
+pub fn setup_engine(temp_dir: &TempDir, statements: &[&str]) -> QueryEngine {
+    let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+    for statement in statements {
+        engine.execute(statement).unwrap();
+    }
+    engine
+}
+
+/// Rows returned by a query that is expected to succeed, with NULLs shown as NULL
+pub fn rows(engine: &mut QueryEngine, query: &str) -> Vec<Vec<String>> {
+    shown(&engine.execute(query).unwrap()
+        .into_select_result()
+        .expect("Expected rows")
+        .rows)
+}
+
+/// Result rows with NULLs shown as NULL, as the CLI prints them
+pub fn shown(rows: &[Vec<Option<String>>]) -> Vec<Vec<String>> {
+    rows.iter()
+        .map(|row| row.iter().map(|value| value.clone().unwrap_or_else(|| NULL_TEXT.to_string())).collect())
+        .collect()
+}
@@ -3,5 +3,20 @@ pub enum TransactionError {
     AlreadyInTransaction,
     NotInTransaction,
     TableNotFound(String),
+    SerializationFailure(String),
     ExecutionError(String),
-}
\ No newline at end of file
+}
+
+impl std::fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransactionError::AlreadyInTransaction => write!(f, "A transaction is already in progress"),
+            TransactionError::NotInTransaction => write!(f, "No transaction is in progress"),
+            TransactionError::TableNotFound(msg) => write!(f, "Table not found: {}", msg),
+            TransactionError::SerializationFailure(msg) => write!(f, "Could not serialize access: {}", msg),
+            TransactionError::ExecutionError(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
@@ -0,0 +1,124 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use base64::{Engine as _, engine::general_purpose};
+use super::error::TransactionError;
+
+/// A write of a committed transaction: the new data of a table record, or None for a delete
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionWrite {
+    pub table: String,
+    pub id: u64,
+    pub data: Option<Vec<u8>>,
+}
+
+/// Redo log of the transaction being committed
+/// A transaction is committed once its writes and the COMMIT line after them are on disk;
+/// until the writes have reached every table they can be replayed from here
+pub struct TransactionLog {
+    log_path: String,
+}
+
+fn log_error(action: &str, e: io::Error) -> TransactionError {
+    TransactionError::ExecutionError(format!("Failed to {} transaction log: {}", action, e))
+}
+
+impl TransactionLog {
+    pub fn new(log_path: &str) -> Self {
+        Self {
+            log_path: log_path.to_string(),
+        }
+    }
+
+    /// Write the writes of a transaction followed by its COMMIT line, with the commit's sequence number,
+    /// and sync them to disk
+    pub fn log_commit(&self, writes: &[TransactionWrite], sequence: u64) -> Result<(), TransactionError> {
+        let mut content = String::new();
+        for write in writes {
+            match &write.data {
+                Some(data) => content.push_str(&format!("WRITE,{},{},{}\n", write.table, write.id, general_purpose::STANDARD.encode(data))),
+                None => content.push_str(&format!("DELETE,{},{}\n", write.table, write.id)),
+            }
+        }
+        content.push_str(&format!("COMMIT,{},{}\n", writes.len(), sequence));
+
+        let mut file = File::create(&self.log_path).map_err(|e| log_error("create", e))?;
+        file.write_all(content.as_bytes()).map_err(|e| log_error("write", e))?;
+        file.sync_all().map_err(|e| log_error("sync", e))
+    }
+
+    /// Sequence number and writes of a transaction that committed but may not have reached its tables
+    /// A log cut short before its COMMIT line belongs to a transaction that never committed
+    pub fn recover(&self) -> Result<Option<(u64, Vec<TransactionWrite>)>, TransactionError> {
+        let file = match File::open(&self.log_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(log_error("open", e)),
+        };
+
+        let mut writes = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| log_error("read", e))?;
+            let parts: Vec<&str> = line.splitn(4, ',').collect();
+            match parts.as_slice() {
+                ["COMMIT", count, sequence] if count.parse() == Ok(writes.len()) => {
+                    let Ok(sequence) = sequence.parse() else {
+                        break;
+                    };
+                    return Ok(Some((sequence, writes)));
+                }
+                ["WRITE", table, id, data] => {
+                    let (Ok(id), Ok(data)) = (id.parse(), general_purpose::STANDARD.decode(data)) else {
+                        break;
+                    };
+                    writes.push(TransactionWrite { table: table.to_string(), id, data: Some(data) });
+                }
+                ["DELETE", table, id] => {
+                    let Ok(id) = id.parse() else {
+                        break;
+                    };
+                    writes.push(TransactionWrite { table: table.to_string(), id, data: None });
+                }
+                _ => break,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Forget the logged transaction once its writes have reached every table
+    pub fn clear(&self) -> Result<(), TransactionError> {
+        match fs::remove_file(&self.log_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(log_error("remove", e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_transaction_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_path = format!("{}/transaction.log", temp_dir.path().to_str().unwrap());
+        let log = TransactionLog::new(&log_path);
+        assert!(log.recover().unwrap().is_none());
+
+        let writes = vec![
+            TransactionWrite { table: "users".to_string(), id: 1, data: Some(vec![0, 1, 2]) },
+            TransactionWrite { table: "orders".to_string(), id: 7, data: None },
+        ];
+        log.log_commit(&writes, 4).unwrap();
+        assert_eq!(log.recover().unwrap(), Some((4, writes)));
+
+        // A log without its COMMIT line is ignored
+        let content = fs::read_to_string(&log_path).unwrap();
+        fs::write(&log_path, content.lines().take(2).collect::<Vec<_>>().join("\n")).unwrap();
+        assert!(log.recover().unwrap().is_none());
+
+        log.clear().unwrap();
+        log.clear().unwrap();
+        assert!(log.recover().unwrap().is_none());
+    }
+}
@@ -1,5 +1,8 @@
 pub mod error;
+pub mod log;
+#[allow(clippy::module_inception)]
 pub mod transaction;
 
 pub use error::TransactionError;
-pub use transaction::Transaction;
\ No newline at end of file
+pub use log::{TransactionLog, TransactionWrite};
+pub use transaction::{IsolationLevel, Transaction};
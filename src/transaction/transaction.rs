@@ -1,34 +1,117 @@
-use std::collections::HashMap;
-use crate::metadata::Table;
 use super::error::TransactionError;
 
+/// How much of the work of concurrent transactions a transaction sees
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IsolationLevel {
+    /// Each statement reads the rows committed before it started
+    #[default]
+    ReadCommitted,
+    /// Every statement reads the rows committed before the transaction's first statement
+    RepeatableRead,
+    /// Like REPEATABLE READ, and the commit fails if a table the transaction read changed since its snapshot
+    Serializable,
+}
+
+impl IsolationLevel {
+    /// Parse the words after ISOLATION LEVEL
+    /// READ UNCOMMITTED is accepted and behaves as READ COMMITTED
+    pub fn parse(words: &[&str]) -> Option<Self> {
+        let words: Vec<String> = words.iter().map(|word| word.to_uppercase()).collect();
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        match words.as_slice() {
+            ["READ", "COMMITTED"] | ["READ", "UNCOMMITTED"] => Some(IsolationLevel::ReadCommitted),
+            ["REPEATABLE", "READ"] => Some(IsolationLevel::RepeatableRead),
+            ["SERIALIZABLE"] => Some(IsolationLevel::Serializable),
+            _ => None,
+        }
+    }
+}
+
+/// State of the explicit transaction of a session, opened with BEGIN
 pub struct Transaction {
-    tables: HashMap<String, Table>,
+    id: u64,
     is_active: bool,
+    isolation_level: IsolationLevel,
+    next_isolation_level: Option<IsolationLevel>, // set by SET TRANSACTION outside a transaction
+    snapshot: Option<u64>,                        // taken by the first statement
+}
+
+impl Default for Transaction {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Transaction {
     pub fn new() -> Self {
         Transaction {
-            tables: HashMap::new(),
+            id: 0,
             is_active: false,
+            isolation_level: IsolationLevel::default(),
+            next_isolation_level: None,
+            snapshot: None,
         }
     }
 
-    pub fn begin(&mut self) -> Result<(), TransactionError> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    pub fn isolation_level(&self) -> IsolationLevel {
+        self.isolation_level
+    }
+
+    /// Snapshot of the transaction's first statement, if one ran
+    pub fn snapshot(&self) -> Option<u64> {
+        self.snapshot
+    }
+
+    /// Start transaction `id`, at the level set for it by SET TRANSACTION, if any
+    pub fn begin(&mut self, id: u64) -> Result<(), TransactionError> {
         if self.is_active {
             return Err(TransactionError::AlreadyInTransaction);
         }
+        self.id = id;
         self.is_active = true;
+        self.isolation_level = self.next_isolation_level.take().unwrap_or_default();
+        self.snapshot = None;
         Ok(())
     }
 
+    /// Set the level of the open transaction, which must not have run a statement yet,
+    /// or else of the next one
+    pub fn set_isolation_level(&mut self, level: IsolationLevel) -> Result<(), TransactionError> {
+        if !self.is_active {
+            self.next_isolation_level = Some(level);
+        } else if self.snapshot.is_none() {
+            self.isolation_level = level;
+        } else {
+            return Err(TransactionError::ExecutionError(
+                "SET TRANSACTION ISOLATION LEVEL must come before any statement of the transaction".to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Snapshot the next statement reads, given the sequence number of the latest commit
+    pub fn statement_snapshot(&mut self, latest: u64) -> u64 {
+        let first = *self.snapshot.get_or_insert(latest);
+        match self.isolation_level {
+            IsolationLevel::ReadCommitted => latest,
+            IsolationLevel::RepeatableRead | IsolationLevel::Serializable => first,
+        }
+    }
+
     pub fn commit(&mut self) -> Result<(), TransactionError> {
         if !self.is_active {
             return Err(TransactionError::NotInTransaction);
         }
         self.is_active = false;
-        self.tables.clear();
+        self.snapshot = None;
         Ok(())
     }
 
@@ -37,7 +120,302 @@ impl Transaction {
             return Err(TransactionError::NotInTransaction);
         }
         self.is_active = false;
-        self.tables.clear();
+        self.snapshot = None;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::{QueryEngine, QueryError, QueryResult, SessionId};
+    use crate::transaction::{TransactionLog, TransactionWrite};
+    use std::fs;
+    use tempfile::TempDir;
+    use crate::test_util::{setup_engine, rows, shown};
+
+    const SETUP: &[&str] = &[
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, name VARCHAR 20, age INTEGER)",
+        "INSERT INTO users VALUES (1, 'Ann', 30), (2, 'Bob', 25), (3, 'Cid', 41)",
+        "CREATE INDEX users_name ON users (name)",
+    ];
+
+    fn names(engine: &mut QueryEngine) -> Vec<String> {
+        names_in(engine, 0)
+    }
+
+    fn names_in(engine: &mut QueryEngine, session: SessionId) -> Vec<String> {
+        let result = engine.execute_in(session, "SELECT name FROM users ORDER BY id").unwrap()
+            .into_select_result()
+            .expect("Expected rows");
+        shown(&result.rows).into_iter().map(|row| row[0].clone()).collect()
+    }
+
+    fn is_serialization_failure(result: Result<QueryResult, QueryError>) -> bool {
+        matches!(result, Err(QueryError::TransactionError(msg)) if msg.starts_with("Could not serialize access"))
+    }
+
+    #[test]
+    fn test_transaction_statements() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        // The transaction reads its own writes, through full scans and indexes alike
+        assert!(matches!(engine.execute("BEGIN").unwrap(), QueryResult::Begin));
+        engine.execute("INSERT INTO users VALUES (4, 'Dee', 19)").unwrap();
+        engine.execute("UPDATE users SET name = 'Ann Lee' WHERE id = 1").unwrap();
+        engine.execute("DELETE FROM users WHERE id = 2").unwrap();
+        assert_eq!(names(&mut engine), vec!["Ann Lee", "Cid", "Dee"]);
+        assert_eq!(rows(&mut engine, "SELECT id FROM users WHERE name = 'Dee'"), vec![vec!["4"]]);
+        assert!(rows(&mut engine, "SELECT id FROM users WHERE name = 'Ann'").is_empty());
+        assert!(matches!(engine.execute("INSERT INTO users VALUES (4, 'Dup', 1)"), Err(QueryError::UniqueViolation(_))));
+
+        // Other sessions see nothing until COMMIT
+        let mut other = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert_eq!(names(&mut other), vec!["Ann", "Bob", "Cid"]);
+        drop(other);
+
+        assert!(matches!(engine.execute("ROLLBACK").unwrap(), QueryResult::Rollback));
+        assert_eq!(names(&mut engine), vec!["Ann", "Bob", "Cid"]);
+        assert_eq!(rows(&mut engine, "SELECT id FROM users WHERE name = 'Ann'"), vec![vec!["1"]]);
+        assert!(rows(&mut engine, "SELECT id FROM users WHERE name = 'Dee'").is_empty());
+
+        engine.execute("START TRANSACTION").unwrap();
+        engine.execute("UPDATE users SET age = age + 1 WHERE name = 'Bob'").unwrap();
+        engine.execute("DELETE FROM users WHERE id = 3").unwrap();
+        assert!(matches!(engine.execute("COMMIT WORK").unwrap(), QueryResult::Commit));
+        drop(engine);
+
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert_eq!(rows(&mut engine, "SELECT name, age FROM users ORDER BY id"), vec![vec!["Ann", "30"], vec!["Bob", "26"]]);
+        assert!(rows(&mut engine, "SELECT id FROM users WHERE name = 'Cid'").is_empty());
+    }
+
+    #[test]
+    fn test_transaction_errors() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+
+        assert!(matches!(engine.execute("COMMIT"), Err(QueryError::TransactionError(_))));
+        assert!(matches!(engine.execute("ROLLBACK"), Err(QueryError::TransactionError(_))));
+        assert!(matches!(engine.execute("START"), Err(QueryError::SyntaxError(_))));
+        engine.execute("BEGIN TRANSACTION").unwrap();
+        assert!(matches!(engine.execute("BEGIN"), Err(QueryError::TransactionError(_))));
+        assert!(matches!(engine.execute("CREATE TABLE t (id INTEGER)"), Err(QueryError::TransactionError(_))));
+        assert!(matches!(engine.execute("DROP TABLE users"), Err(QueryError::TransactionError(_))));
+        engine.execute("ROLLBACK").unwrap();
+        engine.execute("CREATE TABLE t (id INTEGER)").unwrap();
+    }
+
+    #[test]
+    fn test_commit_recovery() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().to_str().unwrap();
+        drop(setup_engine(&temp_dir, SETUP));
+        let ids: Vec<u64> = fs::read_to_string(format!("{}/users/write.log", data_dir)).unwrap()
+            .lines()
+            .map(|line| line.split(',').nth(1).unwrap().parse().unwrap())
+            .collect();
+        let log_path = format!("{}/transaction.log", data_dir);
+
+        // A transaction cut short before its COMMIT line never happened
+        fs::write(&log_path, format!("DELETE,users,{}\n", ids[0])).unwrap();
+        let mut engine = QueryEngine::new_with_data_dir(data_dir);
+        assert_eq!(names(&mut engine), vec!["Ann", "Bob", "Cid"]);
+        engine.flush_all().unwrap();
+        drop(engine);
+
+        // A committed one is finished on restart, indexes included, and its deletes hide the flushed rows for good
+        let delete = TransactionWrite { table: "users".to_string(), id: ids[1], data: None };
+        TransactionLog::new(&log_path).log_commit(&[delete], 5).unwrap();
+        let mut engine = QueryEngine::new_with_data_dir(data_dir);
+        assert_eq!(names(&mut engine), vec!["Ann", "Cid"]);
+        assert!(rows(&mut engine, "SELECT id FROM users WHERE name = 'Bob'").is_empty());
+        assert!(!std::path::Path::new(&log_path).exists());
+        engine.compact_all().unwrap();
+        drop(engine);
+
+        let mut engine = QueryEngine::new_with_data_dir(data_dir);
+        assert_eq!(names(&mut engine), vec!["Ann", "Cid"]);
+    }
+
+    #[test]
+    fn test_committed_delete_after_flush() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().to_str().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.flush_all().unwrap();
+
+        engine.execute("BEGIN").unwrap();
+        engine.execute("DELETE FROM users WHERE id = 2").unwrap();
+        engine.execute("COMMIT").unwrap();
+        drop(engine);
+
+        let mut engine = QueryEngine::new_with_data_dir(data_dir);
+        assert_eq!(names(&mut engine), vec!["Ann", "Cid"]);
+        assert!(rows(&mut engine, "SELECT id FROM users WHERE name = 'Bob'").is_empty());
+        engine.compact_all().unwrap();
+        engine.execute("INSERT INTO users VALUES (2, 'Bea', 22)").unwrap();
+        assert_eq!(names(&mut engine), vec!["Ann", "Bea", "Cid"]);
+    }
+
+    #[test]
+    fn test_snapshot_isolation() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        let other = engine.open_session();
+
+        // A REPEATABLE READ transaction reads the snapshot of its first statement throughout
+        engine.execute("BEGIN ISOLATION LEVEL REPEATABLE READ").unwrap();
+        assert_eq!(names(&mut engine), vec!["Ann", "Bob", "Cid"]);
+        engine.execute_in(other, "UPDATE users SET name = 'Ann Lee' WHERE id = 1").unwrap();
+        engine.execute_in(other, "INSERT INTO users VALUES (4, 'Dee', 19)").unwrap();
+        engine.execute_in(other, "DELETE FROM users WHERE id = 3").unwrap();
+        assert_eq!(names_in(&mut engine, other), vec!["Ann Lee", "Bob", "Dee"]);
+        assert_eq!(names(&mut engine), vec!["Ann", "Bob", "Cid"]);
+        assert_eq!(rows(&mut engine, "SELECT id FROM users WHERE name = 'Ann'"), vec![vec!["1"]]);
+        assert_eq!(rows(&mut engine, "EXPLAIN SELECT id FROM users WHERE name = 'Ann'"), vec![vec!["Full Scan on users"]]);
+        assert_eq!(rows(&mut engine, "SELECT COUNT(*) FROM users WHERE age > 20"), vec![vec!["3"]]);
+        engine.execute("COMMIT").unwrap();
+        assert_eq!(names(&mut engine), vec!["Ann Lee", "Bob", "Dee"]);
+        assert_eq!(rows(&mut engine, "EXPLAIN SELECT id FROM users WHERE name = 'Dee'"), vec![vec!["Index Lookup on users using users_name (name = 'Dee')"]]);
+
+        // READ COMMITTED, the default, reads what committed before each statement, but never uncommitted writes
+        engine.execute("BEGIN").unwrap();
+        assert_eq!(names(&mut engine), vec!["Ann Lee", "Bob", "Dee"]);
+        engine.execute_in(other, "BEGIN").unwrap();
+        engine.execute_in(other, "UPDATE users SET name = 'Bo' WHERE id = 2").unwrap();
+        assert_eq!(names(&mut engine), vec!["Ann Lee", "Bob", "Dee"]);
+        engine.execute_in(other, "COMMIT").unwrap();
+        assert_eq!(names(&mut engine), vec!["Ann Lee", "Bo", "Dee"]);
+        engine.execute("COMMIT").unwrap();
+
+        // Schema changes wait for every open transaction
+        engine.execute_in(other, "BEGIN").unwrap();
+        assert!(matches!(engine.execute("CREATE INDEX users_age ON users (age)"), Err(QueryError::TransactionError(_))));
+        engine.close_session(other).unwrap();
+        engine.execute("CREATE INDEX users_age ON users (age)").unwrap();
+        assert!(matches!(engine.execute_in(other, "SELECT name FROM users"), Err(QueryError::TransactionError(_))));
+    }
+
+    #[test]
+    fn test_write_conflicts() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.execute("CREATE UNIQUE INDEX users_age ON users (age)").unwrap();
+        let other = engine.open_session();
+
+        // The first committer wins, and the other transaction is rolled back
+        engine.execute("BEGIN").unwrap();
+        engine.execute("UPDATE users SET age = age + 1 WHERE id = 1").unwrap();
+        engine.execute("INSERT INTO users VALUES (4, 'Dee', 19)").unwrap();
+        engine.execute_in(other, "BEGIN").unwrap();
+        engine.execute_in(other, "UPDATE users SET age = age + 10 WHERE id = 1").unwrap();
+        engine.execute_in(other, "COMMIT").unwrap();
+        assert!(is_serialization_failure(engine.execute("COMMIT")));
+        assert!(matches!(engine.execute("ROLLBACK"), Err(QueryError::TransactionError(_))));
+        assert_eq!(rows(&mut engine, "SELECT name, age FROM users ORDER BY id"), vec![
+            vec!["Ann", "40"], vec!["Bob", "25"], vec!["Cid", "41"],
+        ]);
+
+        // Rows committed meanwhile count against the uniqueness of the others' writes
+        engine.execute("BEGIN").unwrap();
+        engine.execute("INSERT INTO users VALUES (4, 'Dee', 19)").unwrap();
+        engine.execute_in(other, "BEGIN").unwrap();
+        engine.execute_in(other, "INSERT INTO users VALUES (4, 'Dan', 20)").unwrap();
+        engine.execute_in(other, "INSERT INTO users VALUES (5, 'Eve', 19)").unwrap();
+        engine.execute("COMMIT").unwrap();
+        assert!(matches!(engine.execute_in(other, "COMMIT"), Err(QueryError::UniqueViolation(_))));
+
+        // Within a transaction, uniqueness follows its own writes
+        engine.execute("BEGIN").unwrap();
+        engine.execute("DELETE FROM users WHERE id = 4").unwrap();
+        engine.execute("INSERT INTO users VALUES (5, 'Eve', 19)").unwrap();
+        assert!(matches!(engine.execute("INSERT INTO users VALUES (6, 'Fay', 19)"), Err(QueryError::UniqueViolation(_))));
+        engine.execute("COMMIT").unwrap();
+        assert_eq!(names(&mut engine), vec!["Ann", "Bob", "Cid", "Eve"]);
+        assert_eq!(rows(&mut engine, "SELECT name FROM users WHERE age = 19"), vec![vec!["Eve"]]);
+    }
+
+    #[test]
+    fn test_serializable() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        engine.execute("CREATE TABLE doctors (id INTEGER PRIMARY KEY, name VARCHAR 20, on_call INTEGER)").unwrap();
+        engine.execute("INSERT INTO doctors VALUES (1, 'Ann', 1), (2, 'Bob', 1)").unwrap();
+        let other = engine.open_session();
+
+        // Each takes a doctor off call after checking the other is on call; together they leave no one
+        let write_skew = |engine: &mut QueryEngine, level: &str| {
+            engine.execute(&format!("SET TRANSACTION ISOLATION LEVEL {}", level)).unwrap();
+            engine.execute_in(other, &format!("SET TRANSACTION ISOLATION LEVEL {}", level)).unwrap();
+            engine.execute("BEGIN").unwrap();
+            engine.execute_in(other, "BEGIN").unwrap();
+            assert_eq!(rows(engine, "SELECT COUNT(*) FROM doctors WHERE on_call = 1"), vec![vec!["2"]]);
+            engine.execute_in(other, "SELECT COUNT(*) FROM doctors WHERE on_call = 1").unwrap();
+            engine.execute("UPDATE doctors SET on_call = 0 WHERE id = 1").unwrap();
+            engine.execute_in(other, "UPDATE doctors SET on_call = 0 WHERE id = 2").unwrap();
+            engine.execute("COMMIT").unwrap();
+            let result = engine.execute_in(other, "COMMIT");
+            engine.execute("UPDATE doctors SET on_call = 1").unwrap();
+            result
+        };
+        assert!(write_skew(&mut engine, "REPEATABLE READ").is_ok());
+        assert!(is_serialization_failure(write_skew(&mut engine, "SERIALIZABLE")));
+
+        // The level is set before the transaction's first statement
+        engine.execute("BEGIN").unwrap();
+        engine.execute("SET TRANSACTION ISOLATION LEVEL SERIALIZABLE").unwrap();
+        engine.execute("SELECT name FROM doctors").unwrap();
+        assert!(matches!(engine.execute("SET TRANSACTION ISOLATION LEVEL READ COMMITTED"), Err(QueryError::TransactionError(_))));
+        engine.execute("COMMIT").unwrap();
+        assert!(matches!(engine.execute("SET TRANSACTION ISOLATION LEVEL SOMETIMES"), Err(QueryError::SyntaxError(_))));
+        assert!(matches!(engine.execute("COMMIT ISOLATION LEVEL SERIALIZABLE"), Err(QueryError::SyntaxError(_))));
+    }
+
+    #[test]
+    fn test_version_garbage_collection() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        let other = engine.open_session();
+
+        engine.execute_in(other, "BEGIN ISOLATION LEVEL REPEATABLE READ").unwrap();
+        assert_eq!(names_in(&mut engine, other), vec!["Ann", "Bob", "Cid"]);
+        engine.execute("UPDATE users SET name = 'Ann Lee' WHERE id = 1").unwrap();
+        engine.execute("UPDATE users SET name = 'Ann Ray' WHERE id = 1").unwrap();
+        engine.execute("DELETE FROM users WHERE id = 2").unwrap();
+
+        // Compaction keeps the versions the open snapshot reads, until its transaction ends
+        engine.compact_all().unwrap();
+        assert_eq!(engine.get_table_stats("users").unwrap().old_versions, 3);
+        assert_eq!(names_in(&mut engine, other), vec!["Ann", "Bob", "Cid"]);
+        engine.execute_in(other, "COMMIT").unwrap();
+        engine.compact_all().unwrap();
+        assert_eq!(engine.get_table_stats("users").unwrap().old_versions, 0);
+        assert_eq!(names_in(&mut engine, other), vec!["Ann Ray", "Cid"]);
+    }
+
+    #[test]
+    fn test_deletes_survive_compaction() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut engine = setup_engine(&temp_dir, SETUP);
+        engine.flush_all().unwrap();
+
+        // The delete of a flushed row is kept as a tombstone until compaction drops the row itself
+        engine.execute("DELETE FROM users WHERE id = 1").unwrap();
+        assert_eq!(names(&mut engine), vec!["Bob", "Cid"]);
+        engine.compact_all().unwrap();
+        assert_eq!(names(&mut engine), vec!["Bob", "Cid"]);
+        engine.execute("INSERT INTO users VALUES (1, 'Ada', 33)").unwrap();
+        drop(engine);
+
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert_eq!(names(&mut engine), vec!["Ada", "Bob", "Cid"]);
+        engine.execute("DELETE FROM users WHERE name = 'Ada'").unwrap();
+        engine.compact_all().unwrap();
+        drop(engine);
+
+        let mut engine = QueryEngine::new_with_data_dir(temp_dir.path().to_str().unwrap());
+        assert_eq!(names(&mut engine), vec!["Bob", "Cid"]);
+        assert!(rows(&mut engine, "SELECT id FROM users WHERE name = 'Ada'").is_empty());
+    }
+}